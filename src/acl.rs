@@ -0,0 +1,375 @@
+use anyhow::{bail, ensure, Context};
+use parking_lot::RwLock;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::LazyLock,
+};
+
+use crate::{commands::CommandSpec, glob::glob_match, RedisError, Resp};
+
+pub static ACL: LazyLock<Acl> = LazyLock::new(Acl::new);
+
+/// Categories commands are grouped in, see [`CommandSpec::categories`]
+pub const CATEGORIES: &[&str] = &[
+    "keyspace",
+    "read",
+    "write",
+    "string",
+    "hash",
+    "sortedset",
+    "stream",
+    "pubsub",
+    "admin",
+    "fast",
+    "slow",
+    "blocking",
+    "dangerous",
+    "connection",
+    "transaction",
+    "scripting",
+];
+
+/// Users and their permissions
+#[derive(Debug)]
+pub struct Acl {
+    users: RwLock<BTreeMap<String, User>>,
+    /// Password of the default user set by `requirepass`
+    requirepass: RwLock<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct User {
+    pub name: String,
+    pub enabled: bool,
+    /// Any password authenticates the user
+    pub nopass: bool,
+    /// Hex encoded SHA-256 of the passwords
+    pub passwords: BTreeSet<String>,
+    /// `+`/`-` command and category rules, applied in order
+    pub commands: Vec<String>,
+    /// Glob patterns of the keys the user can access
+    pub keys: Vec<String>,
+}
+
+impl Acl {
+    pub const DEFAULT_USER: &'static str = "default";
+
+    fn new() -> Self {
+        let default = User {
+            enabled: true,
+            nopass: true,
+            commands: vec!["+@all".to_owned()],
+            keys: vec!["*".to_owned()],
+            ..User::new(Self::DEFAULT_USER)
+        };
+        Self {
+            users: RwLock::new(BTreeMap::from([(default.name.clone(), default)])),
+            requirepass: RwLock::new(String::new()),
+        }
+    }
+
+    pub fn requirepass(&self) -> String {
+        self.requirepass.read().clone()
+    }
+
+    /// Makes `password` the only password of the default user, or removes
+    /// the need for one if it's empty
+    pub fn set_requirepass(&self, password: &str) {
+        let rules = if password.is_empty() {
+            vec!["nopass".to_owned()]
+        } else {
+            vec!["resetpass".to_owned(), format!(">{password}")]
+        };
+        self.setuser(Self::DEFAULT_USER, &rules)
+            .expect("Valid password rules");
+        password.clone_into(&mut self.requirepass.write());
+    }
+
+    /// Creates or modifies a user. Rules are applied together or not at all.
+    pub fn setuser(&self, name: &str, rules: &[String]) -> anyhow::Result<()> {
+        let mut user = self.getuser(name).unwrap_or_else(|| User::new(name));
+        for rule in rules {
+            user.apply(rule)
+                .map_err(|e| anyhow::anyhow!("ERR Error in ACL SETUSER modifier '{rule}': {e}"))?;
+        }
+        self.users.write().insert(name.to_owned(), user);
+        Ok(())
+    }
+
+    pub fn getuser(&self, name: &str) -> Option<User> {
+        self.users.read().get(name).cloned()
+    }
+
+    /// Deletes the users, returning how many existed
+    pub fn deluser(&self, names: &[String]) -> anyhow::Result<usize> {
+        ensure!(
+            names.iter().all(|name| name != Self::DEFAULT_USER),
+            "ERR The 'default' user cannot be removed"
+        );
+        let mut users = self.users.write();
+        Ok(names
+            .iter()
+            .filter(|name| users.remove(name.as_str()).is_some())
+            .count())
+    }
+
+    /// ACL LIST lines, sorted by user name
+    pub fn list(&self) -> Vec<String> {
+        self.users.read().values().map(User::describe).collect()
+    }
+
+    /// Whether `password` authenticates `name`
+    pub fn authenticate(&self, name: &str, password: &str) -> bool {
+        self.users.read().get(name).is_some_and(|user| {
+            user.enabled && (user.nopass || user.passwords.contains(&hash(password)))
+        })
+    }
+
+    /// User new connections are authenticated as, unless the default user needs a password
+    pub fn default_login(&self) -> Option<String> {
+        self.users
+            .read()
+            .get(Self::DEFAULT_USER)
+            .filter(|default| default.enabled && default.nopass)
+            .map(|default| default.name.clone())
+    }
+
+    /// Checks `user` can run the command `args` and access its keys
+    pub fn check(&self, user: &str, args: &[Resp]) -> anyhow::Result<()> {
+        let Some(spec) = CommandSpec::of(args) else {
+            return Ok(());
+        };
+        // Needed to authenticate in the first place
+        if spec.has_flag("no_auth") {
+            return Ok(());
+        }
+        let (command, keys) = self.users.read().get(user).map_or((false, false), |user| {
+            let keys = spec.keys.keys(args);
+            (
+                user.allows_command(spec),
+                keys.into_iter().all(|key| user.allows_key(key)),
+            )
+        });
+        ensure!(
+            command,
+            RedisError::NoPermCommand {
+                user: user.to_owned(),
+                command: spec.name.to_owned(),
+            }
+        );
+        ensure!(keys, RedisError::NoPermKey);
+        Ok(())
+    }
+}
+
+impl User {
+    /// A disabled user without any permissions
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            enabled: false,
+            nopass: false,
+            passwords: BTreeSet::new(),
+            commands: Vec::new(),
+            keys: Vec::new(),
+        }
+    }
+
+    fn apply(&mut self, rule: &str) -> anyhow::Result<()> {
+        let (prefix, rest) = rule.split_at(rule.chars().next().map_or(0, char::len_utf8));
+        match prefix {
+            ">" => {
+                self.passwords.insert(hash(rest));
+                self.nopass = false;
+            }
+            "<" => ensure!(self.passwords.remove(&hash(rest)), "no such password"),
+            "#" => {
+                ensure!(
+                    rest.len() == 64 && rest.bytes().all(|b| b.is_ascii_hexdigit()),
+                    "The password hash must be exactly 64 characters and contain only lowercase hexadecimal characters"
+                );
+                self.passwords.insert(rest.to_ascii_lowercase());
+                self.nopass = false;
+            }
+            "!" => ensure!(
+                self.passwords.remove(&rest.to_ascii_lowercase()),
+                "no such password"
+            ),
+            "~" => self.keys.push(rest.to_owned()),
+            "+" | "-" => self.apply_command_rule(prefix, &rest.to_ascii_lowercase())?,
+            _ => match rule.to_ascii_lowercase().as_str() {
+                "on" => self.enabled = true,
+                "off" => self.enabled = false,
+                "nopass" => {
+                    self.passwords.clear();
+                    self.nopass = true;
+                }
+                "resetpass" => {
+                    self.passwords.clear();
+                    self.nopass = false;
+                }
+                "allkeys" => self.keys = vec!["*".to_owned()],
+                "resetkeys" => self.keys.clear(),
+                "allcommands" => self.apply_command_rule("+", "@all")?,
+                "nocommands" => self.apply_command_rule("-", "@all")?,
+                "reset" => *self = Self::new(&self.name),
+                _ => bail!("Syntax error"),
+            },
+        }
+        Ok(())
+    }
+
+    fn apply_command_rule(&mut self, sign: &str, target: &str) -> anyhow::Result<()> {
+        match target.strip_prefix('@') {
+            // Overrides every previous rule
+            Some("all") => self.commands.clear(),
+            Some(category) => ensure!(
+                CATEGORIES.contains(&category),
+                "Unknown command or category name in ACL"
+            ),
+            None => {
+                CommandSpec::lookup(target.as_bytes())
+                    .context("Unknown command or category name in ACL")?;
+            }
+        }
+        self.commands.push(format!("{sign}{target}"));
+        Ok(())
+    }
+
+    fn allows_command(&self, spec: &CommandSpec) -> bool {
+        self.commands.iter().fold(false, |allowed, rule| {
+            let (sign, target) = rule.split_at(1);
+            let matches = match target.strip_prefix('@') {
+                Some("all") => true,
+                Some(category) => spec.categories.contains(&category),
+                None => target == spec.name,
+            };
+            if matches {
+                sign == "+"
+            } else {
+                allowed
+            }
+        })
+    }
+
+    fn allows_key(&self, key: &Resp) -> bool {
+        let Some(key) = key.as_bulk() else {
+            return false;
+        };
+        self.keys.iter().any(|pattern| glob_match(pattern, key))
+    }
+
+    pub fn flags(&self) -> Vec<&'static str> {
+        let mut flags = vec![if self.enabled { "on" } else { "off" }];
+        if self.nopass {
+            flags.push("nopass");
+        }
+        flags
+    }
+
+    /// Command rules as given to ACL SETUSER
+    pub fn describe_commands(&self) -> String {
+        if self.commands.is_empty() {
+            "-@all".to_owned()
+        } else {
+            self.commands.join(" ")
+        }
+    }
+
+    /// Key patterns as given to ACL SETUSER
+    pub fn describe_keys(&self) -> String {
+        self.keys
+            .iter()
+            .map(|pattern| format!("~{pattern}"))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// ACL LIST line of the user
+    fn describe(&self) -> String {
+        let mut parts = vec!["user".to_owned(), self.name.clone()];
+        parts.extend(self.flags().into_iter().map(str::to_owned));
+        parts.extend(self.passwords.iter().map(|hash| format!("#{hash}")));
+        if !self.keys.is_empty() {
+            parts.push(self.describe_keys());
+        }
+        parts.push(self.describe_commands());
+        parts.join(" ")
+    }
+}
+
+fn hash(password: &str) -> String {
+    hex::encode(Sha256::digest(password.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&'static str]) -> Vec<Resp> {
+        args.iter().copied().map(Resp::bulk).collect()
+    }
+
+    #[test]
+    fn rules() {
+        let mut user = User::new("alice");
+        for rule in ["on", ">secret", "~cache:*", "+@read", "-type", "+set"] {
+            user.apply(rule).unwrap();
+        }
+        pretty_assertions::assert_eq!(
+            user.describe(),
+            format!(
+                "user alice on #{} ~cache:* +@read -type +set",
+                hash("secret")
+            )
+        );
+
+        let allows = |cmd: &[&'static str]| {
+            let args = args(cmd);
+            let spec = CommandSpec::lookup(args[0].as_bulk().unwrap()).unwrap();
+            user.allows_command(spec)
+                && spec
+                    .keys
+                    .keys(&args)
+                    .into_iter()
+                    .all(|key| user.allows_key(key))
+        };
+        assert!(allows(&["GET", "cache:a"]));
+        assert!(!allows(&["GET", "other"]));
+        assert!(!allows(&["TYPE", "cache:a"]));
+        assert!(allows(&["SET", "cache:a", "1"]));
+        assert!(!allows(&["DEL", "cache:a"]));
+        assert!(allows(&[
+            "XREAD", "STREAMS", "cache:a", "cache:b", "0", "0"
+        ]));
+        assert!(!allows(&["XREAD", "STREAMS", "cache:a", "b", "0", "0"]));
+
+        assert!(user.apply("+nope").is_err());
+        assert!(user.apply("+@nope").is_err());
+        assert!(user.apply("<wrong").is_err());
+        user.apply("reset").unwrap();
+        pretty_assertions::assert_eq!(user, User::new("alice"));
+    }
+
+    /// Protected mode only lets remote clients in once the default user needs a password
+    #[test]
+    fn requirepass() {
+        let acl = Acl::new();
+        pretty_assertions::assert_eq!(acl.default_login().as_deref(), Some(Acl::DEFAULT_USER));
+
+        acl.set_requirepass("secret");
+        pretty_assertions::assert_eq!(acl.requirepass(), "secret");
+        pretty_assertions::assert_eq!(acl.default_login(), None);
+        assert!(acl.authenticate(Acl::DEFAULT_USER, "secret"));
+        assert!(!acl.authenticate(Acl::DEFAULT_USER, "wrong"));
+
+        // Replaces the previous password
+        acl.set_requirepass("other");
+        assert!(!acl.authenticate(Acl::DEFAULT_USER, "secret"));
+
+        acl.set_requirepass("");
+        pretty_assertions::assert_eq!(acl.default_login().as_deref(), Some(Acl::DEFAULT_USER));
+        assert!(acl.authenticate(Acl::DEFAULT_USER, "anything"));
+    }
+}
@@ -0,0 +1,495 @@
+use anyhow::{bail, ensure, Context};
+use bytes::{Buf, Bytes, BytesMut};
+use parking_lot::{Mutex, RwLock};
+use std::{
+    fmt::Display,
+    io::Cursor,
+    path::Path,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, LazyLock,
+    },
+    time::{Duration, Instant},
+};
+use tokio::{
+    fs::{File, OpenOptions},
+    io::AsyncWriteExt,
+    sync::{watch, Notify},
+};
+
+use crate::{
+    commands::{Ctx, Session},
+    resp, Arguments, Command, Db, Rdb, Resp,
+};
+
+pub static AOF: LazyLock<Aof> = LazyLock::new(Aof::new);
+
+/// Outcome of [`Aof::replay`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Replayed {
+    /// Commands read, including those of a discarded transaction
+    pub read: usize,
+    /// Length of the complete commands, when the file ends in the middle
+    /// of a command or of a transaction
+    pub truncated: Option<usize>,
+}
+
+/// `appendfsync`: when the appended commands are flushed to the disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fsync {
+    /// After every write, before the client gets its reply
+    Always,
+    /// At most once per second
+    EverySec,
+    /// Whenever the OS decides to
+    No,
+}
+
+impl FromStr for Fsync {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_ascii_lowercase().as_str() {
+            "always" => Self::Always,
+            "everysec" => Self::EverySec,
+            "no" => Self::No,
+            _ => bail!("argument(s) must be one of the following: always, everysec, no"),
+        })
+    }
+}
+
+impl Display for Fsync {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Always => "always",
+            Self::EverySec => "everysec",
+            Self::No => "no",
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+struct Pending {
+    buf: BytesMut,
+    /// Number of batches fed so far
+    seq: u64,
+}
+
+/// Append-only file: every applied write is appended in RESP form by
+/// [`Aof::flush`], which also fsyncs the file according to [`Fsync`]
+#[derive(Debug)]
+pub struct Aof {
+    enabled: AtomicBool,
+    fsync: RwLock<Fsync>,
+    pending: Mutex<Pending>,
+    /// Wakes the flush task when something is fed
+    fed: Notify,
+    /// Last batch written, and synced under [`Fsync::Always`]
+    flushed: watch::Sender<u64>,
+}
+
+impl Aof {
+    const SYNC_PERIOD: Duration = Duration::from_secs(1);
+
+    fn new() -> Self {
+        Self {
+            enabled: AtomicBool::new(false),
+            fsync: RwLock::new(Fsync::EverySec),
+            pending: Mutex::new(Pending::default()),
+            fed: Notify::new(),
+            flushed: watch::Sender::new(0),
+        }
+    }
+
+    #[inline]
+    pub fn enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn enable(&self) {
+        self.enabled.store(true, Ordering::Relaxed);
+    }
+
+    /// Stops appending once the flush task is gone, dropping what it didn't write.
+    /// Clients waiting for their batch under [`Fsync::Always`] are released.
+    pub fn disable(&self) {
+        self.enabled.store(false, Ordering::Relaxed);
+        let seq = {
+            let mut pending = self.pending.lock();
+            pending.buf.clear();
+            pending.seq
+        };
+        self.flushed.send_replace(seq);
+    }
+
+    pub fn fsync(&self) -> Fsync {
+        *self.fsync.read()
+    }
+
+    pub fn set_fsync(&self, fsync: Fsync) {
+        *self.fsync.write() = fsync;
+    }
+
+    /// Appends the commands as one batch. Under [`Fsync::Always`] this
+    /// returns once the batch is on the disk. PUBLISH is left out,
+    /// it's propagated to the replicas but there's nothing to persist.
+    pub async fn feed(&self, resps: &[Resp]) {
+        if !self.enabled() {
+            return;
+        }
+        let resps = resps
+            .iter()
+            .filter(|resp| !is_command(resp, b"publish"))
+            .collect::<Vec<_>>();
+        // What's left of a transaction that only published messages
+        let empty_transaction = resps.len() == 2 && is_command(resps[0], b"multi");
+        if resps.is_empty() || empty_transaction {
+            return;
+        }
+        let seq = {
+            let mut pending = self.pending.lock();
+            for resp in resps {
+                resp.encode(&mut pending.buf);
+            }
+            pending.seq += 1;
+            pending.seq
+        };
+        self.fed.notify_one();
+        if self.fsync() == Fsync::Always {
+            let mut flushed = self.flushed.subscribe();
+            // The sender lives as long as the static, so this can't fail
+            let _ = flushed.wait_for(|&flushed| flushed >= seq).await;
+        }
+    }
+
+    /// Replays the AOF at `path`, after loading the RDB preamble it may start with.
+    /// Returns whether there was a file to load.
+    /// A last command cut by a crash is dropped from the file under `aof-load-truncated`,
+    /// and fails the load otherwise.
+    pub fn load(path: &Path, db: &Arc<Db>, args: &Arc<Arguments>) -> anyhow::Result<bool> {
+        let mut bytes = match std::fs::read(path) {
+            Ok(bytes) => Bytes::from(bytes),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("Can't read the append-only file {}", path.display()))
+            }
+        };
+        let len = bytes.len();
+        if bytes.starts_with(b"REDIS") {
+            let rdb = Rdb::parse_prefix(&mut bytes, db.persistence.rdbchecksum())?;
+            db.apply_rdb(rdb);
+        }
+        let preamble = len - bytes.len();
+
+        let replayed = Self::replay(&bytes, db, args)?;
+        tracing::info!(
+            "Replayed {} commands from {}",
+            replayed.read,
+            path.display()
+        );
+        if let Some(valid) = replayed.truncated {
+            let valid = preamble + valid;
+            ensure!(
+                args.aof_load_truncated,
+                "The append-only file {} is truncated at offset {valid}, \
+                 set aof-load-truncated to yes to load it without its last command",
+                path.display()
+            );
+            tracing::warn!(
+                "The append-only file {} is truncated, dropping the last {} bytes",
+                path.display(),
+                len - valid
+            );
+            Self::truncate(path, valid as u64)?;
+        }
+        Ok(true)
+    }
+
+    /// Cuts the file at `len`, so what's appended next doesn't follow a partial command
+    fn truncate(path: &Path, len: u64) -> anyhow::Result<()> {
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(path)
+            .and_then(|file| {
+                file.set_len(len)?;
+                file.sync_data()
+            })
+            .with_context(|| format!("Can't truncate the append-only file {}", path.display()))
+    }
+
+    /// Applies the commands of an AOF, which follow its RDB preamble
+    pub(crate) fn replay(
+        bytes: &[u8],
+        db: &Arc<Db>,
+        args: &Arc<Arguments>,
+    ) -> anyhow::Result<Replayed> {
+        let mut session = Session::default();
+        let mut ctx = Ctx::new(db, args, &mut session);
+        let mut cur = Cursor::new(bytes);
+        // Commands between MULTI and EXEC, only applied once the EXEC is read
+        // With the offset of their MULTI
+        let mut transaction: Option<(Vec<Command>, usize)> = None;
+        let (mut read, mut applied) = (0_usize, 0_usize);
+        let mut truncated = None;
+        while cur.has_remaining() {
+            let start = usize::try_from(cur.position())?;
+            let resp = match Resp::parse(&mut cur) {
+                Ok(resp) => resp,
+                Err(resp::Error::Incomplete | resp::Error::IncompleteBulk(_)) => {
+                    truncated = Some(start);
+                    break;
+                }
+                Err(e) => bail!("Bad file format reading the append only file: {e}"),
+            };
+            let (cmd, raw_cmd) =
+                Command::parse(&resp).context("Unknown command reading the append only file")?;
+            read += 1;
+            match cmd {
+                Command::Multi(_) => transaction = Some((Vec::new(), start)),
+                Command::Exec => {
+                    let queued = transaction.take().map(|(queued, _)| queued);
+                    for cmd in queued.unwrap_or_default() {
+                        applied += usize::from(Self::apply(cmd, &mut ctx));
+                    }
+                }
+                Command::Discard(_) => transaction = None,
+                cmd if !cmd.is_write(&raw_cmd) => {}
+                cmd => match &mut transaction {
+                    Some((queued, _)) => queued.push(cmd),
+                    None => applied += usize::from(Self::apply(cmd, &mut ctx)),
+                },
+            }
+        }
+        if let Some((_, multi)) = transaction {
+            tracing::warn!("Discarding the unterminated transaction at the end of the AOF");
+            // Or the commands appended next would be queued in it
+            truncated = Some(multi);
+        }
+        tracing::debug!("Applied {applied} writes");
+        Ok(Replayed { read, truncated })
+    }
+
+    fn apply(cmd: Command, ctx: &mut Ctx<'_>) -> bool {
+        cmd.execute_write(ctx)
+            .inspect_err(|e| tracing::warn!("Failed replaying a write from the AOF: {e}"))
+            .is_ok()
+    }
+
+    /// Opens the AOF at `path` for appending. A new file starts with a RDB
+    /// preamble of the current dataset, so what was loaded from the RDB isn't lost.
+    pub async fn open(path: &Path, db: &Db) -> anyhow::Result<File> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await
+            .with_context(|| format!("Can't open the append-only file {}", path.display()))?;
+        if file.metadata().await?.len() == 0 {
+            file.write_all(&db.dump_rdb(None))
+                .await
+                .context("Can't write to the append-only file")?;
+            file.sync_data()
+                .await
+                .context("Can't fsync the append-only file")?;
+        }
+        Ok(file)
+    }
+
+    /// Appends whatever is fed to `file`, forever.
+    /// Fails if the file can't be written, as the writes would be lost.
+    pub async fn flush(&self, mut file: File) -> anyhow::Result<()> {
+        let mut last_sync = Instant::now();
+        let mut unsynced = false;
+        loop {
+            let _ = tokio::time::timeout(Self::SYNC_PERIOD, self.fed.notified()).await;
+
+            let (batch, seq) = {
+                let mut pending = self.pending.lock();
+                (pending.buf.split().freeze(), pending.seq)
+            };
+            if !batch.is_empty() {
+                file.write_all(&batch)
+                    .await
+                    .context("Can't write to the append-only file")?;
+                file.flush()
+                    .await
+                    .context("Can't write to the append-only file")?;
+                unsynced = true;
+            }
+
+            let sync = match self.fsync() {
+                Fsync::Always => true,
+                Fsync::EverySec => last_sync.elapsed() >= Self::SYNC_PERIOD,
+                Fsync::No => false,
+            };
+            if unsynced && sync {
+                file.sync_data()
+                    .await
+                    .context("Can't fsync the append-only file")?;
+                last_sync = Instant::now();
+                unsynced = false;
+            }
+            self.flushed.send_replace(seq);
+        }
+    }
+}
+
+fn is_command(resp: &Resp, name: &[u8]) -> bool {
+    resp.as_array()
+        .and_then(|args| args.first())
+        .and_then(Resp::as_bulk)
+        .is_some_and(|arg| arg.eq_ignore_ascii_case(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(args: &[&'static str]) -> Resp {
+        Resp::Array(args.iter().copied().map(Resp::bulk).collect())
+    }
+
+    /// Feeds `batches` to a new AOF at `path` opened on `db`, running its flush task until
+    /// they're on the disk. An AOF of its own, the static one is fed by every test server.
+    async fn write_aof(path: &Path, db: &Db, batches: &[Vec<Resp>]) {
+        let _ = std::fs::remove_file(path);
+        let aof = Aof::new();
+        aof.enable();
+        aof.set_fsync(Fsync::Always);
+        let file = Aof::open(path, db).await.unwrap();
+        let feed = async {
+            for batch in batches {
+                aof.feed(batch).await;
+            }
+        };
+        tokio::select! {
+            res = aof.flush(file) => panic!("The flush task stopped: {res:?}"),
+            () = feed => {}
+        }
+    }
+
+    #[tokio::test]
+    async fn appends_writes() {
+        let path = std::env::temp_dir().join(format!("append-{}.aof", std::process::id()));
+        let batches = [
+            vec![command(&["SET", "a", "1"])],
+            vec![
+                command(&["MULTI"]),
+                command(&["INCR", "a"]),
+                command(&["PUBLISH", "c", "m"]),
+                command(&["EXEC"]),
+            ],
+            // Nothing is left of a transaction that only published
+            vec![
+                command(&["MULTI"]),
+                command(&["PUBLISH", "c", "m"]),
+                command(&["EXEC"]),
+            ],
+            vec![command(&["SET", "b", "2"])],
+        ];
+        write_aof(&path, &Db::default(), &batches).await;
+
+        // A new file starts with a RDB preamble
+        let mut bytes = Bytes::from(std::fs::read(&path).unwrap());
+        assert!(bytes.starts_with(b"REDIS"));
+        Rdb::parse_prefix(&mut bytes, true).unwrap();
+        let mut expected = BytesMut::new();
+        for cmd in [
+            &["SET", "a", "1"][..],
+            &["MULTI"],
+            &["INCR", "a"],
+            &["EXEC"],
+            &["SET", "b", "2"],
+        ] {
+            command(cmd).encode(&mut expected);
+        }
+        pretty_assertions::assert_eq!(bytes, expected);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    /// Keys with their expiration and value, in a form that compares
+    fn dataset(db: &Db) -> std::collections::BTreeMap<String, String> {
+        db.inner
+            .read()
+            .iter()
+            .map(|(key, value)| {
+                let at = value.expiration;
+                (key.clone(), format!("{at:?} {:?}", value.v_type))
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn replay_roundtrip() {
+        let path = std::env::temp_dir().join(format!("roundtrip-{}.aof", std::process::id()));
+        let args = Arc::new(Arguments::default());
+        let source = Arc::new(Db::default());
+        let apply = |cmds: &[&[&'static str]]| {
+            let mut session = Session::default();
+            let mut ctx = Ctx::new(&source, &args, &mut session);
+            for &cmd in cmds {
+                let (cmd, _) = Command::parse(&command(cmd)).unwrap();
+                cmd.execute_write(&mut ctx).unwrap();
+            }
+        };
+        // Saved in the preamble, then changed by the commands
+        apply(&[
+            &["SET", "s", "1"],
+            &["HSET", "h", "f", "v"],
+            &["SET", "gone", "v"],
+        ]);
+        let writes: [&[&str]; 6] = [
+            &["INCR", "s"],
+            &["SET", "px", "v", "PXAT", "99999999999999"],
+            &["HSET", "h", "g", "w"],
+            &["XADD", "x", "1-1", "k", "v"],
+            &["DEL", "gone"],
+            &["SET", "t", "1"],
+        ];
+        let batches = [
+            writes[..4].iter().map(|&cmd| command(cmd)).collect(),
+            vec![command(writes[4])],
+            vec![command(&["MULTI"]), command(writes[5]), command(&["EXEC"])],
+        ];
+        write_aof(&path, &source, &batches).await;
+        apply(&writes);
+        let keys = dataset(&source).into_keys().collect::<Vec<_>>();
+        pretty_assertions::assert_eq!(keys, ["h", "px", "s", "t", "x"]);
+
+        let loaded = Arc::new(Db::default());
+        assert!(Aof::load(&path, &loaded, &args).unwrap());
+        pretty_assertions::assert_eq!(dataset(&loaded), dataset(&source));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn truncated_tail() {
+        let mut aof = BytesMut::new();
+        let encode = |cmd: &[&'static str], aof: &mut BytesMut| {
+            Resp::Array(cmd.iter().map(|&arg| Resp::bulk(arg)).collect()).encode(aof);
+        };
+        encode(&["SET", "a", "1"], &mut aof);
+        let valid = aof.len();
+        encode(&["MULTI"], &mut aof);
+        encode(&["SET", "b", "2"], &mut aof);
+        let path = std::env::temp_dir().join(format!("truncated-{}.aof", std::process::id()));
+        std::fs::write(&path, &aof[..aof.len() - 3]).unwrap();
+
+        let args = Arguments {
+            aof_load_truncated: false,
+            ..Arguments::default()
+        };
+        let err = Aof::load(&path, &Arc::new(Db::default()), &Arc::new(args)).unwrap_err();
+        assert!(err.to_string().contains("aof-load-truncated"), "{err}");
+
+        // The cut command goes, with the transaction it was in
+        let db = Arc::new(Db::default());
+        assert!(Aof::load(&path, &db, &Arc::new(Arguments::default())).unwrap());
+        pretty_assertions::assert_eq!(std::fs::read(&path).unwrap(), &aof[..valid]);
+        assert!(db.view("a", |_| ()).is_some());
+        assert!(db.view("b", |_| ()).is_none());
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -1,27 +1,125 @@
-use clap::{arg, value_parser, ArgAction, Command};
-use once_cell::sync::Lazy;
+use anyhow::{bail, ensure, Context};
+use clap::{arg, error::ErrorKind, value_parser, ArgAction, ArgMatches, Command};
 use std::{
-    net::{Ipv4Addr, SocketAddrV4},
-    path::PathBuf,
+    convert::Infallible,
+    ffi::OsString,
+    fmt::Display,
+    net::{IpAddr, Ipv4Addr, SocketAddrV4},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
 };
+use tracing::level_filters::LevelFilter;
 
-use crate::{Role, Slave};
-
-pub static ARGUMENTS: Lazy<Arguments> = Lazy::new(Arguments::parse);
+use crate::{
+    aof::Fsync,
+    check::Check,
+    db::{persistence::SavePoints, EncodingLimits},
+    pubsub::Overflow,
+    Resp, Role, Slave,
+};
 
+/// Configuration of a server, from the command line or a [`crate::ServerBuilder`]
 #[derive(Debug)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct Arguments {
+    /// `redis.conf`-style file the parameters were read from, before the flags
+    pub config_file: Option<PathBuf>,
     pub port: u16,
     pub role: Role,
     pub dir: Option<PathBuf>,
     pub db_filename: Option<PathBuf>,
+    pub proto_max_bulk_len: usize,
+    pub repl_ping_replica_period: Duration,
+    pub repl_timeout: Duration,
+    /// Stream the RDB of a full resync to the replicas that support it,
+    /// instead of serializing it whole before sending its length
+    pub repl_diskless_sync: bool,
+    pub save: SavePoints,
+    pub rdbchecksum: bool,
+    pub appendonly: bool,
+    pub appendfsync: Fsync,
+    pub appendfilename: PathBuf,
+    /// Load an AOF whose last command was cut, dropping it
+    pub aof_load_truncated: bool,
+    /// Addresses listened on, all with the same port
+    pub bind: Vec<IpAddr>,
+    pub protected_mode: bool,
+    /// Memory of the connection buffers above which the largest clients are evicted,
+    /// no limit if zero
+    pub maxmemory_clients: usize,
+    /// Published messages that may wait for a subscriber to read them, no limit if zero
+    pub pubsub_queue_len: usize,
+    /// What happens to a subscriber with `pubsub_queue_len` messages pending
+    pub pubsub_overflow: Overflow,
+    /// Serve only the keys of the hash slots assigned to this node,
+    /// redirecting clients to the other nodes for the rest
+    pub cluster_enabled: bool,
+    /// Where a cluster node keeps its id and the other nodes, relative to `dir`
+    pub cluster_config_file: PathBuf,
+    /// Time a cluster node has to reply to a ping before it's flagged as failing
+    pub cluster_node_timeout: Duration,
+    pub requirepass: Option<String>,
+    /// Interval of the TCP keepalive probes, disabled if zero
+    pub tcp_keepalive: Duration,
+    pub tcp_nodelay: bool,
+    /// Running time after which a script makes the other clients' commands fail with `BUSY`
+    pub busy_reply_threshold: Duration,
+    pub encoding: EncodingLimits,
+    /// File every command run by a client is appended to, relative to `dir`
+    pub audit_log: Option<PathBuf>,
+    /// Size the audit log is rotated at, never if zero
+    pub audit_log_max_size: u64,
+    /// Rotated audit logs kept next to the current one
+    pub audit_log_max_files: usize,
+    /// File the server logs to, relative to `dir`, or the standard error if `None`
+    pub logfile: Option<PathBuf>,
+    pub loglevel: LogLevel,
+    /// Dump to verify, instead of running the server
+    pub check: Option<Check>,
 }
 
 impl Arguments {
-    #[must_use]
-    #[allow(clippy::cognitive_complexity)]
-    pub fn parse() -> Self {
-        let mut matches = Command::new(env!("CARGO_CRATE_NAME"))
+    /// Where SAVE writes the dataset, defaulting to `./dump.rdb`
+    pub fn rdb_path(&self) -> PathBuf {
+        let dir = self.dir.as_deref().unwrap_or_else(|| Path::new("."));
+        let name = self
+            .db_filename
+            .as_deref()
+            .unwrap_or_else(|| Path::new("dump.rdb"));
+        dir.join(name)
+    }
+
+    /// Where the append-only file is written, next to the RDB
+    pub fn aof_path(&self) -> PathBuf {
+        let dir = self.dir.as_deref().unwrap_or_else(|| Path::new("."));
+        dir.join(&self.appendfilename)
+    }
+
+    pub fn cluster_config_path(&self) -> PathBuf {
+        let dir = self.dir.as_deref().unwrap_or_else(|| Path::new("."));
+        dir.join(&self.cluster_config_file)
+    }
+
+    /// Where the server logs, if not to the standard error
+    pub fn logfile_path(&self) -> Option<PathBuf> {
+        let dir = self.dir.as_deref().unwrap_or_else(|| Path::new("."));
+        self.logfile.as_deref().map(|name| dir.join(name))
+    }
+
+    /// Where the audit log is written, if enabled
+    pub fn audit_log_path(&self) -> Option<PathBuf> {
+        let dir = self.dir.as_deref().unwrap_or_else(|| Path::new("."));
+        self.audit_log.as_deref().map(|name| dir.join(name))
+    }
+
+    // Run once at startup, each chained call keeping its own `Command` on the stack
+    #[allow(clippy::too_many_lines, clippy::large_stack_frames)]
+    fn command() -> Command {
+        Command::new(env!("CARGO_CRATE_NAME"))
+            // Flags may repeat a directive of the config file, the last one wins
+            .args_override_self(true)
+            .arg(arg!([config] "redis.conf-style config file").value_parser(value_parser!(PathBuf)))
             .arg(
                 arg!(--port)
                     .action(ArgAction::Set)
@@ -30,6 +128,7 @@ impl Arguments {
             )
             .arg(
                 arg!(--replicaof)
+                    .visible_alias("slaveof")
                     .action(ArgAction::Set)
                     .value_names(["HOST PORT"])
                     .value_delimiter(' ')
@@ -45,10 +144,260 @@ impl Arguments {
                     .action(ArgAction::Set)
                     .value_parser(value_parser!(PathBuf)),
             )
-            .get_matches();
+            .arg(
+                arg!(--"proto-max-bulk-len")
+                    .action(ArgAction::Set)
+                    .value_parser(value_parser!(u64).range(1024 * 1024..)),
+            )
+            .arg(
+                arg!(--"repl-ping-replica-period")
+                    .visible_alias("repl-ping-slave-period")
+                    .action(ArgAction::Set)
+                    .default_value("10")
+                    .value_parser(value_parser!(u64).range(1..)),
+            )
+            .arg(
+                arg!(--"repl-timeout")
+                    .action(ArgAction::Set)
+                    .default_value("60")
+                    .value_parser(value_parser!(u64).range(1..)),
+            )
+            .arg(
+                arg!(--"repl-diskless-sync" <"yes|no">)
+                    .action(ArgAction::Set)
+                    .default_value("yes")
+                    .value_parser(|s: &str| parse_yes_no(s)),
+            )
+            .arg(
+                arg!(--save <"SECONDS CHANGES">)
+                    .action(ArgAction::Set)
+                    .default_value(SavePoints::DEFAULT)
+                    .value_parser(|s: &str| s.parse::<SavePoints>().map_err(|e| e.to_string())),
+            )
+            .arg(
+                arg!(--rdbchecksum <"yes|no">)
+                    .action(ArgAction::Set)
+                    .default_value("yes")
+                    .value_parser(|s: &str| parse_yes_no(s)),
+            )
+            .arg(
+                arg!(--appendonly <"yes|no">)
+                    .action(ArgAction::Set)
+                    .default_value("no")
+                    .value_parser(|s: &str| parse_yes_no(s)),
+            )
+            .arg(
+                arg!(--appendfsync <"always|everysec|no">)
+                    .action(ArgAction::Set)
+                    .default_value("everysec")
+                    .value_parser(|s: &str| s.parse::<Fsync>().map_err(|e| e.to_string())),
+            )
+            .arg(
+                arg!(--appendfilename)
+                    .action(ArgAction::Set)
+                    .default_value("appendonly.aof")
+                    .value_parser(value_parser!(PathBuf)),
+            )
+            .arg(
+                arg!(--"aof-load-truncated" <"yes|no">)
+                    .action(ArgAction::Set)
+                    .default_value("yes")
+                    .value_parser(|s: &str| parse_yes_no(s)),
+            )
+            .arg(
+                arg!(--bind <ADDRESS> "Addresses to listen on, IPv4 or IPv6")
+                    .num_args(1..)
+                    // A config file directive is a single space-separated value
+                    .value_delimiter(' ')
+                    .default_value("127.0.0.1")
+                    .value_parser(value_parser!(IpAddr)),
+            )
+            .arg(
+                arg!(--"protected-mode" <"yes|no">)
+                    .action(ArgAction::Set)
+                    .default_value("yes")
+                    .value_parser(|s: &str| parse_yes_no(s)),
+            )
+            .arg(
+                arg!(--"maxmemory-clients" <BYTES>)
+                    .action(ArgAction::Set)
+                    .default_value("0")
+                    .value_parser(|s: &str| parse_memory(s)),
+            )
+            .arg(
+                arg!(--"pubsub-queue-len" <MESSAGES>)
+                    .action(ArgAction::Set)
+                    .default_value("10000")
+                    .value_parser(value_parser!(usize)),
+            )
+            .arg(
+                arg!(--"pubsub-overflow" <"drop-oldest|disconnect">)
+                    .action(ArgAction::Set)
+                    .default_value("disconnect")
+                    .value_parser(|s: &str| s.parse::<Overflow>().map_err(|e| e.to_string())),
+            )
+            .arg(
+                arg!(--"cluster-enabled" <"yes|no">)
+                    .action(ArgAction::Set)
+                    .default_value("no")
+                    .value_parser(|s: &str| parse_yes_no(s)),
+            )
+            .arg(
+                arg!(--"cluster-config-file" <FILE>)
+                    .action(ArgAction::Set)
+                    .default_value("nodes.conf")
+                    .value_parser(value_parser!(PathBuf)),
+            )
+            .arg(
+                arg!(--"cluster-node-timeout" <MILLISECONDS>)
+                    .action(ArgAction::Set)
+                    .default_value("15000")
+                    .value_parser(value_parser!(u64)),
+            )
+            .arg(arg!(--requirepass <PASSWORD>).action(ArgAction::Set))
+            .arg(
+                arg!(--"tcp-keepalive" <SECONDS>)
+                    .action(ArgAction::Set)
+                    .default_value("300")
+                    .value_parser(value_parser!(u64)),
+            )
+            .arg(
+                arg!(--"tcp-nodelay" <"yes|no">)
+                    .action(ArgAction::Set)
+                    .default_value("yes")
+                    .value_parser(|s: &str| parse_yes_no(s)),
+            )
+            .arg(
+                arg!(--"busy-reply-threshold" <MILLISECONDS>)
+                    .visible_alias("lua-time-limit")
+                    .action(ArgAction::Set)
+                    .default_value("5000")
+                    .value_parser(value_parser!(u64)),
+            )
+            .arg(
+                arg!(--"hash-max-listpack-entries" <N>)
+                    .action(ArgAction::Set)
+                    .default_value("128")
+                    .value_parser(value_parser!(usize)),
+            )
+            .arg(
+                arg!(--"hash-max-listpack-value" <N>)
+                    .action(ArgAction::Set)
+                    .default_value("64")
+                    .value_parser(value_parser!(usize)),
+            )
+            .arg(
+                arg!(--"list-max-listpack-size" <N>)
+                    .action(ArgAction::Set)
+                    .default_value("-2")
+                    .value_parser(value_parser!(i64).range(-5..)),
+            )
+            .arg(
+                arg!(--"set-max-intset-entries" <N>)
+                    .action(ArgAction::Set)
+                    .default_value("512")
+                    .value_parser(value_parser!(usize)),
+            )
+            .arg(
+                arg!(--"set-max-listpack-entries" <N>)
+                    .action(ArgAction::Set)
+                    .default_value("128")
+                    .value_parser(value_parser!(usize)),
+            )
+            .arg(
+                arg!(--"set-max-listpack-value" <N>)
+                    .action(ArgAction::Set)
+                    .default_value("64")
+                    .value_parser(value_parser!(usize)),
+            )
+            .arg(
+                arg!(--"zset-max-listpack-entries" <N>)
+                    .action(ArgAction::Set)
+                    .default_value("128")
+                    .value_parser(value_parser!(usize)),
+            )
+            .arg(
+                arg!(--"zset-max-listpack-value" <N>)
+                    .action(ArgAction::Set)
+                    .default_value("64")
+                    .value_parser(value_parser!(usize)),
+            )
+            .arg(
+                arg!(--"audit-log" <FILE> "Append every command run by a client to FILE")
+                    .value_parser(value_parser!(PathBuf)),
+            )
+            .arg(
+                arg!(--"audit-log-max-size" <BYTES>)
+                    .action(ArgAction::Set)
+                    .default_value("67108864")
+                    .value_parser(value_parser!(u64)),
+            )
+            .arg(
+                arg!(--"audit-log-max-files" <N>)
+                    .action(ArgAction::Set)
+                    .default_value("5")
+                    .value_parser(value_parser!(usize)),
+            )
+            .arg(
+                arg!(--logfile <FILE> "Log to FILE, or to the standard error if empty")
+                    .action(ArgAction::Set)
+                    // Unlike `value_parser!(PathBuf)`, this accepts `logfile ""`
+                    .value_parser(|s: &str| Ok::<_, Infallible>(PathBuf::from(s))),
+            )
+            .arg(
+                arg!(--loglevel <"debug|verbose|notice|warning|nothing">)
+                    .action(ArgAction::Set)
+                    .default_value("notice")
+                    .value_parser(|s: &str| s.parse::<LogLevel>().map_err(|e| e.to_string())),
+            )
+            .arg(
+                arg!(--"check-rdb" <FILE> "Verify a RDB file and exit")
+                    .value_parser(value_parser!(PathBuf))
+                    .conflicts_with("check-aof"),
+            )
+            .arg(
+                arg!(--"check-aof" <FILE> "Replay an append-only file to verify it and exit")
+                    .value_parser(value_parser!(PathBuf)),
+            )
+    }
 
-        let port = matches.remove_one::<u16>("port").unwrap();
-        let role = matches
+    /// Parses the flags, after the directives of the config file if the first argument names one
+    #[must_use]
+    #[allow(clippy::cognitive_complexity)]
+    pub fn parse() -> Self {
+        let mut command = Self::command();
+        let mut args = std::env::args_os().collect::<Vec<_>>();
+        if let Some(path) = args
+            .get(1)
+            .and_then(|arg| arg.to_str())
+            .filter(|arg| !arg.starts_with('-'))
+        {
+            let directives = std::fs::read_to_string(path)
+                .with_context(|| format!("Can't open config file '{path}'"))
+                .and_then(|contents| parse_config(&contents))
+                .unwrap_or_else(|e| command.error(ErrorKind::Io, format!("{e:#}")).exit());
+            let file_args = directives
+                .into_iter()
+                .filter(|(name, _)| {
+                    let known = command.get_arguments().any(|arg| {
+                        arg.get_long_and_visible_aliases()
+                            .is_some_and(|names| names.contains(&name.as_str()))
+                    });
+                    if !known {
+                        eprintln!("Ignoring unsupported config directive '{name}'");
+                    }
+                    known
+                })
+                .flat_map(|(name, value)| [OsString::from(format!("--{name}")), value.into()])
+                .collect::<Vec<_>>();
+            args.splice(2..2, file_args);
+        }
+        Self::from_matches(command.get_matches_from(args))
+    }
+
+    /// A replica of the master given by `--replicaof`, otherwise a master
+    fn role(matches: &mut ArgMatches) -> Role {
+        matches
             .remove_many::<String>("replicaof")
             .and_then(|mut x| {
                 let host = {
@@ -63,15 +412,359 @@ impl Arguments {
                 let slave = Slave::new(SocketAddrV4::new(host, port));
                 Some(Role::Slave(slave))
             })
-            .unwrap_or_default();
+            .unwrap_or_default()
+    }
+
+    #[allow(clippy::too_many_lines)]
+    fn from_matches(mut matches: ArgMatches) -> Self {
+        let config_file = matches.remove_one::<PathBuf>("config");
+        let port = matches.remove_one::<u16>("port").unwrap();
+        let role = Self::role(&mut matches);
 
         let dir = matches.remove_one::<PathBuf>("dir");
         let db_filename = matches.remove_one::<PathBuf>("dbfilename");
+        let proto_max_bulk_len = matches
+            .remove_one::<u64>("proto-max-bulk-len")
+            .map_or(Resp::DEFAULT_MAX_BULK_LEN, |len| {
+                len.try_into().unwrap_or(usize::MAX)
+            });
+        let repl_ping_replica_period = matches
+            .remove_one::<u64>("repl-ping-replica-period")
+            .map(Duration::from_secs)
+            .unwrap();
+        let repl_timeout = matches
+            .remove_one::<u64>("repl-timeout")
+            .map(Duration::from_secs)
+            .unwrap();
+        let repl_diskless_sync = matches.remove_one::<bool>("repl-diskless-sync").unwrap();
+        let save = matches.remove_one::<SavePoints>("save").unwrap();
+        let rdbchecksum = matches.remove_one::<bool>("rdbchecksum").unwrap();
+        let appendonly = matches.remove_one::<bool>("appendonly").unwrap();
+        let appendfsync = matches.remove_one::<Fsync>("appendfsync").unwrap();
+        let appendfilename = matches.remove_one::<PathBuf>("appendfilename").unwrap();
+        let aof_load_truncated = matches.remove_one::<bool>("aof-load-truncated").unwrap();
+        let bind = matches.remove_many::<IpAddr>("bind").unwrap().collect();
+        let protected_mode = matches.remove_one::<bool>("protected-mode").unwrap();
+        let maxmemory_clients = matches.remove_one::<usize>("maxmemory-clients").unwrap();
+        let pubsub_queue_len = matches.remove_one::<usize>("pubsub-queue-len").unwrap();
+        let pubsub_overflow = matches.remove_one::<Overflow>("pubsub-overflow").unwrap();
+        let cluster_enabled = matches.remove_one::<bool>("cluster-enabled").unwrap();
+        let cluster_config_file = matches
+            .remove_one::<PathBuf>("cluster-config-file")
+            .unwrap();
+        let cluster_node_timeout = matches
+            .remove_one::<u64>("cluster-node-timeout")
+            .map(Duration::from_millis)
+            .unwrap();
+        let requirepass = matches.remove_one::<String>("requirepass");
+        let tcp_keepalive = matches
+            .remove_one::<u64>("tcp-keepalive")
+            .map(Duration::from_secs)
+            .unwrap();
+        let tcp_nodelay = matches.remove_one::<bool>("tcp-nodelay").unwrap();
+        let busy_reply_threshold = matches
+            .remove_one::<u64>("busy-reply-threshold")
+            .map(Duration::from_millis)
+            .unwrap();
+        let mut limit = |name| matches.remove_one::<usize>(name).unwrap();
+        let encoding = EncodingLimits {
+            hash_max_listpack_entries: limit("hash-max-listpack-entries"),
+            hash_max_listpack_value: limit("hash-max-listpack-value"),
+            set_max_intset_entries: limit("set-max-intset-entries"),
+            set_max_listpack_entries: limit("set-max-listpack-entries"),
+            set_max_listpack_value: limit("set-max-listpack-value"),
+            zset_max_listpack_entries: limit("zset-max-listpack-entries"),
+            zset_max_listpack_value: limit("zset-max-listpack-value"),
+            list_max_listpack_size: matches.remove_one::<i64>("list-max-listpack-size").unwrap(),
+        };
+        let audit_log = matches.remove_one::<PathBuf>("audit-log");
+        let audit_log_max_size = matches.remove_one::<u64>("audit-log-max-size").unwrap();
+        let audit_log_max_files = matches.remove_one::<usize>("audit-log-max-files").unwrap();
+        let logfile = matches
+            .remove_one::<PathBuf>("logfile")
+            .filter(|path| !path.as_os_str().is_empty());
+        let loglevel = matches.remove_one::<LogLevel>("loglevel").unwrap();
+        let check = matches
+            .remove_one::<PathBuf>("check-rdb")
+            .map(Check::Rdb)
+            .or_else(|| matches.remove_one::<PathBuf>("check-aof").map(Check::Aof));
         Self {
+            config_file,
             port,
             role,
             dir,
             db_filename,
+            proto_max_bulk_len,
+            repl_ping_replica_period,
+            repl_timeout,
+            repl_diskless_sync,
+            save,
+            rdbchecksum,
+            appendonly,
+            appendfsync,
+            appendfilename,
+            aof_load_truncated,
+            bind,
+            protected_mode,
+            maxmemory_clients,
+            pubsub_queue_len,
+            pubsub_overflow,
+            cluster_enabled,
+            cluster_config_file,
+            cluster_node_timeout,
+            requirepass,
+            tcp_keepalive,
+            tcp_nodelay,
+            busy_reply_threshold,
+            encoding,
+            audit_log,
+            audit_log_max_size,
+            audit_log_max_files,
+            logfile,
+            loglevel,
+            check,
+        }
+    }
+}
+
+impl Default for Arguments {
+    /// The defaults of every parameter, as if no flag was given
+    fn default() -> Self {
+        Self::from_matches(Self::command().get_matches_from([env!("CARGO_CRATE_NAME")]))
+    }
+}
+
+/// `loglevel`: the least severe events logged, unless `RUST_LOG` says otherwise
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogLevel {
+    Debug,
+    Verbose,
+    Notice,
+    Warning,
+    Nothing,
+}
+
+impl LogLevel {
+    #[must_use]
+    pub const fn filter(self) -> LevelFilter {
+        match self {
+            Self::Debug => LevelFilter::TRACE,
+            Self::Verbose => LevelFilter::DEBUG,
+            Self::Notice => LevelFilter::INFO,
+            Self::Warning => LevelFilter::WARN,
+            Self::Nothing => LevelFilter::OFF,
+        }
+    }
+}
+
+impl FromStr for LogLevel {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_ascii_lowercase().as_str() {
+            "debug" => Self::Debug,
+            "verbose" => Self::Verbose,
+            "notice" => Self::Notice,
+            "warning" => Self::Warning,
+            "nothing" => Self::Nothing,
+            _ => bail!(
+                "argument(s) must be one of the following: debug, verbose, notice, warning, nothing"
+            ),
+        })
+    }
+}
+
+impl Display for LogLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Debug => "debug",
+            Self::Verbose => "verbose",
+            Self::Notice => "notice",
+            Self::Warning => "warning",
+            Self::Nothing => "nothing",
+        })
+    }
+}
+
+/// Parses the `yes`/`no` values of boolean parameters
+pub fn parse_yes_no(s: &str) -> Result<bool, &'static str> {
+    match s.to_ascii_lowercase().as_str() {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _ => Err("argument must be 'yes' or 'no'"),
+    }
+}
+
+/// Parses a number of bytes with an optional unit, like `100`, `1k` (1000 bytes)
+/// or `1kb` (1024 bytes), up to gigabytes
+pub fn parse_memory(s: &str) -> Result<usize, String> {
+    let lower = s.to_ascii_lowercase();
+    let digits = lower.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let unit = match &lower[digits.len()..] {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        unit => return Err(format!("unknown unit '{unit}'")),
+    };
+    let n = digits.parse::<usize>().map_err(|e| e.to_string())?;
+    n.checked_mul(unit)
+        .ok_or_else(|| "value is too large".to_owned())
+}
+
+/// Parses the directives of a `redis.conf`-style file, joining their arguments with spaces.
+/// Like in Redis, `save` lines add up, and `save ""` clears the previous ones.
+pub fn parse_config(contents: &str) -> anyhow::Result<Vec<(String, String)>> {
+    let mut directives = Vec::<(String, String)>::new();
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut args = split_args(line)
+            .with_context(|| format!("Unbalanced quotes in configuration line {}", i + 1))?;
+        let name = args.remove(0).to_ascii_lowercase();
+        ensure!(
+            !args.is_empty(),
+            "Bad directive or wrong number of arguments at line {}: '{line}'",
+            i + 1
+        );
+        let value = args.join(" ");
+        if name == "save" {
+            if let Some((_, points)) = directives.iter_mut().find(|(name, _)| name == "save") {
+                if value.is_empty() {
+                    points.clear();
+                } else if points.is_empty() {
+                    *points = value;
+                } else {
+                    points.push(' ');
+                    points.push_str(&value);
+                }
+                continue;
+            }
+        }
+        directives.push((name, value));
+    }
+    Ok(directives)
+}
+
+/// Splits a config line, or a line typed at the redis-cli prompt, into arguments,
+/// which can be "double quoted" with escape sequences or 'single quoted'
+pub fn split_args(line: &str) -> anyhow::Result<Vec<String>> {
+    let mut args = Vec::new();
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.next_if(char::is_ascii_whitespace).is_some() {}
+        let Some(&first) = chars.peek() else {
+            return Ok(args);
+        };
+        let mut arg = String::new();
+        match first {
+            '"' => {
+                chars.next();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some('n') => arg.push('\n'),
+                            Some('r') => arg.push('\r'),
+                            Some('t') => arg.push('\t'),
+                            Some('x') => {
+                                let hex = chars
+                                    .next()
+                                    .into_iter()
+                                    .chain(chars.next())
+                                    .collect::<String>();
+                                let byte =
+                                    u8::from_str_radix(&hex, 16).context("Invalid \\x escape")?;
+                                arg.push(char::from(byte));
+                            }
+                            Some(c) => arg.push(c),
+                            None => bail!("Unterminated quotes"),
+                        },
+                        Some(c) => arg.push(c),
+                        None => bail!("Unterminated quotes"),
+                    }
+                }
+            }
+            '\'' => {
+                chars.next();
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some('\\') if chars.next_if_eq(&'\'').is_some() => arg.push('\''),
+                        Some(c) => arg.push(c),
+                        None => bail!("Unterminated quotes"),
+                    }
+                }
+            }
+            _ => {
+                while let Some(c) = chars.next_if(|c| !c.is_ascii_whitespace()) {
+                    arg.push(c);
+                }
+                args.push(arg);
+                continue;
+            }
         }
+        // A closing quote must end the argument
+        ensure!(
+            chars.peek().is_none_or(char::is_ascii_whitespace),
+            "Closing quote must be followed by a space"
+        );
+        args.push(arg);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_quoted_args() {
+        pretty_assertions::assert_eq!(
+            split_args(r#"  save "" 'it\'s'  "a\tb\x41" plain "#).unwrap(),
+            ["save", "", "it's", "a\tbA", "plain"]
+        );
+        assert!(split_args(r#"dir "/tmp"x"#).is_err());
+        assert!(split_args(r#"dir "/tmp"#).is_err());
+    }
+
+    #[test]
+    fn parse_config_file() {
+        let config = r#"
+# comment
+port 6380
+replicaof localhost 6379
+save 3600 1
+save 300 100
+dir "/tmp/redis data"
+"#;
+        pretty_assertions::assert_eq!(
+            parse_config(config).unwrap(),
+            [
+                ("port".to_owned(), "6380".to_owned()),
+                ("replicaof".to_owned(), "localhost 6379".to_owned()),
+                ("save".to_owned(), "3600 1 300 100".to_owned()),
+                ("dir".to_owned(), "/tmp/redis data".to_owned()),
+            ]
+        );
+        pretty_assertions::assert_eq!(
+            parse_config("save 60 1\nsave \"\"\n").unwrap(),
+            [("save".to_owned(), String::new())]
+        );
+        assert!(parse_config("port").is_err());
+    }
+
+    #[test]
+    fn memory_units() {
+        pretty_assertions::assert_eq!(parse_memory("100"), Ok(100));
+        pretty_assertions::assert_eq!(parse_memory("1k"), Ok(1000));
+        pretty_assertions::assert_eq!(parse_memory("2KB"), Ok(2048));
+        pretty_assertions::assert_eq!(parse_memory("1gb"), Ok(1 << 30));
+        assert!(parse_memory("1tb").is_err());
+        assert!(parse_memory("mb").is_err());
     }
 }
@@ -0,0 +1,257 @@
+use anyhow::Context;
+use std::{
+    ffi::OsString,
+    fmt::Write as _,
+    future::Future,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    fs::{File, OpenOptions},
+    io::AsyncWriteExt,
+    sync::mpsc,
+};
+
+use crate::{
+    client::quote,
+    clients::CLIENTS,
+    hooks::{Call, Hook},
+    Resp,
+};
+
+/// Hook appending every command run by a client to a file, with its outcome.
+/// Lines are written by a dedicated task, so clients never wait on the disk.
+///
+/// ```text
+/// 1712345678.123456 id=3 addr=127.0.0.1:51234 db=0 "SET" "k" "v" -> ok
+/// 1712345678.234567 id=3 addr=127.0.0.1:51234 db=0 "INCR" "k" -> err "ERR ..."
+/// ```
+#[derive(Debug)]
+pub struct AuditLog {
+    tx: mpsc::UnboundedSender<String>,
+}
+
+impl AuditLog {
+    /// Opens the log at `path` for appending. The returned task writes the entries,
+    /// moving the file to `path.1` once it would exceed `max_size` bytes, `path.1` to
+    /// `path.2` and so on, keeping `max_files` rotated files. A zero `max_size` never rotates.
+    pub(crate) async fn open(
+        path: PathBuf,
+        max_size: u64,
+        max_files: usize,
+    ) -> anyhow::Result<(Self, impl Future<Output = ()> + Send)> {
+        let mut writer = Writer::open(path, max_size, max_files).await?;
+        let (tx, mut rx) = mpsc::unbounded_channel::<String>();
+        let task = async move {
+            let mut lines = Vec::new();
+            while rx.recv_many(&mut lines, 256).await > 0 {
+                if let Err(e) = writer.write(&lines).await {
+                    tracing::error!("Can't write to the audit log: {e:#}");
+                }
+                lines.clear();
+            }
+        };
+        Ok((Self { tx }, task))
+    }
+}
+
+impl Hook for AuditLog {
+    fn after(&self, call: &Call, reply: &Resp, _: Duration) {
+        let _ = self.tx.send(entry(call, reply, SystemTime::now()));
+    }
+}
+
+/// One line of the log, without its newline
+fn entry(call: &Call, reply: &Resp, now: SystemTime) -> String {
+    let now = now.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let mut line = format!(
+        "{}.{:06} id={}",
+        now.as_secs(),
+        now.subsec_micros(),
+        call.client_id
+    );
+    if let Some(addr) = CLIENTS.with(call.client_id, |client| client.addr) {
+        let _ = write!(line, " addr={addr}");
+    }
+    line.push_str(" db=0");
+    for arg in &call.args {
+        line.push(' ');
+        match arg.to_bytes() {
+            Ok(bytes) => quote(&bytes, &mut line),
+            Err(_) => quote(format!("{arg:?}").as_bytes(), &mut line),
+        }
+    }
+    match reply {
+        Resp::Err(e) => {
+            line.push_str(" -> err ");
+            quote(e.as_bytes(), &mut line);
+        }
+        _ => line.push_str(" -> ok"),
+    }
+    line
+}
+
+/// The log file, and its rotated copies
+#[derive(Debug)]
+struct Writer {
+    path: PathBuf,
+    max_size: u64,
+    max_files: usize,
+    file: File,
+    size: u64,
+}
+
+impl Writer {
+    async fn open(path: PathBuf, max_size: u64, max_files: usize) -> anyhow::Result<Self> {
+        let file = Self::open_file(&path).await?;
+        let size = file.metadata().await?.len();
+        Ok(Self {
+            path,
+            max_size,
+            max_files,
+            file,
+            size,
+        })
+    }
+
+    async fn open_file(path: &Path) -> anyhow::Result<File> {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await
+            .with_context(|| format!("Can't open the audit log {}", path.display()))
+    }
+
+    async fn write(&mut self, lines: &[String]) -> anyhow::Result<()> {
+        for line in lines {
+            let len = line.len() as u64 + 1;
+            if self.max_size > 0 && self.size > 0 && self.size + len > self.max_size {
+                self.rotate().await?;
+            }
+            self.file.write_all(line.as_bytes()).await?;
+            self.file.write_all(b"\n").await?;
+            self.size += len;
+        }
+        self.file.flush().await?;
+        Ok(())
+    }
+
+    /// Shifts the rotated files by one, dropping the oldest, and starts a new file
+    async fn rotate(&mut self) -> anyhow::Result<()> {
+        self.file.flush().await?;
+        if self.max_files == 0 {
+            self.file.set_len(0).await?;
+            self.size = 0;
+            return Ok(());
+        }
+        for n in (1..self.max_files).rev() {
+            match tokio::fs::rename(self.rotated(n), self.rotated(n + 1)).await {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        tokio::fs::rename(&self.path, self.rotated(1))
+            .await
+            .with_context(|| format!("Can't rotate the audit log {}", self.path.display()))?;
+        self.file = Self::open_file(&self.path).await?;
+        self.size = 0;
+        Ok(())
+    }
+
+    /// Path of the `n`th most recent rotated file
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut path = OsString::from(&self.path);
+        path.push(format!(".{n}"));
+        path.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{testutil::Client, Server};
+
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("{name}-{}.log", std::process::id()))
+    }
+
+    #[test]
+    fn entries() {
+        let call = Call {
+            client_id: 7,
+            args: vec![
+                Resp::bulk("SET"),
+                Resp::bulk("k"),
+                Resp::bulk("a \"b\"\n\x01"),
+            ],
+        };
+        let now = UNIX_EPOCH + Duration::from_millis(1_500);
+        pretty_assertions::assert_eq!(
+            entry(&call, &Resp::simple("OK"), now),
+            r#"1.500000 id=7 db=0 "SET" "k" "a \"b\"\n\x01" -> ok"#
+        );
+        pretty_assertions::assert_eq!(
+            entry(&call, &Resp::Err("ERR no".to_owned()), now),
+            r#"1.500000 id=7 db=0 "SET" "k" "a \"b\"\n\x01" -> err "ERR no""#
+        );
+    }
+
+    #[tokio::test]
+    async fn rotation() {
+        let path = temp_path("audit-rotation");
+        let mut writer = Writer::open(path.clone(), 10, 2).await.unwrap();
+        let files = [path.clone(), writer.rotated(1), writer.rotated(2)];
+        writer.file.set_len(0).await.unwrap();
+        writer.size = 0;
+        let lines = ["first", "second", "third", "fourth"].map(str::to_owned);
+        writer.write(&lines).await.unwrap();
+
+        let contents = files
+            .iter()
+            .map(|path| std::fs::read_to_string(path).unwrap())
+            .collect::<Vec<_>>();
+        pretty_assertions::assert_eq!(contents, ["fourth\n", "third\n", "second\n"]);
+        assert!(!writer.rotated(3).exists());
+        for path in files {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+
+    #[tokio::test]
+    async fn audit() {
+        let path = temp_path("audit");
+        let _ = std::fs::remove_file(&path);
+        let (audit, task) = AuditLog::open(path.clone(), 0, 0).await.unwrap();
+        tokio::spawn(task);
+        let server = Server::builder().port(0).hook(audit).spawn().await.unwrap();
+        let mut client = Client::connect(&server).await;
+        client.cmd(&["SET", "k", "v"]).await;
+        client.cmd(&["INCR", "k"]).await;
+
+        let log = tokio::time::timeout(Duration::from_secs(1), async {
+            loop {
+                let log = std::fs::read_to_string(&path).unwrap();
+                if log.lines().count() == 2 {
+                    break log;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("Both commands are logged");
+        let _ = std::fs::remove_file(&path);
+        let entries = log
+            .lines()
+            .map(|line| line.split_once(" db=0 ").unwrap().1)
+            .collect::<Vec<_>>();
+        pretty_assertions::assert_eq!(
+            entries,
+            [
+                r#""SET" "k" "v" -> ok"#,
+                r#""INCR" "k" -> err "ERR value is not an integer or out of range""#,
+            ]
+        );
+    }
+}
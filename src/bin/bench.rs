@@ -0,0 +1,277 @@
+//! Load generator: runs each test with concurrent clients and reports throughput and latencies
+
+use anyhow::{bail, Context};
+use bytes::Bytes;
+use clap::{arg, value_parser, ArgAction, Command};
+use rand::Rng;
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use tokio::task::JoinSet;
+
+use redis_starter_rust::{client::Client, Resp};
+
+fn command() -> Command {
+    Command::new("bench")
+        .about("Benchmarks a server with concurrent clients")
+        // -h is the host, like in redis-benchmark
+        .disable_help_flag(true)
+        .arg(arg!(--help "Print help").action(ArgAction::Help))
+        .arg(
+            arg!(-h --host <HOSTNAME> "Server hostname")
+                .action(ArgAction::Set)
+                .default_value("127.0.0.1"),
+        )
+        .arg(
+            arg!(-p --port <PORT> "Server port")
+                .action(ArgAction::Set)
+                .default_value("6379")
+                .value_parser(value_parser!(u16)),
+        )
+        .arg(
+            arg!(-c --clients <N> "Number of parallel connections")
+                .action(ArgAction::Set)
+                .default_value("50")
+                .value_parser(|s: &str| positive(s)),
+        )
+        .arg(
+            arg!(-n --requests <N> "Number of requests of each test")
+                .action(ArgAction::Set)
+                .default_value("100000")
+                .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            arg!(-P --pipeline <N> "Requests sent at once by a connection")
+                .action(ArgAction::Set)
+                .default_value("1")
+                .value_parser(|s: &str| positive(s)),
+        )
+        .arg(
+            arg!(-d --size <BYTES> "Size of the values written")
+                .action(ArgAction::Set)
+                .default_value("3")
+                .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            arg!(-r --keyspace <N> "Use random keys among N, instead of a single key")
+                .action(ArgAction::Set)
+                .default_value("0")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            arg!(-t --tests <TESTS> "Comma-separated tests to run")
+                .action(ArgAction::Set)
+                .default_value("set,get,incr")
+                .value_delimiter(',')
+                .value_parser(|s: &str| s.parse::<Test>()),
+        )
+}
+
+fn positive(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(0) => Err("must be at least 1".to_owned()),
+        Ok(n) => Ok(n),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Test {
+    Set,
+    Get,
+    Incr,
+    /// Not run by default, lists being served by real Redis only
+    Lpush,
+}
+
+impl std::str::FromStr for Test {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Ok(match s.to_ascii_lowercase().as_str() {
+            "set" => Self::Set,
+            "get" => Self::Get,
+            "incr" => Self::Incr,
+            "lpush" => Self::Lpush,
+            _ => bail!("unknown test '{s}', expected set, get, incr or lpush"),
+        })
+    }
+}
+
+impl Test {
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Set => "SET",
+            Self::Get => "GET",
+            Self::Incr => "INCR",
+            Self::Lpush => "LPUSH",
+        }
+    }
+
+    fn args(self, opts: &Options) -> Vec<Bytes> {
+        let key = |prefix: &str| {
+            if opts.keyspace == 0 {
+                Bytes::from(format!("{prefix}:__rand_int__"))
+            } else {
+                let n = rand::thread_rng().gen_range(0..opts.keyspace);
+                Bytes::from(format!("{prefix}:{n:012}"))
+            }
+        };
+        match self {
+            Self::Set => vec!["SET".into(), key("key"), opts.value.clone()],
+            Self::Get => vec!["GET".into(), key("key")],
+            Self::Incr => vec!["INCR".into(), key("counter")],
+            Self::Lpush => vec!["LPUSH".into(), key("mylist"), opts.value.clone()],
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Options {
+    addr: String,
+    clients: usize,
+    requests: usize,
+    pipeline: usize,
+    keyspace: u64,
+    value: Bytes,
+}
+
+/// What a connection measured during a test
+#[derive(Debug, Default)]
+struct Report {
+    latencies: Vec<Duration>,
+    errors: usize,
+}
+
+#[tokio::main]
+async fn main() {
+    let mut matches = command().get_matches();
+    let host = matches.remove_one::<String>("host").unwrap();
+    let port = matches.remove_one::<u16>("port").unwrap();
+    let size = matches.remove_one::<usize>("size").unwrap();
+    let opts = Arc::new(Options {
+        addr: format!("{host}:{port}"),
+        clients: matches.remove_one("clients").unwrap(),
+        requests: matches.remove_one("requests").unwrap(),
+        pipeline: matches.remove_one("pipeline").unwrap(),
+        keyspace: matches.remove_one("keyspace").unwrap(),
+        value: Bytes::from(vec![b'x'; size]),
+    });
+    for test in matches.remove_many::<Test>("tests").unwrap() {
+        if let Err(e) = run(test, &opts).await {
+            eprintln!("{}: {e:#}", test.name());
+            std::process::exit(1);
+        }
+    }
+}
+
+async fn run(test: Test, opts: &Arc<Options>) -> anyhow::Result<()> {
+    let remaining = Arc::new(AtomicUsize::new(opts.requests));
+    let mut clients = Vec::with_capacity(opts.clients);
+    for _ in 0..opts.clients {
+        let client = Client::connect(&opts.addr)
+            .await
+            .with_context(|| format!("Could not connect to {}", opts.addr))?;
+        clients.push(client);
+    }
+
+    let start = Instant::now();
+    let mut tasks = JoinSet::new();
+    for client in clients {
+        tasks.spawn(connection(
+            client,
+            test,
+            Arc::clone(opts),
+            Arc::clone(&remaining),
+        ));
+    }
+    let mut total = Report::default();
+    while let Some(report) = tasks.join_next().await {
+        let report = report??;
+        total.latencies.extend(report.latencies);
+        total.errors += report.errors;
+    }
+    print_report(test, opts, &mut total, start.elapsed());
+    Ok(())
+}
+
+/// Sends batches of requests until the test has sent all of them
+async fn connection(
+    mut client: Client,
+    test: Test,
+    opts: Arc<Options>,
+    remaining: Arc<AtomicUsize>,
+) -> anyhow::Result<Report> {
+    let mut report = Report::default();
+    loop {
+        let claimed = remaining.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |left| {
+            (left > 0).then(|| left.saturating_sub(opts.pipeline))
+        });
+        let Ok(left) = claimed else {
+            return Ok(report);
+        };
+        let batch = left.min(opts.pipeline);
+        let cmds = (0..batch).map(|_| test.args(&opts)).collect::<Vec<_>>();
+        let start = Instant::now();
+        let replies = client.pipeline(cmds).await?;
+        // Every request of a batch waits for the whole batch
+        let elapsed = start.elapsed();
+        report.latencies.extend(std::iter::repeat_n(elapsed, batch));
+        report.errors += replies
+            .iter()
+            .filter(|reply| matches!(reply, Resp::Err(_)))
+            .count();
+    }
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn print_report(test: Test, opts: &Options, report: &mut Report, elapsed: Duration) {
+    report.latencies.sort_unstable();
+    let percentile = |p: usize| {
+        let len = report.latencies.len();
+        let ms = report
+            .latencies
+            .get((len.saturating_sub(1)) * p / 100)
+            .map_or(0.0, |latency| latency.as_secs_f64() * 1000.0);
+        format!("{ms:.3}")
+    };
+    let count = report.latencies.len();
+    let avg = report
+        .latencies
+        .iter()
+        .sum::<Duration>()
+        .checked_div(count.try_into().unwrap_or(u32::MAX))
+        .unwrap_or_default();
+
+    println!("====== {} ======", test.name());
+    println!(
+        "  {count} requests completed in {:.2} seconds",
+        elapsed.as_secs_f64()
+    );
+    println!(
+        "  {} parallel clients, pipeline {}, {} bytes payload",
+        opts.clients,
+        opts.pipeline,
+        opts.value.len()
+    );
+    if report.errors > 0 {
+        println!("  {} error replies", report.errors);
+    }
+    println!(
+        "  throughput: {:.2} requests per second",
+        count as f64 / elapsed.as_secs_f64()
+    );
+    println!(
+        "  latency (msec): avg {:.3} p50 {} p95 {} p99 {} max {}",
+        avg.as_secs_f64() * 1000.0,
+        percentile(50),
+        percentile(95),
+        percentile(99),
+        percentile(100),
+    );
+    println!();
+}
@@ -0,0 +1,138 @@
+//! Command line client: runs the command given as arguments, or prompts for commands
+
+use bytes::Bytes;
+use clap::{arg, value_parser, ArgAction, ArgMatches, Command};
+use std::io::{Read, Write};
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+use redis_starter_rust::{
+    client::{self, split_args, Client},
+    Resp,
+};
+
+fn command() -> Command {
+    Command::new("redis-cli")
+        .about("Sends commands to a server and prints its replies")
+        // -h is the host, like in redis-cli
+        .disable_help_flag(true)
+        .arg(arg!(--help "Print help").action(ArgAction::Help))
+        .arg(
+            arg!(-h --host <HOSTNAME> "Server hostname")
+                .action(ArgAction::Set)
+                .default_value("127.0.0.1"),
+        )
+        .arg(
+            arg!(-p --port <PORT> "Server port")
+                .action(ArgAction::Set)
+                .default_value("6379")
+                .value_parser(value_parser!(u16)),
+        )
+        .arg(arg!(-a --pass <PASSWORD> "Password to AUTH with").action(ArgAction::Set))
+        .arg(arg!(stdin: -x "Read the last argument from the standard input"))
+        .arg(arg!([args] ... "Command to run, instead of prompting for commands"))
+}
+
+#[tokio::main]
+async fn main() {
+    let mut matches = command().get_matches();
+    let host = matches.remove_one::<String>("host").unwrap();
+    let port = matches.remove_one::<u16>("port").unwrap();
+    let addr = format!("{host}:{port}");
+    let password = matches.remove_one::<String>("pass");
+
+    let mut client = match connect(&addr, password.as_deref()).await {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("Could not connect to {addr}: {e:#}");
+            std::process::exit(1);
+        }
+    };
+    match one_shot(&mut matches) {
+        Some(args) => match client.cmd(args).await {
+            Ok(reply) => {
+                println!("{}", client::format(&reply));
+                if matches!(reply, Resp::Err(_)) {
+                    std::process::exit(1);
+                }
+            }
+            Err(e) => {
+                eprintln!("Error: {e:#}");
+                std::process::exit(1);
+            }
+        },
+        None => prompt(Some(client), &addr, password.as_deref()).await,
+    }
+}
+
+/// The command given as arguments, with the standard input as last argument under `-x`
+fn one_shot(matches: &mut ArgMatches) -> Option<Vec<Bytes>> {
+    let mut args = matches
+        .remove_many::<String>("args")
+        .map(|args| args.map(Bytes::from).collect::<Vec<_>>())
+        .unwrap_or_default();
+    if matches.get_flag("stdin") {
+        let mut stdin = Vec::new();
+        if let Err(e) = std::io::stdin().read_to_end(&mut stdin) {
+            eprintln!("Can't read the standard input: {e}");
+            std::process::exit(1);
+        }
+        args.push(stdin.into());
+    }
+    (!args.is_empty()).then_some(args)
+}
+
+async fn connect(addr: &str, password: Option<&str>) -> anyhow::Result<Client> {
+    let mut client = Client::connect(addr).await?;
+    if let Some(password) = password {
+        if let Resp::Err(e) = client.cmd(["AUTH".to_owned(), password.to_owned()]).await? {
+            eprintln!("AUTH failed: {e}");
+        }
+    }
+    Ok(client)
+}
+
+/// Reads commands from the standard input until it's closed, reconnecting when needed
+async fn prompt(mut client: Option<Client>, addr: &str, password: Option<&str>) {
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    loop {
+        if client.is_some() {
+            print!("{addr}> ");
+        } else {
+            print!("not connected> ");
+        }
+        let _ = std::io::stdout().flush();
+        let Ok(Some(line)) = lines.next_line().await else {
+            return;
+        };
+        let args = match split_args(&line) {
+            Ok(args) if args.is_empty() => continue,
+            Ok(args) => args,
+            Err(_) => {
+                println!("Invalid argument(s)");
+                continue;
+            }
+        };
+        if ["quit", "exit"].contains(&args[0].to_ascii_lowercase().as_str()) {
+            return;
+        }
+        if client.is_none() {
+            match connect(addr, password).await {
+                Ok(connected) => client = Some(connected),
+                Err(e) => {
+                    println!("Could not connect to {addr}: {e:#}");
+                    continue;
+                }
+            }
+        }
+        let Some(connected) = client.as_mut() else {
+            continue;
+        };
+        match connected.cmd(args).await {
+            Ok(reply) => println!("{}", client::format(&reply)),
+            Err(e) => {
+                println!("Error: {e:#}");
+                client = None;
+            }
+        }
+    }
+}
@@ -0,0 +1,163 @@
+use anyhow::{bail, Context};
+use bytes::Bytes;
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{db::Value, Aof, Arguments, Db, Rdb};
+
+/// Dump to verify instead of starting the server, from `--check-rdb` or `--check-aof`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Check {
+    Rdb(PathBuf),
+    Aof(PathBuf),
+}
+
+impl Check {
+    /// Parses the whole dump, failing if it's corrupted
+    pub fn run(&self) -> anyhow::Result<DumpSummary> {
+        // Packed encodings are decoded without bounds checks, so a corrupted one panics
+        let res = std::panic::catch_unwind(|| match self {
+            Self::Rdb(path) => check_rdb(path),
+            Self::Aof(path) => check_aof(path),
+        });
+        res.unwrap_or_else(|_| bail!("Truncated or corrupted dump"))
+    }
+}
+
+/// What a valid dump holds
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct DumpSummary {
+    /// Version of the RDB, or of the preamble of an AOF
+    pub version: Option<u32>,
+    /// Whether the RDB had a checksum, which was verified
+    pub checksummed: bool,
+    /// Commands of an AOF, after its preamble
+    pub commands: Option<usize>,
+    /// Number of keys of each type
+    pub keys: BTreeMap<&'static str, usize>,
+    pub expires: usize,
+}
+
+impl DumpSummary {
+    fn from_rdb(rdb: &Rdb) -> Self {
+        let mut summary = Self {
+            version: Some(rdb.version()),
+            checksummed: rdb.checksummed(),
+            ..Self::default()
+        };
+        summary.count(rdb.db.maps.iter().flat_map(|map| map.values()));
+        summary
+    }
+
+    fn count<'a>(&mut self, values: impl Iterator<Item = &'a Value>) {
+        for value in values {
+            *self.keys.entry(value.v_type.name()).or_default() += 1;
+            self.expires += usize::from(value.expiration.is_some());
+        }
+    }
+}
+
+impl Display for DumpSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(version) = self.version {
+            writeln!(f, "RDB version: {version}")?;
+            let checksum = if self.checksummed {
+                "OK"
+            } else {
+                "not computed"
+            };
+            writeln!(f, "Checksum: {checksum}")?;
+        }
+        if let Some(commands) = self.commands {
+            writeln!(f, "Commands: {commands}")?;
+        }
+        write!(f, "Keys: {}", self.keys.values().sum::<usize>())?;
+        for (i, (ty, count)) in self.keys.iter().enumerate() {
+            let sep = if i == 0 { " (" } else { ", " };
+            write!(f, "{sep}{ty}: {count}")?;
+        }
+        if !self.keys.is_empty() {
+            write!(f, ")")?;
+        }
+        writeln!(f)?;
+        writeln!(f, "Expires: {}", self.expires)
+    }
+}
+
+fn read(path: &Path) -> anyhow::Result<Bytes> {
+    std::fs::read(path)
+        .map(Bytes::from)
+        .with_context(|| format!("Can't read {}", path.display()))
+}
+
+fn check_rdb(path: &Path) -> anyhow::Result<DumpSummary> {
+    let file = File::open(path).with_context(|| format!("Can't read {}", path.display()))?;
+    let rdb = Rdb::read(BufReader::new(file), true)?;
+    Ok(DumpSummary::from_rdb(&rdb))
+}
+
+fn check_aof(path: &Path) -> anyhow::Result<DumpSummary> {
+    let mut bytes = read(path)?;
+    let len = bytes.len();
+    let db = Arc::new(Db::default());
+    // Keys that expired since the dump are still counted
+    db.set_replica(true);
+    let mut summary = DumpSummary::default();
+    if bytes.starts_with(b"REDIS") {
+        let rdb = Rdb::parse_prefix(&mut bytes, true)?;
+        summary.version = Some(rdb.version());
+        summary.checksummed = rdb.checksummed();
+        db.apply_rdb(rdb);
+    }
+    let replayed = Aof::replay(&bytes, &db, &Arc::new(Arguments::default()))?;
+    if let Some(valid) = replayed.truncated {
+        bail!("Truncated command at offset {}", len - bytes.len() + valid);
+    }
+    summary.commands = Some(replayed.read);
+    summary.count(db.inner.read().values().map(AsRef::as_ref));
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::*;
+    use crate::{db::Type, Resp};
+
+    #[test]
+    fn aof_with_preamble() {
+        let db = Db::default();
+        db.inner.write().insert(
+            "s".to_owned(),
+            Arc::new(Value::new(
+                Type::String("v".into()),
+                Some(SystemTime::now() + Duration::from_mins(1)),
+            )),
+        );
+        let mut aof = db.dump_rdb(None).to_vec();
+        for cmd in [["SET", "a", "1"], ["SET", "b", "2"]] {
+            let mut buf = bytes::BytesMut::new();
+            Resp::Array(cmd.into_iter().map(Resp::bulk).collect()).encode(&mut buf);
+            aof.extend_from_slice(&buf);
+        }
+        let path = std::env::temp_dir().join(format!("check-{}.aof", std::process::id()));
+        std::fs::write(&path, &aof).unwrap();
+
+        let summary = Check::Aof(path.clone()).run().unwrap();
+        pretty_assertions::assert_eq!(summary.commands, Some(2));
+        pretty_assertions::assert_eq!(summary.keys, BTreeMap::from([("string", 3)]));
+        pretty_assertions::assert_eq!(summary.expires, 1);
+
+        // A cut command is corruption
+        std::fs::write(&path, &aof[..aof.len() - 3]).unwrap();
+        assert!(Check::Aof(path.clone()).run().is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,208 @@
+//! Async client for this server, or any server speaking RESP
+
+use anyhow::Context;
+use bytes::Bytes;
+use futures_util::{SinkExt, StreamExt};
+use std::fmt::Write as _;
+use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio_util::codec::Framed;
+
+use crate::{Resp, RespCodec};
+
+pub use crate::args::split_args;
+
+/// Connection to a server, sending commands as arrays of bulk strings.
+/// Error replies are returned as [`Resp::Err`], only I/O and protocol errors fail.
+#[derive(Debug)]
+pub struct Client {
+    framed: Framed<TcpStream, RespCodec>,
+}
+
+impl Client {
+    pub async fn connect(addr: impl ToSocketAddrs) -> anyhow::Result<Self> {
+        let stream = TcpStream::connect(addr)
+            .await
+            .context("Can't connect to the server")?;
+        stream.set_nodelay(true)?;
+        Ok(Self {
+            framed: Framed::new(stream, RespCodec::default()),
+        })
+    }
+
+    /// Sends a command and reads its reply
+    pub async fn cmd<I>(&mut self, args: I) -> anyhow::Result<Resp>
+    where
+        I: IntoIterator,
+        I::Item: Into<Bytes>,
+    {
+        self.send(args).await?;
+        self.read().await
+    }
+
+    pub async fn send<I>(&mut self, args: I) -> anyhow::Result<()>
+    where
+        I: IntoIterator,
+        I::Item: Into<Bytes>,
+    {
+        self.framed.send(Self::command(args)).await?;
+        Ok(())
+    }
+
+    /// Sends every command at once, then reads their replies in order
+    pub async fn pipeline<C, I>(&mut self, cmds: C) -> anyhow::Result<Vec<Resp>>
+    where
+        C: IntoIterator<Item = I>,
+        I: IntoIterator,
+        I::Item: Into<Bytes>,
+    {
+        let mut sent = 0;
+        for args in cmds {
+            self.framed.feed(Self::command(args)).await?;
+            sent += 1;
+        }
+        SinkExt::<Resp>::flush(&mut self.framed).await?;
+        let mut replies = Vec::with_capacity(sent);
+        for _ in 0..sent {
+            replies.push(self.read().await?);
+        }
+        Ok(replies)
+    }
+
+    /// Reads the next frame, a reply or a pushed message
+    pub async fn read(&mut self) -> anyhow::Result<Resp> {
+        Ok(self
+            .framed
+            .next()
+            .await
+            .context("Connection closed by the server")??)
+    }
+
+    fn command<I>(args: I) -> Resp
+    where
+        I: IntoIterator,
+        I::Item: Into<Bytes>,
+    {
+        Resp::Array(args.into_iter().map(|arg| Resp::Bulk(arg.into())).collect())
+    }
+}
+
+/// Appends `bytes` between double quotes, escaping what isn't printable ASCII
+pub fn quote(bytes: &[u8], out: &mut String) {
+    out.push('"');
+    for &b in bytes {
+        match b {
+            b'"' => out.push_str("\\\""),
+            b'\\' => out.push_str("\\\\"),
+            b'\n' => out.push_str("\\n"),
+            b'\r' => out.push_str("\\r"),
+            b'\t' => out.push_str("\\t"),
+            b if b.is_ascii_graphic() || b == b' ' => out.push(b as char),
+            b => {
+                let _ = write!(out, "\\x{b:02x}");
+            }
+        }
+    }
+    out.push('"');
+}
+
+/// Renders a reply like `redis-cli` does, one line per element of an aggregate
+#[must_use]
+pub fn format(resp: &Resp) -> String {
+    let mut out = String::new();
+    write_reply(resp, 0, &mut out);
+    out
+}
+
+fn write_reply(resp: &Resp, indent: usize, out: &mut String) {
+    match resp {
+        Resp::Simple(s) => out.push_str(s),
+        Resp::Err(e) => {
+            let _ = write!(out, "(error) {e}");
+        }
+        Resp::Integer(int) => {
+            let _ = write!(out, "(integer) {int}");
+        }
+        Resp::Bulk(bytes) | Resp::Data(bytes) => quote(bytes, out),
+        Resp::Null | Resp::NullArray => out.push_str("(nil)"),
+        Resp::Array(items) | Resp::Push(items) if items.is_empty() => {
+            out.push_str("(empty array)");
+        }
+        Resp::Map(pairs) if pairs.is_empty() => out.push_str("(empty hash)"),
+        Resp::Array(items) | Resp::Push(items) => {
+            let width = items.len().to_string().len();
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push('\n');
+                    out.extend(std::iter::repeat_n(' ', indent));
+                }
+                let _ = write!(out, "{:>width$}) ", i + 1);
+                write_reply(item, indent + width + 2, out);
+            }
+        }
+        Resp::Map(pairs) => {
+            let width = pairs.len().to_string().len();
+            for (i, (key, value)) in pairs.iter().enumerate() {
+                if i > 0 {
+                    out.push('\n');
+                    out.extend(std::iter::repeat_n(' ', indent));
+                }
+                let _ = write!(out, "{:>width$}# ", i + 1);
+                write_reply(key, indent + width + 2, out);
+                out.push_str(" => ");
+                write_reply(value, indent + width + 2, out);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testutil::master;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn pipeline() {
+        let server = master().await;
+        let mut client = Client::connect(server.addr()).await.unwrap();
+        let replies = client
+            .pipeline([
+                vec!["SET", "k", "1"],
+                vec!["INCR", "k"],
+                vec!["HGET", "k", "f"],
+            ])
+            .await
+            .unwrap();
+        pretty_assertions::assert_eq!(
+            replies,
+            [
+                Resp::simple("OK"),
+                Resp::Integer(2),
+                Resp::Err(
+                    "WRONGTYPE Operation against a key holding the wrong kind of value".to_owned()
+                ),
+            ]
+        );
+        pretty_assertions::assert_eq!(client.cmd(["GET", "k"]).await.unwrap(), Resp::bulk("2"));
+    }
+
+    #[test]
+    fn formatting() {
+        let nested = Resp::Array(
+            (1..=10)
+                .map(Resp::Integer)
+                .chain([Resp::Array(vec![Resp::bulk("a\"b"), Resp::Null])])
+                .collect(),
+        );
+        pretty_assertions::assert_eq!(
+            format(&nested),
+            " 1) (integer) 1\n 2) (integer) 2\n 3) (integer) 3\n 4) (integer) 4\n \
+             5) (integer) 5\n 6) (integer) 6\n 7) (integer) 7\n 8) (integer) 8\n \
+             9) (integer) 9\n10) (integer) 10\n11) 1) \"a\\\"b\"\n    2) (nil)"
+        );
+        let map = Resp::Map(vec![(Resp::bulk("proto"), Resp::Integer(3))]);
+        pretty_assertions::assert_eq!(format(&map), "1# \"proto\" => (integer) 3");
+        pretty_assertions::assert_eq!(format(&Resp::Array(vec![])), "(empty array)");
+        pretty_assertions::assert_eq!(format(&Resp::Err("ERR no".to_owned())), "(error) ERR no");
+    }
+}
@@ -0,0 +1,384 @@
+use parking_lot::RwLock;
+use std::{
+    cmp::Reverse,
+    collections::BTreeMap,
+    fmt::Write,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+use tokio::sync::{oneshot, Notify};
+
+use crate::{resp::Protocol, ACL, STATS, TRACKING};
+
+pub static CLIENTS: Clients = Clients::new();
+
+/// Registry of the connected clients
+#[derive(Debug)]
+pub struct Clients {
+    next_id: AtomicU64,
+    inner: RwLock<BTreeMap<u64, ClientInfo>>,
+    /// Only loopback clients are accepted while the default user has no password
+    protected_mode: AtomicBool,
+    /// Memory of the connection buffers above which clients are evicted, no limit if zero
+    memory_limit: AtomicUsize,
+    /// Memory of the buffers of every client not being evicted yet
+    used_memory: AtomicUsize,
+}
+
+impl Clients {
+    pub(crate) const PROTECTED_MODE_DENIED: &'static str = "DENIED Redis is running in protected mode because protected mode is enabled and no password is set for the default user. \
+        In this mode connections are only accepted from the loopback interface. \
+        If you want to connect from external computers to Redis you may adopt one of the following solutions: \
+        1) Just disable protected mode sending the command 'CONFIG SET protected-mode no' from the loopback interface by connecting to Redis from the same host the server is running, however MAKE SURE Redis is not publicly accessible from internet if you do so. \
+        2) Alternatively you can just disable the protected mode by editing the Redis configuration file, and setting the protected mode option to 'no', and then restarting the server. \
+        3) If you started the server manually just for testing, restart it with the '--protected-mode no' option. \
+        4) Set up an authentication password for the default user. \
+        NOTE: You only need to do one of the above things in order for the server to start accepting connections from the outside.";
+
+    const fn new() -> Self {
+        Self {
+            next_id: AtomicU64::new(1),
+            inner: RwLock::new(BTreeMap::new()),
+            protected_mode: AtomicBool::new(true),
+            memory_limit: AtomicUsize::new(0),
+            used_memory: AtomicUsize::new(0),
+        }
+    }
+
+    #[inline]
+    pub fn protected_mode(&self) -> bool {
+        self.protected_mode.load(Ordering::Relaxed)
+    }
+
+    pub fn set_protected_mode(&self, enabled: bool) {
+        self.protected_mode.store(enabled, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn maxmemory_clients(&self) -> usize {
+        self.memory_limit.load(Ordering::Relaxed)
+    }
+
+    pub fn set_maxmemory_clients(&self, limit: usize) {
+        self.memory_limit.store(limit, Ordering::Relaxed);
+    }
+
+    /// Memory used by the connection buffers of the clients
+    #[inline]
+    pub fn used_memory(&self) -> usize {
+        self.used_memory.load(Ordering::Relaxed)
+    }
+
+    /// Whether protected mode refuses a connection from `addr`
+    pub(crate) fn denies(&self, addr: SocketAddr) -> bool {
+        self.protected_mode() && !addr.ip().is_loopback() && ACL.default_login().is_some()
+    }
+
+    /// Adds a client to the registry, which is removed when the guard is dropped
+    pub(crate) fn register(&self, addr: SocketAddr) -> ClientGuard {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let client = ClientInfo::new(id, addr);
+        let evicted = Arc::clone(&client.evicted);
+        self.inner.write().insert(id, client);
+        ClientGuard { id, evicted }
+    }
+
+    /// Records the size of the buffer client `id` reads commands into
+    pub(crate) fn set_query_buffer(&self, id: u64, qbuf: usize) {
+        self.resize(id, |client| client.qbuf = qbuf);
+    }
+
+    /// Records the size of the buffers client `id` writes replies from
+    pub(crate) fn set_output_buffer(&self, id: u64, omem: usize) {
+        self.resize(id, |client| client.omem = omem);
+    }
+
+    /// Updates the buffers of client `id`. If that puts the clients over
+    /// `maxmemory-clients`, the largest consumers are evicted.
+    fn resize(&self, id: u64, f: impl FnOnce(&mut ClientInfo)) {
+        let mut inner = self.inner.write();
+        let Some(client) = inner.get_mut(&id).filter(|client| !client.evicting) else {
+            return;
+        };
+        let before = client.tot_mem();
+        f(client);
+        let after = client.tot_mem();
+        let used = if after >= before {
+            self.used_memory
+                .fetch_add(after - before, Ordering::Relaxed)
+                + (after - before)
+        } else {
+            self.used_memory
+                .fetch_sub(before - after, Ordering::Relaxed)
+                - (before - after)
+        };
+        let limit = self.maxmemory_clients();
+        if limit > 0 && used > limit {
+            let freed = evict(&mut inner, used, limit);
+            self.used_memory.fetch_sub(freed, Ordering::Relaxed);
+        }
+        drop(inner);
+    }
+
+    pub(crate) fn with<T>(&self, id: u64, f: impl FnOnce(&mut ClientInfo) -> T) -> Option<T> {
+        self.inner.write().get_mut(&id).map(f)
+    }
+
+    /// Flags client `id` as blocked, until [`Clients::unblock`] or [`ClientInfo::wake_up`].
+    /// The receiver tells how `CLIENT UNBLOCK` woke it up meanwhile, if it did.
+    pub(crate) fn block(&self, id: u64) -> oneshot::Receiver<Unblock> {
+        let (tx, rx) = oneshot::channel();
+        self.with(id, |client| client.blocked = Some(tx));
+        rx
+    }
+
+    /// Wakes up client `id` if it's blocked, returning whether it was
+    pub(crate) fn unblock(&self, id: u64, how: Unblock) -> bool {
+        self.with(id, |client| client.blocked.take())
+            .flatten()
+            .is_some_and(|blocked| blocked.send(how).is_ok())
+    }
+
+    /// `CLIENT LIST` output, one line per client
+    pub(crate) fn list(&self) -> String {
+        let now = Instant::now();
+        self.inner
+            .read()
+            .values()
+            .fold(String::new(), |mut acc, client| {
+                client
+                    .write_line(&mut acc, now)
+                    .expect("Writing to a String");
+                acc
+            })
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.inner.read().len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.inner.read().is_empty()
+    }
+}
+
+#[derive(Debug)]
+pub struct ClientInfo {
+    id: u64,
+    pub(crate) addr: SocketAddr,
+    pub(crate) name: Option<String>,
+    pub(crate) lib_name: Option<String>,
+    pub(crate) lib_ver: Option<String>,
+    created: Instant,
+    last_interaction: Instant,
+    last_cmd: String,
+    pub(crate) sub: usize,
+    pub(crate) psub: usize,
+    pub(crate) multi: Option<usize>,
+    /// Waiting in a blocking command, which `CLIENT UNBLOCK` wakes up through the sender
+    blocked: Option<oneshot::Sender<Unblock>>,
+    pub(crate) protocol: Protocol,
+    /// Size of the buffer commands are read into
+    qbuf: usize,
+    /// Size of the buffer replies are written from
+    omem: usize,
+    /// Set by `CLIENT NO-EVICT`, so `maxmemory-clients` never evicts the client
+    pub(crate) no_evict: bool,
+    /// Already chosen for eviction, so it doesn't count anymore
+    evicting: bool,
+    evicted: Arc<Notify>,
+}
+
+impl ClientInfo {
+    fn new(id: u64, addr: SocketAddr) -> Self {
+        let now = Instant::now();
+        Self {
+            id,
+            addr,
+            name: None,
+            lib_name: None,
+            lib_ver: None,
+            created: now,
+            last_interaction: now,
+            last_cmd: "NULL".into(),
+            sub: 0,
+            psub: 0,
+            multi: None,
+            blocked: None,
+            protocol: Protocol::default(),
+            qbuf: 0,
+            omem: 0,
+            no_evict: false,
+            evicting: false,
+            evicted: Arc::new(Notify::new()),
+        }
+    }
+
+    #[inline]
+    const fn tot_mem(&self) -> usize {
+        self.qbuf + self.omem
+    }
+
+    /// Clears the blocked flag, once the blocking command returned
+    pub(crate) fn wake_up(&mut self) {
+        self.blocked = None;
+    }
+
+    pub(crate) fn touch(&mut self, cmd: &str) {
+        self.last_interaction = Instant::now();
+        cmd.clone_into(&mut self.last_cmd);
+    }
+
+    fn write_line(&self, out: &mut String, now: Instant) -> std::fmt::Result {
+        let mut flags = String::new();
+        if self.sub + self.psub > 0 {
+            flags.push('P');
+        }
+        if self.multi.is_some() {
+            flags.push('x');
+        }
+        if self.blocked.is_some() {
+            flags.push('b');
+        }
+        if self.no_evict {
+            flags.push('e');
+        }
+        if flags.is_empty() {
+            flags.push('N');
+        }
+
+        writeln!(
+            out,
+            "id={id} addr={addr} name={name} age={age} idle={idle} flags={flags} db=0 \
+            sub={sub} psub={psub} multi={multi} qbuf={qbuf} omem={omem} tot-mem={tot_mem} cmd={cmd} lib-name={lib_name} lib-ver={lib_ver} resp={resp}",
+            id = self.id,
+            addr = self.addr,
+            name = self.name.as_deref().unwrap_or_default(),
+            age = now.duration_since(self.created).as_secs(),
+            idle = now.duration_since(self.last_interaction).as_secs(),
+            sub = self.sub,
+            psub = self.psub,
+            multi = self
+                .multi
+                .map_or(-1, |queued| queued.try_into().unwrap_or(i64::MAX)),
+            qbuf = self.qbuf,
+            omem = self.omem,
+            tot_mem = self.tot_mem(),
+            cmd = self.last_cmd,
+            lib_name = self.lib_name.as_deref().unwrap_or_default(),
+            lib_ver = self.lib_ver.as_deref().unwrap_or_default(),
+            resp = self.protocol.version(),
+        )
+    }
+}
+
+/// How `CLIENT UNBLOCK` wakes up a blocked client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unblock {
+    /// As if the command timed out
+    Timeout,
+    /// With an `-UNBLOCKED` error
+    Error,
+}
+
+/// Evicts the clients using the most memory until `used` is at most `limit`,
+/// returning how much memory they used
+fn evict(clients: &mut BTreeMap<u64, ClientInfo>, mut used: usize, limit: usize) -> usize {
+    let mut candidates = clients
+        .values_mut()
+        .filter(|client| !client.no_evict && !client.evicting)
+        .collect::<Vec<_>>();
+    candidates.sort_unstable_by_key(|client| Reverse(client.tot_mem()));
+    let mut freed = 0;
+    for client in candidates {
+        if used <= limit {
+            break;
+        }
+        let mem = client.tot_mem();
+        tracing::warn!(
+            "Evicting client id={} addr={} using {mem} bytes, maxmemory-clients is {limit}",
+            client.id,
+            client.addr
+        );
+        client.evicting = true;
+        client.evicted.notify_one();
+        STATS.incr_evicted_clients();
+        used -= mem;
+        freed += mem;
+    }
+    freed
+}
+
+/// Keeps a client registered for as long as its connection is alive
+#[derive(Debug)]
+pub struct ClientGuard {
+    id: u64,
+    evicted: Arc<Notify>,
+}
+
+impl ClientGuard {
+    #[inline]
+    pub const fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Resolves once the client is evicted for using too much memory
+    pub(crate) async fn evicted(&self) {
+        self.evicted.notified().await;
+    }
+}
+
+impl Drop for ClientGuard {
+    fn drop(&mut self) {
+        let removed = CLIENTS.inner.write().remove(&self.id);
+        if let Some(client) = removed.filter(|client| !client.evicting) {
+            CLIENTS
+                .used_memory
+                .fetch_sub(client.tot_mem(), Ordering::Relaxed);
+        }
+        TRACKING.disable(self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddrV4};
+
+    use super::*;
+
+    #[test]
+    fn eviction() {
+        let addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 6379).into();
+        let mut clients = [
+            (1, 100, false),
+            (2, 500, true),
+            (3, 300, false),
+            (4, 200, false),
+        ]
+        .into_iter()
+        .map(|(id, qbuf, no_evict)| {
+            let mut client = ClientInfo::new(id, addr);
+            client.qbuf = qbuf;
+            client.no_evict = no_evict;
+            (id, client)
+        })
+        .collect::<BTreeMap<_, _>>();
+        let evicting = |clients: &BTreeMap<u64, ClientInfo>| {
+            let evicting = clients.values().filter(|client| client.evicting);
+            evicting.map(|client| client.id).collect::<Vec<_>>()
+        };
+
+        // The largest client can't be evicted, the next two have to be
+        pretty_assertions::assert_eq!(evict(&mut clients, 1100, 700), 500);
+        pretty_assertions::assert_eq!(evicting(&clients), [3, 4]);
+        pretty_assertions::assert_eq!(evict(&mut clients, 600, 700), 0);
+        pretty_assertions::assert_eq!(evict(&mut clients, 600, 500), 100);
+        pretty_assertions::assert_eq!(evicting(&clients), [1, 3, 4]);
+    }
+}
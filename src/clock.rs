@@ -0,0 +1,74 @@
+//! The time expirations, stream ids and saves are based on. Each [`Db`](crate::Db) reads
+//! it from its own clock, the system time unless a test pauses it to move it forward
+//! by hand instead of sleeping. Scripts and background saves read the same clock.
+//!
+//! This is internal to the crate: only its own tests can pause the clock,
+//! and code embedding a [`Server`](crate::Server) always runs on the system time.
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Handle on a clock, whose clones read the same time
+#[derive(Debug, Clone, Default)]
+pub struct Clock {
+    /// Nanoseconds since the epoch the clock was paused at then moved to, 0 while it runs
+    paused: Arc<AtomicU64>,
+}
+
+impl Clock {
+    /// The current time, as keys and hash fields are expired at
+    pub fn now(&self) -> SystemTime {
+        match self.paused.load(Ordering::Relaxed) {
+            0 => SystemTime::now(),
+            nanos => UNIX_EPOCH + Duration::from_nanos(nanos),
+        }
+    }
+
+    /// Stops the clock at the current time
+    #[cfg(test)]
+    pub fn pause(&self) {
+        let now = self.now().duration_since(UNIX_EPOCH).unwrap();
+        self.paused
+            .store(now.as_nanos().try_into().unwrap(), Ordering::Relaxed);
+    }
+
+    /// Moves the paused clock forward
+    #[cfg(test)]
+    pub fn advance(&self, by: Duration) {
+        assert_ne!(
+            self.paused.load(Ordering::Relaxed),
+            0,
+            "The clock isn't paused"
+        );
+        let by = u64::try_from(by.as_nanos()).unwrap();
+        self.paused.fetch_add(by, Ordering::Relaxed);
+    }
+}
+
+/// When a key or a hash field expires, as given to a command
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expiry {
+    /// After some time, from when the command is applied
+    In(Duration),
+    At(SystemTime),
+}
+
+impl Expiry {
+    /// The latest expiration, its milliseconds since the epoch fitting in an `i64` like Redis
+    const MAX: Duration = Duration::from_millis(i64::MAX.unsigned_abs());
+
+    /// When it expires if applied at `now`, `None` past [`Self::MAX`]
+    pub fn at(self, now: SystemTime) -> Option<SystemTime> {
+        let at = match self {
+            Self::In(duration) => now.checked_add(duration)?,
+            Self::At(at) => at,
+        };
+        let since_epoch = at.duration_since(UNIX_EPOCH).unwrap_or_default();
+        (since_epoch <= Self::MAX).then_some(at)
+    }
+}
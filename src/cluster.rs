@@ -0,0 +1,514 @@
+//! Hash slots and the nodes serving them. This node serves every slot until
+//! `CLUSTER SETSLOT` assigns some elsewhere, so without `cluster-enabled` it
+//! introspects as a single node cluster, for cluster-aware clients.
+//! With it, commands on keys of other nodes' slots are redirected there,
+//! and the nodes meet and watch each other over the cluster bus.
+
+use anyhow::{bail, ensure};
+use parking_lot::{Mutex, RwLock};
+use rand::Rng;
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, LazyLock,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{commands::CommandSpec, RedisError, Resp};
+
+pub mod bus;
+mod config;
+mod crc16;
+mod gossip;
+use crc16::crc16;
+
+pub static CLUSTER: LazyLock<Cluster> = LazyLock::new(Cluster::default);
+
+#[derive(Debug)]
+pub struct Cluster {
+    /// Generated at startup, like the ID of a new cluster node, unless nodes.conf has one
+    node_id: RwLock<String>,
+    /// The other nodes, by id
+    nodes: RwLock<HashMap<String, Peer>>,
+    slots: RwLock<Slots>,
+    /// Nodes whose id isn't known until they reply, by the address of their bus
+    handshakes: Mutex<HashMap<SocketAddr, Handshake>>,
+    /// Highest epoch seen in the cluster
+    current_epoch: AtomicU64,
+    /// Epoch of the configuration of this node
+    config_epoch: AtomicU64,
+    /// `cluster-node-timeout`, in milliseconds
+    node_timeout: AtomicU64,
+    /// Where this node is reached by clients, and the port of its bus
+    myself: RwLock<(SocketAddr, u16)>,
+    /// nodes.conf, written once the bus started
+    config_file: RwLock<Option<PathBuf>>,
+    /// The nodes, their epochs or the slots changed since nodes.conf was written
+    changed: AtomicBool,
+}
+
+impl Default for Cluster {
+    fn default() -> Self {
+        Self {
+            node_id: RwLock::new(random_id()),
+            nodes: RwLock::default(),
+            slots: RwLock::new(Slots {
+                owners: vec![None; SLOTS.into()].into_boxed_slice(),
+                migrating: HashMap::new(),
+                importing: HashMap::new(),
+            }),
+            handshakes: Mutex::default(),
+            current_epoch: AtomicU64::new(0),
+            config_epoch: AtomicU64::new(0),
+            node_timeout: AtomicU64::new(15_000),
+            myself: RwLock::new((([127, 0, 0, 1], 6379).into(), 16379)),
+            config_file: RwLock::new(None),
+            changed: AtomicBool::new(false),
+        }
+    }
+}
+
+/// A 40 hex digits id, like the ones Redis names nodes with
+fn random_id() -> String {
+    let mut rng = rand::thread_rng();
+    (0..40)
+        .map(|_| char::from_digit(rng.gen_range(0..16), 16).unwrap())
+        .collect()
+}
+
+/// Another node of the cluster
+#[derive(Debug, PartialEq, Eq)]
+pub struct Node {
+    pub id: String,
+    /// Where clients reach it
+    pub addr: SocketAddr,
+}
+
+/// What this node knows of another one, from the cluster bus
+#[derive(Debug)]
+struct Peer {
+    node: Arc<Node>,
+    bus_port: u16,
+    config_epoch: u64,
+    health: Health,
+    /// When the oldest ping still waiting for a pong was sent
+    ping_sent: Option<Instant>,
+    /// When a ping was last sent, it's resent in case the link broke
+    last_ping: Option<Instant>,
+    pong_received: Option<Instant>,
+    failed_at: Option<Instant>,
+    /// Nodes that gossiped it doesn't reply to them, and when they last did
+    fail_reports: HashMap<String, Instant>,
+}
+
+impl Peer {
+    fn new(node: Arc<Node>, bus_port: u16) -> Self {
+        Self {
+            node,
+            bus_port,
+            config_epoch: 0,
+            health: Health::Ok,
+            ping_sent: None,
+            last_ping: None,
+            pong_received: None,
+            failed_at: None,
+            fail_reports: HashMap::new(),
+        }
+    }
+
+    fn bus_addr(&self) -> SocketAddr {
+        SocketAddr::new(self.node.addr.ip(), self.bus_port)
+    }
+}
+
+/// Whether a node replies, as seen by this node
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Health {
+    Ok,
+    /// `PFAIL`: it didn't reply to this node within the node timeout
+    PFail,
+    /// `FAIL`: a majority of the nodes serving slots agreed it doesn't reply
+    Fail,
+}
+
+impl Health {
+    /// Flags of a node in CLUSTER NODES and nodes.conf
+    pub const fn flags(self) -> &'static str {
+        match self {
+            Self::Ok => "master",
+            Self::PFail => "master,fail?",
+            Self::Fail => "master,fail",
+        }
+    }
+
+    pub fn from_flags(flags: &str) -> Self {
+        let mut health = Self::Ok;
+        for flag in flags.split(',') {
+            match flag {
+                "fail?" => health = Self::PFail,
+                "fail" => return Self::Fail,
+                _ => {}
+            }
+        }
+        health
+    }
+}
+
+/// A node met at an address, which tells its id when it replies
+#[derive(Debug)]
+struct Handshake {
+    /// Shown in CLUSTER NODES until the real one is known
+    id: String,
+    addr: SocketAddr,
+    started: Instant,
+    sent: Option<Instant>,
+    /// Met with CLUSTER MEET, rather than learned from gossip
+    meet: bool,
+}
+
+/// Who serves each hash slot, and the slots moving between nodes
+#[derive(Debug)]
+struct Slots {
+    /// Node serving each slot, `None` for this one
+    owners: Box<[Option<Arc<Node>>]>,
+    /// Slots this node hands over, to the node taking them
+    migrating: HashMap<u16, Arc<Node>>,
+    /// Slots this node takes over, from the node giving them
+    importing: HashMap<u16, Arc<Node>>,
+}
+
+/// Change made to a slot by `CLUSTER SETSLOT`, naming nodes by id
+#[derive(Debug)]
+pub enum SlotState {
+    Migrating(String),
+    Importing(String),
+    /// Neither migrating nor importing anymore
+    Stable,
+    /// Served by the node from now on, ending a migration
+    Node(String),
+}
+
+/// Consecutive slots served by the same node, `None` for this one
+pub type SlotRange = (u16, u16, Option<Arc<Node>>);
+
+/// Number of hash slots keys are sharded over
+pub const SLOTS: u16 = 16384;
+
+/// Hash slot the key belongs to. Only its hash tag is hashed if it has one,
+/// so keys sharing a tag land in the same slot.
+pub fn key_slot(key: &[u8]) -> u16 {
+    crc16(hash_tag(key).unwrap_or(key)) % SLOTS
+}
+
+/// What's between the first `{` and the first `}` after it, if it isn't empty
+fn hash_tag(key: &[u8]) -> Option<&[u8]> {
+    let start = key.iter().position(|&b| b == b'{')? + 1;
+    let len = key[start..].iter().position(|&b| b == b'}')?;
+    (len > 0).then(|| &key[start..start + len])
+}
+
+impl Cluster {
+    #[inline]
+    pub fn node_id(&self) -> String {
+        self.node_id.read().clone()
+    }
+
+    /// Makes a node known, so slots can be assigned to it. Returns whether it's new.
+    pub fn add_node(&self, id: String, addr: SocketAddr, bus_port: u16) -> bool {
+        let mut nodes = self.nodes.write();
+        if nodes.contains_key(&id) {
+            return false;
+        }
+        tracing::info!("Cluster node {id} added, at {addr}");
+        let node = Arc::new(Node { id, addr });
+        nodes.insert(node.id.clone(), Peer::new(node, bus_port));
+        drop(nodes);
+        self.changed();
+        true
+    }
+
+    /// Number of nodes, this one included
+    pub fn known_nodes(&self) -> usize {
+        self.nodes.read().len() + 1
+    }
+
+    fn node(&self, id: &str) -> anyhow::Result<Arc<Node>> {
+        match self.nodes.read().get(id) {
+            Some(peer) => Ok(Arc::clone(&peer.node)),
+            None => bail!(RedisError::UnknownNode(id.to_owned())),
+        }
+    }
+
+    pub fn node_timeout(&self) -> Duration {
+        Duration::from_millis(self.node_timeout.load(Ordering::Relaxed))
+    }
+
+    pub fn set_node_timeout(&self, timeout: Duration) {
+        let millis = u64::try_from(timeout.as_millis()).unwrap_or(u64::MAX);
+        self.node_timeout.store(millis, Ordering::Relaxed);
+    }
+
+    pub fn current_epoch(&self) -> u64 {
+        self.current_epoch.load(Ordering::Relaxed)
+    }
+
+    pub fn config_epoch(&self) -> u64 {
+        self.config_epoch.load(Ordering::Relaxed)
+    }
+
+    /// Slots served by nodes flagged `PFAIL`, then by nodes flagged `FAIL`
+    pub fn failing_slots(&self) -> (usize, usize) {
+        let nodes = self.nodes.read();
+        let slots = self.slots.read();
+        let health = |owner: &Option<Arc<Node>>| {
+            owner
+                .as_ref()
+                .and_then(|node| nodes.get(&node.id))
+                .map_or(Health::Ok, |peer| peer.health)
+        };
+        let count = |wanted| slots.owners.iter().filter(|o| health(o) == wanted).count();
+        (count(Health::PFail), count(Health::Fail))
+    }
+
+    /// Number of nodes serving slots, a majority of which must agree a node failed
+    fn size(&self) -> usize {
+        let mut owners = self
+            .slots
+            .read()
+            .owners
+            .iter()
+            .map(|owner| owner.as_ref().map(Arc::as_ptr))
+            .collect::<Vec<_>>();
+        owners.sort_unstable();
+        owners.dedup();
+        owners.len()
+    }
+
+    /// nodes.conf needs to be written again
+    fn changed(&self) {
+        self.changed.store(true, Ordering::Relaxed);
+    }
+
+    /// Applies `CLUSTER SETSLOT`. `has_keys` tells whether this node still
+    /// holds keys of the slot, which it can't give away then.
+    pub fn set_slot(
+        &self,
+        slot: u16,
+        state: &SlotState,
+        has_keys: impl FnOnce() -> bool,
+    ) -> anyhow::Result<()> {
+        ensure!(slot < SLOTS, RedisError::InvalidSlot);
+        let index = usize::from(slot);
+        let node = match state {
+            SlotState::Node(id) if *id == self.node_id() => None,
+            SlotState::Migrating(id) | SlotState::Importing(id) | SlotState::Node(id) => {
+                ensure!(*id != self.node_id(), RedisError::SlotToMyself);
+                Some(self.node(id)?)
+            }
+            SlotState::Stable => None,
+        };
+        let mut slots = self.slots.write();
+        let mine = slots.owners[index].is_none();
+        match state {
+            SlotState::Migrating(_) => {
+                ensure!(mine, RedisError::NotSlotOwner(slot));
+                slots.migrating.extend(node.map(|node| (slot, node)));
+            }
+            SlotState::Importing(_) => {
+                ensure!(!mine, RedisError::AlreadySlotOwner(slot));
+                slots.importing.extend(node.map(|node| (slot, node)));
+            }
+            SlotState::Stable => {
+                slots.migrating.remove(&slot);
+                slots.importing.remove(&slot);
+            }
+            SlotState::Node(_) => {
+                ensure!(
+                    node.is_none() || !mine || !has_keys(),
+                    RedisError::SlotHasKeys(slot)
+                );
+                slots.owners[index] = node;
+                slots.migrating.remove(&slot);
+                slots.importing.remove(&slot);
+            }
+        }
+        drop(slots);
+        self.changed();
+        Ok(())
+    }
+
+    /// Checks the keys of the command `args` can be served here, otherwise
+    /// redirecting the client to the node serving them. Keys of a slot being
+    /// migrated away are served while they're all still here. Keys of a slot
+    /// being imported are only served after `ASKING`.
+    pub fn route(
+        &self,
+        args: &[Resp],
+        asking: bool,
+        exists: impl Fn(&[u8]) -> bool,
+    ) -> Result<(), RedisError> {
+        let Some(spec) = args
+            .first()
+            .and_then(Resp::as_bulk)
+            .and_then(|name| CommandSpec::lookup(name))
+        else {
+            return Ok(());
+        };
+        let keys = spec
+            .keys
+            .keys(args)
+            .into_iter()
+            .filter_map(Resp::as_bulk)
+            .collect::<Vec<_>>();
+        let Some(slot) = keys.first().map(|key| key_slot(key)) else {
+            return Ok(());
+        };
+        if keys.iter().any(|key| key_slot(key) != slot) {
+            return Err(RedisError::CrossSlot);
+        }
+
+        let slots = self.slots.read();
+        match &slots.owners[usize::from(slot)] {
+            None => match slots.migrating.get(&slot) {
+                Some(target) if !keys.iter().all(|key| exists(key)) => Err(RedisError::Ask {
+                    slot,
+                    addr: target.addr,
+                }),
+                _ => Ok(()),
+            },
+            Some(_) if asking && slots.importing.contains_key(&slot) => Ok(()),
+            Some(owner) => Err(RedisError::Moved {
+                slot,
+                addr: owner.addr,
+            }),
+        }
+    }
+
+    /// Every slot, grouped in ranges served by the same node
+    pub fn slot_ranges(&self) -> Vec<SlotRange> {
+        let slots = self.slots.read();
+        let mut ranges: Vec<SlotRange> = Vec::new();
+        for (slot, owner) in (0..SLOTS).zip(slots.owners.iter()) {
+            match ranges.last_mut() {
+                Some((_, end, last)) if *last == *owner => *end = slot,
+                _ => ranges.push((slot, slot, owner.clone())),
+            }
+        }
+        drop(slots);
+        ranges
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slots() {
+        pretty_assertions::assert_eq!(key_slot(b"foo"), 12182);
+        pretty_assertions::assert_eq!(key_slot(b"bar"), 5061);
+        pretty_assertions::assert_eq!(key_slot(b""), 0);
+        pretty_assertions::assert_eq!(key_slot(b"{user1000}.following"), key_slot(b"user1000"));
+        pretty_assertions::assert_eq!(key_slot(b"{user1000}.followers"), key_slot(b"user1000"));
+    }
+
+    #[test]
+    fn hash_tags() {
+        pretty_assertions::assert_eq!(hash_tag(b"foo{bar}baz"), Some(&b"bar"[..]));
+        // Only the first braces count
+        pretty_assertions::assert_eq!(hash_tag(b"foo{bar}{zap}"), Some(&b"bar"[..]));
+        pretty_assertions::assert_eq!(hash_tag(b"foo{{bar}}zap"), Some(&b"{bar"[..]));
+        pretty_assertions::assert_eq!(hash_tag(b"foo{}{bar}"), None);
+        pretty_assertions::assert_eq!(hash_tag(b"foo{bar"), None);
+        pretty_assertions::assert_eq!(hash_tag(b"foo}bar{"), None);
+        pretty_assertions::assert_eq!(hash_tag(b"foo"), None);
+        pretty_assertions::assert_eq!(CLUSTER.node_id().len(), 40);
+    }
+
+    #[test]
+    fn routing() {
+        // Not the global one, so other tests keep every slot
+        let cluster = Cluster::default();
+        let other: SocketAddr = ([127, 0, 0, 1], 7001).into();
+        assert!(cluster.add_node("other".to_owned(), other, 17001));
+        let route = |args: &[&str], asking, exists: bool| {
+            let args = args
+                .iter()
+                .map(|&arg| Resp::bulk(arg.to_owned()))
+                .collect::<Vec<_>>();
+            cluster.route(&args, asking, |_| exists)
+        };
+        let set_slot_error = |slot, state: &SlotState, has_keys| {
+            cluster
+                .set_slot(slot, state, || has_keys)
+                .unwrap_err()
+                .downcast::<RedisError>()
+                .unwrap()
+        };
+        let foo = key_slot(b"foo");
+
+        assert!(route(&["GET", "foo"], false, false).is_ok());
+        pretty_assertions::assert_eq!(
+            route(&["DEL", "foo", "bar"], false, true),
+            Err(RedisError::CrossSlot)
+        );
+        assert!(route(&["DEL", "{bar}1", "{bar}2"], false, true).is_ok());
+
+        // Keys missing while the slot migrates may be on the other node already
+        let migrate = |id: &str| SlotState::Migrating(id.to_owned());
+        cluster.set_slot(foo, &migrate("other"), || true).unwrap();
+        assert!(route(&["GET", "foo"], false, true).is_ok());
+        let ask = RedisError::Ask {
+            slot: foo,
+            addr: other,
+        };
+        pretty_assertions::assert_eq!(route(&["GET", "foo"], false, false), Err(ask));
+        pretty_assertions::assert_eq!(
+            set_slot_error(foo, &migrate("nope"), true),
+            RedisError::UnknownNode("nope".to_owned())
+        );
+        pretty_assertions::assert_eq!(
+            set_slot_error(foo, &migrate(&cluster.node_id()), true),
+            RedisError::SlotToMyself
+        );
+        pretty_assertions::assert_eq!(
+            set_slot_error(SLOTS, &SlotState::Stable, true),
+            RedisError::InvalidSlot
+        );
+
+        let assign = || SlotState::Node("other".to_owned());
+        pretty_assertions::assert_eq!(
+            set_slot_error(foo, &assign(), true),
+            RedisError::SlotHasKeys(foo)
+        );
+        cluster.set_slot(foo, &assign(), || false).unwrap();
+        let moved = RedisError::Moved {
+            slot: foo,
+            addr: other,
+        };
+        pretty_assertions::assert_eq!(route(&["GET", "foo"], false, true), Err(moved.clone()));
+        pretty_assertions::assert_eq!(route(&["GET", "foo"], true, true), Err(moved.clone()));
+
+        // Imported keys are served to clients that were told to ASK
+        pretty_assertions::assert_eq!(
+            set_slot_error(foo, &migrate("other"), false),
+            RedisError::NotSlotOwner(foo)
+        );
+        let import = SlotState::Importing("other".to_owned());
+        cluster.set_slot(foo, &import, || false).unwrap();
+        assert!(route(&["GET", "foo"], true, false).is_ok());
+        pretty_assertions::assert_eq!(route(&["GET", "foo"], false, true), Err(moved));
+        let mine = SlotState::Node(cluster.node_id());
+        cluster.set_slot(foo, &mine, || true).unwrap();
+        pretty_assertions::assert_eq!(
+            set_slot_error(foo, &import, false),
+            RedisError::AlreadySlotOwner(foo)
+        );
+        assert!(route(&["GET", "foo"], false, true).is_ok());
+        pretty_assertions::assert_eq!(cluster.slot_ranges().len(), 1);
+    }
+}
@@ -0,0 +1,170 @@
+//! The cluster bus, where nodes exchange [`Message`]s on their port + 10000:
+//! each node keeps a link to every other one to ping it, and replies
+//! to the pings received on the connections it accepted
+
+use anyhow::{ensure, Context};
+use futures_util::{SinkExt, StreamExt};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    time::Duration,
+};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+    task::JoinSet,
+    time::MissedTickBehavior,
+};
+use tokio_util::codec::Framed;
+
+use super::{gossip::Message, Cluster};
+use crate::{Arguments, RespCodec};
+
+/// Offset of the bus port from the port clients connect to
+pub const PORT_OFFSET: u16 = 10000;
+
+/// Listeners of the bus of a node, not accepting connections before [`Bus::run`]
+#[derive(Debug)]
+pub struct Bus {
+    cluster: &'static Cluster,
+    listeners: Vec<TcpListener>,
+}
+
+impl Bus {
+    /// Loads nodes.conf, or creates it for a new node, then listens on the bus port
+    pub fn bind(cluster: &'static Cluster, args: &Arguments) -> anyhow::Result<Self> {
+        let bus_port = args
+            .port
+            .checked_add(PORT_OFFSET)
+            .with_context(|| format!("Port {} leaves no room for the cluster bus", args.port))?;
+        let ip = args
+            .bind
+            .first()
+            .copied()
+            .filter(|ip| !ip.is_unspecified())
+            .unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        *cluster.myself.write() = ((ip, args.port).into(), bus_port);
+        cluster.set_node_timeout(args.cluster_node_timeout);
+
+        let path = args.cluster_config_path();
+        if path.exists() {
+            cluster.load(&path)?;
+            tracing::info!(
+                "Loaded the cluster config from {}, known nodes: {}",
+                path.display(),
+                cluster.known_nodes()
+            );
+        } else {
+            cluster.changed();
+        }
+        *cluster.config_file.write() = Some(path);
+        cluster.save_if_changed()?;
+
+        let listeners = args
+            .bind
+            .iter()
+            .map(|&ip| crate::server::listen((ip, bus_port).into()))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        ensure!(!listeners.is_empty(), "No address to listen on");
+        Ok(Self { cluster, listeners })
+    }
+
+    /// Accepts the other nodes' links, and pings them every 100 ms
+    pub async fn run(self) {
+        let cluster = self.cluster;
+        let mut tasks = JoinSet::new();
+        for listener in self.listeners {
+            tasks.spawn(accept(cluster, listener));
+        }
+        tasks.spawn(cron(cluster));
+        while tasks.join_next().await.is_some() {}
+    }
+}
+
+async fn accept(cluster: &'static Cluster, listener: TcpListener) {
+    let mut links = JoinSet::new();
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, peer)) => {
+                    links.spawn(async move {
+                        if let Err(e) = inbound(cluster, stream, peer.ip()).await {
+                            tracing::debug!("Cluster bus link from {peer} closed: {e:#}");
+                        }
+                    });
+                }
+                Err(e) => tracing::error!("{e}"),
+            },
+            Some(_) = links.join_next(), if !links.is_empty() => {}
+        }
+    }
+}
+
+/// Replies to the messages of a node which linked to this one
+async fn inbound(cluster: &Cluster, stream: TcpStream, ip: IpAddr) -> anyhow::Result<()> {
+    stream.set_nodelay(true)?;
+    let mut framed = Framed::new(stream, RespCodec::default());
+    while let Some(frame) = framed.next().await {
+        let msg = Message::parse(&frame?)?;
+        if let Some(reply) = cluster.receive(&msg, ip) {
+            framed.send(reply.to_resp()).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Sends the messages of each tick over the link to their node, opening it if needed
+async fn cron(cluster: &'static Cluster) {
+    let mut links: HashMap<SocketAddr, mpsc::Sender<Message>> = HashMap::new();
+    let mut tasks = JoinSet::new();
+    let mut interval = tokio::time::interval(Duration::from_millis(100));
+    interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    loop {
+        interval.tick().await;
+        while tasks.try_join_next().is_some() {}
+        links.retain(|_, link| !link.is_closed());
+        for (addr, msg) in cluster.tick() {
+            let link = links.entry(addr).or_insert_with(|| {
+                let (tx, rx) = mpsc::channel(16);
+                tasks.spawn(async move {
+                    if let Err(e) = outbound(cluster, addr, rx).await {
+                        tracing::debug!("Cluster bus link to {addr} closed: {e:#}");
+                    }
+                });
+                tx
+            });
+            // Pings are sent again if they're lost
+            let _ = link.try_send(msg);
+        }
+        if let Err(e) = cluster.save_if_changed() {
+            tracing::error!("Can't save the cluster config: {e}");
+        }
+    }
+}
+
+/// Link to the bus of another node, sending it messages and handling its replies
+async fn outbound(
+    cluster: &Cluster,
+    addr: SocketAddr,
+    mut messages: mpsc::Receiver<Message>,
+) -> anyhow::Result<()> {
+    let stream = tokio::time::timeout(cluster.node_timeout(), TcpStream::connect(addr))
+        .await
+        .context("Timed out")??;
+    stream.set_nodelay(true)?;
+    let mut framed = Framed::new(stream, RespCodec::default());
+    loop {
+        tokio::select! {
+            msg = messages.recv() => {
+                let Some(msg) = msg else {
+                    return Ok(());
+                };
+                framed.send(msg.to_resp()).await?;
+            }
+            frame = framed.next() => {
+                let frame = frame.context("Closed by the node")??;
+                cluster.receive(&Message::parse(&frame)?, addr.ip());
+            }
+        }
+    }
+}
@@ -0,0 +1,228 @@
+//! nodes.conf, where a cluster node keeps its id, the other nodes and who serves
+//! which slots across restarts. Its lines are the ones of CLUSTER NODES:
+//! `id ip:port@bus-port flags master ping-sent pong-received config-epoch link-state slots...`
+
+use anyhow::{bail, ensure, Context};
+use std::{
+    fmt::Write as _,
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    sync::{atomic::Ordering, Arc},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use super::{Cluster, Health, Peer, SLOTS};
+
+impl Cluster {
+    /// The lines of CLUSTER NODES, this node being reached at `ip`
+    pub fn describe_nodes(&self, ip: IpAddr) -> String {
+        let (addr, bus_port) = *self.myself.read();
+        let now = SystemTime::now();
+        let unix_ms = |at: Option<Instant>| {
+            at.and_then(|at| now.checked_sub(at.elapsed()))
+                .and_then(|at| at.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |at| at.as_millis())
+        };
+        let mut out = String::new();
+        let my_id = self.node_id();
+        let _ = write!(
+            out,
+            "{my_id} {ip}:{}@{bus_port} myself,master - 0 0 {} connected",
+            addr.port(),
+            self.config_epoch()
+        );
+        self.write_slots(&mut out, None);
+        out.push('\n');
+
+        let timeout = self.node_timeout();
+        let nodes = self.nodes.read();
+        let mut peers = nodes.values().collect::<Vec<_>>();
+        peers.sort_unstable_by(|a, b| a.node.id.cmp(&b.node.id));
+        for peer in peers {
+            let connected = peer.pong_received.is_some_and(|at| at.elapsed() < timeout);
+            let _ = write!(
+                out,
+                "{} {}@{} {} - {} {} {} {}",
+                peer.node.id,
+                peer.node.addr,
+                peer.bus_port,
+                peer.health.flags(),
+                unix_ms(peer.ping_sent),
+                unix_ms(peer.pong_received),
+                peer.config_epoch,
+                if connected {
+                    "connected"
+                } else {
+                    "disconnected"
+                },
+            );
+            self.write_slots(&mut out, Some(&peer.node.id));
+            out.push('\n');
+        }
+        drop(nodes);
+        for (bus_addr, handshake) in self.handshakes.lock().iter() {
+            let _ = writeln!(
+                out,
+                "{} {}@{} handshake - {} 0 0 disconnected",
+                handshake.id,
+                handshake.addr,
+                bus_addr.port(),
+                unix_ms(handshake.sent),
+            );
+        }
+        out
+    }
+
+    /// Appends the slots served by the node, `None` for this one, as ` start-end` ranges
+    fn write_slots(&self, out: &mut String, id: Option<&str>) {
+        for (start, end, owner) in self.slot_ranges() {
+            if owner.as_ref().map(|node| node.id.as_str()) != id {
+                continue;
+            }
+            if start == end {
+                let _ = write!(out, " {start}");
+            } else {
+                let _ = write!(out, " {start}-{end}");
+            }
+        }
+    }
+
+    /// Writes nodes.conf if anything changed since it was last written
+    pub fn save_if_changed(&self) -> std::io::Result<()> {
+        let Some(path) = self.config_file.read().clone() else {
+            return Ok(());
+        };
+        if !self.changed.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+        let ip = self.myself.read().0.ip();
+        // Nodes still in a handshake are met again after a restart
+        let mut conf = self
+            .describe_nodes(ip)
+            .lines()
+            .filter(|line| !line.contains(" handshake "))
+            .fold(String::new(), |conf, line| conf + line + "\n");
+        let _ = writeln!(
+            conf,
+            "vars currentEpoch {} lastVoteEpoch 0",
+            self.current_epoch()
+        );
+        let res = crate::rdb::write(&path, conf.as_bytes());
+        if res.is_err() {
+            self.changed();
+        }
+        res
+    }
+
+    /// Restores the id of this node, the other nodes and the slots they serve
+    pub fn load(&self, path: &Path) -> anyhow::Result<()> {
+        let conf = std::fs::read_to_string(path)?;
+        for line in conf.lines().filter(|line| !line.is_empty()) {
+            self.load_line(line)
+                .with_context(|| format!("Invalid line in {}: {line}", path.display()))?;
+        }
+        // Not changed since it was read
+        self.changed.store(false, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn load_line(&self, line: &str) -> anyhow::Result<()> {
+        let fields = line.split(' ').collect::<Vec<_>>();
+        if fields[0] == "vars" {
+            for pair in fields[1..].chunks(2) {
+                if let ["currentEpoch", epoch] = pair {
+                    self.current_epoch.store(epoch.parse()?, Ordering::Relaxed);
+                }
+            }
+            return Ok(());
+        }
+        ensure!(fields.len() >= 8, "Missing fields");
+        let (id, flags, config_epoch) = (fields[0], fields[2], fields[6].parse::<u64>()?);
+        // `ip:port@bus-port`, maybe followed by `,hostname`
+        let addr = fields[1].split(',').next().unwrap_or_default();
+        let (addr, bus_port) = addr.split_once('@').context("Missing bus port")?;
+        let (addr, bus_port) = (addr.parse::<SocketAddr>()?, bus_port.parse::<u16>()?);
+
+        let owner = if flags.split(',').any(|flag| flag == "myself") {
+            id.clone_into(&mut self.node_id.write());
+            self.config_epoch.store(config_epoch, Ordering::Relaxed);
+            None
+        } else {
+            let node = Arc::new(super::Node {
+                id: id.to_owned(),
+                addr,
+            });
+            let mut peer = Peer::new(Arc::clone(&node), bus_port);
+            peer.config_epoch = config_epoch;
+            if Health::from_flags(flags) == Health::Fail {
+                peer.health = Health::Fail;
+                peer.failed_at = Some(Instant::now());
+            }
+            self.nodes.write().insert(id.to_owned(), peer);
+            Some(node)
+        };
+
+        let mut slots = self.slots.write();
+        // Slots being migrated are listed as `[slot->-id]`, they're settled again if need be
+        for range in fields[8..].iter().filter(|range| !range.starts_with('[')) {
+            let (start, end) = range.split_once('-').unwrap_or((range, range));
+            let (start, end) = (start.parse::<u16>()?, end.parse::<u16>()?);
+            if start > end || end >= SLOTS {
+                bail!("Invalid slot range {range}");
+            }
+            for slot in start..=end {
+                slots.owners[usize::from(slot)].clone_from(&owner);
+            }
+        }
+        drop(slots);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cluster::SlotState;
+
+    #[test]
+    fn nodes_conf() {
+        let path = std::env::temp_dir().join(format!("nodes-{}.conf", std::process::id()));
+        let cluster = Cluster::default();
+        *cluster.config_file.write() = Some(path.clone());
+        cluster.add_node("other".to_owned(), ([127, 0, 0, 1], 7001).into(), 17001);
+        let assign = SlotState::Node("other".to_owned());
+        for slot in [0, 1, 2, 10] {
+            cluster.set_slot(slot, &assign, || false).unwrap();
+        }
+        cluster.current_epoch.store(5, Ordering::Relaxed);
+        cluster.config_epoch.store(3, Ordering::Relaxed);
+        cluster.save_if_changed().unwrap();
+
+        let conf = std::fs::read_to_string(&path).unwrap();
+        let lines = conf.lines().collect::<Vec<_>>();
+        let me = cluster.node_id();
+        pretty_assertions::assert_eq!(
+            lines[0],
+            format!("{me} 127.0.0.1:6379@16379 myself,master - 0 0 3 connected 3-9 11-16383")
+        );
+        pretty_assertions::assert_eq!(
+            lines[1],
+            "other 127.0.0.1:7001@17001 master - 0 0 0 disconnected 0-2 10"
+        );
+        pretty_assertions::assert_eq!(lines[2], "vars currentEpoch 5 lastVoteEpoch 0");
+
+        let loaded = Cluster::default();
+        loaded.load(&path).unwrap();
+        pretty_assertions::assert_eq!(loaded.node_id(), me);
+        pretty_assertions::assert_eq!(loaded.config_epoch(), 3);
+        pretty_assertions::assert_eq!(loaded.current_epoch(), 5);
+        pretty_assertions::assert_eq!(loaded.known_nodes(), 2);
+        pretty_assertions::assert_eq!(
+            loaded.describe_nodes([127, 0, 0, 1].into()),
+            conf.lines()
+                .take(2)
+                .fold(String::new(), |out, line| out + line + "\n")
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+}
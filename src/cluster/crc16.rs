@@ -0,0 +1,42 @@
+//! CRC-16/XMODEM, which Redis Cluster hashes keys with:
+//! polynomial `0x1021`, not reflected, no initial or final xor.
+
+const POLY: u16 = 0x1021;
+
+static TABLE: [u16; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        #[allow(clippy::cast_possible_truncation)]
+        let mut crc = (i as u16) << 8;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000 == 0 {
+                crc << 1
+            } else {
+                (crc << 1) ^ POLY
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+pub fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0, |crc, &b| {
+        let idx = ((crc >> 8) as u8 ^ b) as usize;
+        TABLE[idx] ^ (crc << 8)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check() {
+        pretty_assertions::assert_eq!(crc16(b"123456789"), 0x31c3);
+    }
+}
@@ -0,0 +1,446 @@
+use anyhow::{bail, Context};
+use rand::seq::IteratorRandom;
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::atomic::Ordering,
+    time::{Duration, Instant},
+};
+
+use super::{random_id, Cluster, Handshake, Health, Peer};
+use crate::Resp;
+
+/// Pings are sent at least this often, or every half node timeout if it's shorter
+const PING_INTERVAL: Duration = Duration::from_secs(1);
+/// Failure reports older than this many node timeouts are forgotten,
+/// and a node is unflagged `FAIL` when it replies again after them
+const FAIL_VALIDITY_MULT: u32 = 2;
+
+/// A message of the cluster bus, sent as a RESP array:
+/// `kind [failed-id] sender port bus-port current-epoch config-epoch`,
+/// then `id ip port bus-port flags` for each gossiped node
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub kind: Kind,
+    pub sender: String,
+    pub port: u16,
+    pub bus_port: u16,
+    pub current_epoch: u64,
+    pub config_epoch: u64,
+    /// What the sender knows of a few other nodes
+    pub gossip: Vec<Gossip>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Kind {
+    /// Asks the receiver to add the sender to its nodes
+    Meet,
+    Ping,
+    /// The reply to `MEET` and `PING`
+    Pong,
+    /// A majority agreed the node with this id failed
+    Fail(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Gossip {
+    pub id: String,
+    pub addr: SocketAddr,
+    pub bus_port: u16,
+    pub health: Health,
+}
+
+impl Message {
+    pub fn to_resp(&self) -> Resp {
+        let mut fields = vec![Resp::bulk(match &self.kind {
+            Kind::Meet => "MEET",
+            Kind::Ping => "PING",
+            Kind::Pong => "PONG",
+            Kind::Fail(_) => "FAIL",
+        })];
+        if let Kind::Fail(id) = &self.kind {
+            fields.push(Resp::bulk(id.clone()));
+        }
+        fields.extend([
+            Resp::bulk(self.sender.clone()),
+            Resp::bulk(self.port.to_string()),
+            Resp::bulk(self.bus_port.to_string()),
+            Resp::bulk(self.current_epoch.to_string()),
+            Resp::bulk(self.config_epoch.to_string()),
+        ]);
+        for gossip in &self.gossip {
+            fields.extend([
+                Resp::bulk(gossip.id.clone()),
+                Resp::bulk(gossip.addr.ip().to_string()),
+                Resp::bulk(gossip.addr.port().to_string()),
+                Resp::bulk(gossip.bus_port.to_string()),
+                Resp::bulk(gossip.health.flags()),
+            ]);
+        }
+        Resp::Array(fields)
+    }
+
+    pub fn parse(resp: &Resp) -> anyhow::Result<Self> {
+        let Resp::Array(fields) = resp else {
+            bail!("Expected an array");
+        };
+        let mut fields = fields.iter();
+        let mut next = || fields.next().context("Truncated cluster bus message");
+        let kind = match next()?.to_string()?.as_str() {
+            "MEET" => Kind::Meet,
+            "PING" => Kind::Ping,
+            "PONG" => Kind::Pong,
+            "FAIL" => Kind::Fail(next()?.to_string()?),
+            kind => bail!("Unknown cluster bus message: {kind}"),
+        };
+        let mut msg = Self {
+            kind,
+            sender: next()?.to_string()?,
+            port: next()?.to_int()?,
+            bus_port: next()?.to_int()?,
+            current_epoch: next()?.to_int()?,
+            config_epoch: next()?.to_int()?,
+            gossip: Vec::new(),
+        };
+        let entries = fields.as_slice();
+        if entries.len() % 5 != 0 {
+            bail!("Truncated cluster bus message");
+        }
+        for entry in entries.chunks(5) {
+            let ip = entry[1].to_string()?.parse::<IpAddr>()?;
+            msg.gossip.push(Gossip {
+                id: entry[0].to_string()?,
+                addr: SocketAddr::new(ip, entry[2].to_int()?),
+                bus_port: entry[3].to_int()?,
+                health: Health::from_flags(&entry[4].to_string()?),
+            });
+        }
+        Ok(msg)
+    }
+}
+
+impl Cluster {
+    /// A message from this node, gossiping about some of the others:
+    /// a tenth of them, at least 3, and every one flagged as failing
+    pub fn message(&self, kind: Kind) -> Message {
+        let (addr, bus_port) = *self.myself.read();
+        let nodes = self.nodes.read();
+        let wanted = (nodes.len() / 10).max(3);
+        let mut gossip = nodes
+            .values()
+            .filter(|peer| peer.health == Health::Ok)
+            .choose_multiple(&mut rand::thread_rng(), wanted);
+        gossip.extend(nodes.values().filter(|peer| peer.health != Health::Ok));
+        let gossip = gossip
+            .into_iter()
+            .map(|peer| Gossip {
+                id: peer.node.id.clone(),
+                addr: peer.node.addr,
+                bus_port: peer.bus_port,
+                health: peer.health,
+            })
+            .collect();
+        drop(nodes);
+        Message {
+            kind,
+            sender: self.node_id(),
+            port: addr.port(),
+            bus_port,
+            current_epoch: self.current_epoch(),
+            config_epoch: self.config_epoch(),
+            gossip,
+        }
+    }
+
+    /// `CLUSTER MEET`: introduces this node to the one at `addr`,
+    /// which adds it to its nodes then gossips about it
+    pub fn meet(&self, addr: SocketAddr, bus_port: u16) {
+        self.handshake(addr, bus_port, true);
+    }
+
+    fn handshake(&self, addr: SocketAddr, bus_port: u16, meet: bool) {
+        let bus_addr = SocketAddr::new(addr.ip(), bus_port);
+        let known = self
+            .nodes
+            .read()
+            .values()
+            .any(|peer| peer.bus_addr() == bus_addr);
+        if known || bus_addr == self.bus_addr() {
+            return;
+        }
+        self.handshakes
+            .lock()
+            .entry(bus_addr)
+            .or_insert_with(|| Handshake {
+                id: random_id(),
+                addr,
+                started: Instant::now(),
+                sent: None,
+                meet,
+            });
+    }
+
+    fn bus_addr(&self) -> SocketAddr {
+        let (addr, bus_port) = *self.myself.read();
+        SocketAddr::new(addr.ip(), bus_port)
+    }
+
+    /// Handles a message from the node at `ip`, returning the reply to send back if any
+    pub fn receive(&self, msg: &Message, ip: IpAddr) -> Option<Message> {
+        let now = Instant::now();
+        self.current_epoch
+            .fetch_max(msg.current_epoch, Ordering::Relaxed);
+        let addr = SocketAddr::new(ip, msg.port);
+        match &msg.kind {
+            Kind::Meet => {
+                self.add_node(msg.sender.clone(), addr, msg.bus_port);
+            }
+            Kind::Pong => {
+                let bus_addr = SocketAddr::new(ip, msg.bus_port);
+                if self.handshakes.lock().remove(&bus_addr).is_some() {
+                    self.add_node(msg.sender.clone(), addr, msg.bus_port);
+                }
+            }
+            Kind::Ping | Kind::Fail(_) => {}
+        }
+
+        let serves_slots = self.serves_slots(&msg.sender);
+        let timeout = self.node_timeout();
+        let my_id = self.node_id();
+        let mut unknown = Vec::new();
+        let mut nodes = self.nodes.write();
+        // Only nodes already met are listened to, anyone gets a pong though
+        if let Some(peer) = nodes.get_mut(&msg.sender) {
+            if msg.kind == Kind::Pong {
+                peer.ping_sent = None;
+                peer.pong_received = Some(now);
+                let undo = !serves_slots
+                    || peer
+                        .failed_at
+                        .is_some_and(|at| at.elapsed() > timeout * FAIL_VALIDITY_MULT);
+                match peer.health {
+                    Health::PFail => peer.health = Health::Ok,
+                    Health::Fail if undo => {
+                        tracing::info!("Cluster node {} is reachable again", msg.sender);
+                        peer.health = Health::Ok;
+                        peer.failed_at = None;
+                        self.changed();
+                    }
+                    Health::Ok | Health::Fail => {}
+                }
+            }
+            if peer.config_epoch != msg.config_epoch {
+                peer.config_epoch = msg.config_epoch;
+                self.changed();
+            }
+            for gossip in &msg.gossip {
+                match nodes.get_mut(&gossip.id) {
+                    Some(peer) if gossip.health == Health::Ok => {
+                        peer.fail_reports.remove(&msg.sender);
+                    }
+                    Some(peer) => {
+                        peer.fail_reports.insert(msg.sender.clone(), now);
+                    }
+                    None if gossip.id == my_id || gossip.health == Health::Fail => {}
+                    None => unknown.push(gossip),
+                }
+            }
+            if let Kind::Fail(id) = &msg.kind {
+                if let Some(peer) = nodes.get_mut(id) {
+                    if peer.health != Health::Fail {
+                        tracing::info!("Cluster node {id} failed, as told by {}", msg.sender);
+                        peer.health = Health::Fail;
+                        peer.failed_at = Some(now);
+                        self.changed();
+                    }
+                }
+            }
+            drop(nodes);
+            for gossip in unknown {
+                self.handshake(gossip.addr, gossip.bus_port, false);
+            }
+            self.resolve_epoch_collision(msg);
+        } else {
+            drop(nodes);
+        }
+
+        matches!(msg.kind, Kind::Meet | Kind::Ping).then(|| self.message(Kind::Pong))
+    }
+
+    fn serves_slots(&self, id: &str) -> bool {
+        let slots = self.slots.read();
+        slots
+            .owners
+            .iter()
+            .any(|owner| owner.as_ref().is_some_and(|node| node.id == id))
+    }
+
+    /// Nodes must have distinct config epochs, so of two with the
+    /// same one, the node with the smallest id takes a new one
+    fn resolve_epoch_collision(&self, msg: &Message) {
+        let epoch = self.config_epoch();
+        if msg.config_epoch != epoch || msg.sender <= self.node_id() {
+            return;
+        }
+        let epoch = self.current_epoch.fetch_add(1, Ordering::Relaxed) + 1;
+        self.config_epoch.store(epoch, Ordering::Relaxed);
+        tracing::info!(
+            "Config epoch collision with node {}, now {epoch}",
+            msg.sender
+        );
+        self.changed();
+    }
+
+    /// Run every 100 ms: flags the nodes that don't reply, and returns the
+    /// messages to send, with the address of the bus they go to
+    pub fn tick(&self) -> Vec<(SocketAddr, Message)> {
+        let timeout = self.node_timeout();
+        let interval = PING_INTERVAL.min(timeout / 2);
+        let needed = self.size() / 2 + 1;
+        let mut out = Vec::new();
+
+        let mut handshakes = self.handshakes.lock();
+        handshakes.retain(|_, handshake| handshake.started.elapsed() < timeout.max(PING_INTERVAL));
+        let mut pings = Vec::new();
+        for (&bus_addr, handshake) in handshakes.iter_mut() {
+            if handshake.sent.is_none_or(|sent| sent.elapsed() >= interval) {
+                handshake.sent = Some(Instant::now());
+                pings.push((
+                    bus_addr,
+                    if handshake.meet {
+                        Kind::Meet
+                    } else {
+                        Kind::Ping
+                    },
+                ));
+            }
+        }
+        drop(handshakes);
+
+        let mut failed = Vec::new();
+        let mut nodes = self.nodes.write();
+        for peer in nodes.values_mut() {
+            if peer.last_ping.is_none_or(|last| last.elapsed() >= interval) {
+                let now = Instant::now();
+                peer.ping_sent.get_or_insert(now);
+                peer.last_ping = Some(now);
+                pings.push((peer.bus_addr(), Kind::Ping));
+            }
+            let late = peer.ping_sent.is_some_and(|sent| sent.elapsed() > timeout);
+            if late && peer.health == Health::Ok {
+                tracing::info!(
+                    "Cluster node {} is not replying, flagged PFAIL",
+                    peer.node.id
+                );
+                peer.health = Health::PFail;
+            }
+            peer.fail_reports
+                .retain(|_, at| at.elapsed() < timeout * FAIL_VALIDITY_MULT);
+            // This node's own view counts too
+            if peer.health == Health::PFail && peer.fail_reports.len() + 1 >= needed {
+                tracing::info!("Cluster node {} failed, as a majority agreed", peer.node.id);
+                peer.health = Health::Fail;
+                peer.failed_at = Some(Instant::now());
+                failed.push(peer.node.id.clone());
+            }
+        }
+        let peers = nodes.values().map(Peer::bus_addr).collect::<Vec<_>>();
+        drop(nodes);
+
+        for id in failed {
+            self.changed();
+            let msg = self.message(Kind::Fail(id));
+            out.extend(peers.iter().map(|&addr| (addr, msg.clone())));
+        }
+        out.extend(
+            pings
+                .into_iter()
+                .map(|(addr, kind)| (addr, self.message(kind))),
+        );
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cluster::SlotState;
+
+    /// Delivers the messages of `from`'s tick to the nodes they're for, and their replies back
+    fn exchange(from: &Cluster, nodes: &[&Cluster]) {
+        let ip = IpAddr::from([127, 0, 0, 1]);
+        for (addr, msg) in from.tick() {
+            let Some(to) = nodes.iter().find(|node| node.bus_addr() == addr) else {
+                continue;
+            };
+            pretty_assertions::assert_eq!(Message::parse(&msg.to_resp()).unwrap(), msg);
+            if let Some(reply) = to.receive(&msg, ip) {
+                from.receive(&reply, ip);
+            }
+        }
+    }
+
+    const TIMEOUT: Duration = Duration::from_millis(100);
+
+    fn node(port: u16) -> Cluster {
+        let cluster = Cluster::default();
+        *cluster.myself.write() = (([127, 0, 0, 1], port).into(), port + 10_000);
+        cluster.set_node_timeout(TIMEOUT);
+        cluster
+    }
+
+    /// Each node's tick in turn, then a wait for the next pings to be due
+    fn round(nodes: &[&Cluster]) {
+        for node in nodes {
+            exchange(node, nodes);
+        }
+        std::thread::sleep(TIMEOUT / 2);
+    }
+
+    #[test]
+    fn meet_gossip_and_fail() {
+        let (a, b, c) = (node(7000), node(7001), node(7002));
+        a.meet(([127, 0, 0, 1], 7001).into(), 17001);
+        a.meet(([127, 0, 0, 1], 7002).into(), 17002);
+        exchange(&a, &[&b, &c]);
+        pretty_assertions::assert_eq!(a.known_nodes(), 3);
+        pretty_assertions::assert_eq!(b.known_nodes(), 2);
+
+        // B and C learn of each other through A's gossip, then meet
+        round(&[&a, &b, &c]);
+        for node in [&a, &b, &c] {
+            pretty_assertions::assert_eq!(node.known_nodes(), 3);
+        }
+
+        // Every node serves slots, so it takes 2 of them to agree C failed
+        for node in [&a, &b] {
+            for (slot, owner) in [(1, &b), (2, &c)] {
+                let state = SlotState::Node(owner.node_id());
+                node.set_slot(slot, &state, || false).unwrap();
+            }
+        }
+        for _ in 0..4 {
+            round(&[&a, &b]);
+        }
+        let health = |node: &Cluster, of: &Cluster| node.nodes.read()[&of.node_id()].health;
+        pretty_assertions::assert_eq!(health(&a, &c), Health::Fail);
+        pretty_assertions::assert_eq!(health(&b, &c), Health::Fail);
+        pretty_assertions::assert_eq!(health(&a, &b), Health::Ok);
+        pretty_assertions::assert_eq!(a.failing_slots(), (0, 1));
+
+        // It's unflagged when it replies again, long enough after
+        std::thread::sleep(TIMEOUT * FAIL_VALIDITY_MULT);
+        round(&[&a, &b, &c]);
+        pretty_assertions::assert_eq!(health(&a, &c), Health::Ok);
+    }
+
+    #[test]
+    fn epoch_collision() {
+        let (a, b) = (node(7000), node(7001));
+        a.meet(([127, 0, 0, 1], 7001).into(), 17001);
+        exchange(&a, &[&b]);
+        exchange(&b, &[&a]);
+        // Both started at epoch 0, so the one with the smallest id moved on
+        pretty_assertions::assert_ne!(a.config_epoch(), b.config_epoch());
+        pretty_assertions::assert_eq!(a.current_epoch(), 1);
+    }
+}
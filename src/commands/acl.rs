@@ -0,0 +1,115 @@
+use anyhow::{bail, ensure, Context};
+
+use crate::{
+    acl::{ACL, CATEGORIES},
+    RedisError, Resp,
+};
+
+use super::{CommandExec, CommandSpec, Ctx, IterResp};
+
+#[derive(Debug)]
+pub enum Acl {
+    SetUser(String, Vec<String>),
+    GetUser(String),
+    DelUser(Vec<String>),
+    List,
+    WhoAmI,
+    Cat(Option<String>),
+}
+
+impl Acl {
+    pub(super) fn parse(mut i: IterResp) -> anyhow::Result<Self> {
+        let Some(arg) = i.next().context("Missing args")?.as_bulk() else {
+            bail!("Expected bulk string");
+        };
+        let sub = arg.to_ascii_lowercase();
+        let res = match sub.as_slice() {
+            b"setuser" => {
+                let name = i
+                    .next()
+                    .with_context(|| arity_err("setuser"))?
+                    .to_string()?;
+                let rules = i
+                    .by_ref()
+                    .map(Resp::to_string)
+                    .collect::<anyhow::Result<_>>()?;
+                Self::SetUser(name, rules)
+            }
+            b"getuser" => Self::GetUser(
+                i.next()
+                    .with_context(|| arity_err("getuser"))?
+                    .to_string()?,
+            ),
+            b"deluser" => {
+                let names = i
+                    .by_ref()
+                    .map(Resp::to_string)
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                ensure!(!names.is_empty(), arity_err("deluser"));
+                Self::DelUser(names)
+            }
+            b"list" => Self::List,
+            b"whoami" => Self::WhoAmI,
+            b"cat" => Self::Cat(i.next().map(Resp::to_string).transpose()?),
+            _ => bail!(RedisError::UnknownSubcommand {
+                command: "ACL",
+                sub: String::from_utf8_lossy(arg).into_owned()
+            }),
+        };
+        ensure!(
+            i.next().is_none(),
+            arity_err(&String::from_utf8_lossy(&sub))
+        );
+        Ok(res)
+    }
+}
+
+impl CommandExec for Acl {
+    fn execute(self, ctx: &mut Ctx<'_>) -> anyhow::Result<Resp> {
+        Ok(match self {
+            Self::SetUser(name, rules) => {
+                ACL.setuser(&name, &rules)?;
+                Resp::simple("OK")
+            }
+            Self::GetUser(name) => {
+                let Some(user) = ACL.getuser(&name) else {
+                    return Ok(Resp::Null);
+                };
+                Resp::Map(vec![
+                    (
+                        Resp::bulk("flags"),
+                        Resp::Array(user.flags().into_iter().map(Resp::bulk).collect()),
+                    ),
+                    (
+                        Resp::bulk("passwords"),
+                        Resp::Array(user.passwords.iter().cloned().map(Resp::bulk).collect()),
+                    ),
+                    (Resp::bulk("commands"), Resp::bulk(user.describe_commands())),
+                    (Resp::bulk("keys"), Resp::bulk(user.describe_keys())),
+                ])
+            }
+            Self::DelUser(names) => Resp::Integer(ACL.deluser(&names)?.try_into()?),
+            Self::List => Resp::Array(ACL.list().into_iter().map(Resp::bulk).collect()),
+            Self::WhoAmI => Resp::bulk(ctx.session.user.clone().unwrap_or_default()),
+            Self::Cat(None) => Resp::Array(CATEGORIES.iter().copied().map(Resp::bulk).collect()),
+            Self::Cat(Some(category)) => {
+                let category = category.to_ascii_lowercase();
+                ensure!(
+                    CATEGORIES.contains(&category.as_str()),
+                    "ERR Unknown category '{category}'"
+                );
+                Resp::Array(
+                    CommandSpec::all()
+                        .iter()
+                        .filter(|spec| spec.categories.contains(&category.as_str()))
+                        .map(|spec| Resp::bulk(spec.name))
+                        .collect(),
+                )
+            }
+        })
+    }
+}
+
+fn arity_err(sub: &str) -> RedisError {
+    RedisError::WrongArity(format!("acl|{sub}"))
+}
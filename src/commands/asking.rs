@@ -0,0 +1,31 @@
+use anyhow::ensure;
+
+use crate::{RedisError, Resp};
+
+use super::{CommandExec, Ctx, IterResp};
+
+/// Lets the next command use the keys of a slot this node is importing,
+/// after a redirection with `-ASK`
+#[derive(Debug)]
+pub struct Asking;
+
+impl Asking {
+    pub(super) fn parse(mut i: IterResp) -> anyhow::Result<Self> {
+        ensure!(
+            i.next().is_none(),
+            RedisError::WrongArity("asking".to_owned())
+        );
+        Ok(Self)
+    }
+}
+
+impl CommandExec for Asking {
+    fn execute(self, ctx: &mut Ctx<'_>) -> anyhow::Result<Resp> {
+        ensure!(
+            ctx.args.cluster_enabled,
+            "ERR This instance has cluster support disabled"
+        );
+        ctx.session.asking = true;
+        Ok(Resp::simple("OK"))
+    }
+}
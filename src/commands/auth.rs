@@ -0,0 +1,51 @@
+use anyhow::{ensure, Context};
+
+use crate::{
+    acl::{Acl, ACL},
+    RedisError, Resp,
+};
+
+use super::{CommandExec, Ctx, IterResp};
+
+#[derive(Debug)]
+pub struct Auth {
+    user: Option<String>,
+    password: String,
+}
+
+impl Auth {
+    pub(super) fn parse(mut i: IterResp) -> anyhow::Result<Self> {
+        let first = i.next().context("Missing password")?.to_string()?;
+        let auth = match i.next() {
+            Some(password) => Self {
+                user: Some(first),
+                password: password.to_string()?,
+            },
+            None => Self {
+                user: None,
+                password: first,
+            },
+        };
+        ensure!(i.next().is_none(), RedisError::Syntax);
+        Ok(auth)
+    }
+}
+
+impl CommandExec for Auth {
+    /// Returns the user the connection is now authenticated as
+    fn execute(self, ctx: &mut Ctx<'_>) -> anyhow::Result<Resp> {
+        if self.user.is_none() {
+            ensure!(
+                ACL.getuser(Acl::DEFAULT_USER).is_none_or(|user| !user.nopass),
+                "ERR AUTH <password> called without any password configured for the default user. Are you sure your configuration is correct?"
+            );
+        }
+        let user = self.user.unwrap_or_else(|| Acl::DEFAULT_USER.to_owned());
+        ensure!(
+            ACL.authenticate(&user, &self.password),
+            RedisError::WrongPass
+        );
+        ctx.session.user = Some(user);
+        Ok(Resp::simple("OK"))
+    }
+}
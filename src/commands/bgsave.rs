@@ -0,0 +1,28 @@
+use anyhow::bail;
+
+use crate::{RedisError, Resp};
+
+use super::{CommandExec, Ctx, IterResp};
+
+#[derive(Debug)]
+pub struct Bgsave;
+
+impl Bgsave {
+    pub(super) fn parse(mut i: IterResp) -> anyhow::Result<Self> {
+        match i.next().and_then(Resp::as_bulk) {
+            // Saves are never delayed, since there's no AOF rewrite to wait for
+            Some(arg) if arg.eq_ignore_ascii_case(b"schedule") => Ok(Self),
+            Some(_) => bail!(RedisError::Syntax),
+            None => Ok(Self),
+        }
+    }
+}
+
+impl CommandExec for Bgsave {
+    #[allow(clippy::unused_self)]
+    fn execute(self, ctx: &mut Ctx<'_>) -> anyhow::Result<Resp> {
+        ctx.db
+            .bgsave(ctx.args.rdb_path(), ctx.args.role.repl_info().as_ref())?;
+        Ok(Resp::simple("Background saving started"))
+    }
+}
@@ -0,0 +1,270 @@
+use anyhow::{bail, ensure, Context};
+
+use crate::{
+    clients::{Unblock, CLIENTS},
+    tracking::{self, Mode, TRACKING},
+    RedisError, Resp,
+};
+
+use super::{CommandExec, Ctx, IterResp};
+
+#[derive(Debug)]
+pub enum Client {
+    Id,
+    SetName(String),
+    GetName,
+    SetInfo(LibAttr, String),
+    List,
+    /// Options to enable tracking with, or `None` to disable it
+    Tracking(Option<tracking::Options>),
+    Caching(bool),
+    GetRedir,
+    NoEvict(bool),
+    Unblock(u64, Unblock),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum LibAttr {
+    Name,
+    Ver,
+}
+
+impl Client {
+    pub(super) fn parse(mut i: IterResp) -> anyhow::Result<Self> {
+        let Some(arg) = i.next().context("Missing args")?.as_bulk() else {
+            bail!("Expected bulk string");
+        };
+        let res = match arg.to_ascii_lowercase().as_slice() {
+            b"id" => Self::Id,
+            b"setname" => {
+                let name = i.next().context("Missing name")?.to_string()?;
+                ensure!(
+                    is_valid(&name),
+                    "ERR Client names cannot contain spaces, newlines or special characters."
+                );
+                Self::SetName(name)
+            }
+            b"getname" => Self::GetName,
+            b"setinfo" => {
+                let attr = i.next().context("Missing attribute")?.to_string()?;
+                let value = i.next().context("Missing value")?.to_string()?;
+                let attr = match attr.to_ascii_lowercase().as_str() {
+                    "lib-name" => LibAttr::Name,
+                    "lib-ver" => LibAttr::Ver,
+                    _ => bail!("ERR Unrecognized option '{attr}'"),
+                };
+                ensure!(
+                    is_valid(&value),
+                    "ERR {} cannot contain spaces, newlines or special characters.",
+                    match attr {
+                        LibAttr::Name => "lib-name",
+                        LibAttr::Ver => "lib-ver",
+                    }
+                );
+                Self::SetInfo(attr, value)
+            }
+            b"list" => Self::List,
+            b"tracking" => Self::Tracking(parse_tracking(&mut i)?),
+            b"caching" => {
+                let arg = i.next().context(RedisError::Syntax)?.to_string()?;
+                match arg.to_ascii_lowercase().as_str() {
+                    "yes" => Self::Caching(true),
+                    "no" => Self::Caching(false),
+                    _ => bail!(RedisError::Syntax),
+                }
+            }
+            b"getredir" => Self::GetRedir,
+            b"no-evict" => {
+                let arg = i.next().context(RedisError::Syntax)?.to_string()?;
+                match arg.to_ascii_lowercase().as_str() {
+                    "on" => Self::NoEvict(true),
+                    "off" => Self::NoEvict(false),
+                    _ => bail!(RedisError::Syntax),
+                }
+            }
+            b"unblock" => {
+                let id = i
+                    .next()
+                    .context(RedisError::WrongArity("client|unblock".to_owned()))?
+                    .to_int::<u64>()
+                    .map_err(|_| RedisError::NotInteger)?;
+                let how = match i.next().map(Resp::to_string).transpose()? {
+                    None => Unblock::Timeout,
+                    Some(how) if how.eq_ignore_ascii_case("timeout") => Unblock::Timeout,
+                    Some(how) if how.eq_ignore_ascii_case("error") => Unblock::Error,
+                    Some(_) => bail!("ERR CLIENT UNBLOCK reason should be TIMEOUT or ERROR"),
+                };
+                Self::Unblock(id, how)
+            }
+            _ => bail!(RedisError::UnknownSubcommand {
+                command: "CLIENT",
+                sub: String::from_utf8_lossy(arg).into_owned()
+            }),
+        };
+        ensure!(i.next().is_none(), RedisError::Syntax);
+        Ok(res)
+    }
+}
+
+impl CommandExec for Client {
+    fn execute(self, ctx: &mut Ctx<'_>) -> anyhow::Result<Resp> {
+        let resp = match self {
+            Self::Id => Resp::Integer(ctx.session.id.try_into()?),
+            Self::SetName(name) => {
+                CLIENTS.with(ctx.session.id, |client| {
+                    client.name = Some(name).filter(|name| !name.is_empty());
+                });
+                Resp::simple("OK")
+            }
+            Self::GetName => CLIENTS
+                .with(ctx.session.id, |client| client.name.clone())
+                .flatten()
+                .map_or(Resp::Null, Resp::bulk),
+            Self::SetInfo(attr, value) => {
+                CLIENTS.with(ctx.session.id, |client| {
+                    let value = Some(value).filter(|value| !value.is_empty());
+                    match attr {
+                        LibAttr::Name => client.lib_name = value,
+                        LibAttr::Ver => client.lib_ver = value,
+                    }
+                });
+                Resp::simple("OK")
+            }
+            Self::List => Resp::bulk(CLIENTS.list()),
+            Self::Tracking(None) => {
+                TRACKING.disable(ctx.session.id);
+                Resp::simple("OK")
+            }
+            Self::Tracking(Some(options)) => {
+                enable_tracking(ctx, options)?;
+                Resp::simple("OK")
+            }
+            Self::Caching(caching) => {
+                let mode = TRACKING.options(ctx.session.id).map(|options| options.mode);
+                match mode {
+                    Some(Mode::OptIn) if caching => {}
+                    Some(Mode::OptOut) if !caching => {}
+                    Some(Mode::OptIn | Mode::OptOut) => bail!(
+                        "ERR CLIENT CACHING {} is only valid when tracking is enabled in {} mode.",
+                        if caching { "YES" } else { "NO" },
+                        if caching { "OPTIN" } else { "OPTOUT" },
+                    ),
+                    _ => bail!("ERR CLIENT CACHING can be called only when the client is in tracking mode with OPTIN or OPTOUT mode enabled"),
+                }
+                ctx.session.caching = Some(caching);
+                Resp::simple("OK")
+            }
+            Self::GetRedir => {
+                let redirect = TRACKING.options(ctx.session.id).map_or(-1, |options| {
+                    options
+                        .redirect
+                        .map_or(0, |id| id.try_into().unwrap_or(i64::MAX))
+                });
+                Resp::Integer(redirect)
+            }
+            Self::NoEvict(no_evict) => {
+                CLIENTS.with(ctx.session.id, |client| client.no_evict = no_evict);
+                Resp::simple("OK")
+            }
+            Self::Unblock(id, how) => Resp::Integer(CLIENTS.unblock(id, how).into()),
+        };
+        Ok(resp)
+    }
+}
+
+/// `ON` or `OFF`, then the options of `CLIENT TRACKING`
+fn parse_tracking(i: &mut IterResp) -> anyhow::Result<Option<tracking::Options>> {
+    let toggle = i.next().context(RedisError::Syntax)?.to_string()?;
+    let on = match toggle.to_ascii_lowercase().as_str() {
+        "on" => true,
+        "off" => false,
+        _ => bail!(RedisError::Syntax),
+    };
+    let mut options = tracking::Options::default();
+    let (mut bcast, mut optin, mut optout) = (false, false, false);
+    let mut prefixes = Vec::new();
+    while let Some(arg) = i.next() {
+        match arg.to_string()?.to_ascii_lowercase().as_str() {
+            "redirect" => {
+                let id = i.next().context(RedisError::Syntax)?;
+                let id = id.to_int::<u64>().ok().context("ERR Invalid client ID")?;
+                options.redirect = Some(id);
+            }
+            "prefix" => prefixes.push(i.next().context(RedisError::Syntax)?.to_bytes()?),
+            "bcast" => bcast = true,
+            "optin" => optin = true,
+            "optout" => optout = true,
+            "noloop" => options.noloop = true,
+            _ => bail!(RedisError::Syntax),
+        }
+    }
+    if !on {
+        return Ok(None);
+    }
+    ensure!(
+        bcast || prefixes.is_empty(),
+        "ERR PREFIX option requires BCAST mode to be enabled"
+    );
+    ensure!(
+        !(optin && optout),
+        "ERR You can't use OPTIN and OPTOUT at the same time"
+    );
+    ensure!(
+        !(bcast && (optin || optout)),
+        "ERR OPTIN and OPTOUT are not compatible with BCAST"
+    );
+    for (n, prefix) in prefixes.iter().enumerate() {
+        if let Some(other) = prefixes[n + 1..]
+            .iter()
+            .find(|other| prefix.starts_with(other) || other.starts_with(prefix))
+        {
+            bail!(
+                "ERR Prefix '{}' overlaps with another provided prefix '{}'. Prefixes for a single client must not overlap.",
+                String::from_utf8_lossy(other),
+                String::from_utf8_lossy(prefix),
+            );
+        }
+    }
+    options.mode = if bcast {
+        Mode::Bcast(prefixes)
+    } else if optin {
+        Mode::OptIn
+    } else if optout {
+        Mode::OptOut
+    } else {
+        Mode::Default
+    };
+    Ok(Some(options))
+}
+
+fn enable_tracking(ctx: &Ctx<'_>, options: tracking::Options) -> anyhow::Result<()> {
+    if let Some(redirect) = options.redirect {
+        ensure!(
+            redirect != ctx.session.id,
+            "ERR A client can't redirect to itself"
+        );
+        ensure!(
+            CLIENTS.with(redirect, |_| ()).is_some(),
+            "ERR The client ID you want redirect to does not exist"
+        );
+    }
+    let bcast = |options: &tracking::Options| matches!(options.mode, Mode::Bcast(_));
+    ensure!(
+        TRACKING
+            .options(ctx.session.id)
+            .is_none_or(|previous| bcast(&previous) == bcast(&options)),
+        "ERR You can't switch BCAST mode on/off before disabling tracking for this client, and then re-enabling it with a different mode."
+    );
+    let tx = ctx
+        .session
+        .pushes
+        .clone()
+        .context("ERR Tracking requires a client connection")?;
+    TRACKING.enable(ctx.session.id, options, tx);
+    Ok(())
+}
+
+/// Names and lib info may only use printable characters other than space
+pub(super) fn is_valid(value: &str) -> bool {
+    value.bytes().all(|b| (b'!'..=b'~').contains(&b))
+}
@@ -0,0 +1,276 @@
+use anyhow::{anyhow, bail, ensure, Context};
+use bytes::Bytes;
+use std::{
+    collections::HashSet,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+};
+
+use crate::{
+    cluster::{self, bus::PORT_OFFSET, SlotState, CLUSTER},
+    RedisError, Resp, Role,
+};
+
+use super::{CommandExec, Ctx, IterResp};
+
+#[derive(Debug)]
+pub enum Cluster {
+    Info,
+    MyId,
+    Nodes,
+    Slots,
+    Shards,
+    KeySlot(Bytes),
+    SetSlot { slot: u16, state: SlotState },
+    Meet { addr: SocketAddr, bus_port: u16 },
+}
+
+impl Cluster {
+    pub(super) fn parse(mut i: IterResp) -> anyhow::Result<Self> {
+        let Some(arg) = i.next().context("Missing args")?.as_bulk() else {
+            bail!("Expected bulk string");
+        };
+        let sub = arg.to_ascii_lowercase();
+        let res = match sub.as_slice() {
+            b"info" => Self::Info,
+            b"myid" => Self::MyId,
+            b"nodes" => Self::Nodes,
+            b"slots" => Self::Slots,
+            b"shards" => Self::Shards,
+            b"keyslot" => Self::KeySlot(
+                i.next()
+                    .with_context(|| RedisError::WrongArity("cluster|keyslot".to_owned()))?
+                    .to_bytes()?,
+            ),
+            b"setslot" => Self::parse_setslot(&mut i)?,
+            b"meet" => Self::parse_meet(&mut i)?,
+            _ => bail!(RedisError::UnknownSubcommand {
+                command: "CLUSTER",
+                sub: String::from_utf8_lossy(arg).into_owned()
+            }),
+        };
+        ensure!(
+            i.next().is_none(),
+            RedisError::WrongArity(format!("cluster|{}", String::from_utf8_lossy(&sub)))
+        );
+        Ok(res)
+    }
+
+    /// `SETSLOT <slot> IMPORTING|MIGRATING|NODE <node-id>` or `SETSLOT <slot> STABLE`
+    fn parse_setslot(i: &mut IterResp) -> anyhow::Result<Self> {
+        let arity = || RedisError::WrongArity("cluster|setslot".to_owned());
+        let slot = i
+            .next()
+            .with_context(arity)?
+            .to_int::<u16>()
+            .map_err(|_| RedisError::InvalidSlot)?;
+        let action = i.next().with_context(arity)?.to_string()?;
+        let mut node_id =
+            || -> anyhow::Result<String> { i.next().with_context(arity)?.to_string() };
+        let state = match action.to_ascii_lowercase().as_str() {
+            "migrating" => SlotState::Migrating(node_id()?),
+            "importing" => SlotState::Importing(node_id()?),
+            "node" => SlotState::Node(node_id()?),
+            "stable" => SlotState::Stable,
+            _ => {
+                bail!("ERR Invalid CLUSTER SETSLOT action or number of arguments. Try CLUSTER HELP")
+            }
+        };
+        Ok(Self::SetSlot { slot, state })
+    }
+
+    /// `MEET <ip> <port> [<cluster-bus-port>]`
+    fn parse_meet(i: &mut IterResp) -> anyhow::Result<Self> {
+        let arity = || RedisError::WrongArity("cluster|meet".to_owned());
+        let ip = i.next().with_context(arity)?.to_string()?;
+        let port = i.next().with_context(arity)?.to_string()?;
+        let port = port
+            .parse::<u16>()
+            .map_err(|_| anyhow!("ERR Invalid base port specified: {port}"))?;
+        let bus_port = match i.next() {
+            Some(bus_port) => {
+                let bus_port = bus_port.to_string()?;
+                bus_port
+                    .parse::<u16>()
+                    .map_err(|_| anyhow!("ERR Invalid bus port specified: {bus_port}"))?
+            }
+            None => port
+                .checked_add(PORT_OFFSET)
+                .with_context(|| format!("ERR Invalid base port specified: {port}"))?,
+        };
+        let ip = ip
+            .parse::<IpAddr>()
+            .map_err(|_| anyhow!("ERR Invalid node address specified: {ip}:{port}"))?;
+        Ok(Self::Meet {
+            addr: (ip, port).into(),
+            bus_port,
+        })
+    }
+}
+
+impl CommandExec for Cluster {
+    fn execute(self, ctx: &mut Ctx<'_>) -> anyhow::Result<Resp> {
+        // Announced with the address the client connected to
+        let local = ctx.session.local_addr.unwrap_or_else(|| {
+            let ip = ctx.args.bind.first().copied();
+            (ip.unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST)), ctx.args.port).into()
+        });
+        let (ip, port) = (local.ip().to_string(), local.port());
+        let ranges = || Served::ranges(&ip, port);
+        Ok(match self {
+            Self::Info => {
+                let slots = cluster::SLOTS;
+                let known = CLUSTER.known_nodes();
+                let ranges = ranges();
+                let size = ranges.iter().map(|range| &range.id).collect::<HashSet<_>>();
+                let (pfail, fail) = CLUSTER.failing_slots();
+                Resp::bulk(format!(
+                    "cluster_state:{}\r\n\
+                     cluster_slots_assigned:{slots}\r\n\
+                     cluster_slots_ok:{}\r\n\
+                     cluster_slots_pfail:{pfail}\r\n\
+                     cluster_slots_fail:{fail}\r\n\
+                     cluster_known_nodes:{known}\r\n\
+                     cluster_size:{}\r\n\
+                     cluster_current_epoch:{}\r\n\
+                     cluster_my_epoch:{}\r\n",
+                    if fail == 0 { "ok" } else { "fail" },
+                    usize::from(slots) - pfail - fail,
+                    size.len(),
+                    CLUSTER.current_epoch(),
+                    CLUSTER.config_epoch(),
+                ))
+            }
+            Self::MyId => Resp::bulk(CLUSTER.node_id()),
+            Self::Nodes => Resp::bulk(CLUSTER.describe_nodes(local.ip())),
+            Self::Slots => Resp::Array(ranges().into_iter().map(Served::into_slots).collect()),
+            Self::Shards => {
+                let (role, offset) = match &ctx.args.role {
+                    Role::Master(master) => ("master", master.repl_offset()),
+                    Role::Slave(slave) => ("replica", slave.offset()),
+                };
+                let me = shard_node(&CLUSTER.node_id(), &ip, port, role, offset);
+                shards(ranges(), me)
+            }
+            Self::KeySlot(key) => Resp::Integer(cluster::key_slot(&key).into()),
+            Self::SetSlot { slot, state } => {
+                ensure!(
+                    ctx.args.cluster_enabled,
+                    "ERR This instance has cluster support disabled"
+                );
+                let has_keys = || {
+                    let map = ctx.db.inner.read();
+                    map.keys()
+                        .any(|key| cluster::key_slot(key.as_bytes()) == slot)
+                };
+                CLUSTER.set_slot(slot, &state, has_keys)?;
+                Resp::simple("OK")
+            }
+            Self::Meet { addr, bus_port } => {
+                ensure!(
+                    ctx.args.cluster_enabled,
+                    "ERR This instance has cluster support disabled"
+                );
+                CLUSTER.meet(addr, bus_port);
+                Resp::simple("OK")
+            }
+        })
+    }
+}
+
+/// Slots served by a node, with where clients reach it
+struct Served {
+    start: u16,
+    end: u16,
+    id: String,
+    ip: String,
+    port: u16,
+}
+
+impl Served {
+    /// Every slot, this node being reached at `ip` and `port`
+    fn ranges(ip: &str, port: u16) -> Vec<Self> {
+        CLUSTER
+            .slot_ranges()
+            .into_iter()
+            .map(|(start, end, owner)| {
+                let (id, ip, port) = owner.map_or_else(
+                    || (CLUSTER.node_id(), ip.to_owned(), port),
+                    |node| {
+                        (
+                            node.id.clone(),
+                            node.addr.ip().to_string(),
+                            node.addr.port(),
+                        )
+                    },
+                );
+                Self {
+                    start,
+                    end,
+                    id,
+                    ip,
+                    port,
+                }
+            })
+            .collect()
+    }
+
+    /// An entry of `CLUSTER SLOTS`
+    fn into_slots(self) -> Resp {
+        Resp::Array(vec![
+            Resp::Integer(self.start.into()),
+            Resp::Integer(self.end.into()),
+            Resp::Array(vec![
+                Resp::bulk(self.ip),
+                Resp::Integer(self.port.into()),
+                Resp::bulk(self.id),
+                Resp::Map(Vec::new()),
+            ]),
+        ])
+    }
+}
+
+/// `CLUSTER SHARDS`: this node first, described by `me`, then each other node serving slots
+fn shards(ranges: Vec<Served>, me: Resp) -> Resp {
+    let mut shards = vec![(CLUSTER.node_id(), Vec::new(), me)];
+    for range in ranges {
+        let at = shards
+            .iter()
+            .position(|(id, ..)| *id == range.id)
+            .unwrap_or_else(|| {
+                let node = shard_node(&range.id, &range.ip, range.port, "master", 0);
+                shards.push((range.id.clone(), Vec::new(), node));
+                shards.len() - 1
+            });
+        shards[at].1.extend([
+            Resp::Integer(range.start.into()),
+            Resp::Integer(range.end.into()),
+        ]);
+    }
+    Resp::Array(
+        shards
+            .into_iter()
+            .map(|(_, slots, node)| {
+                Resp::Map(vec![
+                    (Resp::bulk("slots"), Resp::Array(slots)),
+                    (Resp::bulk("nodes"), Resp::Array(vec![node])),
+                ])
+            })
+            .collect(),
+    )
+}
+
+/// A node of `CLUSTER SHARDS`
+fn shard_node(id: &str, ip: &str, port: u16, role: &str, offset: u64) -> Resp {
+    Resp::Map(vec![
+        (Resp::bulk("id"), Resp::bulk(id.to_owned())),
+        (Resp::bulk("port"), Resp::Integer(port.into())),
+        (Resp::bulk("ip"), Resp::bulk(ip.to_owned())),
+        (Resp::bulk("endpoint"), Resp::bulk(ip.to_owned())),
+        (Resp::bulk("role"), Resp::bulk(role.to_owned())),
+        (
+            Resp::bulk("replication-offset"),
+            Resp::Integer(offset.try_into().unwrap_or(i64::MAX)),
+        ),
+        (Resp::bulk("health"), Resp::bulk("online")),
+    ])
+}
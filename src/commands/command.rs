@@ -0,0 +1,176 @@
+use anyhow::{bail, ensure};
+
+use crate::{RedisError, Resp};
+
+use super::{table::KeySpec, CommandExec, CommandSpec, Ctx, IterResp};
+
+/// `COMMAND`, describing the commands of the table
+#[derive(Debug)]
+pub enum Introspect {
+    /// Details of the given commands, or of every command
+    Info(Option<Vec<String>>),
+    Count,
+    List,
+    /// Keys of a command, with its name first
+    GetKeys(Vec<Resp>),
+}
+
+impl Introspect {
+    pub(super) fn parse(mut i: IterResp) -> anyhow::Result<Self> {
+        let Some(arg) = i.next() else {
+            return Ok(Self::Info(None));
+        };
+        let Some(arg) = arg.as_bulk() else {
+            bail!("Expected bulk string");
+        };
+        let sub = arg.to_ascii_lowercase();
+        let res = match sub.as_slice() {
+            b"info" => Self::Info(Some(i.map(Resp::to_string).collect::<Result<_, _>>()?)),
+            b"count" => Self::Count,
+            b"list" => Self::List,
+            b"getkeys" => {
+                let args = i.cloned().collect::<Vec<_>>();
+                ensure!(
+                    !args.is_empty(),
+                    RedisError::WrongArity("command|getkeys".to_owned())
+                );
+                Self::GetKeys(args)
+            }
+            _ => bail!(RedisError::UnknownSubcommand {
+                command: "COMMAND",
+                sub: String::from_utf8_lossy(arg).into_owned()
+            }),
+        };
+        Ok(res)
+    }
+}
+
+impl CommandExec for Introspect {
+    fn execute(self, _: &mut Ctx<'_>) -> anyhow::Result<Resp> {
+        Ok(match self {
+            Self::Info(None) => Resp::Array(CommandSpec::all().iter().map(info).collect()),
+            Self::Info(Some(names)) => Resp::Array(
+                names
+                    .iter()
+                    .map(|name| CommandSpec::lookup(name.as_bytes()).map_or(Resp::Null, info))
+                    .collect(),
+            ),
+            Self::Count => Resp::Integer(CommandSpec::all().len().try_into()?),
+            Self::List => Resp::Array(
+                CommandSpec::all()
+                    .iter()
+                    .map(|spec| Resp::bulk(spec.name))
+                    .collect(),
+            ),
+            Self::GetKeys(args) => {
+                let spec = args[0]
+                    .as_bulk()
+                    .and_then(|name| CommandSpec::lookup(name))
+                    .ok_or(RedisError::InvalidCommand)?;
+                ensure!(
+                    spec.check_arity(args.len()),
+                    RedisError::InvalidCommandArity
+                );
+                let keys = spec.keys.keys(&args);
+                ensure!(!keys.is_empty(), RedisError::NoKeyArguments);
+                Resp::Array(keys.into_iter().cloned().collect())
+            }
+        })
+    }
+}
+
+/// `COMMAND INFO` entry: name, arity, flags, first key, last key,
+/// key step, ACL categories, then tips, key specs and subcommands
+fn info(spec: &CommandSpec) -> Resp {
+    let (first, last, step) = spec.keys.legacy();
+    let mut flags = spec
+        .flags
+        .iter()
+        .copied()
+        .map(Resp::simple)
+        .collect::<Vec<_>>();
+    if matches!(spec.keys, KeySpec::Streams | KeySpec::Keynum { .. }) {
+        flags.push(Resp::simple("movablekeys"));
+    }
+    Resp::Array(vec![
+        Resp::bulk(spec.name),
+        Resp::Integer(spec.arity.into()),
+        Resp::Array(flags),
+        Resp::Integer(first),
+        Resp::Integer(last),
+        Resp::Integer(step),
+        Resp::Array(
+            spec.categories
+                .iter()
+                .map(|category| Resp::simple(format!("@{category}")))
+                .collect(),
+        ),
+        Resp::Array(Vec::new()),
+        Resp::Array(Vec::new()),
+        Resp::Array(Vec::new()),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testutil::{master, Client};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn getkeys() {
+        let server = master().await;
+        let mut client = Client::connect(&server).await;
+        let getkeys = ["COMMAND", "GETKEYS"];
+
+        pretty_assertions::assert_eq!(
+            client
+                .cmd(&[&getkeys[..], &["SET", "k", "v", "EX", "10"]].concat())
+                .await,
+            Resp::Array(vec![Resp::bulk("k")])
+        );
+        pretty_assertions::assert_eq!(
+            client
+                .cmd(
+                    &[
+                        &getkeys[..],
+                        &["XREAD", "COUNT", "1", "STREAMS", "a", "b", "0", "0"]
+                    ]
+                    .concat()
+                )
+                .await,
+            Resp::Array(vec![Resp::bulk("a"), Resp::bulk("b")])
+        );
+        pretty_assertions::assert_eq!(
+            client.cmd(&[&getkeys[..], &["PING"]].concat()).await,
+            RedisError::NoKeyArguments.into()
+        );
+        pretty_assertions::assert_eq!(
+            client.cmd(&[&getkeys[..], &["GET"]].concat()).await,
+            RedisError::InvalidCommandArity.into()
+        );
+        pretty_assertions::assert_eq!(
+            client.cmd(&[&getkeys[..], &["NOPE", "k"]].concat()).await,
+            RedisError::InvalidCommand.into()
+        );
+
+        let Resp::Array(info) = client.cmd(&["COMMAND", "INFO", "del", "nope"]).await else {
+            panic!("Expected an array");
+        };
+        let Resp::Array(del) = &info[0] else {
+            panic!("Expected an array");
+        };
+        pretty_assertions::assert_eq!(
+            del[..6],
+            [
+                Resp::bulk("del"),
+                Resp::Integer(-2),
+                Resp::Array(vec![Resp::simple("write")]),
+                Resp::Integer(1),
+                Resp::Integer(-1),
+                Resp::Integer(1),
+            ]
+        );
+        pretty_assertions::assert_eq!(info[1], Resp::Null);
+    }
+}
@@ -1,14 +1,24 @@
-use anyhow::{bail, Context};
+use anyhow::{bail, ensure, Context};
 use bytes::Bytes;
+use std::path::Path;
 
-use crate::{Resp, ARGUMENTS};
+use crate::{
+    aof::Fsync,
+    args::{parse_config, parse_memory, parse_yes_no},
+    clients::CLIENTS,
+    cluster::CLUSTER,
+    db::persistence::SavePoints,
+    glob::glob_match_nocase,
+    pubsub::Overflow,
+    Arguments, Db, RedisError, Resp, Role, ACL, AOF, PUBSUB,
+};
 
-use super::IterResp;
+use super::{CommandExec, Ctx, IterResp};
 
 #[derive(Debug)]
 pub enum Config {
     Get(Vec<Bytes>),
-    // TODO
+    Set(Vec<(Bytes, Bytes)>),
 }
 
 impl Config {
@@ -17,36 +27,516 @@ impl Config {
             bail!("Expected bulk string");
         };
         Ok(match arg.to_ascii_lowercase().as_slice() {
-            b"get" => Self::Get(i.filter_map(Resp::as_bulk).map(Bytes::clone).collect()),
-            _ => todo!("{arg:?}"),
+            b"get" => {
+                let patterns = i
+                    .filter_map(Resp::as_bulk)
+                    .map(Bytes::clone)
+                    .collect::<Vec<_>>();
+                if patterns.is_empty() {
+                    bail!(RedisError::WrongArity("config|get".to_owned()));
+                }
+                Self::Get(patterns)
+            }
+            b"set" => {
+                let args = i.map(Resp::to_bytes).collect::<anyhow::Result<Vec<_>>>()?;
+                if args.is_empty() || !args.len().is_multiple_of(2) {
+                    bail!(RedisError::WrongArity("config|set".to_owned()));
+                }
+                Self::Set(
+                    args.chunks_exact(2)
+                        .map(|x| (x[0].clone(), x[1].clone()))
+                        .collect(),
+                )
+            }
+            _ => bail!(RedisError::UnknownSubcommand {
+                command: "CONFIG",
+                sub: String::from_utf8_lossy(arg).into_owned()
+            }),
         })
     }
 
-    pub fn execute(&self) -> Resp {
-        match self {
-            Self::Get(params) => Self::handle_get(params),
+    /// Validates every parameter before applying any of them
+    fn handle_set(params: &[(Bytes, Bytes)], db: &Db) -> anyhow::Result<Resp> {
+        let mut applies = Vec::with_capacity(params.len());
+        for (name, value) in params {
+            let Some(param) = Param::lookup(name) else {
+                bail!(
+                    "ERR Unknown option or number of arguments for CONFIG SET - '{}'",
+                    String::from_utf8_lossy(name)
+                );
+            };
+            let failed = |e| {
+                anyhow::anyhow!(
+                    "ERR CONFIG SET failed (possibly related to argument '{}') - {e}",
+                    param.name
+                )
+            };
+            let set = param
+                .set
+                .ok_or_else(|| failed("can't set immutable config".to_owned()))?;
+            let value = std::str::from_utf8(value)?;
+            applies.push(set(value).map_err(|e| failed(e.to_string()))?);
         }
+        for apply in applies {
+            apply(db);
+        }
+        Ok(Resp::simple("OK"))
     }
 
-    fn handle_get(params: &[Bytes]) -> Resp {
-        let v = params.iter().fold(Vec::new(), |mut acc, param| {
-            match param.to_ascii_lowercase().as_slice() {
-                b"dir" => {
-                    if let Some(dir) = &ARGUMENTS.dir {
-                        acc.push(Resp::Bulk(param.clone()));
-                        acc.push(Resp::bulk(dir.as_os_str().as_encoded_bytes()));
-                    }
-                }
-                b"dbfilename" => {
-                    if let Some(dbfilename) = &ARGUMENTS.db_filename {
-                        acc.push(Resp::Bulk(param.clone()));
-                        acc.push(Resp::bulk(dbfilename.as_os_str().as_encoded_bytes()));
-                    }
+    /// Re-reads the config file at `path`, applying the parameters that can change
+    /// at runtime like `CONFIG SET` does, all of them or none if one is invalid.
+    /// Returns the directives that changed a parameter only a restart applies.
+    pub(crate) fn reload(path: &Path, args: &Arguments, db: &Db) -> anyhow::Result<Vec<String>> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Can't open config file '{}'", path.display()))?;
+        let mut mutable = Vec::new();
+        let mut restart = Vec::new();
+        for (name, value) in parse_config(&contents)? {
+            match Param::lookup(name.as_bytes()) {
+                Some(param) if param.set.is_some() => mutable.push((name.into(), value.into())),
+                Some(param) if !(param.get)(args, db).eq_ignore_ascii_case(value.as_bytes()) => {
+                    restart.push(name);
                 }
-                _ => todo!("{param:?}"),
+                Some(_) => {}
+                None => tracing::warn!("Ignoring unsupported config directive '{name}'"),
             }
-            acc
-        });
-        Resp::Array(v)
+        }
+        Self::handle_set(&mutable, db)?;
+        Ok(restart)
+    }
+
+    fn handle_get(patterns: &[Bytes], args: &Arguments, db: &Db) -> Resp {
+        Resp::Map(
+            Param::matching(patterns)
+                .map(|param| (Resp::bulk(param.name), Resp::Bulk((param.get)(args, db))))
+                .collect(),
+        )
+    }
+}
+
+impl CommandExec for Config {
+    fn execute(self, ctx: &mut Ctx<'_>) -> anyhow::Result<Resp> {
+        match self {
+            Self::Get(patterns) => Ok(Self::handle_get(&patterns, ctx.args, ctx.db)),
+            Self::Set(params) => Self::handle_set(&params, ctx.db),
+        }
+    }
+}
+
+/// Applies a value validated by a [`Param`] setter
+type Apply = Box<dyn FnOnce(&Db)>;
+
+/// A server parameter, which can be changed at runtime if it has a setter
+struct Param {
+    name: &'static str,
+    get: fn(&Arguments, &Db) -> Bytes,
+    set: Option<fn(&str) -> anyhow::Result<Apply>>,
+}
+
+impl Param {
+    fn lookup(name: &[u8]) -> Option<&'static Self> {
+        PARAMS
+            .iter()
+            .find(|param| param.name.as_bytes().eq_ignore_ascii_case(name))
+    }
+
+    /// Parameters matching any of the glob patterns, each listed once
+    fn matching(patterns: &[Bytes]) -> impl Iterator<Item = &'static Self> + '_ {
+        PARAMS.iter().filter(move |param| {
+            patterns
+                .iter()
+                .any(|pattern| glob_match_nocase(pattern, param.name))
+        })
+    }
+}
+
+const fn yes_no(enabled: bool) -> &'static str {
+    if enabled {
+        "yes"
+    } else {
+        "no"
+    }
+}
+
+const PARAMS: &[Param] = &[
+    Param {
+        name: "port",
+        get: |args, _| args.port.to_string().into(),
+        set: None,
+    },
+    Param {
+        name: "replicaof",
+        get: |args, _| match &args.role {
+            Role::Master(_) => Bytes::new(),
+            Role::Slave(slave) => format!("{} {}", slave.addr.ip(), slave.addr.port()).into(),
+        },
+        set: None,
+    },
+    Param {
+        name: "dir",
+        get: |args, _| {
+            let dir = args.dir.clone().or_else(|| std::env::current_dir().ok());
+            dir.map(|dir| Bytes::copy_from_slice(dir.as_os_str().as_encoded_bytes()))
+                .unwrap_or_default()
+        },
+        set: None,
+    },
+    Param {
+        name: "dbfilename",
+        get: |args, _| {
+            let path = args.rdb_path();
+            let name = path.file_name().unwrap_or_default();
+            Bytes::copy_from_slice(name.as_encoded_bytes())
+        },
+        set: None,
+    },
+    Param {
+        name: "proto-max-bulk-len",
+        get: |args, _| args.proto_max_bulk_len.to_string().into(),
+        set: None,
+    },
+    Param {
+        name: "repl-diskless-sync",
+        get: |args, _| yes_no(args.repl_diskless_sync).into(),
+        set: None,
+    },
+    Param {
+        name: "repl-ping-replica-period",
+        get: |args, _| args.repl_ping_replica_period.as_secs().to_string().into(),
+        set: None,
+    },
+    Param {
+        name: "repl-timeout",
+        get: |args, _| args.repl_timeout.as_secs().to_string().into(),
+        set: None,
+    },
+    Param {
+        name: "save",
+        get: |_, db| db.persistence.save_points().to_string().into(),
+        set: Some(|value| {
+            let points = value.parse::<SavePoints>()?;
+            Ok(Box::new(move |db: &Db| {
+                db.persistence.set_save_points(points);
+            }))
+        }),
+    },
+    Param {
+        name: "rdbchecksum",
+        get: |_, db| yes_no(db.persistence.rdbchecksum()).into(),
+        set: Some(|value| {
+            let enabled = parse_yes_no(value).map_err(anyhow::Error::msg)?;
+            Ok(Box::new(move |db: &Db| {
+                db.persistence.set_rdbchecksum(enabled);
+            }))
+        }),
+    },
+    Param {
+        name: "appendonly",
+        get: |_, _| yes_no(AOF.enabled()).into(),
+        set: None,
+    },
+    Param {
+        name: "appendfsync",
+        get: |_, _| AOF.fsync().to_string().into(),
+        set: Some(|value| {
+            let fsync = value.parse::<Fsync>()?;
+            Ok(Box::new(move |_: &Db| AOF.set_fsync(fsync)))
+        }),
+    },
+    Param {
+        name: "appendfilename",
+        get: |args, _| Bytes::copy_from_slice(args.appendfilename.as_os_str().as_encoded_bytes()),
+        set: None,
+    },
+    Param {
+        name: "aof-load-truncated",
+        get: |args, _| yes_no(args.aof_load_truncated).into(),
+        set: None,
+    },
+    Param {
+        name: "bind",
+        get: |args, _| {
+            let addrs = args.bind.iter().map(ToString::to_string);
+            addrs.collect::<Vec<_>>().join(" ").into()
+        },
+        set: None,
+    },
+    Param {
+        name: "tcp-keepalive",
+        get: |args, _| args.tcp_keepalive.as_secs().to_string().into(),
+        set: None,
+    },
+    Param {
+        name: "tcp-nodelay",
+        get: |args, _| yes_no(args.tcp_nodelay).into(),
+        set: None,
+    },
+    #[cfg(feature = "scripting")]
+    Param {
+        name: "busy-reply-threshold",
+        get: |_, db| db.busy.threshold().as_millis().to_string().into(),
+        set: Some(|value| {
+            let threshold = std::time::Duration::from_millis(value.parse::<u64>()?);
+            Ok(Box::new(move |db: &Db| db.busy.set_threshold(threshold)))
+        }),
+    },
+    Param {
+        name: "protected-mode",
+        get: |_, _| yes_no(CLIENTS.protected_mode()).into(),
+        set: Some(|value| {
+            let enabled = parse_yes_no(value).map_err(anyhow::Error::msg)?;
+            Ok(Box::new(move |_: &Db| CLIENTS.set_protected_mode(enabled)))
+        }),
+    },
+    Param {
+        name: "maxmemory-clients",
+        get: |_, _| CLIENTS.maxmemory_clients().to_string().into(),
+        set: Some(|value| {
+            let limit = parse_memory(value).map_err(anyhow::Error::msg)?;
+            Ok(Box::new(move |_: &Db| CLIENTS.set_maxmemory_clients(limit)))
+        }),
+    },
+    Param {
+        name: "pubsub-queue-len",
+        get: |_, _| PUBSUB.queue_len().to_string().into(),
+        set: Some(|value| {
+            let len = value.parse::<usize>()?;
+            Ok(Box::new(move |_: &Db| PUBSUB.set_queue_len(len)))
+        }),
+    },
+    Param {
+        name: "pubsub-overflow",
+        get: |_, _| PUBSUB.overflow().to_string().into(),
+        set: Some(|value| {
+            let overflow = value.parse::<Overflow>()?;
+            Ok(Box::new(move |_: &Db| PUBSUB.set_overflow(overflow)))
+        }),
+    },
+    Param {
+        name: "cluster-enabled",
+        get: |args, _| yes_no(args.cluster_enabled).into(),
+        set: None,
+    },
+    Param {
+        name: "cluster-config-file",
+        get: |args, _| {
+            Bytes::copy_from_slice(args.cluster_config_file.as_os_str().as_encoded_bytes())
+        },
+        set: None,
+    },
+    Param {
+        name: "cluster-node-timeout",
+        get: |_, _| CLUSTER.node_timeout().as_millis().to_string().into(),
+        set: Some(|value| {
+            let timeout = std::time::Duration::from_millis(value.parse::<u64>()?);
+            Ok(Box::new(move |_: &Db| CLUSTER.set_node_timeout(timeout)))
+        }),
+    },
+    Param {
+        name: "requirepass",
+        get: |_, _| ACL.requirepass().into(),
+        set: Some(|value| {
+            let password = value.to_owned();
+            Ok(Box::new(move |_: &Db| ACL.set_requirepass(&password)))
+        }),
+    },
+    Param {
+        name: "logfile",
+        get: |args, _| {
+            args.logfile
+                .as_ref()
+                .map(|path| Bytes::copy_from_slice(path.as_os_str().as_encoded_bytes()))
+                .unwrap_or_default()
+        },
+        set: None,
+    },
+    Param {
+        name: "loglevel",
+        get: |args, _| args.loglevel.to_string().into(),
+        set: None,
+    },
+    Param {
+        name: "audit-log",
+        get: |args, _| {
+            args.audit_log
+                .as_ref()
+                .map(|path| Bytes::copy_from_slice(path.as_os_str().as_encoded_bytes()))
+                .unwrap_or_default()
+        },
+        set: None,
+    },
+    Param {
+        name: "audit-log-max-size",
+        get: |args, _| args.audit_log_max_size.to_string().into(),
+        set: None,
+    },
+    Param {
+        name: "audit-log-max-files",
+        get: |args, _| args.audit_log_max_files.to_string().into(),
+        set: None,
+    },
+    Param {
+        name: "hash-max-listpack-entries",
+        get: |_, db| {
+            db.encoding
+                .read()
+                .hash_max_listpack_entries
+                .to_string()
+                .into()
+        },
+        set: Some(|value| {
+            let limit = value.parse::<usize>()?;
+            Ok(Box::new(move |db: &Db| {
+                db.encoding.write().hash_max_listpack_entries = limit;
+            }))
+        }),
+    },
+    Param {
+        name: "hash-max-listpack-value",
+        get: |_, db| {
+            db.encoding
+                .read()
+                .hash_max_listpack_value
+                .to_string()
+                .into()
+        },
+        set: Some(|value| {
+            let limit = value.parse::<usize>()?;
+            Ok(Box::new(move |db: &Db| {
+                db.encoding.write().hash_max_listpack_value = limit;
+            }))
+        }),
+    },
+    Param {
+        name: "list-max-listpack-size",
+        get: |_, db| db.encoding.read().list_max_listpack_size.to_string().into(),
+        set: Some(|value| {
+            let limit = value.parse::<i64>()?;
+            ensure!(
+                limit >= -5,
+                "argument must be between -5 and 9223372036854775807"
+            );
+            Ok(Box::new(move |db: &Db| {
+                db.encoding.write().list_max_listpack_size = limit;
+            }))
+        }),
+    },
+    Param {
+        name: "set-max-intset-entries",
+        get: |_, db| db.encoding.read().set_max_intset_entries.to_string().into(),
+        set: Some(|value| {
+            let limit = value.parse::<usize>()?;
+            Ok(Box::new(move |db: &Db| {
+                db.encoding.write().set_max_intset_entries = limit;
+            }))
+        }),
+    },
+    Param {
+        name: "set-max-listpack-entries",
+        get: |_, db| {
+            db.encoding
+                .read()
+                .set_max_listpack_entries
+                .to_string()
+                .into()
+        },
+        set: Some(|value| {
+            let limit = value.parse::<usize>()?;
+            Ok(Box::new(move |db: &Db| {
+                db.encoding.write().set_max_listpack_entries = limit;
+            }))
+        }),
+    },
+    Param {
+        name: "set-max-listpack-value",
+        get: |_, db| db.encoding.read().set_max_listpack_value.to_string().into(),
+        set: Some(|value| {
+            let limit = value.parse::<usize>()?;
+            Ok(Box::new(move |db: &Db| {
+                db.encoding.write().set_max_listpack_value = limit;
+            }))
+        }),
+    },
+    Param {
+        name: "zset-max-listpack-entries",
+        get: |_, db| {
+            db.encoding
+                .read()
+                .zset_max_listpack_entries
+                .to_string()
+                .into()
+        },
+        set: Some(|value| {
+            let limit = value.parse::<usize>()?;
+            Ok(Box::new(move |db: &Db| {
+                db.encoding.write().zset_max_listpack_entries = limit;
+            }))
+        }),
+    },
+    Param {
+        name: "zset-max-listpack-value",
+        get: |_, db| {
+            db.encoding
+                .read()
+                .zset_max_listpack_value
+                .to_string()
+                .into()
+        },
+        set: Some(|value| {
+            let limit = value.parse::<usize>()?;
+            Ok(Box::new(move |db: &Db| {
+                db.encoding.write().zset_max_listpack_value = limit;
+            }))
+        }),
+    },
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matching() {
+        let names = |patterns: &[&'static str]| {
+            let patterns = patterns.iter().map(|p| Bytes::from_static(p.as_bytes()));
+            Param::matching(&patterns.collect::<Vec<_>>())
+                .map(|param| param.name)
+                .collect::<Vec<_>>()
+        };
+        pretty_assertions::assert_eq!(names(&["DIR"]), ["dir"]);
+        pretty_assertions::assert_eq!(
+            names(&["append*", "appendonly"]),
+            ["appendonly", "appendfsync", "appendfilename"]
+        );
+        pretty_assertions::assert_eq!(
+            names(&["repl-*"]),
+            [
+                "repl-diskless-sync",
+                "repl-ping-replica-period",
+                "repl-timeout"
+            ]
+        );
+        assert!(names(&["nope*"]).is_empty());
+    }
+
+    #[test]
+    fn reload() {
+        let path = std::env::temp_dir().join(format!("reload-{}.conf", std::process::id()));
+        let args = Arguments::default();
+        let db = Db::default();
+        let reload = |contents: &str| {
+            std::fs::write(&path, contents).unwrap();
+            Config::reload(&path, &args, &db)
+        };
+
+        let restart = reload("port 6379\nport 7000\nhash-max-listpack-entries 5\nappendonly no\n");
+        pretty_assertions::assert_eq!(restart.unwrap(), ["port"]);
+        pretty_assertions::assert_eq!(db.encoding.read().hash_max_listpack_entries, 5);
+
+        // Nothing is applied if a value is invalid
+        let err = reload("hash-max-listpack-entries 7\nset-max-intset-entries x\n").unwrap_err();
+        assert!(err.to_string().contains("set-max-intset-entries"));
+        pretty_assertions::assert_eq!(db.encoding.read().hash_max_listpack_entries, 5);
+        let _ = std::fs::remove_file(&path);
     }
 }
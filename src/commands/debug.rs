@@ -0,0 +1,73 @@
+use anyhow::{bail, ensure, Context};
+
+use crate::{RedisError, Resp, Role};
+
+use super::{CommandExec, Ctx, IterResp};
+
+/// Internals poked at by tests, the way Redis's test suite does
+#[derive(Debug)]
+pub enum Debugging {
+    /// Starts a new replication history, so replicas can't continue the former one
+    ChangeReplId,
+}
+
+impl Debugging {
+    pub(super) fn parse(mut i: IterResp) -> anyhow::Result<Self> {
+        let Some(arg) = i.next().context("Missing args")?.as_bulk() else {
+            bail!("Expected bulk string");
+        };
+        let res = match arg.to_ascii_lowercase().as_slice() {
+            b"change-repl-id" => Self::ChangeReplId,
+            _ => bail!(RedisError::UnknownSubcommand {
+                command: "DEBUG",
+                sub: String::from_utf8_lossy(arg).into_owned()
+            }),
+        };
+        ensure!(i.next().is_none(), RedisError::Syntax);
+        Ok(res)
+    }
+}
+
+impl CommandExec for Debugging {
+    fn execute(self, ctx: &mut Ctx<'_>) -> anyhow::Result<Resp> {
+        match self {
+            Self::ChangeReplId => {
+                let Role::Master(master) = &ctx.args.role else {
+                    bail!("ERR Replicas follow the replication id of their master");
+                };
+                master.change_replid();
+            }
+        }
+        Ok(Resp::simple("OK"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testutil::{master, Client};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn change_repl_id() {
+        let server = master().await;
+        let mut client = Client::connect(&server).await;
+        let ids = |info: Resp| {
+            let info = String::from_utf8(info.as_bulk().unwrap().to_vec()).unwrap();
+            info.lines()
+                .filter(|line| line.starts_with("master_replid"))
+                .map(str::to_owned)
+                .collect::<Vec<_>>()
+        };
+
+        let before = ids(client.cmd(&["INFO", "replication"]).await);
+        pretty_assertions::assert_eq!(
+            client.cmd(&["DEBUG", "CHANGE-REPL-ID"]).await,
+            Resp::simple("OK")
+        );
+        let after = ids(client.cmd(&["INFO", "replication"]).await);
+        assert_ne!(after[0], before[0]);
+        // Nor is the former history kept as the secondary one
+        pretty_assertions::assert_eq!(after[1], format!("master_replid2:{}", "0".repeat(40)));
+    }
+}
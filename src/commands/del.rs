@@ -1,6 +1,6 @@
-use crate::{Resp, DB};
+use crate::Resp;
 
-use super::IterResp;
+use super::{CommandExec, Ctx, IterResp};
 
 #[derive(Debug)]
 pub struct Del {
@@ -13,9 +13,11 @@ impl Del {
             keys: i.flat_map(Resp::to_string).collect(),
         }
     }
+}
 
-    pub fn execute(&self) -> anyhow::Result<Resp> {
-        let deleted = DB.del(&self.keys);
+impl CommandExec for Del {
+    fn execute(self, ctx: &mut Ctx<'_>) -> anyhow::Result<Resp> {
+        let deleted = ctx.db.del(&self.keys);
         let resp = Resp::Integer(i64::try_from(deleted)?);
         Ok(resp)
     }
@@ -1,6 +1,6 @@
 use anyhow::ensure;
 
-use crate::Resp;
+use crate::{RedisError, Resp};
 
 use super::IterResp;
 
@@ -11,7 +11,7 @@ impl Discard {
     pub(super) fn parse(mut i: IterResp) -> anyhow::Result<Self> {
         ensure!(
             i.next().is_none(),
-            "ERR wrong number of arguments for 'discard' command"
+            RedisError::WrongArity("discard".to_owned())
         );
         Ok(Self)
     }
@@ -3,7 +3,7 @@ use bytes::Bytes;
 
 use crate::Resp;
 
-use super::IterResp;
+use super::{CommandExec, Ctx, IterResp};
 
 #[derive(Debug)]
 pub struct Echo {
@@ -11,7 +11,7 @@ pub struct Echo {
 }
 
 impl Echo {
-    fn new(msg: Bytes) -> Self {
+    const fn new(msg: Bytes) -> Self {
         Self { msg }
     }
 
@@ -22,8 +22,10 @@ impl Echo {
             .map(Self::new)
             .context("Expected bulk string")
     }
+}
 
-    pub fn execute(self) -> Resp {
-        Resp::Bulk(self.msg)
+impl CommandExec for Echo {
+    fn execute(self, _ctx: &mut Ctx<'_>) -> anyhow::Result<Resp> {
+        Ok(Resp::Bulk(self.msg))
     }
 }
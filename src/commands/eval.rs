@@ -0,0 +1,91 @@
+use crate::{
+    scripting::{self, FUNCTIONS, SCRIPTS},
+    Arguments, Db, RedisError, Resp,
+};
+use anyhow::{ensure, Context};
+use bytes::Bytes;
+use std::sync::Arc;
+
+use super::{CommandExec, Ctx, IterResp};
+
+#[derive(Debug)]
+pub struct Eval {
+    script: Source,
+    keys: Vec<Bytes>,
+    args: Vec<Bytes>,
+}
+
+#[derive(Debug)]
+enum Source {
+    Body(Bytes),
+    /// EVALSHA of a cached script
+    Sha(String),
+    /// FCALL of a library function
+    Function(String),
+}
+
+/// Which command an [`Eval`] is parsed from
+#[derive(Debug, Clone, Copy)]
+pub(super) enum Kind {
+    Eval,
+    EvalSha,
+    Fcall,
+}
+
+impl Eval {
+    pub(super) fn parse(mut i: IterResp, kind: Kind) -> anyhow::Result<Self> {
+        let script = i.next().context("Missing script")?.to_bytes()?;
+        let numkeys = i
+            .next()
+            .context("Missing numkeys")?
+            .to_int::<i64>()
+            .ok()
+            .context(RedisError::NotInteger)?;
+        ensure!(numkeys >= 0, "ERR Number of keys can't be negative");
+        let mut keys = i.map(Resp::to_bytes).collect::<anyhow::Result<Vec<_>>>()?;
+        let numkeys = usize::try_from(numkeys).unwrap_or(usize::MAX);
+        ensure!(
+            numkeys <= keys.len(),
+            "ERR Number of keys can't be greater than number of args"
+        );
+        let args = keys.split_off(numkeys);
+        let script = match kind {
+            Kind::Eval => Source::Body(script),
+            Kind::EvalSha => Source::Sha(String::from_utf8_lossy(&script).into_owned()),
+            Kind::Fcall => Source::Function(String::from_utf8_lossy(&script).into_owned()),
+        };
+        Ok(Self { script, keys, args })
+    }
+
+    /// Runs the script or function as `user`, returning its reply and the writes it made
+    pub(crate) fn run(
+        self,
+        db: &Arc<Db>,
+        server_args: &Arc<Arguments>,
+        user: &str,
+    ) -> anyhow::Result<(Resp, Vec<Vec<Resp>>)> {
+        match self.script {
+            Source::Body(body) => {
+                SCRIPTS.load(body.clone());
+                scripting::run(db, server_args, &body, &self.keys, &self.args, user)
+            }
+            Source::Sha(sha) => {
+                let body = SCRIPTS.get(&sha).context(RedisError::NoScript)?;
+                scripting::run(db, server_args, &body, &self.keys, &self.args, user)
+            }
+            Source::Function(name) => {
+                FUNCTIONS.call(db, server_args, &name, &self.keys, &self.args, user)
+            }
+        }
+    }
+}
+
+impl CommandExec for Eval {
+    /// Runs the script or function as the session's user, recording the writes it made
+    fn execute(self, ctx: &mut Ctx<'_>) -> anyhow::Result<Resp> {
+        let user = ctx.session.user.clone().unwrap_or_default();
+        let (resp, effects) = self.run(ctx.db, ctx.args, &user)?;
+        ctx.effects.extend(effects);
+        Ok(resp)
+    }
+}
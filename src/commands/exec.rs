@@ -1,5 +1,7 @@
 use anyhow::ensure;
 
+use crate::RedisError;
+
 use super::IterResp;
 
 pub struct Exec;
@@ -8,7 +10,7 @@ impl Exec {
     pub(super) fn parse(mut i: IterResp) -> anyhow::Result<()> {
         ensure!(
             i.next().is_none(),
-            "ERR wrong number of arguments for 'exec' command"
+            RedisError::WrongArity("exec".to_owned())
         );
         Ok(())
     }
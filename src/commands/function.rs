@@ -0,0 +1,174 @@
+use anyhow::{bail, ensure, Context};
+use bytes::Bytes;
+
+use crate::{
+    scripting::functions::{RestorePolicy, FUNCTIONS},
+    RedisError, Resp,
+};
+
+use super::{CommandExec, Ctx, IterResp};
+
+#[derive(Debug)]
+pub enum Function {
+    Load {
+        code: Bytes,
+        replace: bool,
+    },
+    Delete(String),
+    Flush,
+    List {
+        pattern: Option<String>,
+        with_code: bool,
+    },
+    Dump,
+    Restore {
+        payload: Bytes,
+        policy: RestorePolicy,
+    },
+    /// Interrupts the running function, which other commands wait for
+    Kill,
+}
+
+impl Function {
+    pub(super) fn parse(mut i: IterResp) -> anyhow::Result<Self> {
+        let Some(arg) = i.next().context("Missing args")?.as_bulk() else {
+            bail!("Expected bulk string");
+        };
+        let sub = arg.to_ascii_lowercase();
+        let res = match sub.as_slice() {
+            b"load" => {
+                let mut arg = i.next().with_context(|| arity_err("load"))?.to_bytes()?;
+                let replace = arg.eq_ignore_ascii_case(b"replace");
+                if replace {
+                    arg = i.next().with_context(|| arity_err("load"))?.to_bytes()?;
+                }
+                Self::Load { code: arg, replace }
+            }
+            b"delete" => Self::Delete(i.next().with_context(|| arity_err("delete"))?.to_string()?),
+            b"flush" => {
+                // Libraries are flushed synchronously either way
+                if let Some(mode) = i.next() {
+                    let mode = mode.to_string()?;
+                    ensure!(
+                        mode.eq_ignore_ascii_case("async") || mode.eq_ignore_ascii_case("sync"),
+                        "ERR FUNCTION FLUSH only supports SYNC|ASYNC option"
+                    );
+                }
+                Self::Flush
+            }
+            b"list" => {
+                let mut pattern = None;
+                let mut with_code = false;
+                while let Some(arg) = i.next() {
+                    let arg = arg.to_string()?;
+                    if arg.eq_ignore_ascii_case("withcode") {
+                        with_code = true;
+                    } else if arg.eq_ignore_ascii_case("libraryname") {
+                        pattern = Some(
+                            i.next()
+                                .context("ERR library name argument was not given")?
+                                .to_string()?,
+                        );
+                    } else {
+                        bail!("ERR Unknown argument {arg}");
+                    }
+                }
+                Self::List { pattern, with_code }
+            }
+            b"dump" => Self::Dump,
+            b"kill" => Self::Kill,
+            b"restore" => {
+                let payload = i.next().with_context(|| arity_err("restore"))?.to_bytes()?;
+                let policy = match i.next().map(Resp::to_string).transpose()? {
+                    None => RestorePolicy::Append,
+                    Some(policy) => match policy.to_ascii_lowercase().as_str() {
+                        "append" => RestorePolicy::Append,
+                        "replace" => RestorePolicy::Replace,
+                        "flush" => RestorePolicy::Flush,
+                        _ => bail!("ERR Wrong restore policy given, value should be either FLUSH, APPEND or REPLACE."),
+                    },
+                };
+                Self::Restore { payload, policy }
+            }
+            _ => bail!(RedisError::UnknownSubcommand {
+                command: "FUNCTION",
+                sub: String::from_utf8_lossy(arg).into_owned()
+            }),
+        };
+        ensure!(
+            i.next().is_none(),
+            arity_err(&String::from_utf8_lossy(&sub))
+        );
+        Ok(res)
+    }
+
+    /// Whether the subcommand changes the registered libraries
+    pub const fn is_write(&self) -> bool {
+        matches!(
+            self,
+            Self::Load { .. } | Self::Delete(_) | Self::Flush | Self::Restore { .. }
+        )
+    }
+}
+
+impl CommandExec for Function {
+    fn execute(self, ctx: &mut Ctx<'_>) -> anyhow::Result<Resp> {
+        Ok(match self {
+            Self::Load { code, replace } => Resp::bulk(FUNCTIONS.load(code, replace)?),
+            Self::Delete(name) => {
+                FUNCTIONS.delete(&name)?;
+                Resp::simple("OK")
+            }
+            Self::Flush => {
+                FUNCTIONS.flush();
+                Resp::simple("OK")
+            }
+            Self::List { pattern, with_code } => Resp::Array(
+                FUNCTIONS
+                    .list(pattern.as_deref())
+                    .into_iter()
+                    .map(|library| {
+                        let functions = library
+                            .functions
+                            .into_iter()
+                            .map(|function| {
+                                Resp::Map(vec![
+                                    (Resp::bulk("name"), Resp::bulk(function.name)),
+                                    (Resp::bulk("description"), Resp::Null),
+                                    (
+                                        Resp::bulk("flags"),
+                                        Resp::Array(
+                                            function.flags.into_iter().map(Resp::simple).collect(),
+                                        ),
+                                    ),
+                                ])
+                            })
+                            .collect();
+                        let mut fields = vec![
+                            (Resp::bulk("library_name"), Resp::bulk(library.name)),
+                            (Resp::bulk("engine"), Resp::bulk("LUA")),
+                            (Resp::bulk("functions"), Resp::Array(functions)),
+                        ];
+                        if with_code {
+                            fields.push((Resp::bulk("library_code"), Resp::bulk(library.code)));
+                        }
+                        Resp::Map(fields)
+                    })
+                    .collect(),
+            ),
+            Self::Dump => Resp::bulk(FUNCTIONS.dump()),
+            Self::Restore { payload, policy } => {
+                FUNCTIONS.restore(payload, policy)?;
+                Resp::simple("OK")
+            }
+            Self::Kill => {
+                ctx.db.busy.kill(true)?;
+                Resp::simple("OK")
+            }
+        })
+    }
+}
+
+fn arity_err(sub: &str) -> RedisError {
+    RedisError::WrongArity(format!("function|{sub}"))
+}
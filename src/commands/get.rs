@@ -1,8 +1,8 @@
 use anyhow::Context;
 
-use crate::{Resp, DB};
+use crate::{db::RedisString, Resp};
 
-use super::IterResp;
+use super::{CommandExec, Ctx, IterResp};
 
 #[derive(Debug)]
 pub struct Get {
@@ -18,11 +18,18 @@ impl Get {
         let key = i.next().context("Missing key")?.to_string()?;
         Ok(Self { key })
     }
+}
 
-    pub fn execute(&self) -> anyhow::Result<Resp> {
-        let value = DB
-            .get(self)
-            .map(|v| v.v_type.as_string().context("Invalid type").cloned())
+impl CommandExec for Get {
+    fn execute(self, ctx: &mut Ctx<'_>) -> anyhow::Result<Resp> {
+        let value = ctx
+            .db
+            .view(&self.key, |v| {
+                v.v_type
+                    .as_string()
+                    .context("Invalid type")
+                    .map(RedisString::to_bytes)
+            })
             .transpose()?
             .map_or(Resp::Null, Resp::Bulk);
         Ok(value)
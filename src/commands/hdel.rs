@@ -0,0 +1,44 @@
+use anyhow::{ensure, Context};
+use bytes::Bytes;
+
+use crate::{RedisError, Resp};
+
+use super::{CommandExec, Ctx, IterResp};
+
+#[derive(Debug)]
+pub struct Hdel {
+    key: String,
+    fields: Vec<Bytes>,
+}
+
+impl Hdel {
+    pub(super) fn parse(mut i: IterResp) -> anyhow::Result<Self> {
+        let key = i.next().context("Missing key")?.to_string()?;
+        let fields = i.map(Resp::to_bytes).collect::<anyhow::Result<Vec<_>>>()?;
+        ensure!(
+            !fields.is_empty(),
+            RedisError::WrongArity("hdel".to_owned())
+        );
+        Ok(Self { key, fields })
+    }
+}
+
+impl CommandExec for Hdel {
+    fn execute(self, ctx: &mut Ctx<'_>) -> anyhow::Result<Resp> {
+        let now = ctx.db.clock.now();
+        let mut lock = ctx.db.inner.write();
+        let Some(hash) = ctx.db.hash_mut(&mut lock, &self.key)? else {
+            return Ok(Resp::Integer(0));
+        };
+        let deleted = self
+            .fields
+            .iter()
+            .filter(|field| hash.remove(field, now))
+            .count();
+        if hash.is_empty() {
+            lock.remove(&self.key);
+        }
+        drop(lock);
+        Ok(Resp::Integer(deleted.try_into()?))
+    }
+}
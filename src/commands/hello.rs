@@ -0,0 +1,173 @@
+use anyhow::{bail, ensure, Context};
+
+use crate::{clients::CLIENTS, resp::Protocol, RedisError, Resp, Role, ACL};
+
+use super::{client::is_valid, CommandExec, Ctx, IterResp};
+
+#[derive(Debug)]
+pub struct Hello {
+    protover: Option<Protocol>,
+    auth: Option<(String, String)>,
+    setname: Option<String>,
+}
+
+impl Hello {
+    /// Redis version reported to clients
+    const VERSION: &'static str = "7.4.0";
+
+    pub(super) fn parse(mut i: IterResp) -> anyhow::Result<Self> {
+        let mut hello = Self {
+            protover: None,
+            auth: None,
+            setname: None,
+        };
+        let Some(protover) = i.next() else {
+            return Ok(hello);
+        };
+        let protover = protover
+            .to_int::<i64>()
+            .ok()
+            .context("ERR Protocol version is not an integer or out of range")?;
+        hello.protover = Some(match protover {
+            2 => Protocol::Resp2,
+            3 => Protocol::Resp3,
+            _ => bail!("NOPROTO unsupported protocol version"),
+        });
+
+        while let Some(arg) = i.next() {
+            let arg = arg.to_string()?;
+            match arg.to_ascii_lowercase().as_str() {
+                "auth" => {
+                    let user = i.next().context(RedisError::Syntax)?.to_string()?;
+                    let pass = i.next().context(RedisError::Syntax)?.to_string()?;
+                    hello.auth = Some((user, pass));
+                }
+                "setname" => {
+                    let name = i.next().context(RedisError::Syntax)?.to_string()?;
+                    ensure!(
+                        is_valid(&name),
+                        "ERR Client names cannot contain spaces, newlines or special characters."
+                    );
+                    hello.setname = Some(name);
+                }
+                _ => bail!("ERR Syntax error in HELLO option '{arg}'"),
+            }
+        }
+        Ok(hello)
+    }
+}
+
+impl CommandExec for Hello {
+    fn execute(self, ctx: &mut Ctx<'_>) -> anyhow::Result<Resp> {
+        match self.auth {
+            Some((name, password)) => {
+                ensure!(
+                    ACL.authenticate(&name, &password),
+                    RedisError::WrongPass
+                );
+                ctx.session.user = Some(name);
+            }
+            None => ensure!(
+                ctx.session.user.is_some(),
+                "NOAUTH HELLO must be called with the client already authenticated, otherwise the HELLO <proto> AUTH <user> <pass> option can be used to authenticate the client and select the RESP protocol version at the same time"
+            ),
+        }
+        if let Some(protover) = self.protover {
+            ctx.session.protocol = protover;
+        }
+        CLIENTS.with(ctx.session.id, |client| {
+            client.protocol = ctx.session.protocol;
+            if let Some(name) = self.setname {
+                client.name = Some(name).filter(|name| !name.is_empty());
+            }
+        });
+
+        let role = match ctx.args.role {
+            Role::Master(_) => "master",
+            Role::Slave(_) => "replica",
+        };
+        let mode = if ctx.args.cluster_enabled {
+            "cluster"
+        } else {
+            "standalone"
+        };
+        Ok(Resp::Map(vec![
+            (Resp::bulk("server"), Resp::bulk("redis")),
+            (Resp::bulk("version"), Resp::bulk(Self::VERSION)),
+            (
+                Resp::bulk("proto"),
+                Resp::Integer(ctx.session.protocol.version()),
+            ),
+            (Resp::bulk("id"), Resp::Integer(ctx.session.id.try_into()?)),
+            (Resp::bulk("mode"), Resp::bulk(mode)),
+            (Resp::bulk("role"), Resp::bulk(role)),
+            (Resp::bulk("modules"), Resp::Array(Vec::new())),
+        ]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::{commands::Session, Arguments, Db};
+
+    use super::*;
+
+    fn hello(args: &[&'static str]) -> anyhow::Result<Resp> {
+        let args = args.iter().copied().map(Resp::bulk).collect::<Vec<_>>();
+        let guard = CLIENTS.register("127.0.0.1:6380".parse().unwrap());
+        let db = Arc::new(Db::default());
+        let server_args = Arc::new(Arguments::default());
+        let mut session = Session {
+            id: guard.id(),
+            user: Some("default".to_owned()),
+            ..Session::default()
+        };
+        Hello::parse(args.iter())?.execute(&mut Ctx::new(&db, &server_args, &mut session))
+    }
+
+    #[test]
+    fn invalid() {
+        for (args, err) in [
+            (&["4"][..], "NOPROTO unsupported protocol version"),
+            (
+                &["three"],
+                "ERR Protocol version is not an integer or out of range",
+            ),
+            (
+                &["2", "SETNAME", "hello conn"],
+                "ERR Client names cannot contain spaces, newlines or special characters.",
+            ),
+            (&["3", "AUTH", "default"], "ERR syntax error"),
+            (
+                &["3", "SETUSER"],
+                "ERR Syntax error in HELLO option 'SETUSER'",
+            ),
+            (
+                &["3", "AUTH", "hello-user", "secret"],
+                "WRONGPASS invalid username-password pair or user is disabled.",
+            ),
+        ] {
+            assert_eq!(hello(args).unwrap_err().to_string(), err, "{args:?}");
+        }
+    }
+
+    #[test]
+    fn cluster_mode() {
+        let db = Arc::new(Db::default());
+        let args = Arc::new(Arguments {
+            cluster_enabled: true,
+            ..Arguments::default()
+        });
+        let mut session = Session {
+            user: Some("default".to_owned()),
+            ..Session::default()
+        };
+        let mut ctx = Ctx::new(&db, &args, &mut session);
+        let Resp::Map(reply) = Hello::parse([].iter()).unwrap().execute(&mut ctx).unwrap() else {
+            panic!("HELLO didn't reply a map");
+        };
+        assert!(reply.contains(&(Resp::bulk("mode"), Resp::bulk("cluster"))));
+    }
+}
@@ -0,0 +1,161 @@
+use anyhow::{bail, ensure, Context};
+use bytes::Bytes;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::{
+    clock::Expiry,
+    db::hash::{ExpireCond, FieldExpire},
+    RedisError, Resp,
+};
+
+use super::{CommandExec, Ctx, IterResp};
+
+/// Which command an [`Hexpire`] is parsed from
+#[derive(Debug, Clone, Copy)]
+pub(super) enum Kind {
+    Expire,
+    Pexpire,
+    ExpireAt,
+    PexpireAt,
+}
+
+impl Kind {
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Expire => "hexpire",
+            Self::Pexpire => "hpexpire",
+            Self::ExpireAt => "hexpireat",
+            Self::PexpireAt => "hpexpireat",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Hexpire {
+    kind: Kind,
+    key: String,
+    expiry: Expiry,
+    cond: Option<ExpireCond>,
+    fields: Vec<Bytes>,
+}
+
+impl Hexpire {
+    pub(super) fn parse(mut i: IterResp, kind: Kind) -> anyhow::Result<Self> {
+        let key = i.next().context("Missing key")?.to_string()?;
+        let time = i
+            .next()
+            .context("Missing time")?
+            .to_int::<i64>()
+            .ok()
+            .context(RedisError::NotInteger)?;
+        let invalid = RedisError::InvalidExpireTime(kind.name());
+        let time = u64::try_from(time).ok().context(invalid.clone())?;
+        let time = match kind {
+            Kind::Expire | Kind::ExpireAt => time.checked_mul(1000).context(invalid.clone())?,
+            Kind::Pexpire | Kind::PexpireAt => time,
+        };
+        let time = Duration::from_millis(time);
+        let expiry = match kind {
+            Kind::Expire | Kind::Pexpire => Expiry::In(time),
+            Kind::ExpireAt | Kind::PexpireAt => {
+                Expiry::At(UNIX_EPOCH.checked_add(time).context(invalid)?)
+            }
+        };
+
+        let mut i = i.peekable();
+        let cond = match i
+            .peek()
+            .and_then(|arg| arg.as_bulk())
+            .map(|arg| arg.to_ascii_uppercase())
+            .as_deref()
+        {
+            Some(b"NX") => Some(ExpireCond::Nx),
+            Some(b"XX") => Some(ExpireCond::Xx),
+            Some(b"GT") => Some(ExpireCond::Gt),
+            Some(b"LT") => Some(ExpireCond::Lt),
+            _ => None,
+        };
+        if cond.is_some() {
+            i.next();
+        }
+        let fields = parse_fields(i)?;
+        Ok(Self {
+            kind,
+            key,
+            expiry,
+            cond,
+            fields,
+        })
+    }
+
+    /// Fixed from `now` and propagated as `HPEXPIREAT`, so replicas
+    /// expire the fields at the same time regardless of when they apply the command
+    pub(super) fn rewrite_effect(
+        &mut self,
+        raw_cmd: &mut [Resp],
+        now: SystemTime,
+    ) -> anyhow::Result<()> {
+        let at = self.at(now)?;
+        self.expiry = Expiry::At(at);
+        let at = at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        raw_cmd[0] = Resp::bulk("HPEXPIREAT");
+        raw_cmd[2] = Resp::bulk(at.to_string());
+        Ok(())
+    }
+
+    fn at(&self, now: SystemTime) -> anyhow::Result<SystemTime> {
+        let invalid = RedisError::InvalidExpireTime(self.kind.name());
+        self.expiry.at(now).context(invalid)
+    }
+}
+
+/// Parses the `FIELDS numfields field [field ...]` ending the field TTL commands
+pub(super) fn parse_fields<'a>(
+    mut i: impl Iterator<Item = &'a Resp>,
+) -> anyhow::Result<Vec<Bytes>> {
+    let fields_arg = i.next().and_then(Resp::as_bulk);
+    if !fields_arg.is_some_and(|arg| arg.eq_ignore_ascii_case(b"FIELDS")) {
+        bail!("ERR Mandatory argument FIELDS is missing or not at the right position");
+    }
+    let numfields = i
+        .next()
+        .context(RedisError::Syntax)?
+        .to_int::<i64>()
+        .ok()
+        .context(RedisError::NotInteger)?;
+    ensure!(
+        numfields > 0,
+        "ERR Parameter `numFields` should be greater than 0"
+    );
+    let fields = i.map(Resp::to_bytes).collect::<anyhow::Result<Vec<_>>>()?;
+    ensure!(
+        usize::try_from(numfields).is_ok_and(|n| n == fields.len()),
+        "ERR The `numfields` parameter must match the number of arguments"
+    );
+    Ok(fields)
+}
+
+impl CommandExec for Hexpire {
+    fn execute(self, ctx: &mut Ctx<'_>) -> anyhow::Result<Resp> {
+        let now = ctx.db.clock.now();
+        let at = self.at(now)?;
+        let mut lock = ctx.db.inner.write();
+        let Some(hash) = ctx.db.hash_mut(&mut lock, &self.key)? else {
+            let missing = Resp::Integer(FieldExpire::NoField as i64);
+            return Ok(Resp::Array(vec![missing; self.fields.len()]));
+        };
+        let codes = self
+            .fields
+            .iter()
+            .map(|field| Resp::Integer(hash.set_expiration(field, at, self.cond, now) as i64))
+            .collect();
+        if hash.is_empty() {
+            lock.remove(&self.key);
+        }
+        drop(lock);
+        Ok(Resp::Array(codes))
+    }
+}
@@ -0,0 +1,36 @@
+use anyhow::Context;
+use bytes::Bytes;
+
+use crate::{RedisError, Resp};
+
+use super::{CommandExec, Ctx, IterResp};
+
+#[derive(Debug)]
+pub struct Hget {
+    key: String,
+    field: Bytes,
+}
+
+impl Hget {
+    pub(super) fn parse(mut i: IterResp) -> anyhow::Result<Self> {
+        let key = i.next().context("Missing key")?.to_string()?;
+        let field = i.next().context("Missing field")?.to_bytes()?;
+        Ok(Self { key, field })
+    }
+}
+
+impl CommandExec for Hget {
+    fn execute(self, ctx: &mut Ctx<'_>) -> anyhow::Result<Resp> {
+        let now = ctx.db.clock.now();
+        let value = ctx
+            .db
+            .view(&self.key, |v| {
+                let hash = v.v_type.as_hash().context(RedisError::WrongType)?;
+                anyhow::Ok(hash.get(&self.field, now))
+            })
+            .transpose()?
+            .flatten()
+            .map_or(Resp::Null, Resp::Bulk);
+        Ok(value)
+    }
+}
@@ -0,0 +1,42 @@
+use anyhow::Context;
+use bytes::Bytes;
+
+use crate::Resp;
+
+use super::{hexpire::parse_fields, CommandExec, Ctx, IterResp};
+
+#[derive(Debug)]
+pub struct Hpersist {
+    key: String,
+    fields: Vec<Bytes>,
+}
+
+impl Hpersist {
+    pub(super) fn parse(mut i: IterResp) -> anyhow::Result<Self> {
+        let key = i.next().context("Missing key")?.to_string()?;
+        let fields = parse_fields(i)?;
+        Ok(Self { key, fields })
+    }
+}
+
+impl CommandExec for Hpersist {
+    fn execute(self, ctx: &mut Ctx<'_>) -> anyhow::Result<Resp> {
+        let now = ctx.db.clock.now();
+        let mut lock = ctx.db.inner.write();
+        let Some(hash) = ctx.db.hash_mut(&mut lock, &self.key)? else {
+            return Ok(Resp::Array(vec![Resp::Integer(-2); self.fields.len()]));
+        };
+        let codes = self
+            .fields
+            .iter()
+            .map(|field| match hash.persist(field, now) {
+                None => -2,
+                Some(false) => -1,
+                Some(true) => 1,
+            })
+            .map(Resp::Integer)
+            .collect();
+        drop(lock);
+        Ok(Resp::Array(codes))
+    }
+}
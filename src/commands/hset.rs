@@ -0,0 +1,55 @@
+use anyhow::{ensure, Context};
+use bytes::Bytes;
+use std::sync::Arc;
+
+use crate::{
+    db::{Hash, Type, Value},
+    RedisError, Resp,
+};
+
+use super::{CommandExec, Ctx, IterResp};
+
+#[derive(Debug)]
+pub struct Hset {
+    key: String,
+    pairs: Vec<(Bytes, Bytes)>,
+}
+
+impl Hset {
+    pub(super) fn parse(mut i: IterResp) -> anyhow::Result<Self> {
+        let key = i.next().context("Missing key")?.to_string()?;
+        ensure!(
+            i.len().is_multiple_of(2),
+            RedisError::WrongArity("hset".to_owned())
+        );
+        let mut pairs = Vec::with_capacity(i.len() / 2);
+        while let (Some(field), Some(value)) = (i.next(), i.next()) {
+            pairs.push((field.to_bytes()?, value.to_bytes()?));
+        }
+        Ok(Self { key, pairs })
+    }
+}
+
+impl CommandExec for Hset {
+    fn execute(self, ctx: &mut Ctx<'_>) -> anyhow::Result<Resp> {
+        let limits = *ctx.db.encoding.read();
+        let now = ctx.db.clock.now();
+        let mut lock = ctx.db.inner.write();
+        if ctx.db.hash_mut(&mut lock, &self.key)?.is_none() {
+            let hash = Type::Hash(Hash::default());
+            lock.insert(self.key.clone(), Arc::new(Value::new(hash, None)));
+        }
+        let hash = ctx
+            .db
+            .hash_mut(&mut lock, &self.key)?
+            .context("Missing hash")?;
+        let added = self
+            .pairs
+            .into_iter()
+            .map(|(field, value)| hash.insert(field, value, &limits, now))
+            .filter(|&new| new)
+            .count();
+        drop(lock);
+        Ok(Resp::Integer(added.try_into()?))
+    }
+}
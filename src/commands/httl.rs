@@ -0,0 +1,53 @@
+use anyhow::Context;
+use bytes::Bytes;
+
+use crate::{RedisError, Resp};
+
+use super::{hexpire::parse_fields, CommandExec, Ctx, IterResp};
+
+/// HTTL, or HPTTL for the remaining time in milliseconds
+#[derive(Debug)]
+pub struct Httl {
+    key: String,
+    fields: Vec<Bytes>,
+    millis: bool,
+}
+
+impl Httl {
+    pub(super) fn parse(mut i: IterResp, millis: bool) -> anyhow::Result<Self> {
+        let key = i.next().context("Missing key")?.to_string()?;
+        let fields = parse_fields(i)?;
+        Ok(Self {
+            key,
+            fields,
+            millis,
+        })
+    }
+}
+
+impl CommandExec for Httl {
+    fn execute(self, ctx: &mut Ctx<'_>) -> anyhow::Result<Resp> {
+        let now = ctx.db.clock.now();
+        let ttls = ctx
+            .db
+            .view(&self.key, |v| {
+                let hash = v.v_type.as_hash().context(RedisError::WrongType)?;
+                let ttls = self
+                    .fields
+                    .iter()
+                    .map(|field| match hash.expiration(field, now) {
+                        _ if !hash.contains(field, now) => -2,
+                        None => -1,
+                        Some(at) => {
+                            let ms = at.duration_since(now).unwrap_or_default().as_millis();
+                            let ttl = if self.millis { ms } else { (ms + 500) / 1000 };
+                            i64::try_from(ttl).unwrap_or(i64::MAX)
+                        }
+                    });
+                anyhow::Ok(ttls.map(Resp::Integer).collect())
+            })
+            .transpose()?
+            .unwrap_or_else(|| vec![Resp::Integer(-2); self.fields.len()]);
+        Ok(Resp::Array(ttls))
+    }
+}
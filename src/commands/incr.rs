@@ -1,13 +1,13 @@
-use std::collections::hash_map::Entry;
+use std::{collections::hash_map::Entry, sync::Arc};
 
 use anyhow::Context;
 
 use crate::{
     db::{Type, Value},
-    slice_to_int, Resp, DB,
+    RedisError, Resp,
 };
 
-use super::IterResp;
+use super::{CommandExec, Ctx, IterResp};
 
 #[derive(Debug)]
 pub struct Incr {
@@ -19,29 +19,28 @@ impl Incr {
         let key = i.next().context("Missing key").and_then(Resp::to_string)?;
         Ok(Self { key })
     }
+}
 
-    pub fn execute(self) -> anyhow::Result<Resp> {
-        let mut lock = DB.inner.write();
+impl CommandExec for Incr {
+    fn execute(self, ctx: &mut Ctx<'_>) -> anyhow::Result<Resp> {
+        let mut lock = ctx.db.inner.write();
+        ctx.db.expire_stale(&mut lock, &self.key);
         let entry = lock.entry(self.key);
-        // TODO store as int? https://redis.io/docs/latest/commands/incr/
         let res = match entry {
             Entry::Occupied(mut entry) => {
-                let entry = entry.get_mut();
+                let entry = Arc::make_mut(entry.get_mut());
                 let value = entry
                     .v_type
                     .as_string()
-                    .context("WRONGTYPE Operation against a key holding the wrong kind of value")
-                    .and_then(|x| {
-                        slice_to_int::<i64>(x)
-                            .context("ERR value is not an integer or out of range")
-                    })
-                    .and_then(|x| x.checked_add(1).context("ERR increment would overflow"))?;
-                entry.v_type = Type::String(value.to_string().into());
+                    .context(RedisError::WrongType)
+                    .and_then(|x| x.to_int().context(RedisError::NotInteger))
+                    .and_then(|x| x.checked_add(1).context(RedisError::Overflow))?;
+                entry.v_type = Type::String(value.into());
                 value
             }
             Entry::Vacant(entry) => {
                 let val = 1;
-                entry.insert(Value::new_no_expiry_string(val.to_string().into()));
+                entry.insert(Arc::new(Value::new_no_expiry_string(val)));
                 val
             }
         };
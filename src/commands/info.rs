@@ -1,43 +1,134 @@
 use std::io::Write;
 
-use crate::{Resp, Role};
+use anyhow::bail;
 
-use super::IterResp;
+use crate::{Db, Resp, Role, AOF, STATS};
 
-#[derive(Debug)]
-pub enum Info {
+use super::{CommandExec, Ctx, IterResp};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Section {
+    Persistence,
+    Stats,
     Replication,
-    // TODO
 }
 
-impl Info {
-    pub(super) fn parse(mut i: IterResp) -> Self {
-        let Some(arg) = i.next().and_then(Resp::as_bulk) else {
-            // TODO return all sections
-            return Self::Replication;
-        };
+impl Section {
+    const ALL: &'static [Self] = &[Self::Persistence, Self::Stats, Self::Replication];
+}
 
-        let resp = match arg.to_ascii_lowercase().as_slice() {
-            b"replication" => Self::Replication,
-            _ => todo!("{arg:?}"),
-        };
-        resp
+/// Sections to report, in the order Redis lists them
+#[derive(Debug)]
+pub struct Info {
+    sections: Vec<Section>,
+}
+
+impl Info {
+    pub(super) fn parse(i: IterResp) -> anyhow::Result<Self> {
+        let mut sections = Vec::new();
+        for arg in i.filter_map(Resp::as_bulk) {
+            match arg.to_ascii_lowercase().as_slice() {
+                b"all" | b"default" | b"everything" => sections.extend(Section::ALL),
+                b"persistence" => sections.push(Section::Persistence),
+                b"stats" => sections.push(Section::Stats),
+                b"replication" => sections.push(Section::Replication),
+                _ => bail!(
+                    "ERR unsupported INFO section '{}'",
+                    String::from_utf8_lossy(arg)
+                ),
+            }
+        }
+        if sections.is_empty() {
+            sections.extend(Section::ALL);
+        }
+        sections.sort_by_key(|section| Section::ALL.iter().position(|x| x == section));
+        sections.dedup();
+        Ok(Self { sections })
     }
+}
 
-    pub async fn execute(&self, role: &Role) -> anyhow::Result<Resp> {
-        match self {
-            Self::Replication => {
-                let resp = Resp::bulk(Replication::to_bytes(role).await?);
-                Ok(resp)
+impl CommandExec for Info {
+    fn execute(self, ctx: &mut Ctx<'_>) -> anyhow::Result<Resp> {
+        let mut bytes = Vec::new();
+        for (i, section) in self.sections.iter().enumerate() {
+            if i > 0 {
+                write!(bytes, "\r\n")?;
+            }
+            match section {
+                Section::Persistence => Persistence::write(&mut bytes, ctx.db)?,
+                Section::Stats => Stats::write(&mut bytes)?,
+                Section::Replication => bytes.extend(Replication::to_bytes(&ctx.args.role)?),
             }
         }
+        Ok(Resp::bulk(bytes))
+    }
+}
+
+struct Persistence;
+
+impl Persistence {
+    fn write(bytes: &mut Vec<u8>, db: &Db) -> std::io::Result<()> {
+        let persistence = &db.persistence;
+        write!(bytes, "# Persistence\r\n")?;
+        write!(bytes, "loading:{}\r\n", u8::from(persistence.loading()))?;
+        write!(
+            bytes,
+            "rdb_changes_since_last_save:{}\r\n",
+            persistence.dirty()
+        )?;
+        write!(
+            bytes,
+            "rdb_bgsave_in_progress:{}\r\n",
+            u8::from(persistence.bgsave_in_progress())
+        )?;
+        write!(bytes, "rdb_last_save_time:{}\r\n", persistence.last_save())?;
+        let status = if persistence.last_bgsave_ok() {
+            "ok"
+        } else {
+            "err"
+        };
+        write!(bytes, "rdb_last_bgsave_status:{status}\r\n")?;
+        write!(bytes, "aof_enabled:{}\r\n", u8::from(AOF.enabled()))?;
+        // The AOF only grows, it's never rewritten
+        write!(bytes, "aof_rewrite_in_progress:0\r\n")?;
+        Ok(())
+    }
+}
+
+struct Stats;
+
+impl Stats {
+    fn write(bytes: &mut Vec<u8>) -> std::io::Result<()> {
+        write!(bytes, "# Stats\r\n")?;
+        write!(
+            bytes,
+            "total_connections_received:{}\r\n",
+            STATS.connections_received()
+        )?;
+        write!(
+            bytes,
+            "total_commands_processed:{}\r\n",
+            STATS.commands_processed()
+        )?;
+        write!(
+            bytes,
+            "instantaneous_ops_per_sec:{}\r\n",
+            STATS.instantaneous_ops_per_sec()
+        )?;
+        write!(bytes, "expired_keys:{}\r\n", STATS.expired_keys())?;
+        // Keys are only evicted under maxmemory, which isn't supported
+        write!(bytes, "evicted_keys:0\r\n")?;
+        write!(bytes, "evicted_clients:{}\r\n", STATS.evicted_clients())?;
+        write!(bytes, "keyspace_hits:{}\r\n", STATS.keyspace_hits())?;
+        write!(bytes, "keyspace_misses:{}\r\n", STATS.keyspace_misses())?;
+        Ok(())
     }
 }
 
 struct Replication;
 
 impl Replication {
-    async fn to_bytes(role: &Role) -> anyhow::Result<Vec<u8>> {
+    fn to_bytes(role: &Role) -> anyhow::Result<Vec<u8>> {
         let mut bytes = Vec::new();
 
         write!(bytes, "# Replication\r\n")?;
@@ -45,26 +136,51 @@ impl Replication {
             Role::Master(master) => {
                 write!(bytes, "role:master\r\n")?;
                 {
-                    let slaves = master.slaves.read().await;
+                    let slaves = master.slaves.read();
                     write!(bytes, "connected_slaves:{}\r\n", slaves.len())?;
                     slaves.iter().enumerate().try_for_each(|(i, slave)| {
-                        let addr = slave.addr();
+                        let addr = slave.listening_addr();
                         write!(
                             bytes,
                             "slave{i}:ip={ip},port={port},offset={off}\r\n",
                             ip = addr.ip(),
                             port = addr.port(),
-                            off = slave.offset,
+                            off = slave.acked_offset(),
                         )
                     })?;
                 }
                 write!(bytes, "master_replid:{}\r\n", master.replid())?;
+                match master.replid2() {
+                    Some(replid2) => write!(
+                        bytes,
+                        "master_replid2:{}\r\nsecond_repl_offset:{}\r\n",
+                        replid2.id, replid2.offset
+                    )?,
+                    None => write!(
+                        bytes,
+                        "master_replid2:{}\r\nsecond_repl_offset:-1\r\n",
+                        "0".repeat(40)
+                    )?,
+                }
                 write!(bytes, "master_repl_offset:{}\r\n", master.repl_offset())?;
             }
             Role::Slave(slave) => {
                 write!(bytes, "role:slave\r\n")?;
                 write!(bytes, "master_host:{}\r\n", slave.addr.ip())?;
                 write!(bytes, "master_port:{}\r\n", slave.addr.port())?;
+                let status = if slave.link_up() { "up" } else { "down" };
+                write!(bytes, "master_link_status:{status}\r\n")?;
+                if let Some(replid) = slave.master_replid() {
+                    write!(bytes, "master_replid:{replid}\r\n")?;
+                }
+                let last_io = slave
+                    .last_io_seconds_ago()
+                    .and_then(|x| i64::try_from(x).ok())
+                    .unwrap_or(-1);
+                write!(bytes, "master_last_io_seconds_ago:{last_io}\r\n")?;
+                write!(bytes, "slave_repl_offset:{}\r\n", slave.offset())?;
+                // Clients can't write to a replica
+                write!(bytes, "slave_read_only:1\r\n")?;
             }
         }
         Ok(bytes)
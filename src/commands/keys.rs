@@ -1,13 +1,13 @@
 use anyhow::Context;
-use glob_match::glob_match;
+use bytes::Bytes;
 
-use crate::{Resp, DB};
+use crate::{glob::glob_match, Resp};
 
-use super::IterResp;
+use super::{CommandExec, Ctx, IterResp};
 
 #[derive(Debug)]
 pub struct Keys {
-    pat: String,
+    pat: Bytes,
 }
 
 impl Keys {
@@ -15,19 +15,23 @@ impl Keys {
         let pat = i
             .next()
             .context("Missing pattern")
-            .and_then(Resp::to_string)?;
+            .and_then(Resp::to_bytes)?;
         Ok(Self { pat })
     }
+}
 
-    pub fn execute(&self) -> Resp {
-        let keys = DB
+impl CommandExec for Keys {
+    fn execute(self, ctx: &mut Ctx<'_>) -> anyhow::Result<Resp> {
+        let now = ctx.db.clock.now();
+        let keys = ctx
+            .db
             .inner
             .read()
-            .keys()
-            .filter(|x| glob_match(&self.pat, x))
-            .cloned()
+            .iter()
+            .filter(|(k, v)| !v.is_expired(now) && glob_match(&self.pat, k))
+            .map(|(k, _)| k.clone())
             .map(Resp::bulk)
             .collect::<Vec<_>>();
-        Resp::Array(keys)
+        Ok(Resp::Array(keys))
     }
 }
@@ -34,6 +34,9 @@ pub use keys::Keys;
 mod r#type;
 pub use r#type::Type;
 
+mod object;
+pub use object::Object;
+
 mod xadd;
 pub use xadd::Xadd;
 
@@ -43,6 +46,69 @@ pub use xrange::Xrange;
 mod xread;
 pub use xread::Xread;
 
+mod xdel;
+pub use xdel::Xdel;
+
+mod xsetid;
+pub use xsetid::Xsetid;
+
+mod xgroup;
+pub use xgroup::Xgroup;
+
+mod xreadgroup;
+pub use xreadgroup::Xreadgroup;
+
+mod xack;
+pub use xack::Xack;
+
+mod xautoclaim;
+pub use xautoclaim::Xautoclaim;
+
+mod hset;
+pub use hset::Hset;
+
+mod hget;
+pub use hget::Hget;
+
+mod hdel;
+pub use hdel::Hdel;
+
+mod hexpire;
+pub use hexpire::Hexpire;
+
+mod httl;
+pub use httl::Httl;
+
+mod hpersist;
+pub use hpersist::Hpersist;
+
+mod zadd;
+pub use zadd::Zadd;
+
+mod zrank;
+pub use zrank::Zrank;
+
+mod zrange;
+pub use zrange::Zrange;
+
+mod zcount;
+pub use zcount::Zcount;
+
+mod subscribe;
+pub use subscribe::Subscribe;
+
+mod unsubscribe;
+pub use unsubscribe::Unsubscribe;
+
+mod publish;
+pub use publish::Publish;
+
+mod pubsub;
+pub use pubsub::Pubsub;
+
+mod client;
+pub use client::Client;
+
 mod incr;
 pub use incr::Incr;
 
@@ -55,12 +121,125 @@ pub use exec::Exec;
 mod discard;
 pub use discard::Discard;
 
-use anyhow::bail;
+mod hello;
+pub use hello::Hello;
+
+mod save;
+pub use save::Save;
+
+mod bgsave;
+pub use bgsave::Bgsave;
+
+mod acl;
+pub use acl::Acl;
+
+mod auth;
+pub use auth::Auth;
+
+mod cluster;
+pub use cluster::Cluster;
 
-use crate::Resp;
+mod asking;
+pub use asking::Asking;
+
+mod select;
+pub use select::Select;
+
+mod command;
+pub use command::Introspect;
+
+mod debug;
+pub use debug::Debugging;
+
+#[cfg(feature = "scripting")]
+mod eval;
+#[cfg(feature = "scripting")]
+pub use eval::Eval;
+
+#[cfg(feature = "scripting")]
+mod script;
+#[cfg(feature = "scripting")]
+pub use script::Script;
+
+#[cfg(feature = "scripting")]
+mod function;
+#[cfg(feature = "scripting")]
+pub use function::Function;
+
+use std::{net::SocketAddr, sync::Arc};
+
+use anyhow::{bail, ensure};
+use tokio::sync::mpsc;
+
+use crate::{db::stream::MaybeAuto, resp::Protocol, Arguments, Db, RedisError, Resp, TRACKING};
+
+mod table;
+pub use table::CommandSpec;
 
 type IterResp<'a> = std::slice::Iter<'a, Resp>;
 
+/// A command that runs against a [`Ctx`], without waiting on anything.
+/// Commands that act on the connection itself, like MULTI or SUBSCRIBE,
+/// are handled by the [`crate::CommandHandler`] instead.
+pub trait CommandExec {
+    fn execute(self, ctx: &mut Ctx<'_>) -> anyhow::Result<Resp>;
+}
+
+/// What a command runs against: the server, the calling connection,
+/// and a sink for the writes to propagate
+pub struct Ctx<'a> {
+    pub db: &'a Arc<Db>,
+    pub args: &'a Arc<Arguments>,
+    pub session: &'a mut Session,
+    /// Effects of the writes applied, in order, to propagate once the command returns
+    pub effects: Vec<Vec<Resp>>,
+}
+
+impl<'a> Ctx<'a> {
+    pub const fn new(db: &'a Arc<Db>, args: &'a Arc<Arguments>, session: &'a mut Session) -> Self {
+        Self {
+            db,
+            args,
+            session,
+            effects: Vec::new(),
+        }
+    }
+}
+
+/// State of the connection a command is called from.
+/// The default is the server itself, replaying the AOF or its master's writes.
+#[derive(Debug, Default)]
+pub struct Session {
+    /// Id the client is registered with, 0 for the server itself
+    pub id: u64,
+    /// ACL user the connection is authenticated as
+    pub user: Option<String>,
+    pub protocol: Protocol,
+    /// Address the peer connected to
+    pub local_addr: Option<SocketAddr>,
+    /// Set by `CLIENT CACHING` for the next command only
+    pub caching: Option<bool>,
+    /// Out-of-band data for the connection, like invalidations of the keys it tracks
+    pub pushes: Option<mpsc::UnboundedSender<Resp>>,
+    /// Announced by a replica with `REPLCONF capa eof`: it can load a RDB
+    /// sent with a delimiter rather than its length
+    pub capa_eof: bool,
+    /// Port a replica serves clients on, announced with `REPLCONF listening-port`
+    pub listening_port: Option<u16>,
+    /// Set by `ASKING` for the next command only
+    pub asking: bool,
+}
+
+/// Where a command is called from, which its flags may forbid
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Caller {
+    /// The server replicates a master, so only the replication link writes
+    pub replica: bool,
+    /// Queued by MULTI
+    pub transaction: bool,
+    pub script: bool,
+}
+
 #[derive(Debug)]
 pub enum Command {
     Ping(Ping),
@@ -75,52 +254,483 @@ pub enum Command {
     Config(Config),
     Keys(Keys),
     Type(Type),
+    Object(Object),
     Xadd(Xadd),
     Xrange(Xrange),
     Xread(Xread),
+    Xdel(Xdel),
+    Xsetid(Xsetid),
+    Xgroup(Xgroup),
+    Xreadgroup(Xreadgroup),
+    Xack(Xack),
+    Xautoclaim(Xautoclaim),
+    Hset(Hset),
+    Hget(Hget),
+    Hdel(Hdel),
+    Hexpire(Hexpire),
+    Httl(Httl),
+    Hpersist(Hpersist),
+    Zadd(Zadd),
+    Zrank(Zrank),
+    Zrange(Zrange),
+    Zcount(Zcount),
+    Subscribe(Subscribe),
+    Unsubscribe(Unsubscribe),
+    Publish(Publish),
+    Pubsub(Pubsub),
+    Client(Client),
     Incr(Incr),
     Multi(Multi),
     Exec,
     Discard(Discard),
+    Hello(Hello),
+    Save(Save),
+    Bgsave(Bgsave),
+    Acl(Acl),
+    Auth(Auth),
+    Cluster(Cluster),
+    Asking(Asking),
+    Select(Select),
+    Introspect(Introspect),
+    Debug(Debugging),
+    #[cfg(feature = "scripting")]
+    Eval(Eval),
+    #[cfg(feature = "scripting")]
+    Script(Script),
+    #[cfg(feature = "scripting")]
+    Function(Function),
 }
 
 impl Command {
-    pub fn parse(resp: &Resp) -> anyhow::Result<(Self, Vec<Resp>)> {
-        let Some(raw_cmd) = resp.as_array() else {
-            bail!("Unsupported RESP for command");
+    /// Whether the command modifies the dataset, as its spec's `write` flag tells.
+    /// Only some FUNCTION subcommands write, so they're asked instead.
+    fn writes(&self, spec: &CommandSpec) -> bool {
+        match self {
+            #[cfg(feature = "scripting")]
+            Self::Function(function) => function.is_write(),
+            _ => spec.has_flag("write"),
+        }
+    }
+
+    /// Like [`Command::writes`], for the command parsed from `raw_cmd`
+    pub(crate) fn is_write(&self, raw_cmd: &[Resp]) -> bool {
+        CommandSpec::of(raw_cmd).is_some_and(|spec| self.writes(spec))
+    }
+
+    /// Rejects the command if its flags forbid it where it's called from
+    pub(crate) fn check_flags(&self, spec: &CommandSpec, caller: Caller) -> Result<(), RedisError> {
+        if caller.script && spec.has_flag("noscript") {
+            return Err(RedisError::NotFromScript);
+        }
+        if caller.transaction && spec.has_flag("no_multi") {
+            return Err(RedisError::NotInTransaction);
+        }
+        if caller.replica && self.writes(spec) {
+            return Err(RedisError::ReadOnly);
+        }
+        Ok(())
+    }
+
+    /// Runs the command, recording the writes it applies in `ctx.effects`
+    pub(crate) fn execute(self, ctx: &mut Ctx<'_>, raw_cmd: Vec<Resp>) -> anyhow::Result<Resp> {
+        if self.is_write(&raw_cmd) {
+            let (resp, effect) = self.execute_effect(ctx, raw_cmd)?;
+            TRACKING.invalidate_command(&effect, ctx.session.id);
+            ctx.effects.push(effect);
+            return Ok(resp);
+        }
+        TRACKING.remember(ctx.session.id, ctx.session.caching, &raw_cmd);
+        match self {
+            Self::Ping(ping) => ping.execute(ctx),
+            Self::Echo(echo) => echo.execute(ctx),
+            Self::Get(get) => get.execute(ctx),
+            Self::Info(info) => info.execute(ctx),
+            Self::ReplConf(replconf) => replconf.execute(ctx),
+            Self::Config(config) => config.execute(ctx),
+            Self::Keys(keys) => keys.execute(ctx),
+            Self::Type(r#type) => r#type.execute(ctx),
+            Self::Object(object) => object.execute(ctx),
+            Self::Xrange(xrange) => xrange.execute(ctx),
+            Self::Xread(xread) => xread.execute(ctx),
+            Self::Hget(hget) => hget.execute(ctx),
+            Self::Httl(httl) => httl.execute(ctx),
+            Self::Zrank(zrank) => zrank.execute(ctx),
+            Self::Zrange(zrange) => zrange.execute(ctx),
+            Self::Zcount(zcount) => zcount.execute(ctx),
+            Self::Publish(publish) => {
+                // For the subscribers of the replicas
+                ctx.effects.push(raw_cmd);
+                publish.execute(ctx)
+            }
+            Self::Pubsub(pubsub) => pubsub.execute(ctx),
+            Self::Client(client) => client.execute(ctx),
+            Self::Hello(hello) => hello.execute(ctx),
+            Self::Save(save) => save.execute(ctx),
+            Self::Bgsave(bgsave) => bgsave.execute(ctx),
+            Self::Acl(acl) => acl.execute(ctx),
+            Self::Auth(auth) => auth.execute(ctx),
+            Self::Cluster(cluster) => cluster.execute(ctx),
+            Self::Asking(asking) => asking.execute(ctx),
+            Self::Select(select) => select.execute(ctx),
+            Self::Introspect(introspect) => introspect.execute(ctx),
+            Self::Debug(debug) => debug.execute(ctx),
+            #[cfg(feature = "scripting")]
+            Self::Eval(eval) => eval.execute(ctx),
+            #[cfg(feature = "scripting")]
+            Self::Script(script) => script.execute(ctx),
+            #[cfg(feature = "scripting")]
+            Self::Function(function) => function.execute(ctx),
+            // WAIT, PSYNC, MULTI, SUBSCRIBE and such act on the connection,
+            // so only its handler runs them
+            _ => bail!("ERR Command not allowed in this context"),
+        }
+    }
+
+    /// Applies a write command to the dataset. Clients and the replication link
+    /// both go through here, so every write is applied the same way on replicas.
+    pub(crate) fn execute_write(self, ctx: &mut Ctx<'_>) -> anyhow::Result<Resp> {
+        let resp = match self {
+            Self::Set(set) => set.execute(ctx),
+            Self::Del(del) => del.execute(ctx),
+            Self::Incr(incr) => incr.execute(ctx),
+            Self::Xadd(xadd) => xadd.execute(ctx),
+            Self::Xdel(xdel) => xdel.execute(ctx),
+            Self::Xsetid(xsetid) => xsetid.execute(ctx),
+            Self::Xgroup(xgroup) => xgroup.execute(ctx),
+            Self::Xreadgroup(xreadgroup) => xreadgroup.execute(ctx),
+            Self::Xack(xack) => xack.execute(ctx),
+            Self::Xautoclaim(xautoclaim) => xautoclaim.execute(ctx),
+            Self::Hset(hset) => hset.execute(ctx),
+            Self::Hdel(hdel) => hdel.execute(ctx),
+            Self::Hexpire(hexpire) => hexpire.execute(ctx),
+            Self::Hpersist(hpersist) => hpersist.execute(ctx),
+            Self::Zadd(zadd) => zadd.execute(ctx),
+            #[cfg(feature = "scripting")]
+            Self::Function(function) => function.execute(ctx),
+            other => bail!("Not a write command: {other:?}"),
+        }?;
+        ctx.db.persistence.incr_dirty(1);
+        Ok(resp)
+    }
+
+    /// Applies a write like [`Self::execute_write`], also returning its deterministic
+    /// effect to propagate, so replicas converge to the same dataset
+    pub(crate) fn execute_effect(
+        mut self,
+        ctx: &mut Ctx<'_>,
+        mut effect: Vec<Resp>,
+    ) -> anyhow::Result<(Resp, Vec<Resp>)> {
+        let now = ctx.db.clock.now();
+        let generated_id = match &mut self {
+            Self::Set(set) => {
+                set.rewrite_effect(&mut effect, now)?;
+                false
+            }
+            Self::Hexpire(hexpire) => {
+                hexpire.rewrite_effect(&mut effect, now)?;
+                false
+            }
+            Self::Xadd(xadd) => !matches!(xadd.id, MaybeAuto::Set(_)),
+            _ => false,
         };
+        let resp = self.execute_write(ctx)?;
+        if generated_id {
+            // XADD key id field value ...
+            effect[2] = resp.clone();
+        }
+        Ok((resp, effect))
+    }
 
-        let mut values = raw_cmd.iter();
-        let Some(command) = values.next().and_then(Resp::as_bulk) else {
-            bail!("Expected bulk string");
+    /// Runs a command called by a script, recording its write in `ctx.effects`.
+    /// Commands flagged `noscript` depend on the connection, and blocking isn't allowed.
+    #[cfg(feature = "scripting")]
+    pub(crate) fn execute_scripted(
+        self,
+        ctx: &mut Ctx<'_>,
+        raw_cmd: Vec<Resp>,
+    ) -> anyhow::Result<Resp> {
+        let Some(spec) = CommandSpec::of(&raw_cmd) else {
+            bail!("ERR Unknown Redis command called from script");
+        };
+        let caller = Caller {
+            replica: ctx.db.is_replica(),
+            script: true,
+            ..Caller::default()
         };
+        self.check_flags(spec, caller)?;
+        ensure!(!self.may_block(), RedisError::NotFromScript);
+        self.execute(ctx, raw_cmd)
+    }
 
-        let parsed_cmd = match command.to_ascii_lowercase().as_slice() {
-            b"ping" => Self::Ping(Ping::parse(values)),
-            b"echo" => Self::Echo(Echo::parse(values)?),
-            b"get" => Self::Get(Get::parse(values)?),
-            b"set" => Self::Set(Set::parse(values)?),
-            b"del" => Self::Del(Del::parse(values)),
-            b"info" => Self::Info(Info::parse(values)),
-            b"replconf" => Self::ReplConf(ReplConf::parse(values)?),
-            b"wait" => Self::Wait(Wait::parse(values)?),
-            b"psync" => Self::Psync(Psync::parse(values)?),
-            b"config" => Self::Config(Config::parse(values)?),
-            b"keys" => Self::Keys(Keys::parse(values)?),
-            b"type" => Self::Type(Type::parse(values)?),
-            b"xadd" => Self::Xadd(Xadd::parse(values)?),
-            b"xrange" => Self::Xrange(Xrange::parse(values)?),
-            b"xread" => Self::Xread(Xread::parse(values)?),
-            b"incr" => Self::Incr(Incr::parse(values)?),
-            b"multi" => Self::Multi(Multi::parse(values)?),
-            b"discard" => Self::Discard(Discard::parse(values)?),
-            b"exec" => {
-                Exec::parse(values)?;
-                Self::Exec
+    /// Whether executing the command can suspend the connection
+    pub(crate) const fn may_block(&self) -> bool {
+        match self {
+            Self::Wait(_) => true,
+            Self::Xread(xread) => xread.blocks(),
+            _ => false,
+        }
+    }
+
+    pub fn parse(resp: &Resp) -> anyhow::Result<(Self, Vec<Resp>)> {
+        let raw_cmd = resp
+            .as_array()
+            .filter(|args| args.iter().all(|arg| arg.as_bulk().is_some()))
+            .ok_or(RedisError::NotBulkArray)?;
+
+        let mut values = raw_cmd.iter();
+        let command = values
+            .next()
+            .and_then(Resp::as_bulk)
+            .ok_or(RedisError::NotBulkArray)?;
+        let spec =
+            CommandSpec::lookup(command).ok_or_else(|| RedisError::unknown_command(raw_cmd))?;
+        ensure!(
+            spec.check_arity(raw_cmd.len()),
+            RedisError::WrongArity(spec.name.to_owned())
+        );
+
+        // Errors without a code of their own are about arguments Redis wouldn't expect
+        let parsed_cmd = (spec.parse)(values).map_err(|e| {
+            let message = e.to_string();
+            let code = message.split(' ').next().unwrap_or_default();
+            if !code.is_empty() && code.bytes().all(|b| b.is_ascii_uppercase()) {
+                return e;
             }
-            _ => unimplemented!("{command:?} {:?}", &raw_cmd[1..]),
-        };
+            tracing::debug!("Invalid {} command: {e:#}", spec.name);
+            RedisError::Syntax.into()
+        })?;
         tracing::debug!("Parsed command: {parsed_cmd:#?}");
         Ok((parsed_cmd, raw_cmd.to_owned()))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::clock::Expiry;
+
+    use super::*;
+
+    fn parse(args: &[&'static str]) -> anyhow::Result<Command> {
+        let resp = Resp::Array(args.iter().copied().map(Resp::bulk).collect());
+        Command::parse(&resp).map(|(cmd, _)| cmd)
+    }
+
+    #[test]
+    fn unknown_command() {
+        pretty_assertions::assert_eq!(
+            parse(&["foo", "a", "b"])
+                .unwrap_err()
+                .downcast::<RedisError>()
+                .unwrap(),
+            RedisError::UnknownCommand {
+                name: "foo".to_owned(),
+                args: vec!["a".to_owned(), "b".to_owned()],
+            }
+        );
+    }
+
+    #[test]
+    fn arity() {
+        pretty_assertions::assert_eq!(
+            parse(&["GET"])
+                .unwrap_err()
+                .downcast::<RedisError>()
+                .unwrap(),
+            RedisError::WrongArity("get".to_owned())
+        );
+        pretty_assertions::assert_eq!(
+            parse(&["xadd", "s", "*"])
+                .unwrap_err()
+                .downcast::<RedisError>()
+                .unwrap(),
+            RedisError::WrongArity("xadd".to_owned())
+        );
+        assert!(parse(&["ping"]).is_ok());
+        assert!(parse(&["ping", "hi"]).is_ok());
+    }
+
+    #[test]
+    fn invalid_arguments() {
+        let error = |resp: &Resp| {
+            Command::parse(resp)
+                .unwrap_err()
+                .downcast::<RedisError>()
+                .unwrap()
+        };
+        let args =
+            |args: &[&'static str]| Resp::Array(args.iter().copied().map(Resp::bulk).collect());
+        pretty_assertions::assert_eq!(
+            error(&Resp::Array(vec![Resp::bulk("GET"), Resp::Integer(1)])),
+            RedisError::NotBulkArray
+        );
+        pretty_assertions::assert_eq!(error(&Resp::Array(vec![])), RedisError::NotBulkArray);
+        pretty_assertions::assert_eq!(
+            error(&args(&["SET", "k", "v", "EX", "soon"])),
+            RedisError::NotInteger
+        );
+        for (unit, time) in [
+            ("EX", "0"),
+            ("PX", "-1"),
+            ("EX", "9223372036854775807"),
+            ("EXAT", "9223372036854775807"),
+            ("PX", "18446744073709551615"),
+            ("PXAT", "18446744073709551615"),
+        ] {
+            pretty_assertions::assert_eq!(
+                error(&args(&["SET", "k", "v", unit, time])),
+                RedisError::InvalidExpireTime("set")
+            );
+        }
+        pretty_assertions::assert_eq!(
+            error(&args(&[
+                "HEXPIRE",
+                "h",
+                "9223372036854775807",
+                "FIELDS",
+                "1",
+                "f"
+            ])),
+            RedisError::InvalidExpireTime("hexpire")
+        );
+        pretty_assertions::assert_eq!(
+            error(&args(&["XREAD", "COUNT", "1", "keys", "s", "0"])),
+            RedisError::Syntax
+        );
+        pretty_assertions::assert_eq!(
+            parse(&["SELECT", "-1"]).unwrap_err().to_string(),
+            "ERR DB index is out of range"
+        );
+        pretty_assertions::assert_eq!(
+            error(&args(&["XREAD", "STREAMS", "s", "t", "0"])),
+            RedisError::UnbalancedStreams {
+                command: "xread",
+                id: "$"
+            }
+        );
+    }
+
+    #[test]
+    fn flags() {
+        // ACL categories agree with the flags, so @write or @admin rules cover them
+        for spec in CommandSpec::all() {
+            let categories = spec.categories;
+            pretty_assertions::assert_eq!(
+                spec.has_flag("readonly"),
+                categories.contains(&"read"),
+                "{}",
+                spec.name
+            );
+            assert!(!spec.has_flag("write") || categories.contains(&"write"));
+            assert!(
+                !spec.has_flag("admin")
+                    || categories.contains(&"admin") && categories.contains(&"dangerous")
+            );
+        }
+
+        let check = |args: &[&'static str], caller: Caller| {
+            let resp = Resp::Array(args.iter().copied().map(Resp::bulk).collect());
+            let (cmd, raw) = Command::parse(&resp).unwrap();
+            cmd.check_flags(CommandSpec::of(&raw).unwrap(), caller)
+        };
+        let replica = Caller {
+            replica: true,
+            ..Caller::default()
+        };
+        pretty_assertions::assert_eq!(
+            check(&["SET", "k", "v"], replica),
+            Err(RedisError::ReadOnly)
+        );
+        pretty_assertions::assert_eq!(check(&["GET", "k"], replica), Ok(()));
+        #[cfg(feature = "scripting")]
+        {
+            let library = "#!lua name=lib\nredis.register_function('f', function() end)";
+            pretty_assertions::assert_eq!(
+                check(&["FUNCTION", "LOAD", library], replica),
+                Err(RedisError::ReadOnly)
+            );
+            pretty_assertions::assert_eq!(check(&["FUNCTION", "LIST"], replica), Ok(()));
+        }
+        let transaction = Caller {
+            transaction: true,
+            ..Caller::default()
+        };
+        pretty_assertions::assert_eq!(
+            check(&["SUBSCRIBE", "c"], transaction),
+            Err(RedisError::NotInTransaction)
+        );
+        pretty_assertions::assert_eq!(check(&["SET", "k", "v"], transaction), Ok(()));
+        let script = Caller {
+            script: true,
+            ..Caller::default()
+        };
+        pretty_assertions::assert_eq!(
+            check(&["CONFIG", "GET", "port"], script),
+            Err(RedisError::NotFromScript)
+        );
+        pretty_assertions::assert_eq!(check(&["GET", "k"], script), Ok(()));
+    }
+
+    #[test]
+    fn set_effect() {
+        let raw = ["SET", "k", "v", "PX", "100"].map(Resp::bulk).to_vec();
+        let Command::Set(mut set) = Command::parse(&Resp::Array(raw.clone())).unwrap().0 else {
+            unreachable!();
+        };
+        let mut effect = raw;
+        let now = std::time::UNIX_EPOCH + Duration::from_secs(1);
+        set.rewrite_effect(&mut effect, now).unwrap();
+
+        pretty_assertions::assert_eq!(effect, ["SET", "k", "v", "PXAT", "1100"].map(Resp::bulk));
+        // Applied at the time it's propagated with
+        pretty_assertions::assert_eq!(
+            set.expiry,
+            Some(Expiry::At(now + Duration::from_millis(100)))
+        );
+
+        // Valid, but past the latest expiration once added to the time
+        let raw = ["SET", "k", "v", "PX", "9223372036854775807"].map(Resp::bulk);
+        let Command::Set(mut set) = Command::parse(&Resp::Array(raw.to_vec())).unwrap().0 else {
+            unreachable!();
+        };
+        let error = set.rewrite_effect(&mut raw.to_vec(), now).unwrap_err();
+        pretty_assertions::assert_eq!(
+            error.downcast::<RedisError>().unwrap(),
+            RedisError::InvalidExpireTime("set")
+        );
+    }
+
+    #[test]
+    fn effects() {
+        let db = Arc::new(Db::default());
+        let args = Arc::new(Arguments::default());
+        let mut session = Session::default();
+        let mut ctx = Ctx::new(&db, &args, &mut session);
+        let mut run = |args: &[&'static str]| {
+            let (cmd, raw) =
+                Command::parse(&Resp::Array(args.iter().copied().map(Resp::bulk).collect()))
+                    .unwrap();
+            cmd.execute(&mut ctx, raw).unwrap()
+        };
+
+        pretty_assertions::assert_eq!(run(&["SET", "k", "1"]), Resp::simple("OK"));
+        pretty_assertions::assert_eq!(run(&["INCR", "k"]), Resp::Integer(2));
+        pretty_assertions::assert_eq!(run(&["GET", "k"]), Resp::bulk("2"));
+        let id = run(&["XADD", "s", "*", "f", "v"]);
+        pretty_assertions::assert_eq!(
+            ctx.effects,
+            [
+                ["SET", "k", "1"].map(Resp::bulk).to_vec(),
+                ["INCR", "k"].map(Resp::bulk).to_vec(),
+                vec![
+                    Resp::bulk("XADD"),
+                    Resp::bulk("s"),
+                    id,
+                    Resp::bulk("f"),
+                    Resp::bulk("v")
+                ],
+            ]
+        );
+    }
+}
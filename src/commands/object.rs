@@ -0,0 +1,37 @@
+use anyhow::{bail, Context};
+
+use crate::{RedisError, Resp};
+
+use super::{CommandExec, Ctx, IterResp};
+
+#[derive(Debug)]
+pub enum Object {
+    Encoding(String),
+}
+
+impl Object {
+    pub(super) fn parse(mut i: IterResp) -> anyhow::Result<Self> {
+        let sub = i.next().context("Missing subcommand")?.to_string()?;
+        match sub.to_ascii_lowercase().as_str() {
+            "encoding" => {
+                let key = i
+                    .next()
+                    .context(RedisError::WrongArity("object|encoding".to_owned()))?
+                    .to_string()?;
+                Ok(Self::Encoding(key))
+            }
+            _ => bail!(RedisError::UnknownSubcommand {
+                command: "OBJECT",
+                sub
+            }),
+        }
+    }
+}
+
+impl CommandExec for Object {
+    fn execute(self, ctx: &mut Ctx<'_>) -> anyhow::Result<Resp> {
+        let Self::Encoding(key) = self;
+        let encoding = ctx.db.view(&key, |v| v.v_type.encoding());
+        Ok(encoding.map_or(Resp::Null, Resp::bulk))
+    }
+}
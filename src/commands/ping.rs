@@ -2,7 +2,7 @@ use bytes::Bytes;
 
 use crate::Resp;
 
-use super::IterResp;
+use super::{CommandExec, Ctx, IterResp};
 
 #[derive(Debug)]
 pub struct Ping {
@@ -19,8 +19,12 @@ impl Ping {
         Self { msg }
     }
 
-    pub fn execute(self) -> Resp {
-        self.msg.map_or_else(|| Resp::simple("PONG"), Resp::Bulk)
+    /// Reply used while the connection is in subscribed mode
+    pub fn execute_subscribed(self) -> Resp {
+        Resp::Array(vec![
+            Resp::bulk("pong"),
+            Resp::Bulk(self.msg.unwrap_or_default()),
+        ])
     }
 
     pub(crate) fn into_resp(self) -> Resp {
@@ -31,3 +35,9 @@ impl Ping {
         Resp::Array(v)
     }
 }
+
+impl CommandExec for Ping {
+    fn execute(self, _ctx: &mut Ctx<'_>) -> anyhow::Result<Resp> {
+        Ok(self.msg.map_or_else(|| Resp::simple("PONG"), Resp::Bulk))
+    }
+}
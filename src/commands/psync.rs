@@ -1,9 +1,8 @@
 use std::fmt::Display;
 
 use anyhow::Context;
-use bytes::Bytes;
 
-use crate::{slice_to_int, Master, Resp};
+use crate::{slice_to_int, Db, Master, Resp};
 
 use super::IterResp;
 
@@ -37,12 +36,10 @@ impl Psync {
     }
 
     #[allow(clippy::unused_self)]
-    pub fn execute(&self, master: &Master) -> anyhow::Result<(Resp, Resp)> {
-        let master_replid = master.replid();
-        let master_repl_offset = master.repl_offset();
-
-        let resp = Resp::Simple(format!("FULLRESYNC {master_replid} {master_repl_offset}"));
-        Ok((resp, get_data()?))
+    pub fn execute(&self, master: &Master, db: &Db) -> (Resp, Resp) {
+        let repl = master.repl_info();
+        let resp = Resp::Simple(format!("FULLRESYNC {} {}", repl.id, repl.offset));
+        (resp, Resp::Data(db.dump_rdb(Some(&repl))))
     }
 
     pub(crate) fn into_resp(self) -> Resp {
@@ -68,16 +65,3 @@ impl Display for Offset {
         }
     }
 }
-
-fn get_data() -> anyhow::Result<Resp> {
-    // TODO
-    const DATA: &str = "524544495330303131fa0972656469732d76657\
-    205372e322e30fa0a72656469732d62697473c040fa056374696d65\
-    c26d08bc65fa08757365642d6d656dc2b0c41000fa08616f662d626\
-    17365c000fff06e3bfec0ff5aa2";
-
-    hex::decode(DATA)
-        .map_err(anyhow::Error::from)
-        .map(Bytes::from)
-        .map(Resp::Data)
-}
@@ -0,0 +1,27 @@
+use anyhow::Context;
+use bytes::Bytes;
+
+use crate::{Resp, PUBSUB};
+
+use super::{CommandExec, Ctx, IterResp};
+
+#[derive(Debug)]
+pub struct Publish {
+    channel: Bytes,
+    message: Bytes,
+}
+
+impl Publish {
+    pub(super) fn parse(mut i: IterResp) -> anyhow::Result<Self> {
+        let channel = i.next().context("Missing channel")?.to_bytes()?;
+        let message = i.next().context("Missing message")?.to_bytes()?;
+        Ok(Self { channel, message })
+    }
+}
+
+impl CommandExec for Publish {
+    fn execute(self, _ctx: &mut Ctx<'_>) -> anyhow::Result<Resp> {
+        let receivers = PUBSUB.publish(&self.channel, &self.message);
+        Ok(Resp::Integer(receivers.try_into()?))
+    }
+}
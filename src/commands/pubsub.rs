@@ -0,0 +1,54 @@
+use anyhow::{bail, Context};
+use bytes::Bytes;
+
+use crate::{RedisError, Resp, PUBSUB};
+
+use super::{CommandExec, Ctx, IterResp};
+
+#[derive(Debug)]
+pub enum Pubsub {
+    Channels(Option<Bytes>),
+    Numsub(Vec<Bytes>),
+    Numpat,
+}
+
+impl Pubsub {
+    pub(super) fn parse(mut i: IterResp) -> anyhow::Result<Self> {
+        let Some(arg) = i.next().context("Missing args")?.as_bulk() else {
+            bail!("Expected bulk string");
+        };
+        Ok(match arg.to_ascii_lowercase().as_slice() {
+            b"channels" => Self::Channels(i.next().map(Resp::to_bytes).transpose()?),
+            b"numsub" => Self::Numsub(i.map(Resp::to_bytes).collect::<anyhow::Result<_>>()?),
+            b"numpat" => Self::Numpat,
+            _ => bail!(RedisError::UnknownSubcommand {
+                command: "PUBSUB",
+                sub: String::from_utf8_lossy(arg).into_owned()
+            }),
+        })
+    }
+}
+
+impl CommandExec for Pubsub {
+    fn execute(self, _ctx: &mut Ctx<'_>) -> anyhow::Result<Resp> {
+        Ok(match self {
+            Self::Channels(pattern) => Resp::Array(
+                PUBSUB
+                    .channels(pattern.as_ref())
+                    .into_iter()
+                    .map(Resp::Bulk)
+                    .collect(),
+            ),
+            Self::Numsub(channels) => {
+                let mut v = Vec::with_capacity(channels.len() * 2);
+                for channel in channels {
+                    let numsub = PUBSUB.numsub(&channel);
+                    v.push(Resp::Bulk(channel));
+                    v.push(Resp::Integer(numsub.try_into()?));
+                }
+                Resp::Array(v)
+            }
+            Self::Numpat => Resp::Integer(PUBSUB.numpat().try_into()?),
+        })
+    }
+}
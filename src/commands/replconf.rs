@@ -3,12 +3,13 @@ use bytes::Bytes;
 
 use crate::{Resp, Slave};
 
-use super::IterResp;
+use super::{CommandExec, Ctx, IterResp};
 
 #[derive(Debug)]
 pub enum ReplConf {
     ListeningPort(u16),
-    Capa(Bytes),
+    /// Capabilities of a replica, given as `capa <name>` pairs
+    Capa(Vec<Bytes>),
     GetAck,
     Ack(u64),
 }
@@ -24,7 +25,15 @@ impl ReplConf {
                 Self::ListeningPort(port)
             }
             b"capa" => {
-                let capas = i.next().context("Missing capa")?.to_bytes()?;
+                let mut capas = vec![i.next().context("Missing capa")?.to_bytes()?];
+                while let Some(arg) = i.next() {
+                    ensure!(
+                        arg.as_bulk()
+                            .is_some_and(|x| x.eq_ignore_ascii_case(b"capa")),
+                        "ERR syntax error"
+                    );
+                    capas.push(i.next().context("Missing capa")?.to_bytes()?);
+                }
                 Self::Capa(capas)
             }
             b"getack" => {
@@ -38,15 +47,13 @@ impl ReplConf {
                 let offset = i.next().context("Missing offset")?.to_int()?;
                 Self::Ack(offset)
             }
-            _ => todo!(),
+            _ => bail!(
+                "ERR Unrecognized REPLCONF option: {}",
+                String::from_utf8_lossy(arg)
+            ),
         })
     }
 
-    #[allow(clippy::unused_self)]
-    pub fn execute(&self) -> Resp {
-        Resp::simple("OK")
-    }
-
     pub fn execute_slave(&self, slave: &Slave) -> anyhow::Result<Resp> {
         let Self::GetAck = self else {
             bail!("Expected getack");
@@ -60,7 +67,15 @@ impl ReplConf {
         let replconf = Resp::bulk("REPLCONF");
         match self {
             Self::GetAck => Resp::Array(vec![replconf, Resp::bulk("GETACK"), Resp::bulk("*")]),
-            Self::Capa(capa) => Resp::Array(vec![replconf, Resp::bulk("capa"), Resp::Bulk(capa)]),
+            Self::Capa(capas) => Resp::Array(
+                std::iter::once(replconf)
+                    .chain(
+                        capas
+                            .into_iter()
+                            .flat_map(|capa| [Resp::bulk("capa"), Resp::Bulk(capa)]),
+                    )
+                    .collect(),
+            ),
             Self::Ack(offset) => Resp::Array(vec![
                 replconf,
                 Resp::bulk("ACK"),
@@ -74,3 +89,16 @@ impl ReplConf {
         }
     }
 }
+
+impl CommandExec for ReplConf {
+    fn execute(self, ctx: &mut Ctx<'_>) -> anyhow::Result<Resp> {
+        match &self {
+            Self::Capa(capas) if capas.iter().any(|capa| capa.eq_ignore_ascii_case(b"eof")) => {
+                ctx.session.capa_eof = true;
+            }
+            Self::ListeningPort(port) => ctx.session.listening_port = Some(*port),
+            _ => {}
+        }
+        Ok(Resp::simple("OK"))
+    }
+}
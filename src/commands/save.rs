@@ -0,0 +1,26 @@
+use anyhow::ensure;
+
+use crate::{RedisError, Resp};
+
+use super::{CommandExec, Ctx, IterResp};
+
+#[derive(Debug)]
+pub struct Save;
+
+impl Save {
+    pub(super) fn parse(mut i: IterResp) -> anyhow::Result<Self> {
+        ensure!(
+            i.next().is_none(),
+            RedisError::WrongArity("save".to_owned())
+        );
+        Ok(Self)
+    }
+}
+
+impl CommandExec for Save {
+    fn execute(self, ctx: &mut Ctx<'_>) -> anyhow::Result<Resp> {
+        ctx.db
+            .save(ctx.args.rdb_path(), ctx.args.role.repl_info().as_ref())?;
+        Ok(Resp::simple("OK"))
+    }
+}
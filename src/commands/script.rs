@@ -0,0 +1,80 @@
+use anyhow::{bail, ensure, Context};
+use bytes::Bytes;
+
+use crate::{scripting::SCRIPTS, RedisError, Resp};
+
+use super::{CommandExec, Ctx, IterResp};
+
+#[derive(Debug)]
+pub enum Script {
+    Load(Bytes),
+    Exists(Vec<String>),
+    Flush,
+    /// Interrupts the running script, which other commands wait for
+    Kill,
+}
+
+impl Script {
+    pub(super) fn parse(mut i: IterResp) -> anyhow::Result<Self> {
+        let Some(arg) = i.next().context("Missing args")?.as_bulk() else {
+            bail!("Expected bulk string");
+        };
+        let res = match arg.to_ascii_lowercase().as_slice() {
+            b"load" => Self::Load(
+                i.next()
+                    .with_context(|| RedisError::WrongArity("script|load".to_owned()))?
+                    .to_bytes()?,
+            ),
+            b"exists" => {
+                let shas = i
+                    .by_ref()
+                    .map(Resp::to_string)
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                ensure!(
+                    !shas.is_empty(),
+                    RedisError::WrongArity("script|exists".to_owned())
+                );
+                Self::Exists(shas)
+            }
+            b"flush" => {
+                // Scripts are flushed synchronously either way
+                if let Some(mode) = i.next() {
+                    let mode = mode.to_string()?;
+                    ensure!(
+                        mode.eq_ignore_ascii_case("async") || mode.eq_ignore_ascii_case("sync"),
+                        "ERR SCRIPT FLUSH only support SYNC|ASYNC option"
+                    );
+                }
+                Self::Flush
+            }
+            b"kill" => Self::Kill,
+            _ => bail!(RedisError::UnknownSubcommand {
+                command: "SCRIPT",
+                sub: String::from_utf8_lossy(arg).into_owned()
+            }),
+        };
+        ensure!(i.next().is_none(), RedisError::Syntax);
+        Ok(res)
+    }
+}
+
+impl CommandExec for Script {
+    fn execute(self, ctx: &mut Ctx<'_>) -> anyhow::Result<Resp> {
+        Ok(match self {
+            Self::Load(body) => Resp::bulk(SCRIPTS.load(body)),
+            Self::Exists(shas) => Resp::Array(
+                shas.iter()
+                    .map(|sha| Resp::Integer(SCRIPTS.exists(sha).into()))
+                    .collect(),
+            ),
+            Self::Flush => {
+                SCRIPTS.flush();
+                Resp::simple("OK")
+            }
+            Self::Kill => {
+                ctx.db.busy.kill(false)?;
+                Resp::simple("OK")
+            }
+        })
+    }
+}
@@ -0,0 +1,44 @@
+use anyhow::{ensure, Context};
+
+use crate::{Db, RedisError, Resp};
+
+use super::{CommandExec, Ctx, IterResp};
+
+/// Switches to another logical database, though [`Db::INDEX`] is the only one.
+/// Masters still send it first in their replication stream.
+#[derive(Debug)]
+pub struct Select {
+    pub(crate) index: usize,
+}
+
+impl Select {
+    pub(crate) const fn new(index: usize) -> Self {
+        Self { index }
+    }
+
+    pub(super) fn parse(mut i: IterResp) -> anyhow::Result<Self> {
+        let index = i
+            .next()
+            .context(RedisError::WrongArity("select".to_owned()))?
+            .to_int::<i64>()
+            .map_err(|_| RedisError::NotInteger)?;
+        let index = usize::try_from(index)
+            .ok()
+            .context("ERR DB index is out of range")?;
+        Ok(Self { index })
+    }
+
+    pub(crate) fn into_resp(self) -> Resp {
+        Resp::Array(vec![
+            Resp::bulk("SELECT"),
+            Resp::bulk(self.index.to_string()),
+        ])
+    }
+}
+
+impl CommandExec for Select {
+    fn execute(self, _ctx: &mut Ctx<'_>) -> anyhow::Result<Resp> {
+        ensure!(self.index == Db::INDEX, "ERR DB index is out of range");
+        Ok(Resp::simple("OK"))
+    }
+}
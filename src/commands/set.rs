@@ -1,46 +1,98 @@
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use anyhow::Context;
+use anyhow::{bail, ensure, Context};
 use bytes::Bytes;
 
-use crate::{db::Type, slice_to_int, Resp, DB};
+use crate::{clock::Expiry, db::Type, RedisError, Resp};
 
-use super::IterResp;
+use super::{CommandExec, Ctx, IterResp};
+
+const INVALID: RedisError = RedisError::InvalidExpireTime("set");
 
 #[derive(Debug)]
 pub struct Set {
     pub(crate) key: String,
     pub(crate) value: Type,
-    pub(crate) expiry: Option<SystemTime>,
+    pub(crate) expiry: Option<Expiry>,
 }
 
 impl Set {
     pub fn new(key: String, value: Bytes, expiry: Option<Duration>) -> Self {
-        let value = Type::String(value);
-        let expiry = expiry.map(|x| SystemTime::now() + x);
+        let value = Type::String(value.into());
+        let expiry = expiry.map(Expiry::In);
         Self { key, value, expiry }
     }
 
     pub(super) fn parse(mut i: IterResp) -> anyhow::Result<Self> {
         let key = i.next().context("Missing key")?.to_string()?;
         let value = i.next().context("Missing Value")?.to_bytes()?;
-        let expiry = i.next().and_then(|x| {
-            let expiry = x.as_bulk()?;
-            let dur = i
-                .next()
-                .and_then(|x| x.to_bytes().and_then(slice_to_int).ok());
-
-            match expiry.to_ascii_lowercase().as_slice() {
-                b"px" => dur.map(Duration::from_millis),
-                b"ex" => dur.map(Duration::from_secs),
-                _ => todo!(),
+        let expiry = match i.next() {
+            Some(x) => {
+                // Whether the time is relative, and in seconds
+                let (relative, secs) = match x.to_bytes()?.to_ascii_lowercase().as_slice() {
+                    b"px" => (true, false),
+                    b"ex" => (true, true),
+                    b"pxat" => (false, false),
+                    b"exat" => (false, true),
+                    _ => bail!(RedisError::Syntax),
+                };
+                let time = i
+                    .next()
+                    .context(RedisError::Syntax)?
+                    .to_int::<i128>()
+                    .ok()
+                    .context(RedisError::NotInteger)?;
+                let time = i64::try_from(time)
+                    .ok()
+                    .filter(|&time| time > 0)
+                    .context(INVALID)?;
+                let millis = if secs {
+                    time.checked_mul(1000).context(INVALID)?
+                } else {
+                    time
+                };
+                let time = Duration::from_millis(millis.unsigned_abs());
+                Some(if relative {
+                    Expiry::In(time)
+                } else {
+                    Expiry::At(UNIX_EPOCH.checked_add(time).context(INVALID)?)
+                })
             }
-        });
-        Ok(Self::new(key, value, expiry))
+            None => None,
+        };
+        ensure!(i.next().is_none(), RedisError::Syntax);
+        Ok(Self {
+            key,
+            value: Type::String(value.into()),
+            expiry,
+        })
+    }
+
+    /// Relative expirations are fixed from `now` and replicated as `PXAT`,
+    /// so replicas expire the key at the same time regardless of when they apply the command
+    pub(super) fn rewrite_effect(
+        &mut self,
+        raw_cmd: &mut Vec<Resp>,
+        now: SystemTime,
+    ) -> anyhow::Result<()> {
+        let Some(expiry) = self.expiry else {
+            return Ok(());
+        };
+        let at = expiry.at(now).context(INVALID)?;
+        self.expiry = Some(Expiry::At(at));
+        let at = at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        raw_cmd.truncate(3);
+        raw_cmd.extend([Resp::bulk("PXAT"), Resp::bulk(at.to_string())]);
+        Ok(())
     }
+}
 
-    pub fn execute(self) -> Resp {
-        DB.set(self);
-        Resp::simple("OK")
+impl CommandExec for Set {
+    fn execute(self, ctx: &mut Ctx<'_>) -> anyhow::Result<Resp> {
+        ctx.db.set(self)?;
+        Ok(Resp::simple("OK"))
     }
 }
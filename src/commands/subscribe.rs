@@ -0,0 +1,46 @@
+use anyhow::ensure;
+use bytes::Bytes;
+
+use crate::{pubsub::Subscriber, RedisError, Resp};
+
+use super::IterResp;
+
+#[derive(Debug)]
+pub struct Subscribe {
+    channels: Vec<Bytes>,
+    pattern: bool,
+}
+
+impl Subscribe {
+    pub(super) fn parse(i: IterResp, pattern: bool) -> anyhow::Result<Self> {
+        let channels = i.map(Resp::to_bytes).collect::<anyhow::Result<Vec<_>>>()?;
+        ensure!(
+            !channels.is_empty(),
+            RedisError::WrongArity(Self::kind(pattern).to_owned())
+        );
+        Ok(Self { channels, pattern })
+    }
+
+    /// Returns one confirmation per channel, to be written as separate frames
+    pub fn execute(self, subscriber: &mut Subscriber) -> Vec<Resp> {
+        self.channels
+            .into_iter()
+            .map(|channel| {
+                subscriber.subscribe(channel.clone(), self.pattern);
+                Resp::Array(vec![
+                    Resp::bulk(Self::kind(self.pattern)),
+                    Resp::Bulk(channel),
+                    Resp::Integer(subscriber.count().try_into().unwrap_or(i64::MAX)),
+                ])
+            })
+            .collect()
+    }
+
+    const fn kind(pattern: bool) -> &'static str {
+        if pattern {
+            "psubscribe"
+        } else {
+            "subscribe"
+        }
+    }
+}
@@ -0,0 +1,221 @@
+#[cfg(feature = "scripting")]
+use super::{eval, Eval, Function, Script};
+use super::{
+    hexpire, Acl, Asking, Auth, Bgsave, Client, Cluster, Command, Config, Debugging, Del, Discard,
+    Echo, Exec, Get, Hdel, Hello, Hexpire, Hget, Hpersist, Hset, Httl, Incr, Info, Introspect,
+    IterResp, Keys, Multi, Object, Ping, Psync, Publish, Pubsub, ReplConf, Save, Select, Set,
+    Subscribe, Type, Unsubscribe, Wait, Xack, Xadd, Xautoclaim, Xdel, Xgroup, Xrange, Xread,
+    Xreadgroup, Xsetid, Zadd, Zcount, Zrange, Zrank,
+};
+use crate::Resp;
+
+pub struct CommandSpec {
+    pub name: &'static str,
+    /// Number of arguments including the command name.
+    /// Negative values mean at least `-arity` arguments.
+    pub arity: i32,
+    /// ACL categories, without the leading `@`
+    pub categories: &'static [&'static str],
+    /// Flags reported by `COMMAND INFO`, which [`Command::check_flags`] enforces
+    pub flags: &'static [&'static str],
+    pub keys: KeySpec,
+    pub parse: fn(IterResp) -> anyhow::Result<Command>,
+}
+
+/// Where the keys of a command are in its arguments
+#[derive(Debug, Clone, Copy)]
+pub enum KeySpec {
+    /// From the `first` argument to the `last` one, negative values counting
+    /// from the end, every `step` arguments
+    Range {
+        first: usize,
+        last: isize,
+        step: usize,
+    },
+    /// The first half of the arguments following `STREAMS`
+    Streams,
+    /// As many arguments as the one at `index` says, following it
+    #[cfg_attr(not(feature = "scripting"), allow(dead_code))]
+    Keynum { index: usize },
+}
+
+impl KeySpec {
+    pub const NONE: Self = Self::Range {
+        first: 0,
+        last: -1,
+        step: 0,
+    };
+    pub const FIRST: Self = Self::Range {
+        first: 1,
+        last: 1,
+        step: 1,
+    };
+    pub const SECOND: Self = Self::Range {
+        first: 2,
+        last: 2,
+        step: 1,
+    };
+    pub const ALL: Self = Self::Range {
+        first: 1,
+        last: -1,
+        step: 1,
+    };
+
+    /// First key, last key and step between keys, as reported by `COMMAND INFO`.
+    /// All zeros when the command has no keys, or when finding them takes parsing.
+    pub fn legacy(&self) -> (i64, i64, i64) {
+        match *self {
+            Self::Range { step: 0, .. } | Self::Streams | Self::Keynum { .. } => (0, 0, 0),
+            Self::Range { first, last, step } => (
+                first.try_into().unwrap_or(i64::MAX),
+                last.try_into().unwrap_or(i64::MAX),
+                step.try_into().unwrap_or(i64::MAX),
+            ),
+        }
+    }
+
+    /// Keys in `args`, the command name included
+    pub fn keys<'a>(&self, args: &'a [Resp]) -> Vec<&'a Resp> {
+        match *self {
+            Self::Range { step: 0, .. } => Vec::new(),
+            Self::Range { first, last, step } => {
+                let Ok(len) = isize::try_from(args.len()) else {
+                    return Vec::new();
+                };
+                let last = if last < 0 { len + last } else { last };
+                let Ok(last) = usize::try_from(last) else {
+                    return Vec::new();
+                };
+                args.iter()
+                    .take(last + 1)
+                    .skip(first)
+                    .step_by(step)
+                    .collect()
+            }
+            Self::Streams => {
+                let Some(at) = args.iter().position(|arg| {
+                    arg.as_bulk()
+                        .is_some_and(|arg| arg.eq_ignore_ascii_case(b"streams"))
+                }) else {
+                    return Vec::new();
+                };
+                let rest = &args[at + 1..];
+                rest[..rest.len() / 2].iter().collect()
+            }
+            Self::Keynum { index } => {
+                let count = args
+                    .get(index)
+                    .and_then(|arg| arg.to_int::<usize>().ok())
+                    .unwrap_or_default();
+                args.iter().skip(index + 1).take(count).collect()
+            }
+        }
+    }
+}
+
+impl CommandSpec {
+    pub fn lookup(name: &[u8]) -> Option<&'static Self> {
+        COMMAND_TABLE
+            .iter()
+            .find(|spec| spec.name.as_bytes().eq_ignore_ascii_case(name))
+    }
+
+    /// Spec of the command named by the first of `args`
+    pub fn of(args: &[Resp]) -> Option<&'static Self> {
+        args.first()
+            .and_then(Resp::as_bulk)
+            .and_then(|name| Self::lookup(name))
+    }
+
+    #[inline]
+    pub fn has_flag(&self, flag: &str) -> bool {
+        self.flags.contains(&flag)
+    }
+
+    #[inline]
+    pub fn all() -> &'static [Self] {
+        COMMAND_TABLE
+    }
+
+    pub(super) fn check_arity(&self, len: usize) -> bool {
+        let Ok(len) = i32::try_from(len) else {
+            return false;
+        };
+        if self.arity < 0 {
+            len >= -self.arity
+        } else {
+            len == self.arity
+        }
+    }
+}
+
+#[rustfmt::skip]
+static COMMAND_TABLE: &[CommandSpec] = &[
+    CommandSpec { name: "ping", arity: -1, categories: &["fast", "connection"], flags: &["fast"], keys: KeySpec::NONE, parse: |i| Ok(Command::Ping(Ping::parse(i))) },
+    CommandSpec { name: "echo", arity: 2, categories: &["fast", "connection"], flags: &["fast"], keys: KeySpec::NONE, parse: |i| Echo::parse(i).map(Command::Echo) },
+    CommandSpec { name: "get", arity: 2, categories: &["read", "string", "fast"], flags: &["readonly", "fast"], keys: KeySpec::FIRST, parse: |i| Get::parse(i).map(Command::Get) },
+    CommandSpec { name: "set", arity: -3, categories: &["write", "string", "slow"], flags: &["write"], keys: KeySpec::FIRST, parse: |i| Set::parse(i).map(Command::Set) },
+    CommandSpec { name: "del", arity: -2, categories: &["keyspace", "write", "slow"], flags: &["write"], keys: KeySpec::ALL, parse: |i| Ok(Command::Del(Del::parse(i))) },
+    CommandSpec { name: "info", arity: -1, categories: &["slow", "dangerous"], flags: &["loading", "stale"], keys: KeySpec::NONE, parse: |i| Info::parse(i).map(Command::Info) },
+    CommandSpec { name: "replconf", arity: -1, categories: &["admin", "slow", "dangerous"], flags: &["admin", "noscript", "loading", "stale"], keys: KeySpec::NONE, parse: |i| ReplConf::parse(i).map(Command::ReplConf) },
+    CommandSpec { name: "wait", arity: 3, categories: &["slow", "connection"], flags: &["noscript"], keys: KeySpec::NONE, parse: |i| Wait::parse(i).map(Command::Wait) },
+    CommandSpec { name: "psync", arity: 3, categories: &["admin", "slow", "dangerous"], flags: &["admin", "noscript", "no_multi"], keys: KeySpec::NONE, parse: |i| Psync::parse(i).map(Command::Psync) },
+    CommandSpec { name: "config", arity: -2, categories: &["admin", "slow", "dangerous"], flags: &["admin", "noscript", "loading", "stale"], keys: KeySpec::NONE, parse: |i| Config::parse(i).map(Command::Config) },
+    CommandSpec { name: "keys", arity: 2, categories: &["keyspace", "read", "slow", "dangerous"], flags: &["readonly"], keys: KeySpec::NONE, parse: |i| Keys::parse(i).map(Command::Keys) },
+    CommandSpec { name: "type", arity: 2, categories: &["keyspace", "read", "fast"], flags: &["readonly", "fast"], keys: KeySpec::FIRST, parse: |i| Type::parse(i).map(Command::Type) },
+    CommandSpec { name: "object", arity: -2, categories: &["keyspace", "read", "slow"], flags: &["readonly"], keys: KeySpec::SECOND, parse: |i| Object::parse(i).map(Command::Object) },
+    CommandSpec { name: "xadd", arity: -5, categories: &["write", "stream", "fast"], flags: &["write", "fast"], keys: KeySpec::FIRST, parse: |i| Xadd::parse(i).map(Command::Xadd) },
+    CommandSpec { name: "xrange", arity: -4, categories: &["read", "stream", "slow"], flags: &["readonly"], keys: KeySpec::FIRST, parse: |i| Xrange::parse(i).map(Command::Xrange) },
+    CommandSpec { name: "xread", arity: -4, categories: &["read", "stream", "slow", "blocking"], flags: &["readonly", "blocking"], keys: KeySpec::Streams, parse: |i| Xread::parse(i).map(Command::Xread) },
+    CommandSpec { name: "xdel", arity: -3, categories: &["write", "stream", "fast"], flags: &["write", "fast"], keys: KeySpec::FIRST, parse: |i| Xdel::parse(i).map(Command::Xdel) },
+    CommandSpec { name: "xsetid", arity: -3, categories: &["write", "stream", "fast"], flags: &["write", "fast"], keys: KeySpec::FIRST, parse: |i| Xsetid::parse(i).map(Command::Xsetid) },
+    CommandSpec { name: "xgroup", arity: -2, categories: &["write", "stream", "slow"], flags: &["write"], keys: KeySpec::SECOND, parse: |i| Xgroup::parse(i).map(Command::Xgroup) },
+    CommandSpec { name: "xreadgroup", arity: -7, categories: &["write", "stream", "slow", "blocking"], flags: &["write", "blocking"], keys: KeySpec::Streams, parse: |i| Xreadgroup::parse(i).map(Command::Xreadgroup) },
+    CommandSpec { name: "xack", arity: -4, categories: &["write", "stream", "fast"], flags: &["write", "fast"], keys: KeySpec::FIRST, parse: |i| Xack::parse(i).map(Command::Xack) },
+    CommandSpec { name: "xautoclaim", arity: -6, categories: &["write", "stream", "fast"], flags: &["write", "fast"], keys: KeySpec::FIRST, parse: |i| Xautoclaim::parse(i).map(Command::Xautoclaim) },
+    CommandSpec { name: "hset", arity: -4, categories: &["write", "hash", "fast"], flags: &["write", "fast"], keys: KeySpec::FIRST, parse: |i| Hset::parse(i).map(Command::Hset) },
+    CommandSpec { name: "hget", arity: 3, categories: &["read", "hash", "fast"], flags: &["readonly", "fast"], keys: KeySpec::FIRST, parse: |i| Hget::parse(i).map(Command::Hget) },
+    CommandSpec { name: "hdel", arity: -3, categories: &["write", "hash", "fast"], flags: &["write", "fast"], keys: KeySpec::FIRST, parse: |i| Hdel::parse(i).map(Command::Hdel) },
+    CommandSpec { name: "hexpire", arity: -6, categories: &["write", "hash", "fast"], flags: &["write", "fast"], keys: KeySpec::FIRST, parse: |i| Hexpire::parse(i, hexpire::Kind::Expire).map(Command::Hexpire) },
+    CommandSpec { name: "hpexpire", arity: -6, categories: &["write", "hash", "fast"], flags: &["write", "fast"], keys: KeySpec::FIRST, parse: |i| Hexpire::parse(i, hexpire::Kind::Pexpire).map(Command::Hexpire) },
+    CommandSpec { name: "hexpireat", arity: -6, categories: &["write", "hash", "fast"], flags: &["write", "fast"], keys: KeySpec::FIRST, parse: |i| Hexpire::parse(i, hexpire::Kind::ExpireAt).map(Command::Hexpire) },
+    CommandSpec { name: "hpexpireat", arity: -6, categories: &["write", "hash", "fast"], flags: &["write", "fast"], keys: KeySpec::FIRST, parse: |i| Hexpire::parse(i, hexpire::Kind::PexpireAt).map(Command::Hexpire) },
+    CommandSpec { name: "httl", arity: -5, categories: &["read", "hash", "fast"], flags: &["readonly", "fast"], keys: KeySpec::FIRST, parse: |i| Httl::parse(i, false).map(Command::Httl) },
+    CommandSpec { name: "hpttl", arity: -5, categories: &["read", "hash", "fast"], flags: &["readonly", "fast"], keys: KeySpec::FIRST, parse: |i| Httl::parse(i, true).map(Command::Httl) },
+    CommandSpec { name: "hpersist", arity: -5, categories: &["write", "hash", "fast"], flags: &["write", "fast"], keys: KeySpec::FIRST, parse: |i| Hpersist::parse(i).map(Command::Hpersist) },
+    CommandSpec { name: "zadd", arity: -4, categories: &["write", "sortedset", "fast"], flags: &["write", "fast"], keys: KeySpec::FIRST, parse: |i| Zadd::parse(i).map(Command::Zadd) },
+    CommandSpec { name: "zrank", arity: 3, categories: &["read", "sortedset", "fast"], flags: &["readonly", "fast"], keys: KeySpec::FIRST, parse: |i| Zrank::parse(i).map(Command::Zrank) },
+    CommandSpec { name: "zrange", arity: -4, categories: &["read", "sortedset", "slow"], flags: &["readonly"], keys: KeySpec::FIRST, parse: |i| Zrange::parse(i).map(Command::Zrange) },
+    CommandSpec { name: "zcount", arity: 4, categories: &["read", "sortedset", "fast"], flags: &["readonly", "fast"], keys: KeySpec::FIRST, parse: |i| Zcount::parse(i).map(Command::Zcount) },
+    CommandSpec { name: "subscribe", arity: -2, categories: &["pubsub", "slow"], flags: &["pubsub", "noscript", "no_multi"], keys: KeySpec::NONE, parse: |i| Subscribe::parse(i, false).map(Command::Subscribe) },
+    CommandSpec { name: "psubscribe", arity: -2, categories: &["pubsub", "slow"], flags: &["pubsub", "noscript", "no_multi"], keys: KeySpec::NONE, parse: |i| Subscribe::parse(i, true).map(Command::Subscribe) },
+    CommandSpec { name: "unsubscribe", arity: -1, categories: &["pubsub", "slow"], flags: &["pubsub", "noscript", "no_multi"], keys: KeySpec::NONE, parse: |i| Unsubscribe::parse(i, false).map(Command::Unsubscribe) },
+    CommandSpec { name: "punsubscribe", arity: -1, categories: &["pubsub", "slow"], flags: &["pubsub", "noscript", "no_multi"], keys: KeySpec::NONE, parse: |i| Unsubscribe::parse(i, true).map(Command::Unsubscribe) },
+    CommandSpec { name: "publish", arity: 3, categories: &["pubsub", "fast"], flags: &["pubsub", "fast"], keys: KeySpec::NONE, parse: |i| Publish::parse(i).map(Command::Publish) },
+    CommandSpec { name: "pubsub", arity: -2, categories: &["pubsub", "slow"], flags: &["pubsub"], keys: KeySpec::NONE, parse: |i| Pubsub::parse(i).map(Command::Pubsub) },
+    CommandSpec { name: "hello", arity: -1, categories: &["fast", "connection"], flags: &["noscript", "no_auth", "fast"], keys: KeySpec::NONE, parse: |i| Hello::parse(i).map(Command::Hello) },
+    CommandSpec { name: "client", arity: -2, categories: &["slow", "connection"], flags: &["noscript"], keys: KeySpec::NONE, parse: |i| Client::parse(i).map(Command::Client) },
+    CommandSpec { name: "incr", arity: 2, categories: &["write", "string", "fast"], flags: &["write", "fast"], keys: KeySpec::FIRST, parse: |i| Incr::parse(i).map(Command::Incr) },
+    CommandSpec { name: "multi", arity: 1, categories: &["fast", "transaction"], flags: &["noscript", "fast"], keys: KeySpec::NONE, parse: |i| Multi::parse(i).map(Command::Multi) },
+    CommandSpec { name: "exec", arity: 1, categories: &["slow", "transaction"], flags: &["noscript"], keys: KeySpec::NONE, parse: |i| Exec::parse(i).map(|()| Command::Exec) },
+    CommandSpec { name: "discard", arity: 1, categories: &["fast", "transaction"], flags: &["noscript", "fast"], keys: KeySpec::NONE, parse: |i| Discard::parse(i).map(Command::Discard) },
+    CommandSpec { name: "save", arity: 1, categories: &["admin", "slow", "dangerous"], flags: &["admin", "noscript", "no_multi"], keys: KeySpec::NONE, parse: |i| Save::parse(i).map(Command::Save) },
+    CommandSpec { name: "bgsave", arity: -1, categories: &["admin", "slow", "dangerous"], flags: &["admin", "noscript"], keys: KeySpec::NONE, parse: |i| Bgsave::parse(i).map(Command::Bgsave) },
+    CommandSpec { name: "acl", arity: -2, categories: &["admin", "slow", "dangerous"], flags: &["admin", "noscript"], keys: KeySpec::NONE, parse: |i| Acl::parse(i).map(Command::Acl) },
+    CommandSpec { name: "auth", arity: -2, categories: &["fast", "connection"], flags: &["noscript", "no_auth", "fast"], keys: KeySpec::NONE, parse: |i| Auth::parse(i).map(Command::Auth) },
+    CommandSpec { name: "cluster", arity: -2, categories: &["slow"], flags: &[], keys: KeySpec::NONE, parse: |i| Cluster::parse(i).map(Command::Cluster) },
+    CommandSpec { name: "command", arity: -1, categories: &["slow", "connection"], flags: &["loading", "stale"], keys: KeySpec::NONE, parse: |i| Introspect::parse(i).map(Command::Introspect) },
+    CommandSpec { name: "asking", arity: 1, categories: &["fast", "connection"], flags: &["fast"], keys: KeySpec::NONE, parse: |i| Asking::parse(i).map(Command::Asking) },
+    CommandSpec { name: "select", arity: 2, categories: &["fast", "connection"], flags: &["fast"], keys: KeySpec::NONE, parse: |i| Select::parse(i).map(Command::Select) },
+    CommandSpec { name: "debug", arity: -2, categories: &["admin", "slow", "dangerous"], flags: &["admin", "noscript", "loading", "stale"], keys: KeySpec::NONE, parse: |i| Debugging::parse(i).map(Command::Debug) },
+    #[cfg(feature = "scripting")]
+    CommandSpec { name: "eval", arity: -3, categories: &["slow", "scripting"], flags: &["noscript"], keys: KeySpec::Keynum { index: 2 }, parse: |i| Eval::parse(i, eval::Kind::Eval).map(Command::Eval) },
+    #[cfg(feature = "scripting")]
+    CommandSpec { name: "evalsha", arity: -3, categories: &["slow", "scripting"], flags: &["noscript"], keys: KeySpec::Keynum { index: 2 }, parse: |i| Eval::parse(i, eval::Kind::EvalSha).map(Command::Eval) },
+    #[cfg(feature = "scripting")]
+    CommandSpec { name: "script", arity: -2, categories: &["slow", "scripting"], flags: &["noscript"], keys: KeySpec::NONE, parse: |i| Script::parse(i).map(Command::Script) },
+    #[cfg(feature = "scripting")]
+    CommandSpec { name: "function", arity: -2, categories: &["write", "slow", "scripting"], flags: &["noscript"], keys: KeySpec::NONE, parse: |i| Function::parse(i).map(Command::Function) },
+    #[cfg(feature = "scripting")]
+    CommandSpec { name: "fcall", arity: -3, categories: &["slow", "scripting"], flags: &["noscript"], keys: KeySpec::Keynum { index: 2 }, parse: |i| Eval::parse(i, eval::Kind::Fcall).map(Command::Eval) },
+];
@@ -1,8 +1,8 @@
 use anyhow::Context;
 
-use crate::{db::Type as DbType, Resp, DB};
+use crate::Resp;
 
-use super::IterResp;
+use super::{CommandExec, Ctx, IterResp};
 
 #[derive(Debug)]
 pub struct Type {
@@ -14,16 +14,14 @@ impl Type {
         let key = i.next().context("Missing key")?.to_string()?;
         Ok(Self { key })
     }
+}
 
-    pub fn execute(&self) -> Resp {
-        let ty = DB
-            .inner
-            .read()
-            .get(&self.key)
-            .map_or("none", |v| match v.v_type {
-                DbType::String(_) => "string",
-                DbType::Stream(_) => "stream",
-            });
-        Resp::simple(ty)
+impl CommandExec for Type {
+    fn execute(self, ctx: &mut Ctx<'_>) -> anyhow::Result<Resp> {
+        let ty = ctx
+            .db
+            .view(&self.key, |v| v.v_type.name())
+            .unwrap_or("none");
+        Ok(Resp::simple(ty))
     }
 }
@@ -0,0 +1,66 @@
+use bytes::Bytes;
+
+use crate::{pubsub::Subscriber, Resp};
+
+use super::IterResp;
+
+#[derive(Debug)]
+pub struct Unsubscribe {
+    channels: Vec<Bytes>,
+    pattern: bool,
+}
+
+impl Unsubscribe {
+    pub(super) fn parse(i: IterResp, pattern: bool) -> anyhow::Result<Self> {
+        let channels = i.map(Resp::to_bytes).collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(Self { channels, pattern })
+    }
+
+    /// Returns one confirmation per channel, to be written as separate frames.
+    /// Without arguments every subscription of the kind is removed.
+    pub fn execute(self, subscriber: Option<&mut Subscriber>) -> Vec<Resp> {
+        let kind = Resp::bulk(if self.pattern {
+            "punsubscribe"
+        } else {
+            "unsubscribe"
+        });
+
+        let Some(subscriber) = subscriber else {
+            return self.not_subscribed(&kind);
+        };
+        let channels = if self.channels.is_empty() {
+            subscriber.subscriptions(self.pattern)
+        } else {
+            self.channels
+        };
+        if channels.is_empty() {
+            return vec![Resp::Array(vec![kind, Resp::Null, Resp::Integer(0)])];
+        }
+
+        channels
+            .into_iter()
+            .map(|channel| {
+                subscriber.unsubscribe(&channel, self.pattern);
+                Resp::Array(vec![
+                    kind.clone(),
+                    Resp::Bulk(channel),
+                    Resp::Integer(subscriber.count().try_into().unwrap_or(i64::MAX)),
+                ])
+            })
+            .collect()
+    }
+
+    fn not_subscribed(self, kind: &Resp) -> Vec<Resp> {
+        if self.channels.is_empty() {
+            return vec![Resp::Array(vec![
+                kind.clone(),
+                Resp::Null,
+                Resp::Integer(0),
+            ])];
+        }
+        self.channels
+            .into_iter()
+            .map(|channel| Resp::Array(vec![kind.clone(), Resp::Bulk(channel), Resp::Integer(0)]))
+            .collect()
+    }
+}
@@ -0,0 +1,47 @@
+use anyhow::{ensure, Context};
+use std::{str::from_utf8 as str_utf8, sync::Arc};
+
+use crate::{
+    db::{stream::EntryId, Type},
+    RedisError, Resp,
+};
+
+use super::{CommandExec, Ctx, IterResp};
+
+#[derive(Debug)]
+pub struct Xack {
+    key: String,
+    group: String,
+    ids: Vec<EntryId>,
+}
+
+impl Xack {
+    pub(super) fn parse(mut i: IterResp) -> anyhow::Result<Self> {
+        let key = i.next().context("Missing key")?.to_string()?;
+        let group = i.next().context("Missing group")?.to_string()?;
+        let ids = i
+            .map(|id| {
+                let id = id.as_bulk().context("Invalid id")?;
+                EntryId::split_or_seq(0, str_utf8(id)?)
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        ensure!(!ids.is_empty(), RedisError::WrongArity("xack".to_owned()));
+        Ok(Self { key, group, ids })
+    }
+}
+
+impl CommandExec for Xack {
+    fn execute(self, ctx: &mut Ctx<'_>) -> anyhow::Result<Resp> {
+        let acked = ctx
+            .db
+            .inner
+            .write()
+            .get_mut(&self.key)
+            .and_then(|value| match &mut Arc::make_mut(value).v_type {
+                Type::Stream(stream) => stream.group_mut(&self.group),
+                _ => None,
+            })
+            .map_or(0, |group| group.ack(&self.ids));
+        Ok(Resp::Integer(acked.try_into()?))
+    }
+}
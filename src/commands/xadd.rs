@@ -1,9 +1,9 @@
 use anyhow::{bail, ensure, Context};
 use std::{str::from_utf8 as str_utf8, time::Duration};
 
-use crate::{db::stream::MaybeAuto, Resp, DB};
+use crate::{db::stream::MaybeAuto, Resp};
 
-use super::IterResp;
+use super::{CommandExec, Ctx, IterResp};
 
 #[derive(Debug)]
 pub struct Xadd {
@@ -61,9 +61,11 @@ impl Xadd {
         ensure!(!k_v.is_empty(), "Missing key-value pairs");
         Ok(Self { key, id, k_v })
     }
+}
 
-    pub fn execute(self) -> anyhow::Result<Resp> {
-        let res = DB.xadd(self)?;
+impl CommandExec for Xadd {
+    fn execute(self, ctx: &mut Ctx<'_>) -> anyhow::Result<Resp> {
+        let res = ctx.db.xadd(self)?;
         let resp = Resp::bulk(res);
         Ok(resp)
     }
@@ -0,0 +1,119 @@
+use anyhow::{bail, Context};
+use std::{str::from_utf8 as str_utf8, sync::Arc, time::Duration};
+
+use crate::{
+    db::{stream::EntryId, Type},
+    RedisError, Resp,
+};
+
+use super::{CommandExec, Ctx, IterResp};
+
+#[derive(Debug)]
+pub struct Xautoclaim {
+    key: String,
+    group: String,
+    consumer: String,
+    min_idle: Duration,
+    start: EntryId,
+    count: usize,
+    justid: bool,
+}
+
+impl Xautoclaim {
+    const DEFAULT_COUNT: usize = 100;
+
+    pub(super) fn parse(mut i: IterResp) -> anyhow::Result<Self> {
+        let key = i.next().context("Missing key")?.to_string()?;
+        let group = i.next().context("Missing group")?.to_string()?;
+        let consumer = i.next().context("Missing consumer")?.to_string()?;
+        let min_idle = i
+            .next()
+            .context("Missing min-idle-time")?
+            .to_int()
+            .map(Duration::from_millis)
+            .map_err(|_| RedisError::InvalidMinIdleTime)?;
+        let start = match i
+            .next()
+            .and_then(Resp::as_bulk)
+            .context("Missing start")?
+            .as_ref()
+        {
+            b"-" => EntryId::MIN,
+            start => EntryId::split_or_seq(0, str_utf8(start)?)?,
+        };
+
+        let mut count = Self::DEFAULT_COUNT;
+        let mut justid = false;
+        while let Some(arg) = i.next().and_then(Resp::as_bulk) {
+            match arg.to_ascii_lowercase().as_slice() {
+                b"count" => {
+                    count = i
+                        .next()
+                        .ok_or(RedisError::Syntax)?
+                        .to_int::<usize>()
+                        .ok()
+                        .filter(|count| (1..=usize::MAX / 10).contains(count))
+                        .ok_or(RedisError::CountNotPositive)?;
+                }
+                b"justid" => justid = true,
+                _ => bail!(RedisError::Syntax),
+            }
+        }
+
+        Ok(Self {
+            key,
+            group,
+            consumer,
+            min_idle,
+            start,
+            count,
+            justid,
+        })
+    }
+}
+
+impl CommandExec for Xautoclaim {
+    fn execute(self, ctx: &mut Ctx<'_>) -> anyhow::Result<Resp> {
+        let now = ctx.db.clock.now();
+        let mut lock = ctx.db.inner.write();
+        let stream = match lock
+            .get_mut(&self.key)
+            .map(|value| &mut Arc::make_mut(value).v_type)
+        {
+            Some(Type::Stream(stream)) => Some(stream),
+            Some(_) => {
+                bail!(RedisError::WrongType)
+            }
+            None => None,
+        };
+        let claim = stream
+            .and_then(|stream| {
+                stream.autoclaim(
+                    &self.group,
+                    &self.consumer,
+                    self.min_idle,
+                    self.start,
+                    self.count,
+                    self.justid,
+                    now,
+                )
+            })
+            .ok_or_else(|| RedisError::NoGroup {
+                key: self.key.clone(),
+                group: self.group.clone(),
+                xreadgroup: false,
+            })?;
+        drop(lock);
+
+        let deleted = claim
+            .deleted
+            .iter()
+            .map(|id| Resp::bulk(id.to_string()))
+            .collect();
+        Ok(Resp::Array(vec![
+            Resp::bulk(claim.next.to_string()),
+            Resp::Array(claim.claimed),
+            Resp::Array(deleted),
+        ]))
+    }
+}
@@ -0,0 +1,48 @@
+use anyhow::{bail, ensure, Context};
+use std::{str::from_utf8 as str_utf8, sync::Arc};
+
+use crate::{
+    db::{stream::EntryId, Type},
+    RedisError, Resp,
+};
+
+use super::{CommandExec, Ctx, IterResp};
+
+#[derive(Debug)]
+pub struct Xdel {
+    key: String,
+    ids: Vec<EntryId>,
+}
+
+impl Xdel {
+    pub(super) fn parse(mut i: IterResp) -> anyhow::Result<Self> {
+        let key = i.next().context("Missing key")?.to_string()?;
+        let ids = i
+            .map(|id| {
+                let id = id.as_bulk().context("Invalid id")?;
+                EntryId::split_or_seq(0, str_utf8(id)?)
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        ensure!(!ids.is_empty(), RedisError::WrongArity("xdel".to_owned()));
+        Ok(Self { key, ids })
+    }
+}
+
+impl CommandExec for Xdel {
+    fn execute(self, ctx: &mut Ctx<'_>) -> anyhow::Result<Resp> {
+        let deleted = match ctx
+            .db
+            .inner
+            .write()
+            .get_mut(&self.key)
+            .map(|value| &mut Arc::make_mut(value).v_type)
+        {
+            Some(Type::Stream(stream)) => stream.xdel(&self.ids),
+            Some(_) => {
+                bail!(RedisError::WrongType)
+            }
+            None => 0,
+        };
+        Ok(Resp::Integer(deleted.try_into()?))
+    }
+}
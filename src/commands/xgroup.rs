@@ -0,0 +1,115 @@
+use anyhow::{bail, ensure, Context};
+use std::{str::from_utf8 as str_utf8, sync::Arc};
+
+use crate::{
+    db::{stream::EntryId, Stream, Type, Value},
+    RedisError, Resp,
+};
+
+use super::{CommandExec, Ctx, IterResp};
+
+#[derive(Debug)]
+pub enum Xgroup {
+    Create {
+        key: String,
+        group: String,
+        id: Option<EntryId>,
+        mkstream: bool,
+    },
+    Destroy {
+        key: String,
+        group: String,
+    },
+}
+
+impl Xgroup {
+    pub(super) fn parse(mut i: IterResp) -> anyhow::Result<Self> {
+        let Some(arg) = i.next().context("Missing args")?.as_bulk() else {
+            bail!("Expected bulk string");
+        };
+        Ok(match arg.to_ascii_lowercase().as_slice() {
+            b"create" => {
+                let key = i.next().context("Missing key")?.to_string()?;
+                let group = i.next().context("Missing group")?.to_string()?;
+                let id = i
+                    .next()
+                    .and_then(Resp::as_bulk)
+                    .map(|id| match id.as_ref() {
+                        b"$" => Ok(None),
+                        id => str_utf8(id)
+                            .map_err(anyhow::Error::from)
+                            .and_then(|id| EntryId::split_or_seq(0, id))
+                            .map(Some),
+                    })
+                    .transpose()?
+                    .context("Missing id")?;
+                let mkstream = i
+                    .next()
+                    .and_then(Resp::as_bulk)
+                    .is_some_and(|x| x.eq_ignore_ascii_case(b"mkstream"));
+                Self::Create {
+                    key,
+                    group,
+                    id,
+                    mkstream,
+                }
+            }
+            b"destroy" => {
+                let key = i.next().context("Missing key")?.to_string()?;
+                let group = i.next().context("Missing group")?.to_string()?;
+                Self::Destroy { key, group }
+            }
+            _ => bail!(RedisError::UnknownSubcommand {
+                command: "XGROUP",
+                sub: String::from_utf8_lossy(arg).into_owned()
+            }),
+        })
+    }
+}
+
+impl CommandExec for Xgroup {
+    fn execute(self, ctx: &mut Ctx<'_>) -> anyhow::Result<Resp> {
+        match self {
+            Self::Create {
+                key,
+                group,
+                id,
+                mkstream,
+            } => {
+                let mut lock = ctx.db.inner.write();
+                if mkstream && !lock.contains_key(&key) {
+                    let value = Value::new_no_expiry(Type::Stream(Stream::new()));
+                    lock.insert(key.clone(), Arc::new(value));
+                }
+                let stream = match lock
+                    .get_mut(&key)
+                    .map(|value| &mut Arc::make_mut(value).v_type)
+                {
+                    Some(Type::Stream(stream)) => stream,
+                    Some(_) => {
+                        bail!(RedisError::WrongType)
+                    }
+                    None => bail!(RedisError::NoGroupStream),
+                };
+                let id = id.unwrap_or_else(|| stream.last_id());
+                let created = stream.create_group(group, id);
+                drop(lock);
+                ensure!(created, RedisError::BusyGroup);
+                Ok(Resp::simple("OK"))
+            }
+            Self::Destroy { key, group } => {
+                let destroyed = ctx
+                    .db
+                    .inner
+                    .write()
+                    .get_mut(&key)
+                    .and_then(|value| match &mut Arc::make_mut(value).v_type {
+                        Type::Stream(stream) => stream.groups.remove(&group),
+                        _ => None,
+                    })
+                    .is_some();
+                Ok(Resp::Integer(destroyed.into()))
+            }
+        }
+    }
+}
@@ -3,10 +3,10 @@ use std::{ops::RangeInclusive, str::from_utf8 as str_utf8};
 
 use crate::{
     db::{stream::EntryId, Stream},
-    slice_to_int, Resp, DB,
+    slice_to_int, Resp,
 };
 
-use super::IterResp;
+use super::{CommandExec, Ctx, IterResp};
 
 #[derive(Debug)]
 pub struct Xrange {
@@ -39,22 +39,25 @@ impl Xrange {
 
         Ok(Self { key, range, count })
     }
+}
 
-    pub fn execute(&self) -> anyhow::Result<Resp> {
-        let resp = DB
-            .inner
-            .read()
-            .get(&self.key)
-            .map(|x| {
-                x.v_type
+impl CommandExec for Xrange {
+    fn execute(self, ctx: &mut Ctx<'_>) -> anyhow::Result<Resp> {
+        let entries = ctx
+            .db
+            .view(&self.key, |x| {
+                let stream = x
+                    .v_type
                     .as_stream()
-                    .with_context(|| format!("XRANGE on invalid key: \"{}\"", self.key))
+                    .with_context(|| format!("XRANGE on invalid key: \"{}\"", self.key))?;
+                let range = self.range.start()..=self.range.end();
+                anyhow::Ok(Stream::format_entries(
+                    stream.iter_with_count(self.count, range),
+                ))
             })
             .transpose()?
-            .map(|stream| stream.iter_with_count(self.count, self.range.start()..=self.range.end()))
-            .map_or_else(Vec::new, Stream::format_entries);
-        let resp = Resp::Array(resp);
-        Ok(resp)
+            .unwrap_or_default();
+        Ok(Resp::Array(entries))
     }
 }
 
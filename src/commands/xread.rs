@@ -11,10 +11,10 @@ use anyhow::{bail, ensure, Context};
 
 use crate::{
     db::{stream::EntryId, Stream},
-    slice_to_int, Resp, DB,
+    slice_to_int, Db, RedisError, Resp,
 };
 
-use super::IterResp;
+use super::{CommandExec, Ctx, IterResp};
 
 #[derive(Debug)]
 pub struct Xread {
@@ -46,14 +46,17 @@ impl Xread {
                         .transpose()?;
                 }
                 b"streams" => break,
-                _ => bail!("Expected \"streams\" argument"),
+                _ => bail!(RedisError::Syntax),
             }
         }
 
         let slice = i.as_slice();
         ensure!(
-            !slice.is_empty() && slice.len() % 2 == 0,
-            "Invalid number of arguments"
+            !slice.is_empty() && slice.len().is_multiple_of(2),
+            RedisError::UnbalancedStreams {
+                command: "xread",
+                id: "$"
+            }
         );
 
         let half = slice.len() / 2;
@@ -86,36 +89,69 @@ impl Xread {
         })
     }
 
-    pub async fn execute(&self) -> anyhow::Result<Resp> {
-        let resp = {
-            let sync = {
-                let iter = self.keys_ids.iter().filter_map(|(key, id)| match id {
-                    MaybeTopId::NotTop(id) => Some((key, (Excluded(*id), Unbounded))),
-                    MaybeTopId::Top => None,
-                });
-
-                self.get_keys_entries(iter)?
-            };
+    #[inline]
+    pub(crate) const fn blocks(&self) -> bool {
+        self.block_time.is_some()
+    }
 
-            if sync != Resp::Null {
-                sync
-            } else if let Some((key, id)) = self.first_unblocked().await? {
-                let iter = std::iter::once((&key, id..));
-                self.get_keys_entries(iter)?
-            } else {
-                Resp::Null
-            }
+    /// Reads like [`CommandExec::execute`], then waits up to the BLOCK
+    /// time for entries if there were none
+    pub async fn execute_blocking(&self, db: &Db) -> anyhow::Result<Resp> {
+        // Register before the first read so an entry added between the read and
+        // the wait still wakes us.
+        let waiter = db
+            .waiters
+            .register(self.keys_ids.iter().map(|(key, _)| key.as_str()));
+
+        let keys_ids = self.resolve_ids(db)?;
+        let ranges = || {
+            keys_ids
+                .iter()
+                .map(|(key, id)| (key, (Excluded(*id), Unbounded)))
         };
 
-        Ok(resp)
+        let block_time = self.block_time.unwrap_or_default();
+        let resp = waiter
+            .block(block_time, || {
+                let resp = self.get_keys_entries(db, ranges())?;
+                Ok((resp != Resp::NullArray).then_some(resp))
+            })
+            .await?;
+        Ok(resp.unwrap_or_else(|| {
+            tracing::debug!("XREAD timed out");
+            Resp::NullArray
+        }))
+    }
+
+    /// Replaces `$` with the id of the last entry currently in each stream.
+    fn resolve_ids(&self, db: &Db) -> anyhow::Result<Vec<(String, EntryId)>> {
+        let lock = db.inner.read();
+        self.keys_ids
+            .iter()
+            .map(|(key, id)| {
+                let id = match id {
+                    MaybeTopId::NotTop(id) => *id,
+                    MaybeTopId::Top => lock
+                        .get(key)
+                        .map(|x| {
+                            x.v_type
+                                .as_stream()
+                                .with_context(|| format!("XREAD on invalid key: \"{key}\""))
+                        })
+                        .transpose()?
+                        .map_or(EntryId::MIN, Stream::last_id),
+                };
+                anyhow::Ok((key.clone(), id))
+            })
+            .collect()
     }
 
-    fn get_keys_entries<'a, I, R>(&self, i: I) -> anyhow::Result<Resp>
+    fn get_keys_entries<'a, I, R>(&self, db: &Db, i: I) -> anyhow::Result<Resp>
     where
         I: IntoIterator<Item = (&'a String, R)>,
         R: RangeBounds<EntryId>,
     {
-        let lock = DB.inner.read();
+        let lock = db.inner.read();
 
         let mut v = Vec::new();
         for (key, range) in i {
@@ -142,59 +178,20 @@ impl Xread {
         drop(lock);
 
         Ok(if v.is_empty() {
-            Resp::Null
+            Resp::NullArray
         } else {
             Resp::Array(v)
         })
     }
+}
 
-    async fn first_unblocked(&self) -> anyhow::Result<Option<(String, EntryId)>> {
-        let Some(block_time) = self.block_time else {
-            return Ok(None);
-        };
-
-        let mut set = self
-            .keys_ids
+impl CommandExec for Xread {
+    fn execute(self, ctx: &mut Ctx<'_>) -> anyhow::Result<Resp> {
+        let keys_ids = self.resolve_ids(ctx.db)?;
+        let ranges = keys_ids
             .iter()
-            .cloned()
-            .map(|(key, id)| async move {
-                let mut rx = DB.added_stream.subscribe();
-                loop {
-                    rx.changed().await.expect("Sender alive");
-                    let Some((added_key, added_id)) = &*rx.borrow_and_update() else {
-                        continue;
-                    };
-
-                    if *added_key != key {
-                        continue;
-                    }
-
-                    match id {
-                        MaybeTopId::Top => (),
-                        MaybeTopId::NotTop(id) => {
-                            if *added_id < id {
-                                continue;
-                            }
-                        }
-                    }
-                    break (added_key.clone(), *added_id);
-                }
-            })
-            .collect::<tokio::task::JoinSet<_>>();
-
-        let sleep = tokio::time::sleep(block_time);
-        tokio::pin!(sleep);
-
-        tokio::select! {
-            res = set.join_next() => {
-                let (key, id) = res.expect("1 task")?;
-                Ok(Some((key,id)))
-            }
-            () = &mut sleep, if block_time.as_millis() != 0 => {
-                println!("timed out");
-                Ok(None)
-            }
-        }
+            .map(|(key, id)| (key, (Excluded(*id), Unbounded)));
+        self.get_keys_entries(ctx.db, ranges)
     }
 }
 
@@ -203,3 +200,38 @@ enum MaybeTopId {
     Top,
     NotTop(EntryId),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::Xadd;
+    use std::sync::Arc;
+
+    fn args(args: &[&'static str]) -> Vec<Resp> {
+        args.iter().copied().map(Resp::bulk).collect()
+    }
+
+    /// An entry added while XREAD is between its read and its wait must still wake it
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn entry_added_while_subscribing() {
+        for _ in 0..200 {
+            let db = Arc::new(Db::default());
+            let xread = Xread::parse(args(&["block", "0", "streams", "s", "0-0"]).iter()).unwrap();
+            let xadd = Xadd::parse(args(&["s", "*", "k", "v"]).iter()).unwrap();
+
+            let reader = tokio::spawn({
+                let db = Arc::clone(&db);
+                async move { xread.execute_blocking(&db).await }
+            });
+            tokio::task::yield_now().await;
+            db.xadd(xadd).unwrap();
+
+            let resp = tokio::time::timeout(Duration::from_secs(5), reader)
+                .await
+                .expect("XREAD missed the entry")
+                .unwrap()
+                .unwrap();
+            assert!(matches!(resp, Resp::Array(_)));
+        }
+    }
+}
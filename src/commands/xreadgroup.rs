@@ -0,0 +1,139 @@
+use anyhow::{bail, ensure, Context};
+use std::{str::from_utf8 as str_utf8, sync::Arc};
+
+use crate::{
+    db::{stream::EntryId, Type},
+    slice_to_int, RedisError, Resp,
+};
+
+use super::{CommandExec, Ctx, IterResp};
+
+#[derive(Debug)]
+pub struct Xreadgroup {
+    group: String,
+    consumer: String,
+    count: Option<usize>,
+    noack: bool,
+    keys_ids: Vec<(String, MaybeNew)>,
+}
+
+impl Xreadgroup {
+    pub(super) fn parse(mut i: IterResp) -> anyhow::Result<Self> {
+        ensure!(
+            i.next()
+                .and_then(Resp::as_bulk)
+                .is_some_and(|x| x.eq_ignore_ascii_case(b"group")),
+            RedisError::Syntax
+        );
+        let group = i.next().context("Missing group")?.to_string()?;
+        let consumer = i.next().context("Missing consumer")?.to_string()?;
+
+        let mut count = None;
+        let mut noack = false;
+        while let Some(arg) = i.next().and_then(Resp::as_bulk) {
+            match arg.to_ascii_lowercase().as_slice() {
+                b"count" => {
+                    count = i
+                        .next()
+                        .and_then(Resp::as_bulk)
+                        .map(slice_to_int::<usize>)
+                        .transpose()?;
+                }
+                b"noack" => noack = true,
+                b"streams" => break,
+                _ => bail!(RedisError::Syntax),
+            }
+        }
+
+        let slice = i.as_slice();
+        ensure!(
+            !slice.is_empty() && slice.len().is_multiple_of(2),
+            RedisError::UnbalancedStreams {
+                command: "xreadgroup",
+                id: ">"
+            }
+        );
+
+        let half = slice.len() / 2;
+        let keys_ids = slice[..half]
+            .iter()
+            .zip(slice[half..].iter())
+            .map(|(key, id)| {
+                let key = key.to_string()?;
+                let id = match id.as_bulk().context("Invalid id")?.as_ref() {
+                    b">" => MaybeNew::New,
+                    id => MaybeNew::Pending(EntryId::split_or_seq(0, str_utf8(id)?)?),
+                };
+                anyhow::Ok((key, id))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        Ok(Self {
+            group,
+            consumer,
+            count,
+            noack,
+            keys_ids,
+        })
+    }
+}
+
+impl CommandExec for Xreadgroup {
+    fn execute(self, ctx: &mut Ctx<'_>) -> anyhow::Result<Resp> {
+        let now = ctx.db.clock.now();
+        let mut lock = ctx.db.inner.write();
+
+        let mut v = Vec::new();
+        for (key, id) in &self.keys_ids {
+            let stream = match lock
+                .get_mut(key)
+                .map(|value| &mut Arc::make_mut(value).v_type)
+            {
+                Some(Type::Stream(stream)) => Some(stream),
+                Some(_) => {
+                    bail!(RedisError::WrongType)
+                }
+                None => None,
+            };
+            let entries = stream
+                .and_then(|stream| match id {
+                    MaybeNew::New => stream.read_group_new(
+                        &self.group,
+                        &self.consumer,
+                        self.count,
+                        self.noack,
+                        now,
+                    ),
+                    MaybeNew::Pending(id) => {
+                        stream.read_group_pending(&self.group, &self.consumer, *id, self.count, now)
+                    }
+                })
+                .ok_or_else(|| RedisError::NoGroup {
+                    key: key.clone(),
+                    group: self.group.clone(),
+                    xreadgroup: true,
+                })?;
+
+            if matches!(id, MaybeNew::New) && entries.is_empty() {
+                continue;
+            }
+            v.push(Resp::Array(vec![
+                Resp::bulk(key.clone()),
+                Resp::Array(entries),
+            ]));
+        }
+        drop(lock);
+
+        Ok(if v.is_empty() {
+            Resp::NullArray
+        } else {
+            Resp::Array(v)
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum MaybeNew {
+    New,
+    Pending(EntryId),
+}
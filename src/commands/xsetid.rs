@@ -0,0 +1,73 @@
+use anyhow::{bail, Context};
+use std::{str::from_utf8 as str_utf8, sync::Arc};
+
+use crate::{
+    db::{stream::EntryId, Type},
+    RedisError, Resp,
+};
+
+use super::{CommandExec, Ctx, IterResp};
+
+#[derive(Debug)]
+pub struct Xsetid {
+    key: String,
+    last_id: EntryId,
+    entries_added: Option<u64>,
+    max_deleted_id: Option<EntryId>,
+}
+
+impl Xsetid {
+    pub(super) fn parse(mut i: IterResp) -> anyhow::Result<Self> {
+        let key = i.next().context("Missing key")?.to_string()?;
+        let last_id = i.next().context("Missing last-id").and_then(to_entry_id)?;
+
+        let mut entries_added = None;
+        let mut max_deleted_id = None;
+        while let Some(arg) = i.next().and_then(Resp::as_bulk) {
+            match arg.to_ascii_lowercase().as_slice() {
+                b"entriesadded" => {
+                    let added = i.next().context(RedisError::Syntax)?.to_int()?;
+                    entries_added = Some(added);
+                }
+                b"maxdeletedid" => {
+                    let id = i.next().context(RedisError::Syntax).and_then(to_entry_id)?;
+                    max_deleted_id = Some(id);
+                }
+                _ => bail!(RedisError::Syntax),
+            }
+        }
+
+        Ok(Self {
+            key,
+            last_id,
+            entries_added,
+            max_deleted_id,
+        })
+    }
+}
+
+impl CommandExec for Xsetid {
+    fn execute(self, ctx: &mut Ctx<'_>) -> anyhow::Result<Resp> {
+        match ctx
+            .db
+            .inner
+            .write()
+            .get_mut(&self.key)
+            .map(|value| &mut Arc::make_mut(value).v_type)
+        {
+            Some(Type::Stream(stream)) => {
+                stream.set_id(self.last_id, self.entries_added, self.max_deleted_id)?;
+            }
+            Some(_) => {
+                bail!(RedisError::WrongType)
+            }
+            None => bail!("ERR no such key"),
+        }
+        Ok(Resp::simple("OK"))
+    }
+}
+
+fn to_entry_id(resp: &Resp) -> anyhow::Result<EntryId> {
+    let id = resp.as_bulk().context("Invalid id")?;
+    EntryId::split_or_seq(0, str_utf8(id)?)
+}
@@ -0,0 +1,57 @@
+use anyhow::{ensure, Context};
+use bytes::Bytes;
+use std::sync::Arc;
+
+use crate::{
+    db::{SortedSet, Type, Value},
+    RedisError, Resp,
+};
+
+use super::{CommandExec, Ctx, IterResp};
+
+#[derive(Debug)]
+pub struct Zadd {
+    key: String,
+    pairs: Vec<(f64, Bytes)>,
+}
+
+impl Zadd {
+    pub(super) fn parse(mut i: IterResp) -> anyhow::Result<Self> {
+        let key = i.next().context("Missing key")?.to_string()?;
+        ensure!(i.len().is_multiple_of(2), RedisError::Syntax);
+        let mut pairs = Vec::with_capacity(i.len() / 2);
+        while let (Some(score), Some(member)) = (i.next(), i.next()) {
+            let score = score
+                .to_string()?
+                .parse::<f64>()
+                .ok()
+                .filter(|score| !score.is_nan())
+                .context("ERR value is not a valid float")?;
+            pairs.push((score, member.to_bytes()?));
+        }
+        Ok(Self { key, pairs })
+    }
+}
+
+impl CommandExec for Zadd {
+    fn execute(self, ctx: &mut Ctx<'_>) -> anyhow::Result<Resp> {
+        let limits = *ctx.db.encoding.read();
+        let mut lock = ctx.db.inner.write();
+        if ctx.db.zset_mut(&mut lock, &self.key)?.is_none() {
+            let zset = Type::SortedSet(SortedSet::default());
+            lock.insert(self.key.clone(), Arc::new(Value::new(zset, None)));
+        }
+        let zset = ctx
+            .db
+            .zset_mut(&mut lock, &self.key)?
+            .context("Missing sorted set")?;
+        let added = self
+            .pairs
+            .into_iter()
+            .map(|(score, member)| zset.insert(member, score, &limits))
+            .filter(|&new| new)
+            .count();
+        drop(lock);
+        Ok(Resp::Integer(added.try_into()?))
+    }
+}
@@ -0,0 +1,49 @@
+use anyhow::Context;
+use std::ops::Bound;
+
+use crate::{RedisError, Resp};
+
+use super::{CommandExec, Ctx, IterResp};
+
+#[derive(Debug)]
+pub struct Zcount {
+    key: String,
+    min: Bound<f64>,
+    max: Bound<f64>,
+}
+
+impl Zcount {
+    pub(super) fn parse(mut i: IterResp) -> anyhow::Result<Self> {
+        let key = i.next().context("Missing key")?.to_string()?;
+        let mut bound = || parse_bound(&i.next().context("Missing bound")?.to_string()?);
+        let (min, max) = (bound()?, bound()?);
+        Ok(Self { key, min, max })
+    }
+}
+
+/// A score, excluded from the range if prefixed by `(`
+fn parse_bound(arg: &str) -> anyhow::Result<Bound<f64>> {
+    let (score, bound): (_, fn(f64) -> Bound<f64>) = arg
+        .strip_prefix('(')
+        .map_or((arg, Bound::Included), |score| (score, Bound::Excluded));
+    score
+        .parse::<f64>()
+        .ok()
+        .filter(|score| !score.is_nan())
+        .map(bound)
+        .context("ERR min or max is not a float")
+}
+
+impl CommandExec for Zcount {
+    fn execute(self, ctx: &mut Ctx<'_>) -> anyhow::Result<Resp> {
+        let count = ctx
+            .db
+            .view(&self.key, |v| {
+                let zset = v.v_type.as_sorted_set().context(RedisError::WrongType)?;
+                anyhow::Ok(zset.count(self.min, self.max))
+            })
+            .transpose()?
+            .unwrap_or(0);
+        Ok(Resp::Integer(count.try_into()?))
+    }
+}
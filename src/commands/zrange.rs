@@ -0,0 +1,74 @@
+use anyhow::{ensure, Context};
+
+use crate::{RedisError, Resp};
+
+use super::{CommandExec, Ctx, IterResp};
+
+/// ZRANGE by rank, from `start` to `stop` included. Negative ranks count from the end.
+#[derive(Debug)]
+pub struct Zrange {
+    key: String,
+    start: i64,
+    stop: i64,
+    with_scores: bool,
+}
+
+impl Zrange {
+    pub(super) fn parse(mut i: IterResp) -> anyhow::Result<Self> {
+        let key = i.next().context("Missing key")?.to_string()?;
+        let mut rank = || {
+            i.next()
+                .context("Missing rank")?
+                .to_int::<i64>()
+                .ok()
+                .context(RedisError::NotInteger)
+        };
+        let (start, stop) = (rank()?, rank()?);
+        let with_scores = match i.next() {
+            Some(arg) => {
+                ensure!(
+                    arg.to_string()?.eq_ignore_ascii_case("withscores"),
+                    RedisError::Syntax
+                );
+                true
+            }
+            None => false,
+        };
+        ensure!(i.next().is_none(), RedisError::Syntax);
+        Ok(Self {
+            key,
+            start,
+            stop,
+            with_scores,
+        })
+    }
+}
+
+impl CommandExec for Zrange {
+    fn execute(self, ctx: &mut Ctx<'_>) -> anyhow::Result<Resp> {
+        let members = ctx
+            .db
+            .view(&self.key, |v| {
+                let zset = v.v_type.as_sorted_set().context(RedisError::WrongType)?;
+                let len = i64::try_from(zset.len())?;
+                let from_end = |rank: i64| if rank < 0 { rank + len } else { rank };
+                let start = from_end(self.start).max(0);
+                let stop = from_end(self.stop).min(len - 1);
+                if start > stop {
+                    return Ok(Vec::new());
+                }
+                let range = zset.range(usize::try_from(start)?);
+                let mut reply = Vec::new();
+                for (member, score) in range.take(usize::try_from(stop - start + 1)?) {
+                    reply.push(Resp::Bulk(member));
+                    if self.with_scores {
+                        reply.push(Resp::bulk(score.to_string()));
+                    }
+                }
+                anyhow::Ok(reply)
+            })
+            .transpose()?
+            .unwrap_or_default();
+        Ok(Resp::Array(members))
+    }
+}
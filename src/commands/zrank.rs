@@ -0,0 +1,37 @@
+use anyhow::Context;
+use bytes::Bytes;
+
+use crate::{RedisError, Resp};
+
+use super::{CommandExec, Ctx, IterResp};
+
+#[derive(Debug)]
+pub struct Zrank {
+    key: String,
+    member: Bytes,
+}
+
+impl Zrank {
+    pub(super) fn parse(mut i: IterResp) -> anyhow::Result<Self> {
+        let key = i.next().context("Missing key")?.to_string()?;
+        let member = i.next().context("Missing member")?.to_bytes()?;
+        Ok(Self { key, member })
+    }
+}
+
+impl CommandExec for Zrank {
+    fn execute(self, ctx: &mut Ctx<'_>) -> anyhow::Result<Resp> {
+        let rank = ctx
+            .db
+            .view(&self.key, |v| {
+                let zset = v.v_type.as_sorted_set().context(RedisError::WrongType)?;
+                anyhow::Ok(zset.rank(&self.member))
+            })
+            .transpose()?
+            .flatten();
+        Ok(match rank {
+            Some(rank) => Resp::Integer(rank.try_into()?),
+            None => Resp::Null,
+        })
+    }
+}
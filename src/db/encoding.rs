@@ -0,0 +1,130 @@
+use super::Type;
+
+/// Limits up to which containers use a compact encoding, from the
+/// `*-max-listpack-*` and `set-max-intset-entries` parameters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EncodingLimits {
+    pub hash_max_listpack_entries: usize,
+    pub hash_max_listpack_value: usize,
+    /// Entries of a list node if positive, else -1 to -5 for 4 to 64 KB nodes
+    pub list_max_listpack_size: i64,
+    pub set_max_intset_entries: usize,
+    pub set_max_listpack_entries: usize,
+    pub set_max_listpack_value: usize,
+    pub zset_max_listpack_entries: usize,
+    pub zset_max_listpack_value: usize,
+}
+
+impl Default for EncodingLimits {
+    fn default() -> Self {
+        Self {
+            hash_max_listpack_entries: 128,
+            hash_max_listpack_value: 64,
+            list_max_listpack_size: -2,
+            set_max_intset_entries: 512,
+            set_max_listpack_entries: 128,
+            set_max_listpack_value: 64,
+            zset_max_listpack_entries: 128,
+            zset_max_listpack_value: 64,
+        }
+    }
+}
+
+impl EncodingLimits {
+    /// Whether a hash of `entries` fields, none longer than `longest`, fits in a listpack
+    pub const fn hash_fits(&self, entries: usize, longest: usize) -> bool {
+        entries <= self.hash_max_listpack_entries && longest <= self.hash_max_listpack_value
+    }
+
+    pub const fn set_fits(&self, entries: usize, longest: usize) -> bool {
+        entries <= self.set_max_listpack_entries && longest <= self.set_max_listpack_value
+    }
+
+    pub const fn zset_fits(&self, entries: usize, longest: usize) -> bool {
+        entries <= self.zset_max_listpack_entries && longest <= self.zset_max_listpack_value
+    }
+
+    /// Whether a list of `entries` elements, `bytes` long in all, fits in a single node
+    pub const fn list_fits(&self, entries: usize, bytes: usize) -> bool {
+        if self.list_max_listpack_size > 0 {
+            return entries as u64 <= self.list_max_listpack_size.unsigned_abs();
+        }
+        // Header and terminator, then the length and the backlen of each entry
+        let size = 7 + bytes + 2 * entries;
+        let shift = match self.list_max_listpack_size {
+            -5..=-1 => self.list_max_listpack_size.unsigned_abs() - 1,
+            _ => 0,
+        };
+        size <= 4096 << shift
+    }
+}
+
+impl Type {
+    /// Encoding reported by OBJECT ENCODING
+    pub(crate) fn encoding(&self) -> &'static str {
+        match self {
+            Self::String(string) => string.encoding(),
+            Self::List(list) => list.encoding(),
+            Self::Set(set) => set.encoding(),
+            Self::SortedSet(zset) => zset.encoding(),
+            Self::Hash(hash) => hash.encoding(),
+            Self::Stream(_) => "stream",
+        }
+    }
+
+    /// Switches a container loaded from a dump to the encoding its size calls for
+    pub(crate) fn fit_encoding(&mut self, limits: &EncodingLimits) {
+        match self {
+            Self::List(list) => list.fit(limits),
+            Self::Set(set) => set.fit(limits),
+            Self::SortedSet(zset) => zset.fit(limits),
+            Self::Hash(hash) => hash.fit(limits),
+            Self::String(_) | Self::Stream(_) => {}
+        }
+    }
+}
+
+/// Parses an integer the way Redis stores it in an intset or as a shared string
+pub fn as_int(bytes: &[u8]) -> Option<i64> {
+    let s = std::str::from_utf8(bytes).ok()?;
+    s.parse::<i64>().ok().filter(|n| n.to_string() == s)
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::db::{set::Set, Hash, List};
+
+    #[test]
+    fn fit() {
+        let limits = EncodingLimits {
+            set_max_intset_entries: 2,
+            hash_max_listpack_value: 3,
+            list_max_listpack_size: 2,
+            ..EncodingLimits::default()
+        };
+        let fitted = |mut value: Type| {
+            value.fit_encoding(&limits);
+            value.encoding()
+        };
+        let set = |members: &[&'static str]| {
+            Type::Set(members.iter().copied().map(Bytes::from).collect::<Set>())
+        };
+        pretty_assertions::assert_eq!(set(&["1", "2"]).encoding(), "hashtable");
+        pretty_assertions::assert_eq!(fitted(set(&["1", "2"])), "intset");
+        pretty_assertions::assert_eq!(fitted(set(&["1", "2", "3"])), "listpack");
+        pretty_assertions::assert_eq!(fitted(set(&["a"])), "listpack");
+
+        let list = |n| Type::List(vec![Bytes::from("a"); n].into_iter().collect::<List>());
+        pretty_assertions::assert_eq!(fitted(list(2)), "listpack");
+        pretty_assertions::assert_eq!(fitted(list(3)), "quicklist");
+
+        let hash = |value: &'static str| {
+            Type::Hash(std::iter::once((Bytes::from("f"), Bytes::from(value))).collect::<Hash>())
+        };
+        pretty_assertions::assert_eq!(fitted(hash("abc")), "listpack");
+        pretty_assertions::assert_eq!(fitted(hash("abcd")), "hashtable");
+    }
+}
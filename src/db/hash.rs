@@ -0,0 +1,357 @@
+use bytes::Bytes;
+use std::{collections::HashMap, time::SystemTime};
+
+use super::{listpack::Listpack, EncodingLimits};
+
+/// Fields of a hash, some of which may expire on their own.
+/// An expired field is skipped by reads until [`Hash::expire`] removes it.
+#[derive(Debug, Clone, Default)]
+pub struct Hash {
+    fields: Fields,
+    /// Expiration of the fields that have one
+    pub(crate) expires: HashMap<Bytes, SystemTime>,
+}
+
+#[derive(Debug, Clone)]
+enum Fields {
+    /// Fields each followed by their value
+    Listpack(Listpack),
+    Hashtable(HashMap<Bytes, Bytes>),
+}
+
+impl Default for Fields {
+    fn default() -> Self {
+        Self::Listpack(Listpack::default())
+    }
+}
+
+/// Condition of HEXPIRE and such on the current expiration of a field,
+/// where a field without one counts as never expiring
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpireCond {
+    /// Only without an expiration
+    Nx,
+    /// Only with an expiration
+    Xx,
+    /// Only if the new expiration is later
+    Gt,
+    /// Only if the new expiration is earlier
+    Lt,
+}
+
+/// Outcome of [`Hash::set_expiration`] for a field, with its HEXPIRE reply code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldExpire {
+    NoField = -2,
+    CondNotMet = 0,
+    Set = 1,
+    /// The expiration was already past, so the field was deleted
+    Deleted = 2,
+}
+
+impl Hash {
+    pub fn is_field_expired(&self, field: &[u8], now: SystemTime) -> bool {
+        self.expires.get(field).is_some_and(|&at| at <= now)
+    }
+
+    /// Value of `field`, even if it expired
+    fn lookup(&self, field: &[u8]) -> Option<Bytes> {
+        match &self.fields {
+            Fields::Listpack(listpack) => {
+                listpack.pairs().find(|(f, _)| f == field).map(|(_, v)| v)
+            }
+            Fields::Hashtable(fields) => fields.get(field).cloned(),
+        }
+    }
+
+    /// Number of fields, the expired ones included
+    fn raw_len(&self) -> usize {
+        match &self.fields {
+            Fields::Listpack(listpack) => listpack.len() / 2,
+            Fields::Hashtable(fields) => fields.len(),
+        }
+    }
+
+    /// Fields and values, the expired ones included
+    fn raw_iter(&self) -> Box<dyn Iterator<Item = (Bytes, Bytes)> + '_> {
+        match &self.fields {
+            Fields::Listpack(listpack) => Box::new(listpack.pairs()),
+            Fields::Hashtable(fields) => {
+                Box::new(fields.iter().map(|(f, v)| (f.clone(), v.clone())))
+            }
+        }
+    }
+
+    pub fn get(&self, field: &[u8], now: SystemTime) -> Option<Bytes> {
+        if self.is_field_expired(field, now) {
+            return None;
+        }
+        self.lookup(field)
+    }
+
+    pub fn contains(&self, field: &[u8], now: SystemTime) -> bool {
+        self.get(field, now).is_some()
+    }
+
+    /// Expiration of `field`, if it's there and has one
+    pub fn expiration(&self, field: &[u8], now: SystemTime) -> Option<SystemTime> {
+        self.get(field, now)?;
+        self.expires.get(field).copied()
+    }
+
+    /// Live fields and their values
+    pub fn iter(&self, now: SystemTime) -> impl Iterator<Item = (Bytes, Bytes)> + '_ {
+        self.raw_iter()
+            .filter(move |(field, _)| !self.is_field_expired(field, now))
+    }
+
+    /// Sets `field`, clearing its expiration. Returns whether it's a new field.
+    /// The listpack is converted to a hashtable once the hash outgrows `limits`.
+    pub fn insert(
+        &mut self,
+        field: Bytes,
+        value: Bytes,
+        limits: &EncodingLimits,
+        now: SystemTime,
+    ) -> bool {
+        let expired = self.is_field_expired(&field, now);
+        self.expires.remove(&field);
+        let new = self.lookup(&field).is_none();
+        if let Fields::Listpack(listpack) = &self.fields {
+            let len = self.raw_len() + usize::from(new);
+            if !limits.hash_fits(len, field.len().max(value.len())) {
+                self.fields = Fields::Hashtable(listpack.pairs().collect());
+            }
+        }
+        match &mut self.fields {
+            Fields::Listpack(listpack) => {
+                let target = field.clone();
+                let replaced = listpack.pairs().map(|(f, v)| {
+                    let v = if f == target { value.clone() } else { v };
+                    (f, v)
+                });
+                let added = new.then_some((field, value.clone()));
+                *listpack = replaced.chain(added).collect();
+            }
+            Fields::Hashtable(fields) => {
+                fields.insert(field, value);
+            }
+        }
+        new || expired
+    }
+
+    /// Returns whether `field` was there
+    pub fn remove(&mut self, field: &[u8], now: SystemTime) -> bool {
+        let expired = self.is_field_expired(field, now);
+        self.expires.remove(field);
+        let removed = match &mut self.fields {
+            Fields::Listpack(listpack) => {
+                let len = listpack.len();
+                *listpack = listpack.pairs().filter(|(f, _)| f != field).collect();
+                listpack.len() != len
+            }
+            Fields::Hashtable(fields) => fields.remove(field).is_some(),
+        };
+        removed && !expired
+    }
+
+    /// Whether there's no field left, the expired ones included
+    pub fn is_empty(&self) -> bool {
+        self.raw_len() == 0
+    }
+
+    /// Whether every field expired, so the whole hash is gone
+    pub fn all_expired(&self, now: SystemTime) -> bool {
+        !self.is_empty()
+            && self.raw_len() == self.expires.len()
+            && self.expires.values().all(|&at| at <= now)
+    }
+
+    pub fn has_expired(&self, now: SystemTime) -> bool {
+        self.expires.values().any(|&at| at <= now)
+    }
+
+    /// Removes the expired fields, returning them
+    pub fn expire(&mut self, now: SystemTime) -> Vec<Bytes> {
+        let mut expired = Vec::new();
+        self.expires.retain(|field, &mut at| {
+            let keep = at > now;
+            if !keep {
+                expired.push(field.clone());
+            }
+            keep
+        });
+        match &mut self.fields {
+            Fields::Listpack(listpack) => {
+                let pairs = listpack.pairs().filter(|(f, _)| !expired.contains(f));
+                *listpack = pairs.collect();
+            }
+            Fields::Hashtable(fields) => {
+                for field in &expired {
+                    fields.remove(field);
+                }
+            }
+        }
+        expired
+    }
+
+    pub fn set_expiration(
+        &mut self,
+        field: &Bytes,
+        at: SystemTime,
+        cond: Option<ExpireCond>,
+        now: SystemTime,
+    ) -> FieldExpire {
+        if !self.contains(field, now) {
+            return FieldExpire::NoField;
+        }
+        let current = self.expiration(field, now);
+        let met = match cond {
+            None => true,
+            Some(ExpireCond::Nx) => current.is_none(),
+            Some(ExpireCond::Xx) => current.is_some(),
+            Some(ExpireCond::Gt) => current.is_some_and(|current| at > current),
+            Some(ExpireCond::Lt) => current.is_none_or(|current| at < current),
+        };
+        if !met {
+            return FieldExpire::CondNotMet;
+        }
+        if at <= now {
+            self.remove(field, now);
+            return FieldExpire::Deleted;
+        }
+        self.expires.insert(field.clone(), at);
+        FieldExpire::Set
+    }
+
+    /// Returns whether `field` had an expiration, `None` if there's no such field
+    pub fn persist(&mut self, field: &[u8], now: SystemTime) -> Option<bool> {
+        if !self.contains(field, now) {
+            return None;
+        }
+        Some(self.expires.remove(field).is_some())
+    }
+}
+
+impl Hash {
+    pub fn encoding(&self) -> &'static str {
+        match &self.fields {
+            Fields::Listpack(_) if self.expires.is_empty() => "listpack",
+            Fields::Listpack(_) => "listpackex",
+            Fields::Hashtable(_) => "hashtable",
+        }
+    }
+
+    /// The listpack of a hash without expirations, which is saved as is
+    pub(crate) fn listpack(&self) -> Option<&Listpack> {
+        match &self.fields {
+            Fields::Listpack(listpack) if self.expires.is_empty() => Some(listpack),
+            _ => None,
+        }
+    }
+
+    pub fn fit(&mut self, limits: &EncodingLimits) {
+        let longest = self.raw_iter().map(|(f, v)| f.len().max(v.len())).max();
+        self.fields = if limits.hash_fits(self.raw_len(), longest.unwrap_or(0)) {
+            Fields::Listpack(self.raw_iter().collect())
+        } else {
+            Fields::Hashtable(self.raw_iter().collect())
+        };
+    }
+}
+
+impl FromIterator<(Bytes, Bytes)> for Hash {
+    fn from_iter<T: IntoIterator<Item = (Bytes, Bytes)>>(iter: T) -> Self {
+        Self {
+            fields: Fields::Hashtable(iter.into_iter().collect()),
+            expires: HashMap::new(),
+        }
+    }
+}
+
+impl PartialEq for Hash {
+    fn eq(&self, other: &Self) -> bool {
+        self.expires == other.expires
+            && self.raw_len() == other.raw_len()
+            && self.raw_iter().all(|(f, v)| other.lookup(&f) == Some(v))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn expirations() {
+        let mut hash = [("a", "1"), ("b", "2")]
+            .map(|(f, v)| (Bytes::from(f), Bytes::from(v)))
+            .into_iter()
+            .collect::<Hash>();
+        let (a, b) = (Bytes::from("a"), Bytes::from("b"));
+        let now = SystemTime::now();
+        let later = now + Duration::from_mins(1);
+
+        assert_eq!(
+            hash.set_expiration(&a, later, Some(ExpireCond::Xx), now),
+            FieldExpire::CondNotMet
+        );
+        assert_eq!(
+            hash.set_expiration(&a, later, Some(ExpireCond::Lt), now),
+            FieldExpire::Set
+        );
+        assert_eq!(
+            hash.set_expiration(&a, later, Some(ExpireCond::Gt), now),
+            FieldExpire::CondNotMet
+        );
+        assert_eq!(hash.expiration(&a, now), Some(later));
+        assert_eq!(hash.persist(&a, now), Some(true));
+        assert_eq!(hash.persist(&a, now), Some(false));
+        assert_eq!(
+            hash.set_expiration(&Bytes::from("c"), later, None, now),
+            FieldExpire::NoField
+        );
+
+        // Expired fields are hidden until removed
+        hash.expires.insert(b.clone(), now);
+        assert_eq!(hash.get(&b, now), None);
+        assert_eq!(hash.iter(now).count(), 1);
+        assert!(!hash.all_expired(now));
+        assert_eq!(hash.expire(now), [b]);
+        assert_eq!(hash.raw_len(), 1);
+
+        assert_eq!(
+            hash.set_expiration(&a, now, None, now),
+            FieldExpire::Deleted
+        );
+        assert!(hash.is_empty());
+    }
+
+    #[test]
+    fn upgrade() {
+        let limits = EncodingLimits {
+            hash_max_listpack_entries: 2,
+            hash_max_listpack_value: 4,
+            ..EncodingLimits::default()
+        };
+        let now = SystemTime::now();
+        let mut hash = Hash::default();
+        assert!(hash.insert("a".into(), "1".into(), &limits, now));
+        assert!(!hash.insert("a".into(), "2".into(), &limits, now));
+        assert!(hash.insert("b".into(), "3".into(), &limits, now));
+        assert_eq!(hash.encoding(), "listpack");
+        assert_eq!(hash.get(b"a", now), Some(Bytes::from("2")));
+
+        // Past the limits the listpack is converted for good
+        assert!(hash.insert("c".into(), "4".into(), &limits, now));
+        assert_eq!(hash.encoding(), "hashtable");
+        assert!(hash.remove(b"c", now));
+        assert_eq!(hash.encoding(), "hashtable");
+        hash.fit(&limits);
+        assert_eq!(hash.encoding(), "listpack");
+        assert!(!hash.insert("b".into(), "12345".into(), &limits, now));
+        assert_eq!(hash.encoding(), "hashtable");
+        assert_eq!(hash.iter(now).count(), 2);
+    }
+}
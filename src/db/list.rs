@@ -0,0 +1,55 @@
+use bytes::Bytes;
+use std::collections::VecDeque;
+
+use super::{listpack::Listpack, EncodingLimits};
+
+#[derive(Debug, Clone)]
+pub enum List {
+    /// Small lists, in a single node
+    Listpack(Listpack),
+    Quicklist(VecDeque<Bytes>),
+}
+
+impl List {
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Listpack(listpack) => listpack.len(),
+            Self::Quicklist(list) => list.len(),
+        }
+    }
+
+    pub fn iter(&self) -> Box<dyn Iterator<Item = Bytes> + '_> {
+        match self {
+            Self::Listpack(listpack) => Box::new(listpack.iter()),
+            Self::Quicklist(list) => Box::new(list.iter().cloned()),
+        }
+    }
+
+    pub const fn encoding(&self) -> &'static str {
+        match self {
+            Self::Listpack(_) => "listpack",
+            Self::Quicklist(_) => "quicklist",
+        }
+    }
+
+    pub fn fit(&mut self, limits: &EncodingLimits) {
+        let bytes = self.iter().map(|entry| entry.len()).sum();
+        *self = if limits.list_fits(self.len(), bytes) {
+            Self::Listpack(self.iter().collect())
+        } else {
+            Self::Quicklist(self.iter().collect())
+        };
+    }
+}
+
+impl FromIterator<Bytes> for List {
+    fn from_iter<T: IntoIterator<Item = Bytes>>(iter: T) -> Self {
+        Self::Quicklist(iter.into_iter().collect())
+    }
+}
+
+impl PartialEq for List {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().eq(other.iter())
+    }
+}
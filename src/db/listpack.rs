@@ -0,0 +1,82 @@
+use bytes::Bytes;
+
+use crate::rdb::{listpack, ListpackEntry};
+
+/// Entries packed one after the other in a single buffer, in the listpack format
+/// of the RDB. Small containers are kept this way, a write rewriting the whole buffer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Listpack(Bytes);
+
+impl Default for Listpack {
+    fn default() -> Self {
+        Self(listpack::encode(&[]))
+    }
+}
+
+impl Listpack {
+    pub fn len(&self) -> usize {
+        match u16::from_le_bytes([self.0[4], self.0[5]]) {
+            // The count saturates
+            u16::MAX => self.iter().count(),
+            len => len.into(),
+        }
+    }
+
+    /// Entries, integers formatted back to strings
+    pub fn iter(&self) -> impl Iterator<Item = Bytes> + use<> {
+        // Only valid listpacks are built, so every entry parses
+        listpack::Entries::new(self.0.clone())
+            .into_iter()
+            .flatten()
+            .map_while(Result::ok)
+            .map(|entry| entry.to_bytes())
+    }
+
+    /// Entries two by two, like the fields and values of a hash
+    pub fn pairs(&self) -> impl Iterator<Item = (Bytes, Bytes)> + use<> {
+        let mut entries = self.iter();
+        std::iter::from_fn(move || Some((entries.next()?, entries.next()?)))
+    }
+
+    /// The listpack itself, as saved in a RDB
+    pub const fn as_bytes(&self) -> &Bytes {
+        &self.0
+    }
+}
+
+impl FromIterator<Bytes> for Listpack {
+    fn from_iter<T: IntoIterator<Item = Bytes>>(iter: T) -> Self {
+        let entries = iter
+            .into_iter()
+            .map(ListpackEntry::from_bytes)
+            .collect::<Vec<_>>();
+        Self(listpack::encode(&entries))
+    }
+}
+
+impl FromIterator<(Bytes, Bytes)> for Listpack {
+    fn from_iter<T: IntoIterator<Item = (Bytes, Bytes)>>(iter: T) -> Self {
+        iter.into_iter().flat_map(<[Bytes; 2]>::from).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packed() {
+        let entries = ["a", "12", "012", "-7", ""].map(Bytes::from);
+        let listpack = entries.iter().cloned().collect::<Listpack>();
+        pretty_assertions::assert_eq!(listpack.len(), 5);
+        pretty_assertions::assert_eq!(listpack.iter().collect::<Vec<_>>(), entries);
+        pretty_assertions::assert_eq!(
+            listpack.pairs().collect::<Vec<_>>(),
+            [
+                (entries[0].clone(), entries[1].clone()),
+                (entries[2].clone(), entries[3].clone())
+            ]
+        );
+        pretty_assertions::assert_eq!(Listpack::default().len(), 0);
+    }
+}
@@ -1,18 +1,22 @@
-use anyhow::bail;
+use anyhow::{bail, ensure, Context};
 use bytes::Bytes;
-use once_cell::sync::Lazy;
-use parking_lot::{MappedRwLockReadGuard, RwLock, RwLockReadGuard};
+use parking_lot::{Mutex, RwLock};
 use std::{
     borrow::Cow,
     collections::{hash_map::Entry, HashMap},
     fmt::Debug,
-    path::Path,
-    time::SystemTime,
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime},
 };
-use stream::EntryId;
-use tokio::sync::watch;
+use tokio::{sync::mpsc, time::MissedTickBehavior};
 
-use crate::Rdb;
+use crate::{clock::Clock, Rdb, RedisError, ReplInfo, STATS, TRACKING};
 
 pub mod r#type;
 pub use r#type::Type;
@@ -20,59 +24,134 @@ pub use r#type::Type;
 pub mod stream;
 pub use stream::Stream;
 
-pub static DB: Lazy<Db> = Lazy::new(Db::new);
+pub mod hash;
+pub use hash::Hash;
 
-type ReadValue<'a> = MappedRwLockReadGuard<'a, Value>;
+pub mod list;
+pub use list::List;
 
+pub mod set;
+
+pub mod skiplist;
+
+pub mod zset;
+pub use zset::SortedSet;
+
+pub mod listpack;
+
+pub mod waiters;
+pub use waiters::Waiters;
+
+pub mod persistence;
+pub use persistence::Persistence;
+
+pub mod encoding;
+pub use encoding::EncodingLimits;
+
+pub mod string;
+pub use string::RedisString;
+
+/// Keys of the dataset. A value is shared with the snapshots taken since it was
+/// last written, and copied by the next write, see [`Db::snapshot`]
+pub type Map = HashMap<String, Arc<Value>>;
+
+/// The dataset of a server, with its persistence state
+#[derive(Debug)]
 pub struct Db {
-    pub(crate) inner: RwLock<HashMap<String, Value>>,
-    pub(crate) added_stream: watch::Sender<Option<(String, EntryId)>>,
+    pub(crate) inner: RwLock<Map>,
+    /// What expirations are checked against, shared with `persistence`
+    pub(crate) clock: Clock,
+    /// Clients blocked on keys, woken by the writes that add data to them
+    pub(crate) waiters: Waiters,
+    /// Replicas keep expired keys until the master's DEL arrives
+    replica: AtomicBool,
+    /// Keys expired on the master that still have to be propagated as DEL
+    expired: Mutex<Vec<String>>,
+    /// Hash fields expired on the master, to be propagated as HDEL
+    expired_fields: Mutex<Vec<(String, Vec<Bytes>)>>,
+    pub persistence: Persistence,
+    pub encoding: RwLock<EncodingLimits>,
+    /// Held for reading by client commands and for writing by scripts,
+    /// so nothing interleaves with the commands of a script
+    pub(crate) exclusive: tokio::sync::RwLock<()>,
+    /// The script holding `exclusive`, which clients may be told about or kill
+    #[cfg(feature = "scripting")]
+    pub(crate) busy: crate::scripting::Busy,
 }
 
-impl Db {
-    fn new() -> Self {
+impl Default for Db {
+    fn default() -> Self {
+        let clock = Clock::default();
         Self {
-            inner: RwLock::new(HashMap::new()),
-            added_stream: watch::Sender::new(None),
+            inner: RwLock::default(),
+            persistence: Persistence::new(clock.clone()),
+            clock,
+            waiters: Waiters::default(),
+            replica: AtomicBool::default(),
+            expired: Mutex::default(),
+            expired_fields: Mutex::default(),
+            encoding: RwLock::default(),
+            exclusive: tokio::sync::RwLock::default(),
+            #[cfg(feature = "scripting")]
+            busy: crate::scripting::Busy::default(),
         }
     }
+}
+
+impl Db {
+    /// Index clients and replication streams `SELECT` the dataset with, the only one
+    pub const INDEX: usize = 0;
+
+    pub fn set_replica(&self, replica: bool) {
+        self.replica.store(replica, Ordering::Relaxed);
+    }
 
-    pub fn set(&self, set: crate::commands::Set) {
-        let value = Value::new(set.value, set.expiry);
+    #[inline]
+    pub(crate) fn is_replica(&self) -> bool {
+        self.replica.load(Ordering::Relaxed)
+    }
+
+    pub fn set(&self, set: crate::commands::Set) -> anyhow::Result<()> {
+        let now = self.clock.now();
+        let expiration = set
+            .expiry
+            .map(|expiry| expiry.at(now).ok_or(RedisError::InvalidExpireTime("set")))
+            .transpose()?;
+        let value = Value::new(set.value, expiration);
         tracing::debug!("Adding to db: \"{}\": {:#?}", set.key, value);
-        self.inner.write().insert(set.key, value);
+        self.inner.write().insert(set.key, Arc::new(value));
+        Ok(())
     }
 
     pub fn xadd(&self, xadd: crate::commands::Xadd) -> anyhow::Result<String> {
+        let now = self.clock.now();
         let mut lock = self.inner.write();
         let entry = lock.entry(xadd.key.clone());
 
         let (res, id) = match entry {
             Entry::Occupied(mut entry) => {
-                let entry = entry.get_mut();
+                let entry = Arc::make_mut(entry.get_mut());
                 let Type::Stream(stream) = &mut entry.v_type else {
                     bail!("XADD on invalid key \"{}\"", xadd.key);
                 };
-                let id = xadd.id.auto_generate(stream)?;
-                let res = stream.xadd(id, xadd.k_v);
+                let id = xadd.id.auto_generate(stream, now)?;
+                let res = stream.xadd(id, &xadd.k_v);
                 (res, id)
             }
             Entry::Vacant(entry) => {
                 let mut stream = Stream::new();
-                let id = xadd.id.auto_generate(&stream)?;
-                let res = stream.xadd(id, xadd.k_v);
-                entry.insert(Value::new_no_expiry(Type::Stream(stream)));
+                let id = xadd.id.auto_generate(&stream, now)?;
+                let res = stream.xadd(id, &xadd.k_v);
+                entry.insert(Arc::new(Value::new_no_expiry(Type::Stream(stream))));
                 (res, id)
             }
         };
         drop(lock);
+        let qnty = self.waiters.notify(&xadd.key);
         tracing::debug!(
-            "Notifying {qnty} waiters of stream added {key} {id}",
-            qnty = self.added_stream.receiver_count(),
+            "Notified {qnty} waiters of stream added {key} {id}",
             key = xadd.key,
-            id = id
         );
-        let _ = self.added_stream.send(Some((xadd.key, id)));
         Ok(res)
     }
 
@@ -91,23 +170,281 @@ impl Db {
             .count()
     }
 
-    pub fn get(&self, get: &crate::commands::Get) -> Option<ReadValue> {
-        let k = &get.key;
-        RwLockReadGuard::try_map(self.inner.read(), |lock| lock.get(k))
-            .map(|lock| {
-                if lock.expiration.is_some_and(|exp| exp <= SystemTime::now()) {
-                    drop(lock);
-                    tracing::info!("\"{k}\" expired");
-                    self.del(std::iter::once(k));
-                    None
-                } else {
-                    Some(lock)
+    /// Whether `k` holds a value that didn't expire, without counting as a lookup
+    pub fn exists(&self, k: &[u8]) -> bool {
+        let now = self.clock.now();
+        std::str::from_utf8(k)
+            .is_ok_and(|k| self.inner.read().get(k).is_some_and(|v| !v.is_expired(now)))
+    }
+
+    /// Runs `f` on the value of `k` under the read lock, which is released before returning,
+    /// so no guard can be kept across an `.await`.
+    /// Expired keys are reported as missing, and deleted unless this is a replica.
+    pub fn view<T>(&self, k: &str, f: impl FnOnce(&Value) -> T) -> Option<T> {
+        let now = self.clock.now();
+        {
+            let lock = self.inner.read();
+            match lock.get(k) {
+                None => {
+                    STATS.incr_lookup(false);
+                    return None;
                 }
-            })
-            .ok()?
+                Some(value) if !value.is_expired(now) => {
+                    STATS.incr_lookup(true);
+                    return Some(f(value));
+                }
+                Some(_) => {}
+            }
+        }
+        self.expire_stale(&mut self.inner.write(), k);
+        STATS.incr_lookup(false);
+        None
     }
 
-    pub fn load_rdb(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+    /// Deletes `k` from `map` if it expired, recording it to be propagated.
+    /// Does nothing on a replica.
+    pub(crate) fn expire_stale(&self, map: &mut Map, k: &str) {
+        let now = self.clock.now();
+        if self.is_replica() || !map.get(k).is_some_and(|v| v.is_expired(now)) {
+            return;
+        }
+        tracing::info!("\"{k}\" expired");
+        map.remove(k);
+        self.persistence.incr_dirty(1);
+        STATS.incr_expired(1);
+        TRACKING.invalidate([Bytes::copy_from_slice(k.as_bytes())], 0);
+        self.expired.lock().push(k.to_owned());
+    }
+
+    /// Deletes the expired fields of the hash at `k`, recording them to be propagated.
+    /// Does nothing on a replica.
+    pub(crate) fn expire_fields(&self, map: &mut Map, k: &str) {
+        if self.is_replica() {
+            return;
+        }
+        let now = self.clock.now();
+        let Some(value) = map.get_mut(k) else {
+            return;
+        };
+        if value
+            .v_type
+            .as_hash()
+            .is_some_and(|hash| hash.has_expired(now))
+        {
+            self.expire_hash_fields(k, value, now);
+        }
+    }
+
+    /// The hash at `k` to write to, `None` if there's no such key.
+    /// What expired in it is deleted first.
+    pub(crate) fn hash_mut<'a>(
+        &self,
+        map: &'a mut Map,
+        k: &str,
+    ) -> anyhow::Result<Option<&'a mut Hash>> {
+        self.expire_stale(map, k);
+        self.expire_fields(map, k);
+        let Some(value) = map.get_mut(k) else {
+            return Ok(None);
+        };
+        ensure!(value.v_type.as_hash().is_some(), RedisError::WrongType);
+        Ok(Arc::make_mut(value).v_type.as_hash_mut())
+    }
+
+    /// The sorted set at `k` to write to, `None` if there's no such key
+    pub(crate) fn zset_mut<'a>(
+        &self,
+        map: &'a mut Map,
+        k: &str,
+    ) -> anyhow::Result<Option<&'a mut SortedSet>> {
+        self.expire_stale(map, k);
+        let Some(value) = map.get_mut(k) else {
+            return Ok(None);
+        };
+        ensure!(
+            value.v_type.as_sorted_set().is_some(),
+            RedisError::WrongType
+        );
+        Ok(Arc::make_mut(value).v_type.as_sorted_set_mut())
+    }
+
+    fn expire_hash_fields(&self, k: &str, value: &mut Arc<Value>, now: SystemTime) {
+        let Type::Hash(hash) = &mut Arc::make_mut(value).v_type else {
+            return;
+        };
+        let fields = hash.expire(now);
+        tracing::info!("{} fields of \"{k}\" expired", fields.len());
+        self.persistence.incr_dirty(fields.len() as u64);
+        TRACKING.invalidate([Bytes::copy_from_slice(k.as_bytes())], 0);
+        self.expired_fields.lock().push((k.to_owned(), fields));
+    }
+
+    /// Deletes every expired key and hash field, recording them to be propagated.
+    /// Does nothing on a replica.
+    pub fn expire_all(&self) {
+        if self.is_replica() {
+            return;
+        }
+        let now = self.clock.now();
+        let mut expired = Vec::new();
+        self.inner.write().retain(|k, v| {
+            let keep = !v.is_expired(now);
+            if !keep {
+                expired.push(k.clone());
+            } else if v.v_type.as_hash().is_some_and(|hash| hash.has_expired(now)) {
+                self.expire_hash_fields(k, v, now);
+            }
+            keep
+        });
+        if !expired.is_empty() {
+            tracing::info!("Expired {} keys", expired.len());
+            self.persistence.incr_dirty(expired.len() as u64);
+            STATS.incr_expired(expired.len() as u64);
+            TRACKING.invalidate(
+                expired.iter().map(|k| Bytes::copy_from_slice(k.as_bytes())),
+                0,
+            );
+            self.expired.lock().extend(expired);
+        }
+    }
+
+    /// Keys expired since the last call, to be propagated as DEL
+    pub fn take_expired(&self) -> Vec<String> {
+        std::mem::take(&mut *self.expired.lock())
+    }
+
+    /// Hash fields expired since the last call, by key, to be propagated as HDEL
+    pub fn take_expired_fields(&self) -> Vec<(String, Vec<Bytes>)> {
+        std::mem::take(&mut *self.expired_fields.lock())
+    }
+
+    pub fn clear(&self) {
+        self.inner.write().clear();
+        TRACKING.invalidate_all();
+    }
+
+    /// The dataset as of now. Only the keys are copied, under the read lock,
+    /// so the writes that follow don't wait for the snapshot to be serialized
+    pub(crate) fn snapshot(&self) -> Map {
+        self.inner.read().clone()
+    }
+
+    /// RDB image of the current dataset, tagged with the replication
+    /// id and offset it corresponds to when given
+    pub fn dump_rdb(&self, repl: Option<&ReplInfo>) -> Bytes {
+        let snapshot = self.snapshot();
+        let now = self.clock.now();
+        Self::encode_snapshot(&snapshot, self.persistence.rdbchecksum(), repl, now)
+    }
+
+    fn encode_snapshot(
+        snapshot: &Map,
+        checksum: bool,
+        repl: Option<&ReplInfo>,
+        now: SystemTime,
+    ) -> Bytes {
+        Rdb::encode(snapshot, &Self::functions(), checksum, repl, now)
+    }
+
+    /// Code of the loaded function libraries, saved along with the keys
+    #[cfg(feature = "scripting")]
+    fn functions() -> Vec<Bytes> {
+        crate::scripting::FUNCTIONS.codes()
+    }
+
+    #[cfg(not(feature = "scripting"))]
+    const fn functions() -> Vec<Bytes> {
+        Vec::new()
+    }
+
+    /// Snapshots the dataset, then serializes it in the background into chunks
+    /// of about `chunk_len` bytes sent to `tx`, for a transfer that doesn't know
+    /// the length of the image up front
+    pub(crate) fn stream_rdb(&self, repl: ReplInfo, chunk_len: usize, tx: mpsc::Sender<Bytes>) {
+        let snapshot = self.snapshot();
+        let now = self.clock.now();
+        let functions = Self::functions();
+        let checksum = self.persistence.rdbchecksum();
+        tokio::task::spawn_blocking(move || {
+            Rdb::encode_chunked(
+                &snapshot,
+                &functions,
+                checksum,
+                Some(&repl),
+                now,
+                chunk_len,
+                |chunk| {
+                    // The receiver is gone if the replica disconnected
+                    let _ = tx.blocking_send(chunk);
+                },
+            );
+        });
+    }
+
+    /// Synchronously dumps the dataset to `path`
+    pub fn save(&self, path: impl AsRef<Path>, repl: Option<&ReplInfo>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let dirty = self.persistence.dirty();
+        let rdb = self.dump_rdb(repl);
+        crate::rdb::write(path, &rdb)
+            .with_context(|| format!("ERR failed saving to {}", path.display()))?;
+        self.persistence.saved(dirty);
+        tracing::info!("DB saved on disk to {}", path.display());
+        Ok(())
+    }
+
+    /// Snapshots the dataset and writes it to `path` in the background
+    pub fn bgsave(self: &Arc<Self>, path: PathBuf, repl: Option<&ReplInfo>) -> anyhow::Result<()> {
+        self.persistence.start_bgsave()?;
+        let dirty = self.persistence.dirty();
+        let snapshot = self.snapshot();
+        let now = self.clock.now();
+        let checksum = self.persistence.rdbchecksum();
+        let repl = repl.cloned();
+        let db = Arc::clone(self);
+        tokio::task::spawn_blocking(move || {
+            let rdb = Self::encode_snapshot(&snapshot, checksum, repl.as_ref(), now);
+            drop(snapshot);
+            let res = crate::rdb::write(&path, &rdb);
+            match &res {
+                Ok(()) => {
+                    db.persistence.saved(dirty);
+                    tracing::info!("Background saving to {} terminated", path.display());
+                }
+                Err(e) => tracing::error!("Background saving to {} failed: {e}", path.display()),
+            }
+            db.persistence.finish_bgsave(res.is_ok());
+        });
+        Ok(())
+    }
+
+    /// Starts a background save whenever a save point is reached
+    pub async fn save_on_schedule(
+        self: Arc<Self>,
+        path: PathBuf,
+        repl: impl Fn() -> Option<ReplInfo>,
+    ) {
+        let mut interval = tokio::time::interval(Duration::from_secs(1));
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            if !self.persistence.should_save() {
+                continue;
+            }
+            tracing::info!(
+                "{} changes since the last save, saving",
+                self.persistence.dirty()
+            );
+            if let Err(e) = self.bgsave(path.clone(), repl().as_ref()) {
+                tracing::error!("{e}");
+            }
+        }
+    }
+
+    const RDB_READ_BUF: usize = 64 * 1024;
+
+    /// Loads the dump at `path`, returning the replication id and offset it recorded
+    pub fn load_rdb(&self, path: impl AsRef<Path>) -> anyhow::Result<Option<ReplInfo>> {
         let path = path.as_ref();
 
         // FIXME windows doesn't like /tmp :(
@@ -119,34 +456,59 @@ impl Db {
             Cow::Borrowed(path)
         };
 
-        let rdb = match std::fs::read(&path) {
-            Ok(rdb) => rdb,
+        let file = match File::open(&path) {
+            Ok(file) => file,
             Err(e) => match e.kind() {
                 std::io::ErrorKind::NotFound => {
-                    tracing::error!("File not found: {}", path.display());
-                    return Ok(());
+                    tracing::info!("No RDB file at {}, starting empty", path.display());
+                    return Ok(None);
                 }
                 _ => return Err(e.into()),
             },
         };
-        let rdb = Rdb::parse(rdb.into())?;
+        // Parsed as it's read, so the file is never held in memory next to the dataset
+        let reader = BufReader::with_capacity(Self::RDB_READ_BUF, file);
+        let rdb = Rdb::read(reader, self.persistence.rdbchecksum())?;
+        let repl = rdb.repl_info();
         self.apply_rdb(rdb);
-        Ok(())
+        Ok(repl)
     }
 
     pub fn apply_rdb(&self, rdb: Rdb) {
-        self.inner
-            .write()
-            .extend(rdb.db.maps.into_iter().flatten().filter(|(key, v)| {
-                let expired = v.expiration.is_some_and(|exp| exp <= SystemTime::now());
-                if expired {
-                    tracing::info!("key: \"{key}\" from rdb expired");
-                }
-                !expired
-            }));
+        #[cfg(feature = "scripting")]
+        for code in rdb.functions {
+            if let Err(e) = crate::scripting::FUNCTIONS.load(code, true) {
+                tracing::warn!("Failed loading a function library from the rdb: {e}");
+            }
+        }
+        #[cfg(not(feature = "scripting"))]
+        if !rdb.functions.is_empty() {
+            tracing::warn!("Ignoring the function libraries of the rdb, scripting is disabled");
+        }
+        let replica = self.is_replica();
+        let limits = *self.encoding.read();
+        let now = self.clock.now();
+        self.inner.write().extend(
+            rdb.db
+                .maps
+                .into_iter()
+                .flatten()
+                .filter(|(key, v)| {
+                    let expired = !replica && v.is_expired(now);
+                    if expired {
+                        tracing::info!("key: \"{key}\" from rdb expired");
+                    }
+                    !expired
+                })
+                .map(|(key, mut v)| {
+                    v.v_type.fit_encoding(&limits);
+                    (key, Arc::new(v))
+                }),
+        );
     }
 }
 
+#[derive(Clone)]
 pub struct Value {
     pub(crate) v_type: Type,
     pub(crate) expiration: Option<SystemTime>,
@@ -161,14 +523,21 @@ impl Value {
         }
     }
 
+    /// Whether the key expired, or the hash it holds lost all of its fields to expiration
+    #[inline]
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        self.expiration.is_some_and(|exp| exp <= now)
+            || matches!(&self.v_type, Type::Hash(hash) if hash.all_expired(now))
+    }
+
     #[inline]
     pub const fn new_no_expiry(r#type: Type) -> Self {
         Self::new(r#type, None)
     }
 
     #[inline]
-    pub const fn new_no_expiry_string(bytes: Bytes) -> Self {
-        Self::new_no_expiry(Type::String(bytes))
+    pub fn new_no_expiry_string(string: impl Into<RedisString>) -> Self {
+        Self::new_no_expiry(Type::String(string.into()))
     }
 }
 
@@ -185,30 +554,32 @@ impl Debug for Value {
 
 #[cfg(test)]
 mod tests {
-    use std::{thread::sleep, time::Duration};
+    use std::time::Duration;
 
-    use crate::commands::{Get, Set};
+    use crate::commands::Set;
 
     use super::*;
 
     #[test]
     fn expires() {
-        let db = Db::new();
+        let db = Db::default();
+        db.clock.pause();
 
         let key = "test".to_owned();
         let value = b"bytes".as_ref().into();
         let expiry = Some(Duration::from_millis(100));
         let set = Set::new(key.clone(), value, expiry);
-        db.set(set);
+        db.set(set).unwrap();
 
-        assert!(db.get(&Get::new(key.clone())).is_some());
-        sleep(Duration::from_millis(100));
-        assert!(db.get(&Get::new(key)).is_none());
+        db.clock.advance(Duration::from_millis(99));
+        assert!(db.view(&key, |_| ()).is_some());
+        db.clock.advance(Duration::from_millis(1));
+        assert!(db.view(&key, |_| ()).is_none());
     }
 
     #[test]
     fn del() {
-        let db = Db::new();
+        let db = Db::default();
 
         let keys = ["key1", "key2", "key3"].map(String::from);
 
@@ -216,7 +587,7 @@ mod tests {
             .into_iter()
             .map(|k| Set::new(k, "test".into(), None))
             .for_each(|set| {
-                db.set(set);
+                db.set(set).unwrap();
             });
 
         assert_eq!(db.inner.read().len(), 3);
@@ -224,4 +595,70 @@ mod tests {
         assert_eq!(db.inner.read().len(), 2);
         assert_eq!(db.del(keys[1..=2].iter()), 2);
     }
+
+    #[test]
+    fn replica_keeps_expired() {
+        let db = Db::default();
+        db.clock.pause();
+        db.set_replica(true);
+
+        let expiry = Some(Duration::from_millis(10));
+        db.set(Set::new("a".to_owned(), "1".into(), expiry))
+            .unwrap();
+        db.clock.advance(Duration::from_millis(10));
+
+        assert!(db.view("a", |_| ()).is_none());
+        db.expire_all();
+        assert_eq!(db.inner.read().len(), 1);
+        assert!(db.take_expired().is_empty());
+
+        db.set_replica(false);
+        assert!(db.view("a", |_| ()).is_none());
+        assert_eq!(db.inner.read().len(), 0);
+        assert_eq!(db.take_expired(), ["a"]);
+    }
+
+    #[test]
+    fn reloads_saved_dataset() {
+        let dir = std::env::temp_dir().join(format!("reload-rdb-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("dump.rdb");
+
+        // Nothing saved yet, so it starts empty
+        let db = Db::default();
+        db.load_rdb(&path).unwrap();
+        assert_eq!(db.inner.read().len(), 0);
+
+        db.set(Set::new("k".to_owned(), "v".into(), None)).unwrap();
+        db.save(&path, None).unwrap();
+        let db = Db::default();
+        db.load_rdb(&path).unwrap();
+        assert!(db.view("k", |_| ()).is_some());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn snapshot_is_isolated() {
+        let db = Db::default();
+        let xadd = |id| crate::commands::Xadd {
+            key: "s".to_owned(),
+            id: stream::MaybeAuto::Set((Duration::from_millis(id), 0)),
+            k_v: vec![("f".to_owned(), "v".to_owned())],
+        };
+        db.xadd(xadd(1)).unwrap();
+        db.set(Set::new("a".to_owned(), "1".into(), None)).unwrap();
+
+        let snapshot = db.snapshot();
+        db.xadd(xadd(2)).unwrap();
+        db.set(Set::new("b".to_owned(), "2".into(), None)).unwrap();
+        db.del(["a"]);
+
+        assert_eq!(snapshot.len(), 2);
+        assert!(snapshot.contains_key("a"));
+        assert_eq!(snapshot["s"].v_type.as_stream().unwrap().inner.len(), 1);
+        assert_eq!(
+            db.view("s", |v| v.v_type.as_stream().unwrap().inner.len()),
+            Some(2)
+        );
+    }
 }
@@ -0,0 +1,245 @@
+use anyhow::{bail, ensure, Context};
+use parking_lot::RwLock;
+use std::{
+    fmt::Display,
+    str::FromStr,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::UNIX_EPOCH,
+};
+
+use crate::clock::Clock;
+
+/// `save <seconds> <changes>`: dump the dataset once at least `changes` writes
+/// happened and `seconds` elapsed since the last save.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SavePoint {
+    pub seconds: u64,
+    pub changes: u64,
+}
+
+/// Parses the `save` parameter, pairs of seconds and changes. Empty disables saving.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SavePoints(pub Vec<SavePoint>);
+
+impl SavePoints {
+    pub const DEFAULT: &'static str = "3600 1 300 100 60 10000";
+}
+
+impl FromStr for SavePoints {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let nums = s
+            .split_ascii_whitespace()
+            .map(|x| x.parse::<u64>().context("Invalid save parameters"))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        ensure!(nums.len().is_multiple_of(2), "Invalid save parameters");
+        let points = nums
+            .chunks_exact(2)
+            .map(|x| SavePoint {
+                seconds: x[0],
+                changes: x[1],
+            })
+            .collect();
+        Ok(Self(points))
+    }
+}
+
+impl Display for SavePoints {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, point) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            write!(f, "{} {}", point.seconds, point.changes)?;
+        }
+        Ok(())
+    }
+}
+
+/// Bookkeeping of the dumps to disk
+#[derive(Debug)]
+pub struct Persistence {
+    /// Writes since the last successful save
+    dirty: AtomicU64,
+    /// Unix time of the last successful save, or of the startup
+    last_save: AtomicU64,
+    bgsave_in_progress: AtomicBool,
+    last_bgsave_ok: AtomicBool,
+    /// Unix time of the last background save attempt
+    last_bgsave_try: AtomicU64,
+    save_points: RwLock<SavePoints>,
+    /// Whether dumps carry a CRC64 and loads verify it
+    rdbchecksum: AtomicBool,
+    /// Whether the dataset is being loaded from the disk
+    loading: AtomicBool,
+    /// The clock of the dataset, saves are timed with
+    clock: Clock,
+}
+
+impl Default for Persistence {
+    fn default() -> Self {
+        Self::new(Clock::default())
+    }
+}
+
+impl Persistence {
+    pub(crate) fn new(clock: Clock) -> Self {
+        Self {
+            dirty: AtomicU64::new(0),
+            last_save: AtomicU64::new(unix_time(&clock)),
+            bgsave_in_progress: AtomicBool::new(false),
+            last_bgsave_ok: AtomicBool::new(true),
+            last_bgsave_try: AtomicU64::new(0),
+            save_points: RwLock::new(SavePoints::default()),
+            rdbchecksum: AtomicBool::new(true),
+            loading: AtomicBool::new(false),
+            clock,
+        }
+    }
+
+    /// Seconds before save points are checked again after a failed background save
+    const BGSAVE_RETRY_DELAY: u64 = 5;
+
+    #[inline]
+    pub fn incr_dirty(&self, by: u64) {
+        self.dirty.fetch_add(by, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn dirty(&self) -> u64 {
+        self.dirty.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub fn last_save(&self) -> u64 {
+        self.last_save.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub fn bgsave_in_progress(&self) -> bool {
+        self.bgsave_in_progress.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub fn last_bgsave_ok(&self) -> bool {
+        self.last_bgsave_ok.load(Ordering::Relaxed)
+    }
+
+    pub fn save_points(&self) -> SavePoints {
+        self.save_points.read().clone()
+    }
+
+    pub fn set_save_points(&self, points: SavePoints) {
+        *self.save_points.write() = points;
+    }
+
+    #[inline]
+    pub fn rdbchecksum(&self) -> bool {
+        self.rdbchecksum.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub fn set_rdbchecksum(&self, enabled: bool) {
+        self.rdbchecksum.store(enabled, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn loading(&self) -> bool {
+        self.loading.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub fn set_loading(&self, loading: bool) {
+        self.loading.store(loading, Ordering::Relaxed);
+    }
+
+    /// Marks a background save as started, failing if one already is
+    pub(crate) fn start_bgsave(&self) -> anyhow::Result<()> {
+        if self.bgsave_in_progress.swap(true, Ordering::AcqRel) {
+            bail!("ERR Background save already in progress");
+        }
+        self.last_bgsave_try
+            .store(unix_time(&self.clock), Ordering::Relaxed);
+        Ok(())
+    }
+
+    pub(crate) fn finish_bgsave(&self, ok: bool) {
+        self.last_bgsave_ok.store(ok, Ordering::Relaxed);
+        self.bgsave_in_progress.store(false, Ordering::Release);
+    }
+
+    /// Records a successful save of the dataset as it was with `dirty` changes,
+    /// keeping the writes that happened while it was written
+    pub(crate) fn saved(&self, dirty: u64) {
+        self.dirty.fetch_sub(dirty, Ordering::Relaxed);
+        self.last_save
+            .store(unix_time(&self.clock), Ordering::Relaxed);
+    }
+
+    /// Whether a save point is reached
+    pub fn should_save(&self) -> bool {
+        let now = unix_time(&self.clock);
+        if self.bgsave_in_progress()
+            || (!self.last_bgsave_ok()
+                && now.saturating_sub(self.last_bgsave_try.load(Ordering::Relaxed))
+                    < Self::BGSAVE_RETRY_DELAY)
+        {
+            return false;
+        }
+        let dirty = self.dirty();
+        let elapsed = now.saturating_sub(self.last_save());
+        self.save_points
+            .read()
+            .0
+            .iter()
+            .any(|point| dirty >= point.changes && elapsed >= point.seconds)
+    }
+}
+
+fn unix_time(clock: &Clock) -> u64 {
+    clock
+        .now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn save_points() {
+        let points = SavePoints::from_str(SavePoints::DEFAULT).unwrap();
+        pretty_assertions::assert_eq!(points.0.len(), 3);
+        pretty_assertions::assert_eq!(
+            points.0[1],
+            SavePoint {
+                seconds: 300,
+                changes: 100
+            }
+        );
+        pretty_assertions::assert_eq!(points.to_string(), SavePoints::DEFAULT);
+
+        assert!(SavePoints::from_str("").unwrap().0.is_empty());
+        assert!(SavePoints::from_str("10").is_err());
+        assert!(SavePoints::from_str("a 1").is_err());
+    }
+
+    #[test]
+    fn should_save() {
+        let clock = Clock::default();
+        clock.pause();
+        let persistence = Persistence::new(clock.clone());
+        persistence.set_save_points(SavePoints::from_str("10 2").unwrap());
+        persistence.incr_dirty(2);
+        assert!(!persistence.should_save());
+        clock.advance(Duration::from_secs(10));
+        assert!(persistence.should_save());
+        persistence.saved(2);
+        assert!(!persistence.should_save());
+    }
+}
@@ -0,0 +1,75 @@
+use bytes::Bytes;
+use std::collections::HashSet;
+
+use super::{encoding::as_int, listpack::Listpack, EncodingLimits};
+
+#[derive(Debug, Clone)]
+pub enum Set {
+    /// Sorted, for sets of integers only
+    Intset(Vec<i64>),
+    Listpack(Listpack),
+    Hashtable(HashSet<Bytes>),
+}
+
+impl Set {
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Intset(ints) => ints.len(),
+            Self::Listpack(listpack) => listpack.len(),
+            Self::Hashtable(set) => set.len(),
+        }
+    }
+
+    pub fn iter(&self) -> Box<dyn Iterator<Item = Bytes> + '_> {
+        match self {
+            Self::Intset(ints) => Box::new(ints.iter().map(|int| int.to_string().into())),
+            Self::Listpack(listpack) => Box::new(listpack.iter()),
+            Self::Hashtable(set) => Box::new(set.iter().cloned()),
+        }
+    }
+
+    pub fn contains(&self, member: &[u8]) -> bool {
+        match self {
+            Self::Intset(ints) => {
+                as_int(member).is_some_and(|int| ints.binary_search(&int).is_ok())
+            }
+            Self::Listpack(listpack) => listpack.iter().any(|m| m == member),
+            Self::Hashtable(set) => set.contains(member),
+        }
+    }
+
+    pub const fn encoding(&self) -> &'static str {
+        match self {
+            Self::Intset(_) => "intset",
+            Self::Listpack(_) => "listpack",
+            Self::Hashtable(_) => "hashtable",
+        }
+    }
+
+    pub fn fit(&mut self, limits: &EncodingLimits) {
+        let len = self.len();
+        let ints = self.iter().map(|m| as_int(&m)).collect::<Option<Vec<_>>>();
+        *self = match ints {
+            Some(mut ints) if len <= limits.set_max_intset_entries => {
+                ints.sort_unstable();
+                Self::Intset(ints)
+            }
+            _ if limits.set_fits(len, self.iter().map(|m| m.len()).max().unwrap_or(0)) => {
+                Self::Listpack(self.iter().collect())
+            }
+            _ => Self::Hashtable(self.iter().collect()),
+        };
+    }
+}
+
+impl FromIterator<Bytes> for Set {
+    fn from_iter<T: IntoIterator<Item = Bytes>>(iter: T) -> Self {
+        Self::Hashtable(iter.into_iter().collect())
+    }
+}
+
+impl PartialEq for Set {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().all(|member| other.contains(&member))
+    }
+}
@@ -0,0 +1,335 @@
+use bytes::Bytes;
+use rand::Rng;
+use std::{cmp::Ordering, collections::HashMap, ops::Bound};
+
+/// Levels a node can be linked at, enough for 4^32 members
+const MAX_LEVEL: usize = 32;
+/// Probability for a node to also be linked at the next level
+const P: f64 = 0.25;
+/// Index of the head node, which holds no member
+const HEAD: usize = 0;
+
+/// Members ordered by score then by member, with a hash index of their scores.
+/// Each link records how many members it skips, so ranks are found in O(log n).
+#[derive(Debug, Clone)]
+pub struct Skiplist {
+    /// The head, then the members, linked by index. Unlinked slots are reused.
+    nodes: Vec<Node>,
+    free: Vec<usize>,
+    scores: HashMap<Bytes, f64>,
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    member: Bytes,
+    score: f64,
+    /// Next node at each level, the head having as many levels as the highest node
+    links: Vec<Link>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Link {
+    next: Option<usize>,
+    /// Distance to `next` in ranks, or to the end of the list if there's none
+    span: usize,
+}
+
+impl Default for Skiplist {
+    fn default() -> Self {
+        let head = Node {
+            member: Bytes::new(),
+            score: 0.0,
+            links: Vec::new(),
+        };
+        Self {
+            nodes: vec![head],
+            free: Vec::new(),
+            scores: HashMap::new(),
+        }
+    }
+}
+
+impl Node {
+    /// Whether the node comes before the member with `score`
+    fn before(&self, score: f64, member: &[u8]) -> bool {
+        self.score
+            .partial_cmp(&score)
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| self.member[..].cmp(member))
+            .is_lt()
+    }
+}
+
+impl Skiplist {
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn score(&self, member: &[u8]) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    /// Adds the member or updates its score, returning whether it's new
+    pub fn insert(&mut self, member: Bytes, score: f64) -> bool {
+        let previous = self.scores.insert(member.clone(), score);
+        if let Some(previous) = previous {
+            if previous.to_bits() == score.to_bits() {
+                return false;
+            }
+            self.unlink(&member, previous);
+        }
+        self.link(member, score);
+        previous.is_none()
+    }
+
+    /// Returns the score of the member, if it was there
+    pub fn remove(&mut self, member: &[u8]) -> Option<f64> {
+        let score = self.scores.remove(member)?;
+        self.unlink(member, score);
+        Some(score)
+    }
+
+    /// Position of the member in the order of the set, from 0
+    pub fn rank(&self, member: &[u8]) -> Option<usize> {
+        let score = self.score(member)?;
+        Some(self.count_while(|node| node.before(score, member)))
+    }
+
+    /// Members with their score, in order
+    pub fn iter(&self) -> impl Iterator<Item = (Bytes, f64)> + '_ {
+        self.range(0)
+    }
+
+    /// Members from rank `start` onwards
+    pub fn range(&self, start: usize) -> impl Iterator<Item = (Bytes, f64)> + '_ {
+        std::iter::successors(self.node_at(start), |&x| self.nodes[x].links[0].next).map(|x| {
+            let node = &self.nodes[x];
+            (node.member.clone(), node.score)
+        })
+    }
+
+    /// Number of members with a score between `min` and `max`
+    pub fn count(&self, min: Bound<f64>, max: Bound<f64>) -> usize {
+        let below_max = self.count_while(|node| match max {
+            Bound::Included(max) => node.score <= max,
+            Bound::Excluded(max) => node.score < max,
+            Bound::Unbounded => true,
+        });
+        let below_min = self.count_while(|node| match min {
+            Bound::Included(min) => node.score < min,
+            Bound::Excluded(min) => node.score <= min,
+            Bound::Unbounded => false,
+        });
+        below_max.saturating_sub(below_min)
+    }
+
+    fn height(&self) -> usize {
+        self.nodes[HEAD].links.len()
+    }
+
+    /// Number of nodes `pred` holds for, which must be a prefix of the list
+    fn count_while(&self, pred: impl Fn(&Node) -> bool) -> usize {
+        let (mut x, mut rank) = (HEAD, 0);
+        for level in (0..self.height()).rev() {
+            loop {
+                let link = self.nodes[x].links[level];
+                match link.next {
+                    Some(next) if pred(&self.nodes[next]) => {
+                        rank += link.span;
+                        x = next;
+                    }
+                    _ => break,
+                }
+            }
+        }
+        rank
+    }
+
+    fn node_at(&self, rank: usize) -> Option<usize> {
+        // Ranks of the nodes count from 1, the head being 0
+        let target = rank + 1;
+        let (mut x, mut traversed) = (HEAD, 0);
+        for level in (0..self.height()).rev() {
+            loop {
+                let link = self.nodes[x].links[level];
+                match link.next {
+                    Some(next) if traversed + link.span <= target => {
+                        traversed += link.span;
+                        x = next;
+                    }
+                    _ => break,
+                }
+            }
+            if traversed == target {
+                return Some(x);
+            }
+        }
+        None
+    }
+
+    /// The last node before the member at each level, with its rank
+    fn predecessors(&self, score: f64, member: &[u8]) -> [(usize, usize); MAX_LEVEL] {
+        let mut update = [(HEAD, 0); MAX_LEVEL];
+        let (mut x, mut rank) = (HEAD, 0);
+        for level in (0..self.height()).rev() {
+            loop {
+                let link = self.nodes[x].links[level];
+                match link.next {
+                    Some(next) if self.nodes[next].before(score, member) => {
+                        rank += link.span;
+                        x = next;
+                    }
+                    _ => break,
+                }
+            }
+            update[level] = (x, rank);
+        }
+        update
+    }
+
+    fn link(&mut self, member: Bytes, score: f64) {
+        let update = self.predecessors(score, &member);
+        let height = self.height();
+        let level = random_level();
+        for _ in height..level {
+            // New levels of the head link to nothing yet, so they span the whole list
+            let len = self.len() - 1;
+            self.nodes[HEAD].links.push(Link {
+                next: None,
+                span: len,
+            });
+        }
+        let node = Node {
+            member,
+            score,
+            links: Vec::with_capacity(level),
+        };
+        let new = if let Some(slot) = self.free.pop() {
+            self.nodes[slot] = node;
+            slot
+        } else {
+            self.nodes.push(node);
+            self.nodes.len() - 1
+        };
+        let rank = update[0].1;
+        for (i, &(prev, prev_rank)) in update.iter().enumerate().take(level) {
+            let link = self.nodes[prev].links[i];
+            self.nodes[new].links.push(Link {
+                next: link.next,
+                span: link.span - (rank - prev_rank),
+            });
+            self.nodes[prev].links[i] = Link {
+                next: Some(new),
+                span: rank - prev_rank + 1,
+            };
+        }
+        // Higher links skip the new node
+        for (i, &(prev, _)) in update.iter().enumerate().take(height).skip(level) {
+            self.nodes[prev].links[i].span += 1;
+        }
+    }
+
+    fn unlink(&mut self, member: &[u8], score: f64) {
+        let update = self.predecessors(score, member);
+        let Some(target) = self.nodes[update[0].0].links[0].next else {
+            return;
+        };
+        for (i, &(prev, _)) in update.iter().enumerate().take(self.height()) {
+            let link = self.nodes[prev].links[i];
+            self.nodes[prev].links[i] = if link.next == Some(target) {
+                let skipped = self.nodes[target].links[i];
+                Link {
+                    next: skipped.next,
+                    span: link.span + skipped.span - 1,
+                }
+            } else {
+                Link {
+                    span: link.span - 1,
+                    ..link
+                }
+            };
+        }
+        while self.nodes[HEAD]
+            .links
+            .last()
+            .is_some_and(|link| link.next.is_none())
+        {
+            self.nodes[HEAD].links.pop();
+        }
+        let node = &mut self.nodes[target];
+        node.member = Bytes::new();
+        node.links = Vec::new();
+        self.free.push(target);
+    }
+}
+
+fn random_level() -> usize {
+    let mut rng = rand::thread_rng();
+    let mut level = 1;
+    while level < MAX_LEVEL && rng.gen_bool(P) {
+        level += 1;
+    }
+    level
+}
+
+impl FromIterator<(Bytes, f64)> for Skiplist {
+    fn from_iter<T: IntoIterator<Item = (Bytes, f64)>>(iter: T) -> Self {
+        let mut list = Self::default();
+        for (member, score) in iter {
+            list.insert(member, score);
+        }
+        list
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::RangeBounds;
+
+    use super::*;
+
+    #[test]
+    fn ranks_and_counts() {
+        let mut rng = rand::thread_rng();
+        let mut list = Skiplist::default();
+        // Same order as the skiplist, scores first
+        let mut model: Vec<(f64, Bytes)> = Vec::new();
+        for _ in 0..2000 {
+            let member = Bytes::from(format!("m{}", rng.gen_range(0..500)));
+            let score = f64::from(rng.gen_range(-50..50));
+            model.retain(|(_, m)| *m != member);
+            if rng.gen_bool(0.2) {
+                list.remove(&member);
+            } else {
+                list.insert(member.clone(), score);
+                model.push((score, member));
+            }
+        }
+        model.sort_by(|a, b| a.0.total_cmp(&b.0).then_with(|| a.1.cmp(&b.1)));
+
+        pretty_assertions::assert_eq!(list.len(), model.len());
+        let ordered = list.iter().map(|(m, s)| (s, m)).collect::<Vec<_>>();
+        pretty_assertions::assert_eq!(ordered, model);
+        for (rank, (score, member)) in model.iter().enumerate() {
+            pretty_assertions::assert_eq!(list.rank(member), Some(rank));
+            pretty_assertions::assert_eq!(list.score(member), Some(*score));
+            pretty_assertions::assert_eq!(list.range(rank).next(), Some((member.clone(), *score)));
+        }
+        pretty_assertions::assert_eq!(list.range(model.len()).next(), None);
+        pretty_assertions::assert_eq!(list.rank(b"missing"), None);
+
+        let count = |min: Bound<f64>, max: Bound<f64>| {
+            let within = |score: f64| (min, max).contains(&score);
+            model.iter().filter(|(score, _)| within(*score)).count()
+        };
+        for (min, max) in [
+            (Bound::Included(-10.0), Bound::Included(10.0)),
+            (Bound::Excluded(-10.0), Bound::Excluded(10.0)),
+            (Bound::Unbounded, Bound::Excluded(0.0)),
+            (Bound::Included(30.0), Bound::Unbounded),
+            (Bound::Included(5.0), Bound::Included(-5.0)),
+        ] {
+            pretty_assertions::assert_eq!(list.count(min, max), count(min, max));
+        }
+    }
+}
@@ -1,49 +1,263 @@
-use anyhow::bail;
+use anyhow::{bail, Context};
 use either::Either;
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, HashMap},
     fmt::Display,
-    ops::RangeBounds,
-    time::{Duration, UNIX_EPOCH},
+    ops::{
+        Bound::{Excluded, Included, Unbounded},
+        RangeBounds,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use crate::Resp;
 
-type StreamInner = BTreeMap<EntryId, StreamValues>;
+mod entries;
+pub use entries::Entries;
+
 type StreamValues = Vec<(String, String)>;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Stream {
-    pub(crate) inner: StreamInner,
+    pub(crate) inner: Entries,
+    /// Id of the last entry ever added, which survives deletion of the top entry.
+    pub(crate) last_id: EntryId,
+    /// Number of entries added over the lifetime of the stream.
+    pub(crate) entries_added: u64,
+    /// Greatest id removed by XDEL.
+    pub(crate) max_deleted_id: EntryId,
+    pub(crate) groups: HashMap<String, ConsumerGroup>,
 }
 
 impl Stream {
     const SMALL_EQ: &'static str =
         "ERR The ID specified in XADD is equal or smaller than the target stream top item";
 
-    pub(crate) const fn new() -> Self {
-        let inner = BTreeMap::new();
-        Self { inner }
+    pub(crate) fn new() -> Self {
+        Self {
+            inner: Entries::default(),
+            last_id: EntryId::MIN,
+            entries_added: 0,
+            max_deleted_id: EntryId::MIN,
+            groups: HashMap::new(),
+        }
+    }
+
+    #[inline]
+    pub(crate) const fn last_id(&self) -> EntryId {
+        self.last_id
+    }
+
+    pub(crate) fn create_group(&mut self, name: String, last_delivered: EntryId) -> bool {
+        if self.groups.contains_key(&name) {
+            return false;
+        }
+        self.groups.insert(name, ConsumerGroup::new(last_delivered));
+        true
+    }
+
+    pub(crate) fn group_mut(&mut self, name: &str) -> Option<&mut ConsumerGroup> {
+        self.groups.get_mut(name)
+    }
+
+    /// Delivers entries never seen by the group (`>` id) to `consumer`,
+    /// adding them to the PEL unless `noack` is set.
+    pub(crate) fn read_group_new(
+        &mut self,
+        group: &str,
+        consumer: &str,
+        count: Option<usize>,
+        noack: bool,
+        now: SystemTime,
+    ) -> Option<Vec<Resp>> {
+        let group = self.groups.get_mut(group)?;
+        group.consumer_mut(consumer, now).seen_time = now;
+
+        let range = (Excluded(group.last_delivered), Unbounded);
+        let entries = match count {
+            Some(count) => Either::Left(self.inner.range(range).take(count)),
+            None => Either::Right(self.inner.range(range)),
+        }
+        .inspect(|(id, _)| {
+            group.last_delivered = *id;
+            group.entries_read += 1;
+            if !noack {
+                group
+                    .pending
+                    .insert(*id, PendingEntry::new(consumer.to_owned(), now));
+            }
+        });
+        Some(Self::format_entries(entries))
+    }
+
+    /// Returns the entries of `consumer`'s pending list with an id greater than `start`.
+    /// Entries deleted from the stream are reported with a nil body.
+    pub(crate) fn read_group_pending(
+        &mut self,
+        group: &str,
+        consumer: &str,
+        start: EntryId,
+        count: Option<usize>,
+        now: SystemTime,
+    ) -> Option<Vec<Resp>> {
+        let group = self.groups.get_mut(group)?;
+        group.consumer_mut(consumer, now).seen_time = now;
+
+        let pending = group
+            .pending
+            .range((Excluded(start), Unbounded))
+            .filter(|(_, pending)| pending.consumer == consumer)
+            .map(|(id, _)| *id);
+        let pending = match count {
+            Some(count) => Either::Left(pending.take(count)),
+            None => Either::Right(pending),
+        };
+
+        let entries = pending
+            .map(|id| {
+                self.inner.get(&id).map_or_else(
+                    || Resp::Array(vec![Resp::bulk(id.to_string()), Resp::Null]),
+                    |values| {
+                        Self::format_entries(std::iter::once((id, values)))
+                            .pop()
+                            .expect("1 entry")
+                    },
+                )
+            })
+            .collect();
+        Some(entries)
+    }
+
+    /// Scans the group's PEL starting at `start`, transferring entries idle for at least
+    /// `min_idle` to `consumer`. Entries that no longer exist in the stream are removed
+    /// from the PEL and reported separately.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn autoclaim(
+        &mut self,
+        group: &str,
+        consumer: &str,
+        min_idle: Duration,
+        start: EntryId,
+        count: usize,
+        justid: bool,
+        now: SystemTime,
+    ) -> Option<AutoClaim> {
+        const ATTEMPTS_FACTOR: usize = 10;
+
+        let group = self.groups.get_mut(group)?;
+        group.consumer_mut(consumer, now).seen_time = now;
+
+        let mut attempts = count.saturating_mul(ATTEMPTS_FACTOR);
+        let mut claimed = Vec::new();
+        let mut deleted = Vec::new();
+        let mut cursor = start;
+        let mut next = EntryId::MIN;
+
+        while let Some((&id, pending)) = group
+            .pending
+            .range_mut((Included(cursor), Unbounded))
+            .next()
+        {
+            if attempts == 0 || claimed.len() >= count {
+                next = id;
+                break;
+            }
+            attempts -= 1;
+
+            if !self.inner.contains_key(&id) {
+                group.pending.remove(&id);
+                deleted.push(id);
+            } else if pending.idle(now) >= min_idle {
+                consumer.clone_into(&mut pending.consumer);
+                pending.delivery_time = now;
+                if !justid {
+                    pending.delivery_count += 1;
+                }
+                claimed.push(id);
+            }
+
+            match id.successor() {
+                Some(successor) => cursor = successor,
+                None => break,
+            }
+        }
+
+        Some(AutoClaim {
+            next,
+            claimed: claimed
+                .into_iter()
+                .map(|id| {
+                    if justid {
+                        return Resp::bulk(id.to_string());
+                    }
+                    let values = self.inner.get(&id).expect("Checked above");
+                    Self::format_entries(std::iter::once((id, values)))
+                        .pop()
+                        .expect("1 entry")
+                })
+                .collect(),
+            deleted,
+        })
     }
 
-    pub(super) fn xadd(&mut self, id: EntryId, values: StreamValues) -> String {
+    pub(crate) fn xadd(&mut self, id: EntryId, values: &StreamValues) -> String {
         let id_res = id.to_string();
         self.inner.insert(id, values);
+        self.last_id = id;
+        self.entries_added += 1;
         id_res
     }
 
-    pub(crate) fn format_entries<'a, I>(entries: I) -> Vec<Resp>
+    pub(crate) fn xdel<'a, I>(&mut self, ids: I) -> usize
     where
-        I: IntoIterator<Item = (&'a EntryId, &'a StreamValues)>,
+        I: IntoIterator<Item = &'a EntryId>,
+    {
+        ids.into_iter()
+            .filter(|id| self.inner.remove(id))
+            .inspect(|id| self.max_deleted_id = self.max_deleted_id.max(**id))
+            .count()
+    }
+
+    pub(crate) fn set_id(
+        &mut self,
+        last_id: EntryId,
+        entries_added: Option<u64>,
+        max_deleted_id: Option<EntryId>,
+    ) -> anyhow::Result<()> {
+        if self.inner.last_id().is_some_and(|id| last_id < id) {
+            bail!("ERR The ID specified in XSETID is smaller than the target stream top item");
+        }
+        if entries_added.is_some_and(|added| added < self.inner.len() as u64) {
+            bail!("ERR The entries_added specified in XSETID is smaller than the target stream length");
+        }
+        if max_deleted_id.is_some_and(|max_deleted| last_id < max_deleted) {
+            bail!(
+                "ERR The ID specified in XSETID is smaller than the provided max_deleted_entry_id"
+            );
+        }
+        self.last_id = last_id;
+        if let Some(entries_added) = entries_added {
+            self.entries_added = entries_added;
+        }
+        if let Some(max_deleted_id) = max_deleted_id {
+            self.max_deleted_id = max_deleted_id;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn format_entries<I>(entries: I) -> Vec<Resp>
+    where
+        I: IntoIterator<Item = (EntryId, StreamValues)>,
     {
         entries
             .into_iter()
             .fold(Vec::new(), |mut acc, (id, key_values)| {
-                let k_v = key_values.iter().fold(
-                    Vec::with_capacity(key_values.len() * 2),
+                let capacity = key_values.len() * 2;
+                let k_v = key_values.into_iter().fold(
+                    Vec::with_capacity(capacity),
                     |mut acc, (key, value)| {
-                        acc.push(Resp::bulk(key.clone()));
-                        acc.push(Resp::bulk(value.clone()));
+                        acc.push(Resp::bulk(key));
+                        acc.push(Resp::bulk(value));
                         acc
                     },
                 );
@@ -59,7 +273,7 @@ impl Stream {
         &self,
         count: Option<usize>,
         range: R,
-    ) -> impl Iterator<Item = (&EntryId, &StreamValues)>
+    ) -> impl Iterator<Item = (EntryId, StreamValues)> + '_
     where
         R: RangeBounds<EntryId>,
     {
@@ -71,6 +285,75 @@ impl Stream {
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct ConsumerGroup {
+    pub(crate) last_delivered: EntryId,
+    pub(crate) entries_read: u64,
+    pub(crate) pending: BTreeMap<EntryId, PendingEntry>,
+    pub(crate) consumers: HashMap<String, Consumer>,
+}
+
+impl ConsumerGroup {
+    fn new(last_delivered: EntryId) -> Self {
+        Self {
+            last_delivered,
+            entries_read: 0,
+            pending: BTreeMap::new(),
+            consumers: HashMap::new(),
+        }
+    }
+
+    fn consumer_mut(&mut self, name: &str, now: SystemTime) -> &mut Consumer {
+        self.consumers
+            .entry(name.to_owned())
+            .or_insert(Consumer { seen_time: now })
+    }
+
+    /// Removes `ids` from the PEL, returning how many were actually pending.
+    pub(crate) fn ack<'a, I>(&mut self, ids: I) -> usize
+    where
+        I: IntoIterator<Item = &'a EntryId>,
+    {
+        ids.into_iter()
+            .filter(|id| self.pending.remove(id).is_some())
+            .count()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Consumer {
+    pub(crate) seen_time: SystemTime,
+}
+
+#[derive(Debug, Clone)]
+pub struct PendingEntry {
+    pub(crate) consumer: String,
+    pub(crate) delivery_time: SystemTime,
+    pub(crate) delivery_count: u64,
+}
+
+impl PendingEntry {
+    const fn new(consumer: String, delivery_time: SystemTime) -> Self {
+        Self {
+            consumer,
+            delivery_time,
+            delivery_count: 1,
+        }
+    }
+
+    #[inline]
+    fn idle(&self, now: SystemTime) -> Duration {
+        now.duration_since(self.delivery_time).unwrap_or_default()
+    }
+}
+
+#[derive(Debug)]
+pub struct AutoClaim {
+    pub(crate) next: EntryId,
+    pub(crate) claimed: Vec<Resp>,
+    pub(crate) deleted: Vec<EntryId>,
+}
+
 #[derive(Debug, PartialEq, PartialOrd, Ord, Eq, Hash, Clone, Copy)]
 pub struct EntryId {
     ms_time: Duration,
@@ -85,6 +368,41 @@ impl EntryId {
         Self { ms_time, sq_num }
     }
 
+    #[inline]
+    pub(crate) fn ms(self) -> u64 {
+        u64::try_from(self.ms_time.as_millis()).unwrap_or(u64::MAX)
+    }
+
+    #[inline]
+    pub(crate) const fn seq(self) -> u64 {
+        self.sq_num
+    }
+
+    /// `self` moved forward by the deltas used in stream listpacks.
+    pub(crate) fn offset(self, ms_diff: i64, seq_diff: i64) -> anyhow::Result<Self> {
+        let ms_time = u64::try_from(self.ms_time.as_millis())?
+            .checked_add_signed(ms_diff)
+            .context("Invalid stream entry id")?;
+        let sq_num = self
+            .sq_num
+            .checked_add_signed(seq_diff)
+            .context("Invalid stream entry id")?;
+        Ok(Self::new(Duration::from_millis(ms_time), sq_num))
+    }
+
+    /// The smallest id greater than `self`, if any.
+    pub(crate) fn successor(self) -> Option<Self> {
+        self.sq_num.checked_add(1).map_or_else(
+            || {
+                let ms_time = u64::try_from(self.ms_time.as_millis())
+                    .ok()?
+                    .checked_add(1)?;
+                Some(Self::new(Duration::from_millis(ms_time), 0))
+            },
+            |sq_num| Some(Self::new(self.ms_time, sq_num)),
+        )
+    }
+
     pub(crate) fn split_or_seq(sq_num: u64, id: &str) -> anyhow::Result<Self> {
         let res = if let Some((ms_time, sq_num)) = id.rsplit_once('-') {
             let ms_time = Duration::from_millis(ms_time.parse::<u64>()?);
@@ -117,13 +435,15 @@ pub enum MaybeAuto {
 }
 
 impl MaybeAuto {
-    pub(crate) fn auto_generate(self, stream: &Stream) -> anyhow::Result<EntryId> {
-        let last_entry = stream.inner.last_key_value();
+    /// The id of the entry to add to `stream`, generated ones
+    /// taking the milliseconds of `now`
+    pub(crate) fn auto_generate(self, stream: &Stream, now: SystemTime) -> anyhow::Result<EntryId> {
+        let last_id = stream.last_id;
 
         let res = match self {
             Self::Set((ms_time, sq_num)) => {
                 let entry_id = EntryId::new(ms_time, sq_num);
-                if last_entry.is_some_and(|(key, _)| entry_id <= *key) {
+                if entry_id <= last_id {
                     bail!(Stream::SMALL_EQ);
                 }
                 entry_id
@@ -131,34 +451,98 @@ impl MaybeAuto {
             Self::AutoSeq(ms_time) => {
                 use std::cmp::Ordering::{Equal, Greater, Less};
 
-                let sq_num = last_entry
-                    .map_or(Ok(0), |(key, _)| match ms_time.cmp(&key.ms_time) {
-                        Less => bail!(Stream::SMALL_EQ),
-                        Equal => Ok(key.sq_num + 1),
-                        Greater => Ok(0),
-                    })
-                    .map(|mut num| {
-                        if ms_time.as_millis() == 0 && num == 0 {
-                            num = 1;
-                        }
-                        num
-                    })?;
+                let sq_num = match ms_time.cmp(&last_id.ms_time) {
+                    Less => bail!(Stream::SMALL_EQ),
+                    Equal => last_id.sq_num.checked_add(1).context(Stream::SMALL_EQ)?,
+                    Greater => 0,
+                };
 
                 EntryId::new(ms_time, sq_num)
             }
             Self::Auto => {
-                let ms_time = UNIX_EPOCH.elapsed()?;
-                let sq_num = last_entry.map_or(0, |(last_key, _)| {
-                    if last_key.ms_time.as_millis() == ms_time.as_millis() {
-                        last_key.sq_num + 1
-                    } else {
-                        0
-                    }
-                });
-
-                EntryId::new(ms_time, sq_num)
+                let now =
+                    Duration::from_millis(now.duration_since(UNIX_EPOCH)?.as_millis().try_into()?);
+                // Never go backwards, even if the clock does
+                if now > last_id.ms_time {
+                    EntryId::new(now, 0)
+                } else {
+                    last_id.successor().context(Stream::SMALL_EQ)?
+                }
             }
         };
         Ok(res)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(ms_time: u64, sq_num: u64) -> EntryId {
+        EntryId::new(Duration::from_millis(ms_time), sq_num)
+    }
+
+    #[test]
+    fn autoclaim() {
+        let now = SystemTime::now();
+        let mut stream = Stream::new();
+        for i in 1..=3 {
+            stream.xadd(id(i, 0), &vec![("k".into(), "v".into())]);
+        }
+        assert!(stream.create_group("group".into(), EntryId::MIN));
+        let read = stream
+            .read_group_new("group", "alice", None, false, now)
+            .unwrap();
+        pretty_assertions::assert_eq!(read.len(), 3);
+        stream.inner.remove(&id(2, 0));
+
+        let claim = stream
+            .autoclaim("group", "bob", Duration::ZERO, EntryId::MIN, 1, false, now)
+            .unwrap();
+        pretty_assertions::assert_eq!(claim.claimed.len(), 1);
+        pretty_assertions::assert_eq!(claim.next, id(2, 0));
+        assert!(claim.deleted.is_empty());
+
+        let claim = stream
+            .autoclaim("group", "bob", Duration::ZERO, claim.next, 10, true, now)
+            .unwrap();
+        pretty_assertions::assert_eq!(claim.claimed, vec![Resp::bulk("3-0")]);
+        pretty_assertions::assert_eq!(claim.deleted, vec![id(2, 0)]);
+        pretty_assertions::assert_eq!(claim.next, EntryId::MIN);
+
+        let group = &stream.groups["group"];
+        assert!(group.pending.values().all(|p| p.consumer == "bob"));
+        pretty_assertions::assert_eq!(group.pending[&id(1, 0)].delivery_count, 2);
+        pretty_assertions::assert_eq!(group.pending[&id(3, 0)].delivery_count, 1);
+    }
+
+    #[test]
+    fn xadd_after_deleting_top() {
+        let mut stream = Stream::new();
+        stream.xadd(id(1, 0), &vec![("k".into(), "v".into())]);
+        stream.xadd(id(2, 0), &vec![("k".into(), "v".into())]);
+        pretty_assertions::assert_eq!(stream.xdel(&[id(2, 0)]), 1);
+        pretty_assertions::assert_eq!(stream.max_deleted_id, id(2, 0));
+
+        let at = |ms| UNIX_EPOCH + Duration::from_millis(ms);
+        assert!(MaybeAuto::Set((Duration::from_millis(2), 0))
+            .auto_generate(&stream, at(5))
+            .is_err());
+        pretty_assertions::assert_eq!(
+            MaybeAuto::AutoSeq(Duration::from_millis(2))
+                .auto_generate(&stream, at(5))
+                .unwrap(),
+            id(2, 1)
+        );
+        pretty_assertions::assert_eq!(
+            MaybeAuto::Auto.auto_generate(&stream, at(5)).unwrap(),
+            id(5, 0)
+        );
+        // Never behind the top id, even if the clock is
+        pretty_assertions::assert_eq!(
+            MaybeAuto::Auto.auto_generate(&stream, at(1)).unwrap(),
+            id(2, 1)
+        );
+        pretty_assertions::assert_eq!(stream.entries_added, 2);
+    }
+}
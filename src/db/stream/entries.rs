@@ -0,0 +1,366 @@
+use anyhow::{ensure, Context};
+use bytes::Bytes;
+use std::{
+    cell::Cell,
+    collections::BTreeMap,
+    ops::{Bound, RangeBounds},
+};
+
+use super::{EntryId, StreamValues};
+use crate::rdb::{listpack, ListpackEntry};
+
+/// Entries of a node, like `stream-node-max-entries`
+const NODE_MAX_ENTRIES: usize = 100;
+/// Size a node stops growing at, like `stream-node-max-bytes`
+const NODE_MAX_BYTES: usize = 4096;
+
+const ENTRY_DELETED: i64 = 1;
+const ENTRY_SAMEFIELDS: i64 = 2;
+
+/// The entries of a stream, packed by consecutive ids in listpack nodes.
+/// Redis indexes the nodes by their master id in a radix tree, an ordered map here.
+#[derive(Debug, Clone, Default)]
+pub struct Entries {
+    nodes: BTreeMap<EntryId, Node>,
+    len: usize,
+}
+
+/// A listpack in the format Redis saves nodes in: the master entry
+/// `count deleted num-fields field... 0`, then each entry as
+/// `flags ms-diff seq-diff [num-fields field value...|value...] lp-count`,
+/// where the fields are left out when they're those of the master entry.
+/// Deleted entries are only flagged, until the whole node is.
+#[derive(Debug, Clone)]
+struct Node {
+    listpack: Bytes,
+    /// Entries not deleted
+    live: usize,
+}
+
+/// An entry decoded from a node
+struct Raw {
+    id: EntryId,
+    deleted: bool,
+    values: StreamValues,
+    /// Index of its flags in the listpack
+    at: usize,
+}
+
+impl Entries {
+    pub(crate) const fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Appends an entry, whose id must be greater than every other
+    pub(crate) fn insert(&mut self, id: EntryId, values: &StreamValues) {
+        debug_assert!(self.last_id().is_none_or(|last| last < id));
+        self.len += 1;
+        if let Some((&master, node)) = self.nodes.iter_mut().next_back() {
+            if node.push(master, id, values) {
+                return;
+            }
+        }
+        self.nodes.insert(id, Node::new(id, values));
+    }
+
+    /// Returns whether the entry was there
+    pub(crate) fn remove(&mut self, id: &EntryId) -> bool {
+        let Some((&master, node)) = self.nodes.range_mut(..=id).next_back() else {
+            return false;
+        };
+        if !node.remove(master, *id) {
+            return false;
+        }
+        self.len -= 1;
+        if node.live == 0 {
+            self.nodes.remove(&master);
+        }
+        true
+    }
+
+    pub(crate) fn get(&self, id: &EntryId) -> Option<StreamValues> {
+        let (&master, node) = self.nodes.range(..=id).next_back()?;
+        node.entries(master)
+            .find(|(entry, _)| entry == id)
+            .map(|(_, values)| values)
+    }
+
+    pub(crate) fn contains_key(&self, id: &EntryId) -> bool {
+        self.get(id).is_some()
+    }
+
+    pub(crate) fn first_id(&self) -> Option<EntryId> {
+        let (&master, node) = self.nodes.first_key_value()?;
+        node.entries(master).next().map(|(id, _)| id)
+    }
+
+    pub(crate) fn last_id(&self) -> Option<EntryId> {
+        let (&master, node) = self.nodes.last_key_value()?;
+        node.entries(master).last().map(|(id, _)| id)
+    }
+
+    /// Entries within `range`, decoding only the nodes that overlap it
+    pub(crate) fn range(
+        &self,
+        range: impl RangeBounds<EntryId>,
+    ) -> impl Iterator<Item = (EntryId, StreamValues)> + '_ {
+        let (start, end) = (range.start_bound().cloned(), range.end_bound().cloned());
+        // The node holding the start, if any, begins before it
+        let first = match start {
+            Bound::Included(id) | Bound::Excluded(id) => self
+                .nodes
+                .range(..=id)
+                .next_back()
+                .map_or(id, |(&master, _)| master),
+            Bound::Unbounded => EntryId::MIN,
+        };
+        self.nodes
+            .range(first..)
+            .flat_map(|(&master, node)| node.entries(master))
+            .skip_while(move |(id, _)| !(start, Bound::Unbounded).contains(id))
+            .take_while(move |(id, _)| (Bound::Unbounded, end).contains(id))
+    }
+
+    /// Nodes with their master id, as saved in a RDB
+    pub(crate) fn nodes(&self) -> impl Iterator<Item = (EntryId, &Bytes)> + '_ {
+        self.nodes
+            .iter()
+            .map(|(&master, node)| (master, &node.listpack))
+    }
+
+    /// Adds a node loaded from a RDB, after the others
+    pub(crate) fn push_node(&mut self, master: EntryId, listpack: Bytes) -> anyhow::Result<()> {
+        let entries = Node::decode(master, &listpack::parse(listpack.clone())?)?;
+        let live = entries.iter().filter(|entry| !entry.deleted).count();
+        ensure!(
+            entries
+                .first()
+                .is_none_or(|first| self.last_id().is_none_or(|last| last < first.id)),
+            "Stream node {master} overlaps the previous one"
+        );
+        if live > 0 {
+            self.nodes.insert(master, Node { listpack, live });
+            self.len += live;
+        }
+        Ok(())
+    }
+}
+
+impl Node {
+    fn new(master: EntryId, values: &StreamValues) -> Self {
+        let mut lp = vec![int(0), int(0), int(values.len())];
+        lp.extend(values.iter().map(|(field, _)| string(field)));
+        lp.push(int(0));
+        let mut node = Self {
+            listpack: Bytes::new(),
+            live: 0,
+        };
+        node.append(master, master, values, lp);
+        node
+    }
+
+    /// Appends the entry unless the node is full
+    fn push(&mut self, master: EntryId, id: EntryId, values: &StreamValues) -> bool {
+        let Ok(lp) = listpack::parse(self.listpack.clone()) else {
+            return false;
+        };
+        let total = lp
+            .first()
+            .and_then(|count| count.to_int().ok())
+            .unwrap_or(0)
+            + lp.get(1)
+                .and_then(|deleted| deleted.to_int().ok())
+                .unwrap_or(0);
+        if self.listpack.len() >= NODE_MAX_BYTES
+            || usize::try_from(total).unwrap_or(usize::MAX) >= NODE_MAX_ENTRIES
+        {
+            return false;
+        }
+        self.append(master, id, values, lp);
+        true
+    }
+
+    fn append(
+        &mut self,
+        master: EntryId,
+        id: EntryId,
+        values: &StreamValues,
+        mut lp: Vec<ListpackEntry>,
+    ) {
+        let master_fields = master_fields(&lp);
+        let same_fields = values.len() == master_fields.len()
+            && values
+                .iter()
+                .zip(&master_fields)
+                .all(|((field, _), master)| field.as_bytes() == master);
+        let start = lp.len();
+        lp.push(int(if same_fields { ENTRY_SAMEFIELDS } else { 0 }));
+        lp.push(diff(id.ms(), master.ms()));
+        lp.push(diff(id.seq(), master.seq()));
+        if same_fields {
+            lp.extend(values.iter().map(|(_, value)| string(value)));
+        } else {
+            lp.push(int(values.len()));
+            for (field, value) in values {
+                lp.push(string(field));
+                lp.push(string(value));
+            }
+        }
+        // Number of elements of the entry, used to walk the listpack backwards
+        lp.push(int(lp.len() - start));
+        self.live += 1;
+        lp[0] = int(self.live);
+        self.listpack = listpack::encode(&lp);
+    }
+
+    /// Flags the entry as deleted, returning whether it was there
+    fn remove(&mut self, master: EntryId, id: EntryId) -> bool {
+        let Ok(mut lp) = listpack::parse(self.listpack.clone()) else {
+            return false;
+        };
+        let Some(at) = Self::decode(master, &lp)
+            .ok()
+            .and_then(|entries| entries.into_iter().find(|e| e.id == id && !e.deleted))
+            .map(|entry| entry.at)
+        else {
+            return false;
+        };
+        let flags = lp[at].to_int().unwrap_or(0);
+        lp[at] = int(flags | ENTRY_DELETED);
+        self.live -= 1;
+        let deleted = lp[1].to_int().unwrap_or(0);
+        lp[0] = int(self.live);
+        lp[1] = int(deleted + 1);
+        self.listpack = listpack::encode(&lp);
+        true
+    }
+
+    /// The entries not deleted
+    fn entries(&self, master: EntryId) -> impl Iterator<Item = (EntryId, StreamValues)> {
+        // Only valid nodes are built or loaded, so they always decode
+        let lp = listpack::parse(self.listpack.clone()).unwrap_or_default();
+        Self::decode(master, &lp)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|entry| !entry.deleted)
+            .map(|entry| (entry.id, entry.values))
+    }
+
+    fn decode(master: EntryId, lp: &[ListpackEntry]) -> anyhow::Result<Vec<Raw>> {
+        let i = Cell::new(0);
+        let next = || {
+            let entry = lp.get(i.get()).context("Truncated stream listpack");
+            i.set(i.get() + 1);
+            entry
+        };
+        let count = next()?.to_int()?;
+        let deleted = next()?.to_int()?;
+        let master_fields = (0..next()?.to_int()?)
+            .map(|_| next()?.to_string())
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        ensure!(next()?.to_int()? == 0, "Expected master entry terminator");
+
+        let mut entries = Vec::new();
+        for _ in 0..count + deleted {
+            let at = i.get();
+            let flags = next()?.to_int()?;
+            let ms_diff = next()?.to_int()?;
+            let seq_diff = next()?.to_int()?;
+            let id = master.offset(ms_diff, seq_diff)?;
+            let values = if flags & ENTRY_SAMEFIELDS == 0 {
+                (0..next()?.to_int()?)
+                    .map(|_| Ok((next()?.to_string()?, next()?.to_string()?)))
+                    .collect::<anyhow::Result<Vec<_>>>()?
+            } else {
+                master_fields
+                    .iter()
+                    .map(|field| Ok((field.clone(), next()?.to_string()?)))
+                    .collect::<anyhow::Result<Vec<_>>>()?
+            };
+            let _lp_count = next()?;
+            entries.push(Raw {
+                id,
+                deleted: flags & ENTRY_DELETED != 0,
+                values,
+                at,
+            });
+        }
+        Ok(entries)
+    }
+}
+
+/// Fields of the master entry
+fn master_fields(lp: &[ListpackEntry]) -> Vec<Bytes> {
+    let len = lp.get(2).and_then(|len| len.to_int().ok()).unwrap_or(0);
+    let len = usize::try_from(len).unwrap_or(0);
+    lp.iter()
+        .skip(3)
+        .take(len)
+        .map(ListpackEntry::to_bytes)
+        .collect()
+}
+
+fn string(s: &str) -> ListpackEntry {
+    ListpackEntry::Str(Bytes::copy_from_slice(s.as_bytes()))
+}
+
+fn int(i: impl TryInto<i64>) -> ListpackEntry {
+    ListpackEntry::Int(i.try_into().unwrap_or(i64::MAX))
+}
+
+const fn diff(a: u64, b: u64) -> ListpackEntry {
+    ListpackEntry::Int(a.wrapping_sub(b).cast_signed())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn id(ms_time: u64, sq_num: u64) -> EntryId {
+        EntryId::new(Duration::from_millis(ms_time), sq_num)
+    }
+
+    #[test]
+    fn nodes() {
+        let mut entries = Entries::default();
+        for i in 0..250 {
+            let fields = if i % 3 == 0 { "a" } else { "b" };
+            entries.insert(id(i, i % 2), &vec![(fields.into(), i.to_string())]);
+        }
+        pretty_assertions::assert_eq!(entries.nodes().count(), 3);
+        pretty_assertions::assert_eq!(entries.len(), 250);
+        pretty_assertions::assert_eq!(entries.get(&id(7, 1)), Some(vec![("b".into(), "7".into())]));
+        pretty_assertions::assert_eq!(entries.get(&id(7, 0)), None);
+
+        let ids = |entries: &Entries, range: (Bound<EntryId>, Bound<EntryId>)| {
+            entries
+                .range(range)
+                .map(|(id, _)| id.ms())
+                .collect::<Vec<_>>()
+        };
+        let range = (Bound::Excluded(id(98, 0)), Bound::Included(id(102, 0)));
+        pretty_assertions::assert_eq!(ids(&entries, range), [99, 100, 101, 102]);
+
+        // Emptied nodes are dropped
+        for i in 100..200 {
+            assert!(entries.remove(&id(i, i % 2)));
+        }
+        assert!(!entries.remove(&id(100, 0)));
+        pretty_assertions::assert_eq!(entries.nodes().count(), 2);
+        pretty_assertions::assert_eq!(ids(&entries, range), [99]);
+        pretty_assertions::assert_eq!(entries.len(), 150);
+        pretty_assertions::assert_eq!(entries.first_id(), Some(id(0, 0)));
+        pretty_assertions::assert_eq!(entries.last_id(), Some(id(249, 1)));
+
+        let mut loaded = Entries::default();
+        for (master, listpack) in entries.nodes() {
+            loaded.push_node(master, listpack.clone()).unwrap();
+        }
+        pretty_assertions::assert_eq!(
+            loaded.range(..).collect::<Vec<_>>(),
+            entries.range(..).collect::<Vec<_>>()
+        );
+    }
+}
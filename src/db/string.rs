@@ -0,0 +1,145 @@
+use bytes::Bytes;
+use std::sync::LazyLock;
+
+use super::encoding::as_int;
+use crate::slice_to_int;
+
+/// Integers from 0 up to this one are shared by every key holding them, like `OBJ_SHARED_INTEGERS`
+const SHARED_INTEGERS: usize = 10_000;
+/// Longest string stored inline, like `OBJ_ENCODING_EMBSTR_SIZE_LIMIT`
+const EMBSTR_MAX: usize = 44;
+/// Longest string that may be an integer, sign included
+const INT_MAX_LEN: usize = 20;
+
+static SHARED: LazyLock<Box<[Bytes]>> = LazyLock::new(|| {
+    (0..SHARED_INTEGERS)
+        .map(|n| Bytes::from(n.to_string()))
+        .collect()
+});
+
+/// The value of a string key, in the cheapest representation that holds it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RedisString {
+    /// Index of a shared integer, so that many keys holding small counters cost no allocation
+    Shared(u16),
+    Int(i64),
+    /// A short string kept inline rather than in an allocation of its own
+    Embedded {
+        len: u8,
+        bytes: [u8; EMBSTR_MAX],
+    },
+    Raw(Bytes),
+}
+
+impl RedisString {
+    /// Picks the representation the way Redis's `tryObjectEncoding` does
+    pub fn new(bytes: Bytes) -> Self {
+        if bytes.len() <= INT_MAX_LEN {
+            if let Some(n) = as_int(&bytes) {
+                return Self::from(n);
+            }
+        }
+        if bytes.len() <= EMBSTR_MAX {
+            let mut inline = [0; EMBSTR_MAX];
+            inline[..bytes.len()].copy_from_slice(&bytes);
+            #[allow(clippy::cast_possible_truncation)]
+            return Self::Embedded {
+                len: bytes.len() as u8,
+                bytes: inline,
+            };
+        }
+        Self::Raw(bytes)
+    }
+
+    pub fn to_bytes(&self) -> Bytes {
+        match self {
+            Self::Shared(n) => SHARED[usize::from(*n)].clone(),
+            Self::Int(n) => Bytes::from(n.to_string()),
+            Self::Embedded { len, bytes } => Bytes::copy_from_slice(&bytes[..usize::from(*len)]),
+            Self::Raw(bytes) => bytes.clone(),
+        }
+    }
+
+    /// The integer the string holds, for INCR and the like
+    pub fn to_int(&self) -> Option<i64> {
+        match self {
+            Self::Shared(n) => Some(i64::from(*n)),
+            Self::Int(n) => Some(*n),
+            Self::Embedded { len, bytes } => slice_to_int(&bytes[..usize::from(*len)]).ok(),
+            Self::Raw(bytes) => slice_to_int(bytes).ok(),
+        }
+    }
+
+    /// Encoding reported by OBJECT ENCODING
+    pub(crate) const fn encoding(&self) -> &'static str {
+        match self {
+            Self::Shared(_) | Self::Int(_) => "int",
+            Self::Embedded { .. } => "embstr",
+            Self::Raw(_) => "raw",
+        }
+    }
+}
+
+impl From<i64> for RedisString {
+    fn from(n: i64) -> Self {
+        match u16::try_from(n) {
+            Ok(shared) if usize::from(shared) < SHARED_INTEGERS => Self::Shared(shared),
+            _ => Self::Int(n),
+        }
+    }
+}
+
+impl From<Bytes> for RedisString {
+    fn from(bytes: Bytes) -> Self {
+        Self::new(bytes)
+    }
+}
+
+impl From<&'static str> for RedisString {
+    fn from(s: &'static str) -> Self {
+        Self::new(Bytes::from_static(s.as_bytes()))
+    }
+}
+
+impl From<String> for RedisString {
+    fn from(s: String) -> Self {
+        Self::new(Bytes::from(s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn representations() {
+        let cases: &[(&str, &str)] = &[
+            ("0", "int"),
+            ("9999", "int"),
+            ("10000", "int"),
+            ("-12", "int"),
+            ("012", "embstr"),
+            ("+1", "embstr"),
+            ("abc", "embstr"),
+            ("-9223372036854775809", "embstr"),
+        ];
+        for &(s, encoding) in cases {
+            let string = RedisString::from(s);
+            pretty_assertions::assert_eq!(string.encoding(), encoding, "{s:?}");
+            pretty_assertions::assert_eq!(string.to_bytes(), s);
+        }
+        let long = "a".repeat(EMBSTR_MAX + 1);
+        pretty_assertions::assert_eq!(RedisString::from(long.clone()).encoding(), "raw");
+        pretty_assertions::assert_eq!(RedisString::from(long.clone()).to_bytes(), long);
+
+        // Small integers all point to the same bytes
+        let shared = |n: i64| RedisString::from(n).to_bytes().as_ptr();
+        assert!(matches!(RedisString::from(42), RedisString::Shared(42)));
+        pretty_assertions::assert_eq!(shared(42), shared(42));
+        assert!(matches!(
+            RedisString::from(10_000),
+            RedisString::Int(10_000)
+        ));
+        pretty_assertions::assert_eq!(RedisString::from("012").to_int(), Some(12));
+    }
+}
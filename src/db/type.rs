@@ -1,27 +1,19 @@
-use bytes::Bytes;
+use super::{set::Set, Hash, List, RedisString, SortedSet, Stream};
 
-use super::Stream;
-
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 #[repr(u8)]
 pub enum Type {
-    String(Bytes) = 0,
-    // List,
-    // Set,
-    // SortedSet,
-    // Hash,
-    // Zipmap,
-    // Ziplist,
-    // Intset Encoding,
-    // Sorted Set in Ziplist Encoding,
-    // Hashmap in Ziplist Encoding,
-    // List in Quicklist Encoding,
+    String(RedisString) = 0,
+    List(List) = 1,
+    Set(Set) = 2,
+    SortedSet(SortedSet) = 3,
+    Hash(Hash) = 4,
     Stream(Stream) = 21,
 }
 
 impl Type {
     #[inline]
-    pub(crate) const fn as_string(&self) -> Option<&Bytes> {
+    pub(crate) const fn as_string(&self) -> Option<&RedisString> {
         #[allow(clippy::match_wildcard_for_single_variants)]
         match self {
             Self::String(string) => Some(string),
@@ -29,12 +21,60 @@ impl Type {
         }
     }
 
+    /// Name reported by TYPE
+    pub(crate) const fn name(&self) -> &'static str {
+        match self {
+            Self::String(_) => "string",
+            Self::List(_) => "list",
+            Self::Set(_) => "set",
+            Self::SortedSet(_) => "zset",
+            Self::Hash(_) => "hash",
+            Self::Stream(_) => "stream",
+        }
+    }
+
+    #[inline]
+    pub(crate) const fn as_hash(&self) -> Option<&Hash> {
+        #[allow(clippy::match_wildcard_for_single_variants)]
+        match self {
+            Self::Hash(hash) => Some(hash),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    pub(crate) const fn as_hash_mut(&mut self) -> Option<&mut Hash> {
+        #[allow(clippy::match_wildcard_for_single_variants)]
+        match self {
+            Self::Hash(hash) => Some(hash),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    pub(crate) const fn as_sorted_set(&self) -> Option<&SortedSet> {
+        #[allow(clippy::match_wildcard_for_single_variants)]
+        match self {
+            Self::SortedSet(zset) => Some(zset),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    pub(crate) const fn as_sorted_set_mut(&mut self) -> Option<&mut SortedSet> {
+        #[allow(clippy::match_wildcard_for_single_variants)]
+        match self {
+            Self::SortedSet(zset) => Some(zset),
+            _ => None,
+        }
+    }
+
     #[inline]
     pub(crate) const fn as_stream(&self) -> Option<&Stream> {
         #[allow(clippy::match_wildcard_for_single_variants)]
         match self {
             Self::Stream(stream) => Some(stream),
-            _ => None
+            _ => None,
         }
     }
 }
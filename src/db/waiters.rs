@@ -0,0 +1,159 @@
+use parking_lot::Mutex;
+use std::{borrow::Borrow, collections::HashMap, hash::Hash, sync::Arc, time::Duration};
+use tokio::sync::Notify;
+
+/// Registry of clients blocked until something happens to a key, like a stream
+/// receiving entries, or a replica acknowledging an offset for `K = ()`.
+/// BLPOP, BLMOVE and BZPOPMIN aren't served yet, lists and sorted sets don't exist.
+#[derive(Debug)]
+pub struct Waiters<K = String> {
+    inner: Mutex<HashMap<K, Vec<Arc<Notify>>>>,
+}
+
+impl<K> Default for Waiters<K> {
+    fn default() -> Self {
+        Self {
+            inner: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone> Waiters<K> {
+    /// Registers a waiter on every key. The returned guard must be created before checking
+    /// the keys for data, so that a write racing with the check is never missed.
+    pub(crate) fn register<I, S>(&self, keys: I) -> WaitGuard<'_, K>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<K>,
+    {
+        let notify = Arc::new(Notify::new());
+        let keys = keys.into_iter().map(Into::into).collect::<Vec<K>>();
+
+        let mut lock = self.inner.lock();
+        for key in &keys {
+            lock.entry(key.clone())
+                .or_default()
+                .push(Arc::clone(&notify));
+        }
+        drop(lock);
+
+        WaitGuard {
+            waiters: self,
+            keys,
+            notify,
+        }
+    }
+
+    /// Wakes every client blocked on `key`, returning how many there were.
+    pub(crate) fn notify<Q>(&self, key: &Q) -> usize
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.inner.lock().get(key).map_or(0, |waiters| {
+            // `notify_one` stores a permit, so a waiter that is not yet polling still wakes up
+            for notify in waiters {
+                notify.notify_one();
+            }
+            waiters.len()
+        })
+    }
+}
+
+/// A client blocked on some keys, unregistered when dropped,
+/// for instance when its connection closes while it waits
+pub struct WaitGuard<'a, K: Hash + Eq + Clone = String> {
+    waiters: &'a Waiters<K>,
+    keys: Vec<K>,
+    notify: Arc<Notify>,
+}
+
+impl<K: Hash + Eq + Clone + Send + Sync> WaitGuard<'_, K> {
+    pub(crate) async fn notified(&self) {
+        self.notify.notified().await;
+    }
+
+    /// Calls `attempt` now, then each time one of the keys is notified, until it
+    /// returns something or `timeout` elapses. A zero `timeout` blocks forever.
+    /// Returns `None` on timeout.
+    pub(crate) async fn block<T: Send>(
+        &self,
+        timeout: Duration,
+        mut attempt: impl FnMut() -> anyhow::Result<Option<T>> + Send,
+    ) -> anyhow::Result<Option<T>> {
+        let sleep = tokio::time::sleep(timeout);
+        tokio::pin!(sleep);
+        loop {
+            if let Some(res) = attempt()? {
+                return Ok(Some(res));
+            }
+            tokio::select! {
+                () = self.notified() => {}
+                () = &mut sleep, if !timeout.is_zero() => return Ok(None),
+            }
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone> Drop for WaitGuard<'_, K> {
+    fn drop(&mut self) {
+        let mut lock = self.waiters.inner.lock();
+        for key in &self.keys {
+            let Some(waiters) = lock.get_mut(key) else {
+                continue;
+            };
+            waiters.retain(|notify| !Arc::ptr_eq(notify, &self.notify));
+            if waiters.is_empty() {
+                lock.remove(key);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn notify_before_wait() {
+        let waiters = Waiters::<String>::default();
+        let guard = waiters.register(["a", "b"]);
+        pretty_assertions::assert_eq!(waiters.notify("c"), 0);
+        pretty_assertions::assert_eq!(waiters.notify("b"), 1);
+
+        tokio::time::timeout(Duration::from_millis(100), guard.notified())
+            .await
+            .expect("Permit was stored");
+
+        drop(guard);
+        assert!(waiters.inner.lock().is_empty());
+    }
+
+    #[tokio::test]
+    async fn block() {
+        let waiters = Waiters::<()>::default();
+        let guard = waiters.register([()]);
+        let timeout = Duration::from_millis(50);
+
+        let mut attempts = 0;
+        let res = guard
+            .block(timeout, || {
+                attempts += 1;
+                Ok(Some(attempts))
+            })
+            .await;
+        pretty_assertions::assert_eq!(res.unwrap(), Some(1));
+
+        // Woken once, then times out
+        waiters.notify(&());
+        let mut attempts = 0;
+        let res = guard
+            .block(timeout, || {
+                attempts += 1;
+                Ok(None::<()>)
+            })
+            .await;
+        pretty_assertions::assert_eq!(res.unwrap(), None);
+        pretty_assertions::assert_eq!(attempts, 2);
+    }
+}
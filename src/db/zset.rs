@@ -0,0 +1,185 @@
+use bytes::Bytes;
+use std::ops::{Bound, RangeBounds};
+
+use super::{listpack::Listpack, skiplist::Skiplist, EncodingLimits};
+
+#[derive(Debug, Clone)]
+pub enum SortedSet {
+    /// Members each followed by their score, in order
+    Listpack(Listpack),
+    Skiplist(Skiplist),
+}
+
+impl SortedSet {
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Listpack(listpack) => listpack.len() / 2,
+            Self::Skiplist(zset) => zset.len(),
+        }
+    }
+
+    pub fn iter(&self) -> Box<dyn Iterator<Item = (Bytes, f64)> + '_> {
+        match self {
+            Self::Listpack(listpack) => Box::new(
+                listpack
+                    .pairs()
+                    .map(|(member, score)| (member, parse_score(&score))),
+            ),
+            Self::Skiplist(zset) => Box::new(zset.iter()),
+        }
+    }
+
+    pub fn score(&self, member: &[u8]) -> Option<f64> {
+        match self {
+            Self::Listpack(_) => self.iter().find(|(m, _)| m == member).map(|(_, s)| s),
+            Self::Skiplist(zset) => zset.score(member),
+        }
+    }
+
+    /// Adds the member or updates its score, returning whether it's new.
+    /// A listpack too large for the limits is converted to a skiplist.
+    pub fn insert(&mut self, member: Bytes, score: f64, limits: &EncodingLimits) -> bool {
+        if let Self::Listpack(_) = self {
+            let len = self.len() + usize::from(self.score(&member).is_none());
+            if !limits.zset_fits(len, member.len()) {
+                *self = Self::Skiplist(self.iter().collect());
+            }
+        }
+        match self {
+            Self::Listpack(listpack) => {
+                let mut pairs = listpack
+                    .pairs()
+                    .map(|(m, score)| (m, parse_score(&score)))
+                    .collect::<Vec<_>>();
+                let before = pairs.len();
+                pairs.retain(|(m, _)| *m != member);
+                let new = pairs.len() == before;
+                let at = pairs.partition_point(|&(ref m, s)| (s, m) < (score, &member));
+                pairs.insert(at, (member, score));
+                *listpack = pack(pairs);
+                new
+            }
+            Self::Skiplist(zset) => zset.insert(member, score),
+        }
+    }
+
+    /// Position of the member in the order of the set, from 0
+    pub fn rank(&self, member: &[u8]) -> Option<usize> {
+        match self {
+            Self::Listpack(_) => self.iter().position(|(m, _)| m == member),
+            Self::Skiplist(zset) => zset.rank(member),
+        }
+    }
+
+    /// Members with their score, in order from rank `start`
+    pub fn range(&self, start: usize) -> Box<dyn Iterator<Item = (Bytes, f64)> + '_> {
+        match self {
+            Self::Listpack(_) => Box::new(self.iter().skip(start)),
+            Self::Skiplist(zset) => Box::new(zset.range(start)),
+        }
+    }
+
+    /// Number of members with a score between `min` and `max`
+    pub fn count(&self, min: Bound<f64>, max: Bound<f64>) -> usize {
+        match self {
+            Self::Listpack(_) => self
+                .iter()
+                .filter(|(_, score)| (min, max).contains(score))
+                .count(),
+            Self::Skiplist(zset) => zset.count(min, max),
+        }
+    }
+
+    pub const fn encoding(&self) -> &'static str {
+        match self {
+            Self::Listpack(_) => "listpack",
+            Self::Skiplist(_) => "skiplist",
+        }
+    }
+
+    pub fn fit(&mut self, limits: &EncodingLimits) {
+        let longest = self.iter().map(|(m, _)| m.len()).max().unwrap_or(0);
+        *self = if limits.zset_fits(self.len(), longest) {
+            Self::Listpack(pack(self.iter()))
+        } else {
+            Self::Skiplist(self.iter().collect())
+        };
+    }
+}
+
+fn pack(pairs: impl IntoIterator<Item = (Bytes, f64)>) -> Listpack {
+    pairs
+        .into_iter()
+        .map(|(member, score)| (member, score.to_string().into()))
+        .collect()
+}
+
+/// Scores are packed as integers when they can, otherwise as strings
+fn parse_score(score: &[u8]) -> f64 {
+    std::str::from_utf8(score)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(f64::NAN)
+}
+
+/// Empty sets start packed, like in Redis
+impl Default for SortedSet {
+    fn default() -> Self {
+        Self::Listpack(pack([]))
+    }
+}
+
+impl<const N: usize> From<[(Bytes, f64); N]> for SortedSet {
+    fn from(pairs: [(Bytes, f64); N]) -> Self {
+        pairs.into_iter().collect()
+    }
+}
+
+impl FromIterator<(Bytes, f64)> for SortedSet {
+    fn from_iter<T: IntoIterator<Item = (Bytes, f64)>>(iter: T) -> Self {
+        Self::Skiplist(iter.into_iter().collect())
+    }
+}
+
+impl PartialEq for SortedSet {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len()
+            && self
+                .iter()
+                .all(|(member, score)| other.score(&member) == Some(score))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodings_agree() {
+        let limits = EncodingLimits {
+            zset_max_listpack_entries: 4,
+            ..EncodingLimits::default()
+        };
+        let mut zset = SortedSet::default();
+        for (member, score) in [("c", 2.0), ("a", 2.0), ("b", -1.0), ("a", 0.5)] {
+            zset.insert(Bytes::from(member), score, &limits);
+        }
+        pretty_assertions::assert_eq!(zset.encoding(), "listpack");
+        let skiplist = SortedSet::Skiplist(zset.iter().collect());
+        for zset in [&zset, &skiplist] {
+            let members = zset.iter().map(|(member, _)| member).collect::<Vec<_>>();
+            pretty_assertions::assert_eq!(members, ["b", "a", "c"]);
+            pretty_assertions::assert_eq!(zset.rank(b"c"), Some(2));
+            pretty_assertions::assert_eq!(zset.range(1).next(), Some(("a".into(), 0.5)));
+            pretty_assertions::assert_eq!(
+                zset.count(Bound::Excluded(-1.0), Bound::Included(2.0)),
+                2
+            );
+        }
+
+        zset.insert(Bytes::from("d"), 3.0, &limits);
+        zset.insert(Bytes::from("e"), 4.0, &limits);
+        pretty_assertions::assert_eq!(zset.encoding(), "skiplist");
+        pretty_assertions::assert_eq!(zset.rank(b"e"), Some(4));
+    }
+}
@@ -0,0 +1,314 @@
+use std::{fmt::Write, net::SocketAddr};
+
+use thiserror::Error;
+
+use crate::Resp;
+
+/// Errors replied to clients. Each is rendered as its code, like `WRONGTYPE`, then its message.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum RedisError {
+    #[error("ERR syntax error")]
+    Syntax,
+    #[error("ERR wrong number of arguments for '{0}' command")]
+    WrongArity(String),
+    #[error("ERR unknown command '{name}', with args beginning with: {}", quoted(.args))]
+    UnknownCommand { name: String, args: Vec<String> },
+    #[error("ERR unknown subcommand '{sub}'. Try {command} HELP.")]
+    UnknownSubcommand { command: &'static str, sub: String },
+    #[error("ERR value is not an integer or out of range")]
+    NotInteger,
+    #[error("ERR increment or decrement would overflow")]
+    Overflow,
+    /// An expiration out of range, or not positive for `SET`
+    #[error("ERR invalid expire time in '{0}' command")]
+    InvalidExpireTime(&'static str),
+    /// A command frame holding something else than bulk strings
+    #[error("ERR Protocol error: expected an array of bulk strings")]
+    NotBulkArray,
+    #[error("ERR Command not allowed inside a transaction")]
+    NotInTransaction,
+    #[error("ERR MULTI calls can not be nested")]
+    NestedMulti,
+    #[error("ERR EXEC without MULTI")]
+    ExecWithoutMulti,
+    #[error("ERR DISCARD without MULTI")]
+    DiscardWithoutMulti,
+    /// Anything but (un)subscribing and PING, from a RESP2 connection in subscribed mode
+    #[error("ERR Can't execute '{0}': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context")]
+    SubscribedContext(String),
+    /// A command only masters serve, like WAIT
+    #[error("ERR {0} cannot be used with replica instances.")]
+    ReplicaInstance(&'static str),
+    /// `COMMAND GETKEYS` of a command that doesn't exist
+    #[error("ERR Invalid command specified")]
+    InvalidCommand,
+    #[error("ERR Invalid number of arguments specified for command")]
+    InvalidCommandArity,
+    #[error("ERR The command has no key arguments")]
+    NoKeyArguments,
+    #[error("ERR This Redis command is not allowed from script")]
+    NotFromScript,
+    #[error("ERR Library not found")]
+    LibraryNotFound,
+    #[error("ERR Function not found")]
+    FunctionNotFound,
+    #[error("ERR Library '{0}' already exists")]
+    LibraryExists(String),
+    /// A function of the library is defined by another one already
+    #[error("ERR Function {0} already exists")]
+    FunctionExists(String),
+    /// A library registering a function twice
+    #[error("ERR Function already exists in the library")]
+    FunctionExistsInLibrary,
+    #[error("ERR No functions registered")]
+    NoFunctions,
+    /// A library not starting with `#!lua name=<name>`
+    #[error("ERR Missing library metadata")]
+    MissingMetadata,
+    #[error("ERR Engine '{0}' not found")]
+    UnknownEngine(String),
+    #[error("ERR Invalid metadata value given: {0}")]
+    InvalidMetadata(String),
+    #[error("ERR Library name was not given")]
+    MissingLibraryName,
+    #[error("ERR Library names can only contain letters, numbers, or underscores(_) and must be at least one character long")]
+    InvalidLibraryName,
+    #[error("ERR Function names can only contain letters, numbers, or underscores(_) and must be at least one character long")]
+    InvalidFunctionName,
+    #[error("ERR Unknown flag given: {0}")]
+    UnknownFunctionFlag(String),
+    /// `redis.register_function` called with something else than
+    /// a name and a callback, or a table of them
+    #[error("ERR wrong number of arguments to redis.register_function")]
+    RegisterArity,
+    #[error("ERR function_name argument given to redis.register_function must be a string")]
+    RegisterName,
+    #[error("ERR callback argument given to redis.register_function must be a function")]
+    RegisterCallback,
+    #[error(
+        "ERR flags argument to redis.register_function must be a table representing function flags"
+    )]
+    RegisterFlags,
+    #[error("WRONGTYPE Operation against a key holding the wrong kind of value")]
+    WrongType,
+    #[error("NOAUTH Authentication required.")]
+    NoAuth,
+    #[error("WRONGPASS invalid username-password pair or user is disabled.")]
+    WrongPass,
+    #[error("NOPERM User {user} has no permissions to run the '{command}' command")]
+    NoPermCommand { user: String, command: String },
+    #[error("NOPERM No permissions to access a key")]
+    NoPermKey,
+    #[error("READONLY You can't write against a read only replica.")]
+    ReadOnly,
+    #[error("EXECABORT Transaction discarded because of previous errors.")]
+    ExecAbort,
+    #[error("NOSCRIPT No matching script. Please use EVAL.")]
+    NoScript,
+    #[error(
+        "NOGROUP No such key '{key}' or consumer group '{group}'{}",
+        if *.xreadgroup { " in XREADGROUP with GROUP option" } else { "" }
+    )]
+    NoGroup {
+        key: String,
+        group: String,
+        xreadgroup: bool,
+    },
+    #[error("BUSYGROUP Consumer Group name already exists")]
+    BusyGroup,
+    #[error("ERR The XGROUP subcommand requires the key to exist. Note that for CREATE you may want to use the MKSTREAM option to create an empty stream automatically.")]
+    NoGroupStream,
+    /// Stream keys and ids don't pair up, `id` being the special one of the command
+    #[error("ERR Unbalanced '{command}' list of streams: for each stream key an ID or '{id}' must be specified.")]
+    UnbalancedStreams {
+        command: &'static str,
+        id: &'static str,
+    },
+    #[error("ERR Invalid min-idle-time argument for XAUTOCLAIM")]
+    InvalidMinIdleTime,
+    #[error("ERR COUNT must be > 0")]
+    CountNotPositive,
+    /// The key's slot is served by another node
+    #[error("MOVED {slot} {addr}")]
+    Moved { slot: u16, addr: SocketAddr },
+    /// The key's slot is being migrated, and the key may already be on `addr`
+    #[error("ASK {slot} {addr}")]
+    Ask { slot: u16, addr: SocketAddr },
+    #[error("CROSSSLOT Keys in request don't hash to the same slot")]
+    CrossSlot,
+    #[error("ERR I don't know about node {0}")]
+    UnknownNode(String),
+    #[error("ERR Invalid or out of range slot")]
+    InvalidSlot,
+    #[error("ERR I can't migrate a slot to myself")]
+    SlotToMyself,
+    #[error("ERR I'm not the owner of hash slot {0}")]
+    NotSlotOwner(u16),
+    #[error("ERR I'm already the owner of hash slot {0}")]
+    AlreadySlotOwner(u16),
+    /// `CLUSTER SETSLOT NODE` of a slot this node still holds keys of
+    #[error("ERR Can't assign hashslot {0} to a different node while I still hold keys for this hash slot.")]
+    SlotHasKeys(u16),
+    /// A script, or a function, has been running for longer than `busy-reply-threshold`
+    #[error(
+        "BUSY Redis is busy running a script. You can only call {} or SHUTDOWN NOSAVE.",
+        if *.function { "FUNCTION KILL" } else { "SCRIPT KILL" }
+    )]
+    Busy { function: bool },
+    /// Woken up by `CLIENT UNBLOCK ... ERROR`
+    #[error("UNBLOCKED client unblocked via CLIENT UNBLOCK")]
+    Unblocked,
+    #[error("NOTBUSY No scripts in execution right now.")]
+    NotBusy,
+    #[error("UNKILLABLE Sorry the script already executed write commands against the dataset. You can either wait the script termination or kill the server in a hard way using the SHUTDOWN NOSAVE command.")]
+    Unkillable,
+}
+
+impl RedisError {
+    /// First word of the error, which clients match on
+    #[must_use]
+    pub const fn code(&self) -> &'static str {
+        match self {
+            Self::Syntax
+            | Self::WrongArity(_)
+            | Self::UnknownCommand { .. }
+            | Self::UnknownSubcommand { .. }
+            | Self::NotInteger
+            | Self::Overflow
+            | Self::InvalidExpireTime(_)
+            | Self::NotBulkArray
+            | Self::NotInTransaction
+            | Self::NestedMulti
+            | Self::ExecWithoutMulti
+            | Self::DiscardWithoutMulti
+            | Self::SubscribedContext(_)
+            | Self::ReplicaInstance(_)
+            | Self::InvalidCommand
+            | Self::InvalidCommandArity
+            | Self::NoKeyArguments
+            | Self::NoGroupStream
+            | Self::UnbalancedStreams { .. }
+            | Self::InvalidMinIdleTime
+            | Self::CountNotPositive
+            | Self::UnknownNode(_)
+            | Self::InvalidSlot
+            | Self::SlotToMyself
+            | Self::NotSlotOwner(_)
+            | Self::AlreadySlotOwner(_)
+            | Self::SlotHasKeys(_)
+            | Self::NotFromScript
+            | Self::LibraryNotFound
+            | Self::FunctionNotFound
+            | Self::LibraryExists(_)
+            | Self::FunctionExists(_)
+            | Self::FunctionExistsInLibrary
+            | Self::NoFunctions
+            | Self::MissingMetadata
+            | Self::UnknownEngine(_)
+            | Self::InvalidMetadata(_)
+            | Self::MissingLibraryName
+            | Self::InvalidLibraryName
+            | Self::InvalidFunctionName
+            | Self::UnknownFunctionFlag(_)
+            | Self::RegisterArity
+            | Self::RegisterName
+            | Self::RegisterCallback
+            | Self::RegisterFlags => "ERR",
+            Self::WrongType => "WRONGTYPE",
+            Self::NoAuth => "NOAUTH",
+            Self::WrongPass => "WRONGPASS",
+            Self::NoPermCommand { .. } | Self::NoPermKey => "NOPERM",
+            Self::ReadOnly => "READONLY",
+            Self::ExecAbort => "EXECABORT",
+            Self::NoScript => "NOSCRIPT",
+            Self::NoGroup { .. } => "NOGROUP",
+            Self::BusyGroup => "BUSYGROUP",
+            Self::Moved { .. } => "MOVED",
+            Self::Ask { .. } => "ASK",
+            Self::CrossSlot => "CROSSSLOT",
+            Self::Busy { .. } => "BUSY",
+            Self::Unblocked => "UNBLOCKED",
+            Self::NotBusy => "NOTBUSY",
+            Self::Unkillable => "UNKILLABLE",
+        }
+    }
+
+    /// For a command frame whose name isn't known
+    pub(crate) fn unknown_command(raw_cmd: &[Resp]) -> Self {
+        let arg = |arg: &Resp| {
+            arg.as_bulk()
+                .map(|arg| String::from_utf8_lossy(arg).into_owned())
+        };
+        Self::UnknownCommand {
+            name: raw_cmd.first().and_then(arg).unwrap_or_default(),
+            args: raw_cmd.iter().skip(1).filter_map(arg).collect(),
+        }
+    }
+}
+
+impl From<RedisError> for Resp {
+    fn from(e: RedisError) -> Self {
+        Self::Err(e.to_string())
+    }
+}
+
+fn quoted(args: &[String]) -> String {
+    args.iter().fold(String::new(), |mut acc, arg| {
+        let _ = write!(acc, "'{arg}' ");
+        acc
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wire_form() {
+        let encode = |e: RedisError| {
+            let mut buf = bytes::BytesMut::new();
+            Resp::from(e).encode(&mut buf);
+            String::from_utf8(buf.to_vec()).unwrap()
+        };
+        pretty_assertions::assert_eq!(
+            encode(RedisError::WrongType),
+            "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n"
+        );
+        pretty_assertions::assert_eq!(
+            encode(RedisError::UnknownCommand {
+                name: "foo".to_owned(),
+                args: vec!["a".to_owned(), "b".to_owned()],
+            }),
+            "-ERR unknown command 'foo', with args beginning with: 'a' 'b' \r\n"
+        );
+        pretty_assertions::assert_eq!(
+            encode(RedisError::Moved {
+                slot: 3999,
+                addr: ([127, 0, 0, 1], 6381).into(),
+            }),
+            "-MOVED 3999 127.0.0.1:6381\r\n"
+        );
+        pretty_assertions::assert_eq!(
+            encode(RedisError::Ask {
+                slot: 3999,
+                addr: ([127, 0, 0, 1], 6381).into(),
+            }),
+            "-ASK 3999 127.0.0.1:6381\r\n"
+        );
+    }
+
+    #[test]
+    fn codes() {
+        for e in [
+            RedisError::Syntax,
+            RedisError::WrongArity("get".to_owned()),
+            RedisError::NoAuth,
+            RedisError::NoPermKey,
+            RedisError::ExecAbort,
+            RedisError::NoScript,
+            RedisError::CrossSlot,
+        ] {
+            assert!(e.to_string().starts_with(&format!("{} ", e.code())));
+        }
+    }
+}
@@ -0,0 +1,167 @@
+//! Glob-style patterns, matched byte by byte like Redis does
+
+/// Whether `string` matches `pattern`, where:
+/// - `*` matches any sequence of bytes, `?` any single byte
+/// - `[abc]` and `[a-z]` match a byte of the class, `[^abc]` one that isn't
+/// - `\` matches the next character literally, also within a class
+pub fn glob_match(pattern: impl AsRef<[u8]>, string: impl AsRef<[u8]>) -> bool {
+    matches(pattern.as_ref(), string.as_ref(), false)
+}
+
+/// [`glob_match`] ignoring the ASCII case
+pub fn glob_match_nocase(pattern: impl AsRef<[u8]>, string: impl AsRef<[u8]>) -> bool {
+    matches(pattern.as_ref(), string.as_ref(), true)
+}
+
+/// Every token but `*` matches a single byte, so when the rest of the pattern fails
+/// it's enough to let the last `*` swallow one more byte and retry
+fn matches(pattern: &[u8], string: &[u8], nocase: bool) -> bool {
+    let (mut p, mut s) = (0, 0);
+    // The pattern after the last star, and where it's retried from in the string
+    let mut backtrack = None;
+    loop {
+        if pattern.get(p) == Some(&b'*') {
+            while pattern.get(p) == Some(&b'*') {
+                p += 1;
+            }
+            if p == pattern.len() {
+                return true;
+            }
+            backtrack = Some((p, s));
+            continue;
+        }
+        let Some(&c) = string.get(s) else {
+            return p == pattern.len();
+        };
+        match single(&pattern[p..], c, nocase) {
+            Some(len) => {
+                p += len;
+                s += 1;
+            }
+            None => match backtrack {
+                Some((star_p, star_s)) => {
+                    backtrack = Some((star_p, star_s + 1));
+                    (p, s) = (star_p, star_s + 1);
+                }
+                None => return false,
+            },
+        }
+    }
+}
+
+/// Length of the token `pattern` starts with, if byte `c` matches it
+fn single(pattern: &[u8], c: u8, nocase: bool) -> Option<usize> {
+    let eq = |b: u8| {
+        if nocase {
+            b.eq_ignore_ascii_case(&c)
+        } else {
+            b == c
+        }
+    };
+    match *pattern {
+        [] => None,
+        [b'?', ..] => Some(1),
+        [b'[', ref class @ ..] => {
+            let (matched, len) = in_class(class, c, nocase);
+            matched.then_some(len + 1)
+        }
+        [b'\\', escaped, ..] => eq(escaped).then_some(2),
+        [literal, ..] => eq(literal).then_some(1),
+    }
+}
+
+/// Whether `c` is in the class following a `[`, and the length of the class
+/// up to its `]`. Like in Redis, an unterminated class runs to the end of the pattern.
+fn in_class(pattern: &[u8], c: u8, nocase: bool) -> (bool, usize) {
+    let fold = |b: u8| {
+        if nocase {
+            b.to_ascii_lowercase()
+        } else {
+            b
+        }
+    };
+    let c = fold(c);
+    let (negated, mut i) = match pattern.first() {
+        Some(b'^') => (true, 1),
+        _ => (false, 0),
+    };
+    let mut matched = false;
+    loop {
+        match pattern[i..] {
+            [] => break,
+            [b'\\', escaped, ..] => {
+                matched |= fold(escaped) == c;
+                i += 2;
+            }
+            [b']', ..] => {
+                i += 1;
+                break;
+            }
+            [start, b'-', end, ..] => {
+                let (start, end) = (fold(start), fold(end));
+                matched |= (start.min(end)..=start.max(end)).contains(&c);
+                i += 3;
+            }
+            [b, ..] => {
+                matched |= fold(b) == c;
+                i += 1;
+            }
+        }
+    }
+    (matched != negated, i)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patterns() {
+        let cases: &[(&str, &str, bool)] = &[
+            ("*", "", true),
+            ("*", "anything", true),
+            ("h?llo", "hello", true),
+            ("h?llo", "hllo", false),
+            ("h*llo", "heeeello", true),
+            ("h*llo", "hello world", false),
+            ("*o*o*", "foo boo", true),
+            ("a**b", "ab", true),
+            ("h[ae]llo", "hallo", true),
+            ("h[ae]llo", "hillo", false),
+            ("h[^e]llo", "hallo", true),
+            ("h[^e]llo", "hello", false),
+            ("h[a-b]llo", "hbllo", true),
+            ("h[b-a]llo", "hbllo", true),
+            ("h[a-b]llo", "hcllo", false),
+            ("[]x", "x", false),
+            ("[^]x", "ax", true),
+            ("[\\]]", "]", true),
+            ("[a\\-z]", "-", true),
+            ("[a\\-z]", "b", false),
+            ("h\\*llo", "h*llo", true),
+            ("h\\*llo", "hello", false),
+            ("\\", "\\", true),
+            ("[abc", "b", true),
+            ("[abc", "bc", false),
+            ("ab", "abc", false),
+            ("abc", "ab", false),
+        ];
+        for &(pattern, string, expected) in cases {
+            assert_eq!(
+                glob_match(pattern, string),
+                expected,
+                "{pattern:?} against {string:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn binary_and_case() {
+        assert!(glob_match(b"\xff*\x00", b"\xff\x01\x02\x00"));
+        assert!(glob_match(b"[\x80-\xff]", b"\xc3"));
+        assert!(!glob_match(b"[a-z]", b"\xc3"));
+        assert!(!glob_match("HELLO*", "hello world"));
+        assert!(glob_match_nocase("HELLO*", "hello world"));
+        assert!(glob_match_nocase("[A-C]x", "bX"));
+    }
+}
@@ -1,270 +1,748 @@
-use bytes::{Buf, Bytes, BytesMut};
-use std::{io::Cursor, net::SocketAddr};
+use anyhow::bail;
+use bytes::BytesMut;
+use either::Either;
+use futures_util::StreamExt;
+use socket2::{SockRef, TcpKeepalive};
+use std::{
+    future::Future,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use thiserror::Error;
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt, BufReader, BufWriter},
+    io::{AsyncWriteExt, BufWriter},
     net::{
         tcp::{OwnedReadHalf, OwnedWriteHalf},
         TcpStream,
     },
+    sync::{mpsc, RwLockReadGuard, RwLockWriteGuard},
 };
+use tokio_util::codec::FramedRead;
 
-use crate::{Command, Resp, Role};
+use crate::{
+    clients::{ClientGuard, ClientInfo, Clients, Unblock, CLIENTS},
+    cluster::CLUSTER,
+    commands::{Caller, Client, CommandSpec, Ctx, Session},
+    hooks::{Call, Hooks},
+    pubsub::Subscriber,
+    resp::{stream_payload, Chunks, Protocol, RespCodec},
+    roles::master::expired_dels,
+    Arguments, Command, Db, Rdb, RedisError, Resp, Role, ACL, AOF, STATS,
+};
+#[cfg(feature = "scripting")]
+use crate::{
+    commands::{Function, Script},
+    TRACKING,
+};
+
+/// Chunks of a RDB payload read ahead of its parsing
+const RDB_CHUNKS_IN_FLIGHT: usize = 16;
 
 #[derive(Debug)]
 pub struct Handler {
     pub(crate) addr: SocketAddr,
-    reader: BufReader<OwnedReadHalf>,
+    /// Address the peer connected to
+    pub(crate) local_addr: SocketAddr,
+    pub(crate) reader: Reader,
     writer: BufWriter<OwnedWriteHalf>,
-    pub(crate) buf: BytesMut,
+    /// Scratch buffer frames are encoded into before being written
+    out: BytesMut,
+    pub(crate) protocol: Protocol,
+}
+
+/// Read half of a connection, parsing the frames it receives
+#[derive(Debug)]
+pub struct Reader {
+    framed: FramedRead<OwnedReadHalf, RespCodec>,
+    /// Client the read buffer is accounted to, and its size when last reported
+    client: Option<(u64, usize)>,
 }
 
 impl Handler {
-    pub fn new(stream: TcpStream) -> Self {
+    pub fn new(stream: TcpStream, args: &Arguments) -> Self {
         let addr = stream.peer_addr().unwrap();
+        let local_addr = stream.local_addr().unwrap();
+        if let Err(e) = configure_socket(&stream, args) {
+            tracing::warn!("Failed to configure the socket of {addr}: {e}");
+        }
         let (reader, writer) = stream.into_split();
         Self {
             addr,
-            reader: BufReader::new(reader),
+            local_addr,
+            reader: Reader {
+                framed: FramedRead::new(reader, RespCodec::new(args.proto_max_bulk_len)),
+                client: None,
+            },
             writer: BufWriter::new(writer),
-            buf: BytesMut::with_capacity(1024),
+            out: BytesMut::with_capacity(1024),
+            protocol: Protocol::default(),
         }
     }
 
-    pub async fn read(&mut self) -> anyhow::Result<Option<Resp>> {
-        loop {
-            if let Some(resp) = self.parse()? {
-                return Ok(Some(resp));
-            }
+    /// Separates the halves, so the connection can be read and written from different tasks
+    pub(crate) fn into_split(self) -> (Reader, BufWriter<OwnedWriteHalf>) {
+        (self.reader, self.writer)
+    }
+
+    #[inline]
+    pub async fn read(&mut self) -> Result<Option<Resp>, crate::resp::Error> {
+        self.reader.read().await
+    }
 
-            if 0 == self.reader.read_buf(&mut self.buf).await? {
-                return Ok(None);
+    #[inline]
+    pub(crate) fn has_buffered_frame(&self) -> bool {
+        self.reader.has_buffered_frame()
+    }
+
+    pub async fn write(&mut self, resp: &Resp) -> std::io::Result<()> {
+        self.queue(resp);
+        self.flush().await
+    }
+
+    /// Buffers a frame, which is sent on the next [`Handler::flush`]
+    pub fn queue(&mut self, resp: &Resp) {
+        tracing::debug!("Queueing: {resp:?}");
+        resp.encode_with(&mut self.out, self.protocol);
+    }
+
+    /// Buffers out-of-band data, as a push frame if the connection negotiated RESP3
+    pub fn queue_push(&mut self, resp: Resp) {
+        let resp = resp.into_push(self.protocol);
+        self.queue(&resp);
+    }
+
+    /// Size of the buffers replies are written from
+    pub(crate) fn output_buffer(&self) -> usize {
+        self.out.capacity() + self.writer.buffer().len()
+    }
+
+    /// Writes every queued frame with a single write
+    pub async fn flush(&mut self) -> std::io::Result<()> {
+        if self.out.is_empty() {
+            return Ok(());
+        }
+        let res = self.writer.write_all(&self.out).await;
+        self.out.clear();
+        res?;
+        self.writer.flush().await
+    }
+}
+
+/// Applies `tcp-nodelay` and `tcp-keepalive`, so replies aren't delayed
+/// and dead peers are eventually noticed
+fn configure_socket(stream: &TcpStream, args: &Arguments) -> std::io::Result<()> {
+    stream.set_nodelay(args.tcp_nodelay)?;
+    let time = args.tcp_keepalive;
+    if !time.is_zero() {
+        // Like Redis, probe 3 times before giving up on the peer
+        let interval = (time / 3).max(Duration::from_secs(1));
+        let keepalive = TcpKeepalive::new()
+            .with_time(time)
+            .with_interval(interval)
+            .with_retries(3);
+        SockRef::from(stream).set_tcp_keepalive(&keepalive)?;
+    }
+    Ok(())
+}
+
+impl Reader {
+    /// Reads the next frame. While it's incomplete, the growth of the read buffer
+    /// is reported to the client registry, which may evict the client.
+    pub async fn read(&mut self) -> Result<Option<Resp>, crate::resp::Error> {
+        std::future::poll_fn(|cx| {
+            let poll = self.framed.poll_next_unpin(cx);
+            if poll.is_pending() {
+                self.report_buffer();
             }
+            poll
+        })
+        .await
+        .transpose()
+    }
+
+    fn report_buffer(&mut self) {
+        let Some((id, reported)) = &mut self.client else {
+            return;
+        };
+        let qbuf = self.framed.read_buffer().capacity();
+        if qbuf != *reported {
+            *reported = qbuf;
+            CLIENTS.set_query_buffer(*id, qbuf);
         }
     }
 
-    pub(crate) async fn read_bytes(&mut self) -> anyhow::Result<()> {
-        self.reader.read_buf(&mut self.buf).await?;
-        Ok(())
+    /// Whether a complete frame is already buffered, so reading it won't block
+    pub(crate) fn has_buffered_frame(&self) -> bool {
+        self.framed.decoder().has_frame(self.framed.read_buffer())
     }
 
-    fn parse(&mut self) -> anyhow::Result<Option<Resp>> {
-        if self.buf.is_empty() {
-            return Ok(None);
+    /// Resolves once the peer closes the connection, without consuming anything.
+    /// Never resolves if it sends more data instead, which is read later on.
+    pub(crate) async fn closed(&mut self) {
+        let mut buf = [0];
+        if self.framed.read_buffer().is_empty()
+            && !matches!(self.framed.get_mut().peek(&mut buf).await, Ok(1..))
+        {
+            return;
         }
-        let mut cur = Cursor::new(self.buf.as_ref());
+        std::future::pending::<()>().await;
+    }
 
-        match Resp::check(&mut cur) {
-            Ok(()) => {
-                let len = cur.position().try_into()?;
-                cur.set_position(0);
-                let resp = Resp::parse(&mut cur).map(Option::Some);
-                self.buf.advance(len);
-                resp
-            }
-            Err(crate::resp::Error::Incomplete) => Ok(None),
-            Err(e) => Err(e.into()),
+    /// Reads the RDB payload sent by a master for a full resync, parsing it on a
+    /// blocking thread as it arrives so that a big dataset is never buffered whole.
+    /// Fails if the master sends nothing for `timeout`.
+    pub(crate) async fn read_rdb(
+        &mut self,
+        verify: bool,
+        timeout: Duration,
+    ) -> anyhow::Result<Rdb> {
+        let (tx, rx) = mpsc::channel(RDB_CHUNKS_IN_FLIGHT);
+        let parser = tokio::task::spawn_blocking(move || Rdb::read(Chunks::new(rx), verify));
+        let mut buf = self.framed.read_buffer_mut().split();
+        let streamed = stream_payload(self.framed.get_mut(), &mut buf, &tx, timeout).await;
+        // What the master sent after the payload is read as frames
+        *self.framed.read_buffer_mut() = buf;
+        drop(tx);
+        // A failed transfer leaves the parser with a truncated RDB, so it's the error to report
+        streamed?;
+        parser.await?
+    }
+}
+
+/// What the connection is used for, which SUBSCRIBE and PSYNC change
+#[derive(Debug)]
+enum Conn {
+    /// Replies to commands
+    Normal(Handler),
+    /// Also pushes the messages published to the subscribed channels
+    Subscribed(Handler, Subscriber),
+    /// Handed over to replication by PSYNC
+    ReplicaLink,
+    /// The peer disconnected or sent malformed input
+    Closed,
+}
+
+impl Conn {
+    /// The connection's handler, while commands are still read from it
+    const fn handler(&mut self) -> Result<&mut Handler, CommandError> {
+        match self {
+            Self::Normal(handler) | Self::Subscribed(handler, _) => Ok(handler),
+            Self::ReplicaLink => Err(CommandError::Replicated),
+            Self::Closed => Err(CommandError::Finished),
         }
     }
 
-    pub async fn write(&mut self, resp: &Resp) -> std::io::Result<()> {
-        tracing::debug!("Writing: {resp:?}");
-        match resp {
-            Resp::Simple(inner) => self.write_simple(inner, '+').await?,
-            Resp::Err(inner) => self.write_simple(inner, '-').await?,
-            Resp::Bulk(inner) => self.write_bulk(inner, true).await?,
-            Resp::Array(elems) => {
-                self.writer.write_u8(b'*').await?;
-                self.writer
-                    .write_all(elems.len().to_string().as_bytes())
-                    .await?;
-                self.writer.write_all(b"\r\n").await?;
-                for resp in elems {
-                    Box::pin(self.write(resp)).await?;
-                }
-            }
-            Resp::Integer(inner) => {
-                self.writer.write_u8(b':').await?;
-                self.writer.write_all(inner.to_string().as_bytes()).await?;
-                self.writer.write_all(b"\r\n").await?;
-            }
-            Resp::Data(inner) => self.write_bulk(inner, false).await?,
-            Resp::Null => self.writer.write_all(b"$-1\r\n").await?,
-        };
-        self.writer.flush().await?;
-        Ok(())
+    const fn subscriber(&self) -> Option<&Subscriber> {
+        match self {
+            Self::Subscribed(_, subscriber) => Some(subscriber),
+            _ => None,
+        }
     }
 
-    async fn write_bulk(&mut self, bulk: &Bytes, crlf: bool) -> std::io::Result<()> {
-        self.writer.write_u8(b'$').await?;
-        self.writer
-            .write_all(bulk.len().to_string().as_bytes())
-            .await?;
-        self.writer.write_all(b"\r\n").await?;
-        self.writer.write_all(bulk).await?;
-        if crlf {
-            self.writer.write_all(b"\r\n").await?;
+    const fn subscriber_mut(&mut self) -> Option<&mut Subscriber> {
+        match self {
+            Self::Subscribed(_, subscriber) => Some(subscriber),
+            _ => None,
         }
-        Ok(())
     }
 
-    async fn write_simple(&mut self, simple: &str, c: char) -> std::io::Result<()> {
-        self.writer.write_u8(c as u8).await?;
-        self.writer.write_all(simple.as_bytes()).await?;
-        self.writer.write_all(b"\r\n").await?;
-        Ok(())
+    const fn take(&mut self) -> Self {
+        std::mem::replace(self, Self::Closed)
+    }
+
+    fn subscribe(
+        &mut self,
+        client_id: u64,
+    ) -> Result<(&mut Handler, &mut Subscriber), CommandError> {
+        *self = match self.take() {
+            Self::Normal(handler) => Self::Subscribed(handler, Subscriber::new(client_id)),
+            state => state,
+        };
+        match self {
+            Self::Subscribed(handler, subscriber) => Ok((handler, subscriber)),
+            Self::ReplicaLink => Err(CommandError::Replicated),
+            Self::Normal(_) | Self::Closed => Err(CommandError::Finished),
+        }
+    }
+
+    fn unsubscribe(&mut self) {
+        *self = match self.take() {
+            Self::Subscribed(handler, _) => Self::Normal(handler),
+            state => state,
+        };
     }
 
-    pub(crate) fn disconnected(e: &std::io::Error) -> bool {
-        use std::io::ErrorKind::{ConnectionAborted, ConnectionReset, UnexpectedEof};
-        matches!(
-            e.kind(),
-            ConnectionAborted | UnexpectedEof | ConnectionReset
-        )
+    /// Runs a blocking command for `client`, abandoned if it disconnects
+    /// or is evicted meanwhile. `None` if `CLIENT UNBLOCK` made it time out.
+    async fn unless_closed<T>(
+        &mut self,
+        client: &ClientGuard,
+        blocked: impl Future<Output = T>,
+    ) -> Result<Option<T>, CommandError> {
+        let handler = self.handler()?;
+        let unblocked = CLIENTS.block(client.id());
+        let res = tokio::select! {
+            res = blocked => Ok(Some(res)),
+            Ok(how) = unblocked => match how {
+                Unblock::Timeout => Ok(None),
+                Unblock::Error => Err(RedisError::Unblocked.into()),
+            },
+            () = handler.reader.closed() => Err(CommandError::Finished),
+            () = client.evicted() => Err(CommandError::Evicted),
+        };
+        CLIENTS.with(client.id(), ClientInfo::wake_up);
+        res
+    }
+
+    /// Writes the queued replies. A subscriber may be too slow to read them,
+    /// so it's given up on once it lags behind its messages.
+    async fn flush(&mut self) -> Result<(), CommandError> {
+        match self {
+            Self::Subscribed(handler, subscriber) => tokio::select! {
+                res = handler.flush() => Ok(res?),
+                () = subscriber.lagged() => Err(CommandError::Lagged),
+            },
+            conn => Ok(conn.handler()?.flush().await?),
+        }
+    }
+
+    /// Gives up the handler to replication
+    fn hand_over(&mut self) -> Result<Handler, CommandError> {
+        match self.take() {
+            Self::Normal(mut handler) | Self::Subscribed(mut handler, _) => {
+                *self = Self::ReplicaLink;
+                handler.reader.client = None;
+                Ok(handler)
+            }
+            state => {
+                *self = state;
+                Err(CommandError::Finished)
+            }
+        }
     }
 }
 
 #[allow(clippy::module_name_repetitions)]
-pub struct CommandHandler<'a> {
-    handler: Option<Handler>,
-    role: &'a Role,
+pub struct CommandHandler {
+    conn: Conn,
+    args: Arc<Arguments>,
+    db: Arc<Db>,
+    client: ClientGuard,
     queued: Vec<(Command, Vec<Resp>)>,
     transaction: bool,
+    /// A command failed to queue, so EXEC discards the transaction
+    exec_abort: bool,
+    /// Write commands executed by EXEC, propagated together once it finishes
+    exec_propagation: Option<Vec<Resp>>,
+    /// Replication offset after the client's last write, which WAIT waits for
+    write_offset: u64,
+    session: Session,
+    hooks: Arc<Hooks>,
+    /// Out-of-band data sent through [`Session::pushes`]
+    pushes: mpsc::UnboundedReceiver<Resp>,
 }
 
-impl<'a> CommandHandler<'a> {
-    pub const fn new(handler: Handler, role: &'a Role) -> Self {
+impl CommandHandler {
+    pub fn new(mut handler: Handler, args: Arc<Arguments>, db: Arc<Db>, hooks: Arc<Hooks>) -> Self {
+        STATS.incr_connections();
+        let client = CLIENTS.register(handler.addr);
+        handler.reader.client = Some((client.id(), 0));
+        let (tx, pushes) = mpsc::unbounded_channel();
+        let session = Session {
+            id: client.id(),
+            user: ACL.default_login(),
+            protocol: handler.protocol,
+            local_addr: Some(handler.local_addr),
+            caching: None,
+            pushes: Some(tx),
+            capa_eof: false,
+            listening_port: None,
+            asking: false,
+        };
         Self {
-            handler: Some(handler),
-            role,
+            client,
+            conn: Conn::Normal(handler),
+            args,
+            db,
             queued: Vec::new(),
             transaction: false,
+            exec_abort: false,
+            exec_propagation: None,
+            write_offset: 0,
+            session,
+            hooks,
+            pushes,
         }
     }
 
+    /// Replies are queued while complete commands remain in the read buffer,
+    /// so a pipelined batch is answered with a single write.
     pub async fn handle_commands(&mut self) -> anyhow::Result<()> {
+        let handler = self.conn.handler()?;
+        if CLIENTS.denies(handler.addr) {
+            tracing::warn!("Refusing {} in protected mode", handler.addr);
+            handler
+                .write(&Resp::Err(Clients::PROTECTED_MODE_DENIED.to_owned()))
+                .await?;
+            return Ok(());
+        }
         loop {
-            match self.handle_command().await {
+            let res = self.handle_command().await;
+            self.update_client_info();
+            match res {
                 Ok(()) => (),
-                Err(CommandError::Finished | CommandError::Replicated) => return Ok(()),
-                Err(e) => {
-                    unsafe { self.handler.as_mut().unwrap_unchecked() }
+                Err(CommandError::Finished) => {
+                    self.conn.handler()?.flush().await?;
+                    self.conn = Conn::Closed;
+                    return Ok(());
+                }
+                Err(CommandError::Replicated) => return Ok(()),
+                // Its buffers are dropped without waiting for the peer to read them
+                Err(CommandError::Evicted) => {
+                    self.conn = Conn::Closed;
+                    return Ok(());
+                }
+                Err(CommandError::Lagged) => {
+                    self.close_lagged();
+                    return Ok(());
+                }
+                // The stream can't be resynchronized after malformed input
+                Err(CommandError::Protocol(e)) => {
+                    tracing::warn!("Closing connection: {e}");
+                    self.conn
+                        .handler()?
                         .write(&Resp::Err(e.to_string()))
                         .await?;
+                    self.conn = Conn::Closed;
+                    return Ok(());
+                }
+                Err(e) => {
+                    self.conn.handler()?.queue(&Resp::Err(e.to_string()));
                 }
             }
+
+            if self.conn.handler()?.has_buffered_frame() {
+                continue;
+            }
+            match self.conn.flush().await {
+                Err(CommandError::Lagged) => {
+                    self.close_lagged();
+                    return Ok(());
+                }
+                res => res?,
+            }
         }
     }
 
-    async fn handle_command(&mut self) -> Result<(), CommandError> {
-        let handler = unsafe { self.handler.as_mut().unwrap_unchecked() };
+    /// Drops a subscriber that fell too far behind the messages published to it
+    fn close_lagged(&mut self) {
+        tracing::warn!(
+            "Closing client {}: more than pubsub-queue-len messages pending",
+            self.client.id()
+        );
+        self.conn = Conn::Closed;
+    }
 
-        let Some(resp) = handler.read().await? else {
+    async fn handle_command(&mut self) -> Result<(), CommandError> {
+        let resp = match &mut self.conn {
+            Conn::Subscribed(handler, subscriber) => tokio::select! {
+                resp = handler.read() => resp?,
+                message = subscriber.recv() => {
+                    let Some(message) = message else {
+                        return Err(CommandError::Lagged);
+                    };
+                    handler.queue_push(message);
+                    return Ok(());
+                }
+                Some(push) = self.pushes.recv() => {
+                    handler.queue_push(push);
+                    return Ok(());
+                }
+                () = self.client.evicted() => return Err(CommandError::Evicted),
+            },
+            Conn::Normal(handler) => tokio::select! {
+                resp = handler.read() => resp?,
+                Some(push) = self.pushes.recv() => {
+                    handler.queue_push(push);
+                    return Ok(());
+                }
+                () = self.client.evicted() => return Err(CommandError::Evicted),
+            },
+            conn => conn.handler()?.read().await?,
+        };
+        let Some(resp) = resp else {
             return Err(CommandError::Finished);
         };
+        let resp = self
+            .hooks_before(resp)
+            .inspect_err(|_| self.exec_abort |= self.transaction)?;
+
+        let (parsed_cmd, raw_cmd) =
+            Command::parse(&resp).inspect_err(|_| self.exec_abort |= self.transaction)?;
+        STATS.incr_commands();
+        if let Some(name) = raw_cmd.first().and_then(Resp::as_bulk) {
+            let name = String::from_utf8_lossy(name).to_ascii_lowercase();
+            CLIENTS.with(self.client.id(), |client| client.touch(&name));
+        }
+        // Parsing found it, from the same name
+        let spec =
+            CommandSpec::of(&raw_cmd).ok_or_else(|| RedisError::unknown_command(&raw_cmd))?;
+        check_acl(self.session.user.as_deref(), spec, &raw_cmd)
+            .inspect_err(|_| self.exec_abort |= self.transaction)?;
 
-        let (parsed_cmd, raw_cmd) = Command::parse(&resp)?;
+        self.check_context(spec, &parsed_cmd, &raw_cmd)?;
 
         if self.transaction {
-            match parsed_cmd {
-                Command::Exec => self.apply_exec().await?,
-                Command::Multi(_) => {
-                    return Err(anyhow::anyhow!("ERR MULTI calls can not be nested").into())
+            return self.queue_in_transaction(parsed_cmd, raw_cmd).await;
+        }
+
+        let parsed_cmd = match parsed_cmd {
+            Command::Subscribe(subscribe) => {
+                let (handler, subscriber) = self.conn.subscribe(self.client.id())?;
+                for resp in subscribe.execute(subscriber) {
+                    handler.queue_push(resp);
                 }
-                Command::Discard(discard) => {
-                    self.queued.clear();
-                    self.transaction = false;
-                    handler.write(&discard.execute()).await?;
+                return Ok(());
+            }
+            Command::Unsubscribe(unsubscribe) => {
+                let resps = unsubscribe.execute(self.conn.subscriber_mut());
+                if self.conn.subscriber().is_some_and(|s| s.count() == 0) {
+                    self.conn.unsubscribe();
                 }
-                other => {
-                    self.queued.push((other, raw_cmd));
-                    handler.write(&Resp::simple("QUEUED")).await?;
+                let handler = self.conn.handler()?;
+                for resp in resps {
+                    handler.queue_push(resp);
                 }
+                return Ok(());
             }
-            return Ok(());
+            Command::Ping(ping)
+                if self.conn.subscriber().is_some() && self.session.protocol == Protocol::Resp2 =>
+            {
+                self.conn.handler()?.queue(&ping.execute_subscribed());
+                return Ok(());
+            }
+            parsed_cmd => parsed_cmd,
         };
 
-        let resp = self.apply_commands(parsed_cmd, raw_cmd).await?;
-        unsafe { self.handler.as_mut().unwrap_unchecked() }
-            .write(&resp)
-            .await?;
+        // Earlier pipelined replies shouldn't wait for a blocking command
+        if parsed_cmd.may_block() {
+            self.conn.handler()?.flush().await?;
+        }
+        // CLIENT CACHING applies to the next command, or to the whole transaction
+        let keep_caching = matches!(
+            parsed_cmd,
+            Command::Client(Client::Caching(_)) | Command::Multi(_)
+        );
+        let resp = self.apply_commands(parsed_cmd, raw_cmd).await;
+        if !keep_caching {
+            self.session.caching = None;
+        }
+        self.propagate_expired().await;
+        let resp = resp?;
+        self.conn.handler()?.queue(&resp);
         Ok(())
     }
 
-    async fn apply_commands(
+    /// Checks the command can run on this connection and server, in their current state
+    fn check_context(
+        &mut self,
+        spec: &CommandSpec,
+        parsed_cmd: &Command,
+        raw_cmd: &[Resp],
+    ) -> Result<(), CommandError> {
+        // RESP3 connections can issue any command while subscribed
+        if self.conn.subscriber().is_some()
+            && self.session.protocol == Protocol::Resp2
+            && !matches!(
+                parsed_cmd,
+                Command::Subscribe(_) | Command::Unsubscribe(_) | Command::Ping(_)
+            )
+        {
+            let name = raw_cmd[0].to_string()?.to_ascii_lowercase();
+            return Err(RedisError::SubscribedContext(name).into());
+        }
+
+        let caller = Caller {
+            replica: matches!(self.args.role, Role::Slave(_)),
+            transaction: self.transaction,
+            script: false,
+        };
+        parsed_cmd
+            .check_flags(spec, caller)
+            .inspect_err(|_| self.exec_abort |= self.transaction)?;
+
+        if self.args.cluster_enabled {
+            let asking = std::mem::take(&mut self.session.asking);
+            CLUSTER
+                .route(raw_cmd, asking, |key| self.db.exists(key))
+                .inspect_err(|_| self.exec_abort |= self.transaction)?;
+        }
+        Ok(())
+    }
+
+    /// Handles a command sent after MULTI, which is queued unless it controls the transaction
+    async fn queue_in_transaction(
         &mut self,
         parsed_cmd: Command,
         raw_cmd: Vec<Resp>,
-    ) -> Result<Resp, CommandError> {
+    ) -> Result<(), CommandError> {
         let resp = match parsed_cmd {
-            Command::Exec => {
-                return Err(anyhow::anyhow!("ERR EXEC without MULTI").into());
-            }
-            Command::Discard(_) => {
-                return Err(anyhow::anyhow!("ERR DISCARD without MULTI").into());
+            Command::Exec if self.exec_abort => {
+                self.end_transaction();
+                return Err(RedisError::ExecAbort.into());
             }
-
-            Command::Set(set) => {
-                let resp = set.execute();
-                propagate(self.role, raw_cmd).await;
-                resp
-            }
-            Command::Del(del) => {
-                let resp = del.execute()?;
-                propagate(self.role, raw_cmd).await;
-                resp
+            Command::Exec => {
+                self.apply_exec().await?;
+                return Ok(());
             }
-            Command::Xadd(xadd) => {
-                let resp = xadd.execute()?;
-                propagate(self.role, raw_cmd).await;
-                resp
+            Command::Multi(_) => return Err(RedisError::NestedMulti.into()),
+            Command::Discard(discard) => {
+                self.end_transaction();
+                discard.execute()
             }
-            Command::Incr(incr) => {
-                let resp = incr.execute()?;
-                propagate(self.role, raw_cmd).await;
-                resp
+            other => {
+                self.queued.push((other, raw_cmd));
+                Resp::simple("QUEUED")
             }
+        };
+        self.conn.handler()?.queue(&resp);
+        Ok(())
+    }
 
-            Command::Ping(ping) => ping.execute(),
-            Command::Echo(echo) => echo.execute(),
-            Command::Get(get) => get.execute()?,
-            Command::Config(config) => config.execute(),
-            Command::Keys(keys) => keys.execute(),
-            Command::Type(r#type) => r#type.execute(),
-            Command::Xrange(xrange) => xrange.execute()?,
-            Command::Xread(xread) => xread.execute().await?,
+    fn end_transaction(&mut self) {
+        self.queued.clear();
+        self.transaction = false;
+        self.exec_abort = false;
+    }
 
-            Command::Info(info) => info.execute(self.role).await?,
-            Command::Wait(wait) => wait.execute(self.role).await?,
+    /// Lets the hooks rewrite or refuse a command, before it's parsed
+    fn hooks_before(&self, resp: Resp) -> anyhow::Result<Resp> {
+        let Resp::Array(args) = resp else {
+            return Ok(resp);
+        };
+        if self.hooks.is_empty() {
+            return Ok(Resp::Array(args));
+        }
+        let mut call = Call {
+            client_id: self.client.id(),
+            args,
+        };
+        self.hooks.before(&mut call)?;
+        Ok(Resp::Array(call.args))
+    }
 
+    /// Runs a command, then shows its reply to the hooks
+    async fn apply_commands(
+        &mut self,
+        parsed_cmd: Command,
+        raw_cmd: Vec<Resp>,
+    ) -> Result<Resp, CommandError> {
+        if self.hooks.is_empty() {
+            return self.dispatch(parsed_cmd, raw_cmd).await;
+        }
+        let call = Call {
+            client_id: self.client.id(),
+            args: raw_cmd.clone(),
+        };
+        let start = Instant::now();
+        let res = self.dispatch(parsed_cmd, raw_cmd).await;
+        let elapsed = start.elapsed();
+        match &res {
+            Ok(reply) => self.hooks.after(&call, reply, elapsed),
+            Err(e @ (CommandError::Redis(_) | CommandError::Other(_))) => {
+                self.hooks.after(&call, &Resp::Err(e.to_string()), elapsed);
+            }
+            // The connection is gone
+            Err(_) => {}
+        }
+        res
+    }
+
+    async fn dispatch(
+        &mut self,
+        parsed_cmd: Command,
+        raw_cmd: Vec<Resp>,
+    ) -> Result<Resp, CommandError> {
+        // The guard borrows its own handle, so the handler stays free to mutate
+        let db = Arc::clone(&self.db);
+        let _exclusive: Option<Either<RwLockReadGuard<()>, RwLockWriteGuard<()>>> =
+            match &parsed_cmd {
+                #[cfg(feature = "scripting")]
+                Command::Eval(_) => {
+                    Some(Either::Right(unless_busy(&db, db.exclusive.write()).await?))
+                }
+                // They interrupt the script holding the lock
+                #[cfg(feature = "scripting")]
+                Command::Script(Script::Kill) | Command::Function(Function::Kill) => None,
+                // These can hold the connection for long
+                Command::Psync(_) => None,
+                cmd if cmd.may_block() => None,
+                _ => Some(Either::Left(unless_busy(&db, db.exclusive.read()).await?)),
+            };
+        // Blocking isn't allowed inside a transaction
+        let block = self.exec_propagation.is_none();
+        let resp = match parsed_cmd {
+            Command::Exec => return Err(RedisError::ExecWithoutMulti.into()),
+            Command::Discard(_) => return Err(RedisError::DiscardWithoutMulti.into()),
             Command::Multi(multi) => {
                 let resp = multi.execute();
                 self.transaction = true;
                 resp
             }
-
-            Command::ReplConf(replconf) => replconf.execute(),
-            Command::Psync(psync) => {
-                if self.transaction {
-                    return Err(
-                        anyhow::anyhow!("ERR Command not allowed inside a transaction").into(),
-                    );
-                }
-
-                let Role::Master(master) = self.role else {
-                    return Err(anyhow::anyhow!("").into()); // FIXME
-                };
-                match psync.execute(master) {
-                    Ok((resp, data)) => {
-                        let mut handler = self.handler.take().unwrap();
-                        handler.write(&resp).await?;
-                        handler.write(&data).await?;
-                        master.add_slave(handler).await;
-                        return Err(CommandError::Replicated);
+            Command::Wait(wait) => {
+                let waiting = wait.execute(&self.args.role, self.write_offset, block);
+                match self.conn.unless_closed(&self.client, waiting).await? {
+                    Some(resp) => resp?,
+                    // The replicas that acknowledged so far
+                    None => {
+                        wait.execute(&self.args.role, self.write_offset, false)
+                            .await?
                     }
-                    Err(e) => return Err(e.into()),
                 }
             }
+            Command::Xread(xread) if xread.blocks() && block => {
+                let xread = xread.execute_blocking(&self.db);
+                self.conn
+                    .unless_closed(&self.client, xread)
+                    .await?
+                    .transpose()?
+                    .unwrap_or(Resp::NullArray)
+            }
+            Command::Psync(psync) => {
+                let Role::Master(master) = &self.args.role else {
+                    return Err(RedisError::ReplicaInstance("PSYNC").into());
+                };
+                let handler = self.conn.hand_over()?;
+                let stream = self.session.capa_eof && self.args.repl_diskless_sync;
+                let port = self.session.listening_port;
+                master.full_resync(&self.db, handler, &psync, stream, port);
+                return Err(CommandError::Replicated);
+            }
+
+            // On a blocking thread, so that the other clients can be told the server is busy
+            #[cfg(feature = "scripting")]
+            Command::Eval(eval) => {
+                TRACKING.remember(self.session.id, self.session.caching, &raw_cmd);
+                let (db, args) = (Arc::clone(&self.db), Arc::clone(&self.args));
+                let user = self.session.user.clone().unwrap_or_default();
+                let (resp, effects) =
+                    tokio::task::spawn_blocking(move || eval.run(&db, &args, &user))
+                        .await
+                        .map_err(anyhow::Error::from)??;
+                self.propagate_effects(effects).await;
+                resp
+            }
+
+            cmd => {
+                let mut ctx = Ctx::new(&self.db, &self.args, &mut self.session);
+                let resp = cmd.execute(&mut ctx, raw_cmd);
+                let effects = ctx.effects;
+                // HELLO may have switched the protocol
+                self.conn.handler()?.protocol = self.session.protocol;
+                self.propagate_effects(effects).await;
+                resp?
+            }
         };
         Ok(resp)
     }
@@ -273,20 +751,129 @@ impl<'a> CommandHandler<'a> {
         let mut queue_res = Vec::with_capacity(self.queued.len());
         let queue = std::mem::take(&mut self.queued); // FIXME use Vec::drain
 
+        self.exec_propagation = Some(Vec::new());
         for (parsed_cmd, raw_cmd) in queue {
             let resp = self
                 .apply_commands(parsed_cmd, raw_cmd)
                 .await
                 .unwrap_or_else(|e| Resp::Err(e.to_string()));
+            self.propagate_expired().await;
             queue_res.push(resp);
         }
+        let propagated = self.exec_propagation.take().unwrap_or_default();
+        self.propagate_transaction(propagated).await;
 
         self.transaction = false;
-        unsafe { self.handler.as_mut().unwrap_unchecked() }
-            .write(&Resp::Array(queue_res))
-            .await?;
+        self.conn.handler()?.queue(&Resp::Array(queue_res));
         Ok(())
     }
+
+    fn update_client_info(&self) {
+        let (sub, psub) = self.conn.subscriber().map_or((0, 0), Subscriber::counts);
+        let multi = self.transaction.then_some(self.queued.len());
+        CLIENTS.with(self.client.id(), |client| {
+            client.sub = sub;
+            client.psub = psub;
+            client.multi = multi;
+        });
+        if let Conn::Normal(handler) | Conn::Subscribed(handler, _) = &self.conn {
+            CLIENTS.set_output_buffer(self.client.id(), handler.output_buffer());
+        }
+    }
+
+    async fn propagate(&mut self, command: Vec<Resp>) {
+        // Keys the command expired are deleted before it on the replicas
+        self.propagate_expired().await;
+        let command = Resp::Array(command);
+        if let Some(propagated) = &mut self.exec_propagation {
+            propagated.push(command);
+            return;
+        }
+        AOF.feed(std::slice::from_ref(&command)).await;
+        if let Role::Master(master) = &self.args.role {
+            master.propagate(Some(Db::INDEX), &command, true);
+            self.write_offset = master.repl_offset();
+        }
+    }
+
+    /// Propagates commands in a MULTI/EXEC block, so they're applied together
+    async fn propagate_transaction(&mut self, commands: Vec<Resp>) {
+        if commands.is_empty() {
+            return;
+        }
+        let multi = Resp::Array(vec![Resp::bulk("MULTI")]);
+        let exec = Resp::Array(vec![Resp::bulk("EXEC")]);
+        let block = [vec![multi], commands, vec![exec]].concat();
+        AOF.feed(&block).await;
+        if let Role::Master(master) = &self.args.role {
+            master.propagate_all(Some(Db::INDEX), &block, true);
+            self.write_offset = master.repl_offset();
+        }
+    }
+
+    /// Propagates the writes of a command, which are wrapped in a transaction
+    /// if there are several like for a script
+    async fn propagate_effects(&mut self, mut effects: Vec<Vec<Resp>>) {
+        if effects.len() <= 1 {
+            if let Some(effect) = effects.pop() {
+                self.propagate(effect).await;
+            }
+            return;
+        }
+        self.propagate_expired().await;
+        let commands = effects.into_iter().map(Resp::Array);
+        match &mut self.exec_propagation {
+            Some(propagated) => propagated.extend(commands),
+            None => self.propagate_transaction(commands.collect()).await,
+        }
+    }
+
+    async fn propagate_expired(&mut self) {
+        let Role::Master(master) = &self.args.role else {
+            return;
+        };
+        if let Some(propagated) = &mut self.exec_propagation {
+            propagated.extend(expired_dels(&self.db));
+        } else {
+            master.propagate_expired(&self.db).await;
+        }
+    }
+}
+
+/// How often clients waiting for a script check whether it runs for too long
+#[cfg(feature = "scripting")]
+const BUSY_CHECK_PERIOD: Duration = Duration::from_millis(10);
+
+/// Waits for `lock`, failing with `BUSY` once the script holding it
+/// has been running for longer than `busy-reply-threshold`
+#[cfg(feature = "scripting")]
+async fn unless_busy<T>(db: &Db, lock: impl Future<Output = T>) -> Result<T, RedisError> {
+    tokio::pin!(lock);
+    if let Some(guard) = futures_util::FutureExt::now_or_never(lock.as_mut()) {
+        return Ok(guard);
+    }
+    loop {
+        db.busy.check()?;
+        tokio::select! {
+            guard = &mut lock => return Ok(guard),
+            () = tokio::time::sleep(BUSY_CHECK_PERIOD) => {}
+        }
+    }
+}
+
+/// Without scripting, nothing holds the lock for long
+#[cfg(not(feature = "scripting"))]
+async fn unless_busy<T>(_: &Db, lock: impl Future<Output = T>) -> Result<T, RedisError> {
+    Ok(lock.await)
+}
+
+/// Unauthenticated connections can only authenticate
+fn check_acl(user: Option<&str>, spec: &CommandSpec, raw_cmd: &[Resp]) -> anyhow::Result<()> {
+    match user {
+        Some(user) => ACL.check(user, raw_cmd),
+        None if spec.has_flag("no_auth") => Ok(()),
+        None => bail!(RedisError::NoAuth),
+    }
 }
 
 #[derive(Debug, Error)]
@@ -295,15 +882,468 @@ pub enum CommandError {
     Finished,
     #[error("Handler was taken for replication")]
     Replicated,
+    #[error("Client evicted by maxmemory-clients")]
+    Evicted,
+    #[error("Subscriber closed by pubsub-overflow")]
+    Lagged,
     #[error(transparent)]
     IO(#[from] std::io::Error),
     #[error(transparent)]
+    Protocol(#[from] crate::resp::Error),
+    #[error(transparent)]
+    Redis(#[from] RedisError),
+    #[error(transparent)]
     Other(#[from] anyhow::Error),
 }
 
-async fn propagate(role: &Role, command: Vec<Resp>) {
-    if let Role::Master(master) = role {
-        let command = Resp::Array(command);
-        master.propagate(&command, true).await;
+#[cfg(test)]
+mod tests {
+    use crate::testutil::{master, until, Client};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn transaction() {
+        let server = master().await;
+        let mut client = Client::connect(&server).await;
+
+        pretty_assertions::assert_eq!(client.cmd(&["MULTI"]).await, Resp::simple("OK"));
+        pretty_assertions::assert_eq!(client.cmd(&["SET", "n", "1"]).await, Resp::simple("QUEUED"));
+        pretty_assertions::assert_eq!(client.cmd(&["INCR", "n"]).await, Resp::simple("QUEUED"));
+        pretty_assertions::assert_eq!(
+            client.cmd(&["EXEC"]).await,
+            Resp::Array(vec![Resp::simple("OK"), Resp::Integer(2)])
+        );
+
+        // A command that can't be queued discards the whole transaction
+        client.cmd(&["MULTI"]).await;
+        client.cmd(&["SET", "n", "5"]).await;
+        client.cmd(&["GET"]).await;
+        pretty_assertions::assert_eq!(client.cmd(&["EXEC"]).await, RedisError::ExecAbort.into());
+        pretty_assertions::assert_eq!(client.cmd(&["GET", "n"]).await, Resp::bulk("2"));
+
+        pretty_assertions::assert_eq!(
+            client.cmd(&["EXEC"]).await,
+            RedisError::ExecWithoutMulti.into()
+        );
+        pretty_assertions::assert_eq!(
+            client.cmd(&["DISCARD"]).await,
+            RedisError::DiscardWithoutMulti.into()
+        );
+        client.cmd(&["MULTI"]).await;
+        pretty_assertions::assert_eq!(client.cmd(&["MULTI"]).await, RedisError::NestedMulti.into());
+        pretty_assertions::assert_eq!(client.cmd(&["DISCARD"]).await, Resp::simple("OK"));
+    }
+
+    #[tokio::test]
+    async fn hello() {
+        let server = master().await;
+        let mut client = Client::connect(&server).await;
+        let Resp::Integer(id) = client.cmd(&["CLIENT", "ID"]).await else {
+            panic!("CLIENT ID didn't reply an integer");
+        };
+        let reply = |proto| {
+            vec![
+                (Resp::bulk("server"), Resp::bulk("redis")),
+                (Resp::bulk("version"), Resp::bulk("7.4.0")),
+                (Resp::bulk("proto"), Resp::Integer(proto)),
+                (Resp::bulk("id"), Resp::Integer(id)),
+                (Resp::bulk("mode"), Resp::bulk("standalone")),
+                (Resp::bulk("role"), Resp::bulk("master")),
+                (Resp::bulk("modules"), Resp::Array(Vec::new())),
+            ]
+        };
+
+        // A flat array of the pairs on RESP2, the protocol connections start with
+        let flat = |proto| {
+            Resp::Array(
+                reply(proto)
+                    .into_iter()
+                    .flat_map(<[Resp; 2]>::from)
+                    .collect(),
+            )
+        };
+        pretty_assertions::assert_eq!(client.cmd(&["HELLO"]).await, flat(2));
+        pretty_assertions::assert_eq!(client.cmd(&["HELLO", "3"]).await, Resp::Map(reply(3)));
+        // The connection stays on RESP3
+        pretty_assertions::assert_eq!(client.cmd(&["HELLO"]).await, Resp::Map(reply(3)));
+        pretty_assertions::assert_eq!(
+            client.cmd(&["HELLO", "4"]).await,
+            Resp::Err("NOPROTO unsupported protocol version".to_owned())
+        );
+
+        pretty_assertions::assert_eq!(
+            client.cmd(&["HELLO", "2", "SETNAME", "hello-conn"]).await,
+            flat(2)
+        );
+        pretty_assertions::assert_eq!(
+            client.cmd(&["CLIENT", "GETNAME"]).await,
+            Resp::bulk("hello-conn")
+        );
+        pretty_assertions::assert_eq!(
+            client.cmd(&["HELLO", "2", "SETNAME", "hello conn"]).await,
+            Resp::Err(
+                "ERR Client names cannot contain spaces, newlines or special characters."
+                    .to_owned()
+            )
+        );
+
+        client
+            .cmd(&[
+                "ACL",
+                "SETUSER",
+                "hello-user",
+                "on",
+                ">secret",
+                "~*",
+                "+@all",
+            ])
+            .await;
+        pretty_assertions::assert_eq!(
+            client
+                .cmd(&["HELLO", "3", "AUTH", "hello-user", "wrong"])
+                .await,
+            RedisError::WrongPass.into()
+        );
+        pretty_assertions::assert_eq!(
+            client
+                .cmd(&["HELLO", "3", "AUTH", "hello-user", "secret"])
+                .await,
+            Resp::Map(reply(3))
+        );
+        pretty_assertions::assert_eq!(
+            client.cmd(&["ACL", "WHOAMI"]).await,
+            Resp::bulk("hello-user")
+        );
+    }
+
+    #[tokio::test]
+    async fn client_names() {
+        let server = master().await;
+        let mut client = Client::connect(&server).await;
+        let Resp::Integer(id) = client.cmd(&["CLIENT", "ID"]).await else {
+            panic!("CLIENT ID didn't reply an integer");
+        };
+        let own_line = |list: Resp| {
+            let list = String::from_utf8(list.as_bulk().unwrap().to_vec()).unwrap();
+            let prefix = format!("id={id} ");
+            list.lines()
+                .find(|line| line.starts_with(&prefix))
+                .unwrap()
+                .to_owned()
+        };
+
+        for name in ["a b", "a\nb"] {
+            pretty_assertions::assert_eq!(
+                client.cmd(&["CLIENT", "SETNAME", name]).await,
+                Resp::Err(
+                    "ERR Client names cannot contain spaces, newlines or special characters."
+                        .to_owned()
+                )
+            );
+        }
+        pretty_assertions::assert_eq!(
+            client
+                .cmd(&["CLIENT", "SETINFO", "lib-name", "redis rs"])
+                .await,
+            Resp::Err(
+                "ERR lib-name cannot contain spaces, newlines or special characters.".to_owned()
+            )
+        );
+        pretty_assertions::assert_eq!(
+            client.cmd(&["CLIENT", "SETINFO", "lib-ver", "1\n"]).await,
+            Resp::Err(
+                "ERR lib-ver cannot contain spaces, newlines or special characters.".to_owned()
+            )
+        );
+
+        for cmd in [
+            &["CLIENT", "SETNAME", "conn"][..],
+            &["CLIENT", "SETINFO", "lib-name", "redis-rs"],
+            &["CLIENT", "SETINFO", "lib-ver", "1.0"],
+        ] {
+            pretty_assertions::assert_eq!(client.cmd(cmd).await, Resp::simple("OK"));
+        }
+        pretty_assertions::assert_eq!(client.cmd(&["CLIENT", "GETNAME"]).await, Resp::bulk("conn"));
+        let line = own_line(client.cmd(&["CLIENT", "LIST"]).await);
+        assert!(line.contains(" name=conn "), "{line}");
+        assert!(line.contains(" lib-name=redis-rs lib-ver=1.0 "), "{line}");
+
+        // An empty name clears it
+        pretty_assertions::assert_eq!(
+            client.cmd(&["CLIENT", "SETNAME", ""]).await,
+            Resp::simple("OK")
+        );
+        pretty_assertions::assert_eq!(client.cmd(&["CLIENT", "GETNAME"]).await, Resp::Null);
+        let line = own_line(client.cmd(&["CLIENT", "LIST"]).await);
+        assert!(line.contains(" name= "), "{line}");
+    }
+
+    #[tokio::test]
+    async fn stream_groups() {
+        let server = master().await;
+        let mut client = Client::connect(&server).await;
+
+        pretty_assertions::assert_eq!(
+            client.cmd(&["XGROUP", "CREATE", "s", "g", "$"]).await,
+            RedisError::NoGroupStream.into()
+        );
+        client
+            .cmd(&["XGROUP", "CREATE", "s", "g", "$", "MKSTREAM"])
+            .await;
+        pretty_assertions::assert_eq!(
+            client.cmd(&["XGROUP", "CREATE", "s", "g", "$"]).await,
+            RedisError::BusyGroup.into()
+        );
+        pretty_assertions::assert_eq!(
+            client
+                .cmd(&["XREADGROUP", "GROUP", "h", "c", "STREAMS", "s", ">"])
+                .await,
+            RedisError::NoGroup {
+                key: "s".to_owned(),
+                group: "h".to_owned(),
+                xreadgroup: true,
+            }
+            .into()
+        );
+        pretty_assertions::assert_eq!(
+            client.cmd(&["XAUTOCLAIM", "s", "h", "c", "0", "-"]).await,
+            RedisError::NoGroup {
+                key: "s".to_owned(),
+                group: "h".to_owned(),
+                xreadgroup: false,
+            }
+            .into()
+        );
+        pretty_assertions::assert_eq!(
+            client
+                .cmd(&["XAUTOCLAIM", "s", "g", "c", "0", "-", "COUNT", "0"])
+                .await,
+            RedisError::CountNotPositive.into()
+        );
+    }
+
+    #[tokio::test]
+    async fn socket_options() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        for (keepalive, nodelay) in [(Duration::from_secs(30), true), (Duration::ZERO, false)] {
+            let stream = TcpStream::connect(addr).await.unwrap();
+            let args = Arguments {
+                tcp_keepalive: keepalive,
+                tcp_nodelay: nodelay,
+                ..Arguments::default()
+            };
+            configure_socket(&stream, &args).unwrap();
+
+            pretty_assertions::assert_eq!(stream.nodelay().unwrap(), nodelay);
+            let socket = SockRef::from(&stream);
+            pretty_assertions::assert_eq!(socket.keepalive().unwrap(), !keepalive.is_zero());
+            if !keepalive.is_zero() {
+                pretty_assertions::assert_eq!(socket.keepalive_time().unwrap(), keepalive);
+                pretty_assertions::assert_eq!(
+                    socket.keepalive_interval().unwrap(),
+                    Duration::from_secs(10)
+                );
+            }
+        }
+    }
+
+    #[cfg(feature = "scripting")]
+    #[tokio::test]
+    async fn busy_script() {
+        let server = master().await;
+        let mut scripted = Client::connect(&server).await;
+        let mut client = Client::connect(&server).await;
+        client
+            .cmd(&["CONFIG", "SET", "busy-reply-threshold", "50"])
+            .await;
+        pretty_assertions::assert_eq!(
+            client.cmd(&["SCRIPT", "KILL"]).await,
+            RedisError::NotBusy.into()
+        );
+
+        scripted.send(&["EVAL", "while true do end", "0"]).await;
+        // Until the script holds the lock, then for the threshold
+        let reply = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                match client.cmd(&["GET", "k"]).await {
+                    Resp::Null => {}
+                    reply => break reply,
+                }
+            }
+        })
+        .await
+        .expect("The script runs past the threshold");
+        let busy: Resp = RedisError::Busy { function: false }.into();
+        pretty_assertions::assert_eq!(reply, busy);
+        pretty_assertions::assert_eq!(client.cmd(&["FUNCTION", "KILL"]).await, busy);
+        pretty_assertions::assert_eq!(client.cmd(&["SCRIPT", "KILL"]).await, Resp::simple("OK"));
+        pretty_assertions::assert_eq!(
+            scripted.read().await,
+            Resp::Err("ERR Script killed by user with SCRIPT KILL...".to_owned())
+        );
+        pretty_assertions::assert_eq!(client.cmd(&["GET", "k"]).await, Resp::Null);
+    }
+
+    #[tokio::test]
+    async fn pubsub() {
+        let server = master().await;
+        let mut subscriber = Client::connect(&server).await;
+        let mut publisher = Client::connect(&server).await;
+
+        subscriber
+            .send(&["SUBSCRIBE", "handler-news", "handler-sports"])
+            .await;
+        for (i, channel) in ["handler-news", "handler-sports"].into_iter().enumerate() {
+            pretty_assertions::assert_eq!(
+                subscriber.read().await,
+                Resp::Array(vec![
+                    Resp::bulk("subscribe"),
+                    Resp::bulk(channel),
+                    Resp::Integer(i64::try_from(i).unwrap() + 1),
+                ])
+            );
+        }
+
+        let Resp::Array(mut channels) = publisher.cmd(&["PUBSUB", "CHANNELS", "handler-*"]).await
+        else {
+            panic!("PUBSUB CHANNELS didn't reply an array");
+        };
+        channels.sort_by_key(|channel| channel.to_string().unwrap());
+        pretty_assertions::assert_eq!(
+            channels,
+            vec![Resp::bulk("handler-news"), Resp::bulk("handler-sports")]
+        );
+        pretty_assertions::assert_eq!(
+            publisher
+                .cmd(&["PUBSUB", "NUMSUB", "handler-news", "handler-weather"])
+                .await,
+            Resp::Array(vec![
+                Resp::bulk("handler-news"),
+                Resp::Integer(1),
+                Resp::bulk("handler-weather"),
+                Resp::Integer(0),
+            ])
+        );
+
+        pretty_assertions::assert_eq!(
+            publisher.cmd(&["PUBLISH", "handler-news", "hello"]).await,
+            Resp::Integer(1)
+        );
+        pretty_assertions::assert_eq!(
+            subscriber.read().await,
+            Resp::Array(vec![
+                Resp::bulk("message"),
+                Resp::bulk("handler-news"),
+                Resp::bulk("hello"),
+            ])
+        );
+
+        // Only the subscription commands, PING and friends while subscribed on RESP2
+        pretty_assertions::assert_eq!(
+            subscriber.cmd(&["GET", "k"]).await,
+            RedisError::SubscribedContext("get".to_owned()).into()
+        );
+
+        subscriber.send(&["UNSUBSCRIBE", "handler-news"]).await;
+        pretty_assertions::assert_eq!(
+            subscriber.read().await,
+            Resp::Array(vec![
+                Resp::bulk("unsubscribe"),
+                Resp::bulk("handler-news"),
+                Resp::Integer(1),
+            ])
+        );
+        pretty_assertions::assert_eq!(
+            publisher.cmd(&["PUBLISH", "handler-news", "hello"]).await,
+            Resp::Integer(0)
+        );
+    }
+
+    #[tokio::test]
+    async fn disconnect_while_blocked() {
+        let server = master().await;
+        let mut blocked = Client::connect(&server).await;
+        // From a new connection each time, which is the last line
+        let list = || async {
+            let mut client = Client::connect(&server).await;
+            let Resp::Bulk(list) = client.cmd(&["CLIENT", "LIST"]).await else {
+                panic!("CLIENT LIST didn't reply a bulk string");
+            };
+            String::from_utf8(list.to_vec()).unwrap()
+        };
+
+        blocked
+            .send(&["XREAD", "BLOCK", "0", "STREAMS", "s", "$"])
+            .await;
+        until(|| async { list().await.contains("flags=b") }).await;
+        drop(blocked);
+        until(|| async { list().await.lines().count() == 1 }).await;
+    }
+
+    #[tokio::test]
+    async fn client_unblock() {
+        let server = master().await;
+        let mut blocked = Client::connect(&server).await;
+        let Resp::Integer(id) = blocked.cmd(&["CLIENT", "ID"]).await else {
+            panic!("CLIENT ID didn't reply an integer");
+        };
+        let id = id.to_string();
+        // From a new connection each time, replying whether the client was blocked
+        let unblock = |how: &'static str| {
+            let (server, id) = (&server, &id);
+            async move {
+                let mut client = Client::connect(server).await;
+                client.cmd(&["CLIENT", "UNBLOCK", id, how]).await == Resp::Integer(1)
+            }
+        };
+        assert!(!unblock("TIMEOUT").await);
+
+        let xread = ["XREAD", "BLOCK", "0", "STREAMS", "s", "$"];
+        for (how, reply) in [
+            ("TIMEOUT", Resp::NullArray),
+            ("ERROR", RedisError::Unblocked.into()),
+        ] {
+            blocked.send(&xread).await;
+            until(|| unblock(how)).await;
+            pretty_assertions::assert_eq!(blocked.read().await, reply);
+        }
+
+        // Without replicas, none acknowledged the write
+        blocked.send(&["WAIT", "1", "0"]).await;
+        until(|| unblock("TIMEOUT")).await;
+        pretty_assertions::assert_eq!(blocked.read().await, Resp::Integer(0));
+    }
+
+    #[tokio::test]
+    async fn expiry() {
+        let server = master().await;
+        let mut client = Client::connect(&server).await;
+
+        let clock = &server.db().clock;
+        clock.pause();
+        client.cmd(&["SET", "k", "v", "PX", "50"]).await;
+        clock.advance(Duration::from_millis(49));
+        pretty_assertions::assert_eq!(client.cmd(&["GET", "k"]).await, Resp::bulk("v"));
+        clock.advance(Duration::from_millis(1));
+        pretty_assertions::assert_eq!(client.cmd(&["GET", "k"]).await, Resp::Null);
+    }
+
+    #[cfg(feature = "scripting")]
+    #[tokio::test]
+    async fn script_expiry() {
+        let server = master().await;
+        let mut client = Client::connect(&server).await;
+        let get = ["EVAL", "return redis.call('GET', KEYS[1])", "1", "k"];
+
+        // Scripts run on their own thread, reading the same clock
+        let clock = &server.db().clock;
+        clock.pause();
+        client.cmd(&["SET", "k", "v", "PX", "50"]).await;
+        clock.advance(Duration::from_millis(49));
+        pretty_assertions::assert_eq!(client.cmd(&get).await, Resp::bulk("v"));
+        clock.advance(Duration::from_millis(1));
+        pretty_assertions::assert_eq!(client.cmd(&get).await, Resp::Null);
     }
 }
@@ -0,0 +1,116 @@
+use std::{fmt::Debug, time::Duration};
+
+use crate::Resp;
+
+/// Code run around the commands of every client, registered with
+/// [`crate::ServerBuilder::hook`], like for auditing or custom metrics
+pub trait Hook: Send + Sync + 'static {
+    /// Runs before the command is parsed, so it can rewrite its arguments.
+    /// An error is replied to the client instead of running the command.
+    fn before(&self, call: &mut Call) -> anyhow::Result<()> {
+        let _ = call;
+        Ok(())
+    }
+
+    /// Runs once the command returned `reply`, an error reply included, after `elapsed`.
+    /// Commands queued by a transaction run, and so are seen here, on EXEC.
+    fn after(&self, call: &Call, reply: &Resp, elapsed: Duration) {
+        let _ = (call, reply, elapsed);
+    }
+}
+
+/// A command sent by a client
+#[derive(Debug, Clone, PartialEq)]
+pub struct Call {
+    pub client_id: u64,
+    /// The command name and its arguments, usually bulk strings
+    pub args: Vec<Resp>,
+}
+
+/// Hooks of a server, run in the order they were registered
+#[derive(Default)]
+pub struct Hooks(Vec<Box<dyn Hook>>);
+
+impl Hooks {
+    pub(crate) fn push(&mut self, hook: impl Hook) {
+        self.0.push(Box::new(hook));
+    }
+
+    #[inline]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Runs every [`Hook::before`], stopping at the first error
+    pub(crate) fn before(&self, call: &mut Call) -> anyhow::Result<()> {
+        self.0.iter().try_for_each(|hook| hook.before(call))
+    }
+
+    pub(crate) fn after(&self, call: &Call, reply: &Resp, elapsed: Duration) {
+        for hook in &self.0 {
+            hook.after(call, reply, elapsed);
+        }
+    }
+}
+
+impl Debug for Hooks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Hooks").field(&self.0.len()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use parking_lot::Mutex;
+    use std::sync::Arc;
+
+    use crate::{testutil::Client, Server};
+
+    use super::*;
+
+    /// Renames `HI` to `PING`, refuses `DEL` and records the replies
+    #[derive(Default)]
+    struct Audit(Arc<Mutex<Vec<(Call, Resp)>>>);
+
+    impl Hook for Audit {
+        fn before(&self, call: &mut Call) -> anyhow::Result<()> {
+            match call.args[0].as_bulk().map(|name| name.to_ascii_uppercase()) {
+                Some(name) if name == b"HI" => call.args[0] = Resp::bulk("PING"),
+                Some(name) if name == b"DEL" => anyhow::bail!("ERR DEL is disabled"),
+                _ => {}
+            }
+            Ok(())
+        }
+
+        fn after(&self, call: &Call, reply: &Resp, _: Duration) {
+            self.0.lock().push((call.clone(), reply.clone()));
+        }
+    }
+
+    #[tokio::test]
+    async fn hooks() {
+        let audit = Audit::default();
+        let log = Arc::clone(&audit.0);
+        let server = Server::builder().port(0).hook(audit).spawn().await.unwrap();
+        let mut client = Client::connect(&server).await;
+
+        pretty_assertions::assert_eq!(client.cmd(&["HI"]).await, Resp::simple("PONG"));
+        pretty_assertions::assert_eq!(
+            client.cmd(&["DEL", "k"]).await,
+            Resp::Err("ERR DEL is disabled".to_owned())
+        );
+        client.cmd(&["INCR", "k"]).await;
+        let log = log
+            .lock()
+            .iter()
+            .map(|(call, reply)| (call.args.clone(), reply.clone()))
+            .collect::<Vec<_>>();
+        pretty_assertions::assert_eq!(
+            log,
+            [
+                (vec![Resp::bulk("PING")], Resp::simple("PONG")),
+                (vec![Resp::bulk("INCR"), Resp::bulk("k")], Resp::Integer(1)),
+            ]
+        );
+    }
+}
@@ -1,10 +1,24 @@
 #![allow(clippy::missing_errors_doc, clippy::missing_panics_doc)]
 
 mod args;
-pub use args::ARGUMENTS;
+pub use args::{Arguments, LogLevel};
+
+mod server;
+pub use server::{Server, ServerBuilder};
 
 mod commands;
-pub use commands::Command;
+pub use commands::{Command, CommandExec, Ctx, Session};
+
+pub mod client;
+
+mod hooks;
+pub use hooks::{Call, Hook};
+
+mod audit;
+pub use audit::AuditLog;
+
+mod error;
+pub use error::RedisError;
 
 mod handler;
 pub use handler::{CommandHandler, Handler};
@@ -13,19 +27,51 @@ pub mod roles;
 pub use roles::{Master, Role, Slave};
 
 mod resp;
-pub use resp::Resp;
+pub use resp::{Protocol, Resp, RespCodec};
 
 mod db;
-pub use db::DB;
+pub use db::Db;
 
 mod rdb;
-pub use rdb::Rdb;
+pub use rdb::{Rdb, ReplInfo};
+
+mod aof;
+pub use aof::{Aof, AOF};
+
+mod clients;
+pub use clients::CLIENTS;
+
+mod pubsub;
+pub use pubsub::PUBSUB;
+
+mod tracking;
+pub use tracking::TRACKING;
+
+mod stats;
+pub use stats::STATS;
+
+mod acl;
+pub use acl::ACL;
+
+mod cluster;
+
+mod glob;
+
+mod clock;
+
+mod check;
+pub use check::{Check, DumpSummary};
+
+#[cfg(feature = "scripting")]
+mod scripting;
+
+#[cfg(test)]
+mod testutil;
 
 #[inline]
 pub fn slice_to_int<T>(slice: impl AsRef<[u8]>) -> anyhow::Result<T>
 where
     T: atoi::FromRadix10SignedChecked,
 {
-    use anyhow::Context;
-    atoi::atoi::<T>(slice.as_ref()).context("Failed to parse int from slice")
+    Ok(atoi::atoi::<T>(slice.as_ref()).ok_or(RedisError::NotInteger)?)
 }
@@ -0,0 +1,388 @@
+use anyhow::bail;
+use bytes::Bytes;
+use parking_lot::{Mutex, RwLock};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fmt::Display,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, LazyLock,
+    },
+};
+use tokio::sync::Notify;
+
+use crate::{glob::glob_match, Resp};
+
+pub static PUBSUB: LazyLock<PubSub> = LazyLock::new(PubSub::new);
+
+type Subscribers = HashMap<Bytes, HashMap<u64, Arc<Mailbox>>>;
+
+#[derive(Debug)]
+pub struct PubSub {
+    channels: RwLock<Subscribers>,
+    patterns: RwLock<Subscribers>,
+    /// Messages a subscriber may have pending, no limit if zero
+    queue_len: AtomicUsize,
+    overflow: RwLock<Overflow>,
+}
+
+impl PubSub {
+    fn new() -> Self {
+        Self {
+            channels: RwLock::new(HashMap::new()),
+            patterns: RwLock::new(HashMap::new()),
+            queue_len: AtomicUsize::new(0),
+            overflow: RwLock::new(Overflow::Disconnect),
+        }
+    }
+
+    pub fn queue_len(&self) -> usize {
+        self.queue_len.load(Ordering::Relaxed)
+    }
+
+    pub fn set_queue_len(&self, len: usize) {
+        self.queue_len.store(len, Ordering::Relaxed);
+    }
+
+    pub fn overflow(&self) -> Overflow {
+        *self.overflow.read()
+    }
+
+    pub fn set_overflow(&self, overflow: Overflow) {
+        *self.overflow.write() = overflow;
+    }
+
+    /// Sends `message` to every subscriber of `channel` and of a pattern matching it,
+    /// returning how many clients received it.
+    pub fn publish(&self, channel: &Bytes, message: &Bytes) -> usize {
+        let (limit, overflow) = (self.queue_len(), self.overflow());
+        let mut receivers = 0;
+
+        if let Some(subscribers) = self.channels.read().get(channel) {
+            let resp = Resp::Array(vec![
+                Resp::bulk("message"),
+                Resp::Bulk(channel.clone()),
+                Resp::Bulk(message.clone()),
+            ]);
+            receivers += subscribers
+                .values()
+                .filter(|mailbox| mailbox.push(resp.clone(), limit, overflow))
+                .count();
+        }
+
+        for (pattern, subscribers) in &*self.patterns.read() {
+            if !glob_match(pattern, channel) {
+                continue;
+            }
+            let resp = Resp::Array(vec![
+                Resp::bulk("pmessage"),
+                Resp::Bulk(pattern.clone()),
+                Resp::Bulk(channel.clone()),
+                Resp::Bulk(message.clone()),
+            ]);
+            receivers += subscribers
+                .values()
+                .filter(|mailbox| mailbox.push(resp.clone(), limit, overflow))
+                .count();
+        }
+        receivers
+    }
+
+    /// Sends `resp` to client `id` only, returning whether it's subscribed to `channel`
+    pub(crate) fn send_to(&self, channel: &[u8], id: u64, resp: Resp) -> bool {
+        self.channels
+            .read()
+            .get(channel)
+            .and_then(|subscribers| subscribers.get(&id))
+            .is_some_and(|mailbox| mailbox.push(resp, self.queue_len(), self.overflow()))
+    }
+
+    /// Channels with at least one subscriber, optionally filtered by a glob pattern
+    pub fn channels(&self, pattern: Option<&Bytes>) -> Vec<Bytes> {
+        self.channels
+            .read()
+            .keys()
+            .filter(|channel| pattern.is_none_or(|pattern| glob_match(pattern, channel)))
+            .cloned()
+            .collect()
+    }
+
+    pub fn numsub(&self, channel: &Bytes) -> usize {
+        self.channels.read().get(channel).map_or(0, HashMap::len)
+    }
+
+    /// Number of distinct patterns subscribed to by any client
+    pub fn numpat(&self) -> usize {
+        self.patterns.read().len()
+    }
+
+    fn add(subscribers: &RwLock<Subscribers>, name: Bytes, subscriber: &Subscriber) {
+        subscribers
+            .write()
+            .entry(name)
+            .or_default()
+            .insert(subscriber.id, Arc::clone(&subscriber.mailbox));
+    }
+
+    fn remove(subscribers: &RwLock<Subscribers>, name: &Bytes, id: u64) {
+        let mut lock = subscribers.write();
+        if let Some(clients) = lock.get_mut(name) {
+            clients.remove(&id);
+            if clients.is_empty() {
+                lock.remove(name);
+            }
+        }
+    }
+}
+
+/// `pubsub-overflow`: what happens to a subscriber whose queue is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    /// Its oldest pending message is dropped for the new one
+    DropOldest,
+    /// It's disconnected, like Redis does past `client-output-buffer-limit pubsub`
+    Disconnect,
+}
+
+impl FromStr for Overflow {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_ascii_lowercase().as_str() {
+            "drop-oldest" => Self::DropOldest,
+            "disconnect" => Self::Disconnect,
+            _ => bail!("argument(s) must be one of the following: drop-oldest, disconnect"),
+        })
+    }
+}
+
+impl Display for Overflow {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::DropOldest => "drop-oldest",
+            Self::Disconnect => "disconnect",
+        })
+    }
+}
+
+/// Messages published to a subscriber and not yet written to its connection,
+/// bounded by `pubsub-queue-len` so that a slow subscriber can't make them pile up
+#[derive(Debug, Default)]
+struct Mailbox {
+    messages: Mutex<VecDeque<Resp>>,
+    /// Set once the subscriber is to be disconnected, nothing is delivered to it anymore
+    overflowed: AtomicBool,
+    notify: Notify,
+}
+
+impl Mailbox {
+    /// Queues `resp` without waiting for the subscriber, returning whether it was queued.
+    /// `overflow` applies once `limit` messages are pending, if it's not zero.
+    fn push(&self, resp: Resp, limit: usize, overflow: Overflow) -> bool {
+        if self.overflowed.load(Ordering::Relaxed) {
+            return false;
+        }
+        let mut messages = self.messages.lock();
+        if limit > 0 && messages.len() >= limit {
+            match overflow {
+                Overflow::DropOldest => {
+                    let excess = messages.len() - limit;
+                    messages.drain(..=excess);
+                }
+                Overflow::Disconnect => {
+                    messages.clear();
+                    self.overflowed.store(true, Ordering::Relaxed);
+                    self.notify.notify_one();
+                    return false;
+                }
+            }
+        }
+        messages.push_back(resp);
+        drop(messages);
+        self.notify.notify_one();
+        true
+    }
+
+    /// The next message, or `None` once the subscriber overflowed its queue
+    async fn pop(&self) -> Option<Resp> {
+        loop {
+            if self.overflowed.load(Ordering::Relaxed) {
+                return None;
+            }
+            let next = self.messages.lock().pop_front();
+            if next.is_some() {
+                return next;
+            }
+            self.notify.notified().await;
+        }
+    }
+}
+
+/// Subscription state of a single connection. Dropping it removes every subscription.
+#[derive(Debug)]
+pub struct Subscriber {
+    id: u64,
+    mailbox: Arc<Mailbox>,
+    channels: HashSet<Bytes>,
+    patterns: HashSet<Bytes>,
+}
+
+impl Subscriber {
+    pub(crate) fn new(id: u64) -> Self {
+        Self {
+            id,
+            mailbox: Arc::default(),
+            channels: HashSet::new(),
+            patterns: HashSet::new(),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn count(&self) -> usize {
+        self.channels.len() + self.patterns.len()
+    }
+
+    /// Number of channel and pattern subscriptions
+    #[inline]
+    pub(crate) fn counts(&self) -> (usize, usize) {
+        (self.channels.len(), self.patterns.len())
+    }
+
+    /// The next message published to it, or `None` once it must be disconnected
+    /// for not keeping up with them
+    pub(crate) async fn recv(&self) -> Option<Resp> {
+        self.mailbox.pop().await
+    }
+
+    /// Resolves once it must be disconnected for not keeping up with its messages
+    pub(crate) async fn lagged(&self) {
+        while !self.mailbox.overflowed.load(Ordering::Relaxed) {
+            self.mailbox.notify.notified().await;
+        }
+    }
+
+    pub(crate) fn subscribe(&mut self, channel: Bytes, pattern: bool) {
+        let (set, subscribers) = self.sets(pattern);
+        if set.insert(channel.clone()) {
+            PubSub::add(subscribers, channel, self);
+        }
+    }
+
+    pub(crate) fn unsubscribe(&mut self, channel: &Bytes, pattern: bool) {
+        let id = self.id;
+        let (set, subscribers) = self.sets(pattern);
+        if set.remove(channel) {
+            PubSub::remove(subscribers, channel, id);
+        }
+    }
+
+    /// Current subscriptions of one kind, sorted for a deterministic reply order
+    pub(crate) fn subscriptions(&self, pattern: bool) -> Vec<Bytes> {
+        let set = if pattern {
+            &self.patterns
+        } else {
+            &self.channels
+        };
+        let mut subscriptions = set.iter().cloned().collect::<Vec<_>>();
+        subscriptions.sort_unstable();
+        subscriptions
+    }
+
+    fn sets(&mut self, pattern: bool) -> (&mut HashSet<Bytes>, &'static RwLock<Subscribers>) {
+        if pattern {
+            (&mut self.patterns, &PUBSUB.patterns)
+        } else {
+            (&mut self.channels, &PUBSUB.channels)
+        }
+    }
+}
+
+impl Drop for Subscriber {
+    fn drop(&mut self) {
+        for channel in &self.channels {
+            PubSub::remove(&PUBSUB.channels, channel, self.id);
+        }
+        for pattern in &self.patterns {
+            PubSub::remove(&PUBSUB.patterns, pattern, self.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn subscribe_publish() {
+        let news = Bytes::from_static(b"pubsub-news");
+        let sports = Bytes::from_static(b"pubsub-sports");
+        let mut subscriber = Subscriber::new(1);
+        subscriber.subscribe(news.clone(), false);
+        subscriber.subscribe(sports.clone(), false);
+        subscriber.subscribe(Bytes::from_static(b"pubsub-n*"), true);
+        assert_eq!(subscriber.count(), 3);
+
+        let mut channels = PUBSUB.channels(Some(&Bytes::from_static(b"pubsub-*")));
+        channels.sort_unstable();
+        assert_eq!(channels, [news.clone(), sports.clone()]);
+        assert_eq!(PUBSUB.numsub(&news), 1);
+        assert_eq!(PUBSUB.numsub(&Bytes::from_static(b"pubsub-weather")), 0);
+
+        // Once for the channel and once for the pattern
+        assert_eq!(PUBSUB.publish(&news, &Bytes::from_static(b"hello")), 2);
+        assert_eq!(
+            subscriber.recv().await,
+            Some(Resp::Array(vec![
+                Resp::bulk("message"),
+                Resp::Bulk(news.clone()),
+                Resp::bulk("hello"),
+            ]))
+        );
+        assert_eq!(
+            subscriber.recv().await,
+            Some(Resp::Array(vec![
+                Resp::bulk("pmessage"),
+                Resp::bulk("pubsub-n*"),
+                Resp::Bulk(news.clone()),
+                Resp::bulk("hello"),
+            ]))
+        );
+
+        subscriber.unsubscribe(&news, false);
+        assert_eq!(PUBSUB.numsub(&news), 0);
+        assert_eq!(PUBSUB.publish(&news, &Bytes::from_static(b"hello")), 1);
+
+        // Dropping the connection's state leaves no subscription behind
+        drop(subscriber);
+        assert_eq!(PUBSUB.numsub(&sports), 0);
+        assert_eq!(PUBSUB.publish(&news, &Bytes::from_static(b"hello")), 0);
+    }
+
+    #[tokio::test]
+    async fn overflow() {
+        let message = |n: i64| Resp::Integer(n);
+
+        let mailbox = Mailbox::default();
+        for n in 0..5 {
+            assert!(mailbox.push(message(n), 3, Overflow::DropOldest));
+        }
+        for n in 2..5 {
+            pretty_assertions::assert_eq!(mailbox.pop().await, Some(message(n)));
+        }
+        // Unbounded
+        for n in 0..5 {
+            assert!(mailbox.push(message(n), 0, Overflow::Disconnect));
+        }
+        pretty_assertions::assert_eq!(mailbox.messages.lock().len(), 5);
+
+        let mailbox = Mailbox::default();
+        for n in 0..3 {
+            assert!(mailbox.push(message(n), 3, Overflow::Disconnect));
+        }
+        assert!(!mailbox.push(message(3), 3, Overflow::Disconnect));
+        // Not even the messages queued before are delivered
+        pretty_assertions::assert_eq!(mailbox.pop().await, None);
+        assert!(!mailbox.push(message(4), 0, Overflow::DropOldest));
+    }
+}
@@ -1,51 +1,108 @@
-use anyhow::ensure;
+use anyhow::{bail, ensure, Context};
 use bytes::{Buf, Bytes};
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap, VecDeque},
     fmt::Debug,
+    io::{BufRead, Cursor},
     ops::{BitAnd, BitOr, Shr},
     str::from_utf8 as str_utf8,
     time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use crate::{db::{Type, Value}, slice_to_int};
+use crate::{
+    db::{
+        stream::{Consumer, ConsumerGroup, EntryId, PendingEntry},
+        Hash, List, SortedSet, Stream, Type, Value,
+    },
+    slice_to_int,
+};
+
+pub mod listpack;
+pub use listpack::ListpackEntry;
+
+pub mod intset;
+mod lzf;
+mod ziplist;
+mod zipmap;
+
+mod crc64;
+
+mod reader;
+use reader::Reader;
+
+mod writer;
+pub use writer::write;
 
 #[derive(Debug)]
 #[allow(dead_code)]
 pub struct Rdb {
     version: u32,
     aux_fields: AuxFields,
+    /// Code of the function libraries
+    pub(crate) functions: Vec<Bytes>,
     pub(crate) db: Db,
     checksum: Bytes,
 }
 
 impl Rdb {
     // https://rdb.fnordig.de/file_format.html
-    pub fn parse(mut bytes: Bytes) -> anyhow::Result<Self> {
-        tracing::trace!("Parsing rdb: {bytes:?}");
+    /// Parses a RDB image, rejecting it on a CRC64 mismatch when `verify` is set.
+    /// A zero checksum means the writer didn't compute one and is never checked.
+    pub fn parse(bytes: Bytes, verify: bool) -> anyhow::Result<Self> {
+        Self::read(Cursor::new(bytes), verify)
+    }
+
+    /// Like [`Rdb::parse`], reading the image as it's parsed so it's never
+    /// entirely in memory. The image must span the rest of `reader`.
+    pub fn read(reader: impl BufRead, verify: bool) -> anyhow::Result<Self> {
+        let mut reader = Reader::new(reader);
+        let rdb = Self::parse_image(&mut reader, verify)?;
+        // FIXME test adds \n ?
+        if reader.peek()? == Some(b'\n') {
+            reader.get_u8()?;
+        }
+        ensure!(reader.peek()?.is_none(), "Unexpected data after the RDB");
+        Ok(rdb)
+    }
 
-        ensure!(&*bytes.split_to(5) == b"REDIS", "Expected magic string");
+    /// Parses a RDB image at the start of `bytes`, leaving what follows it,
+    /// like the commands of an AOF with a RDB preamble
+    pub fn parse_prefix(bytes: &mut Bytes, verify: bool) -> anyhow::Result<Self> {
+        let mut reader = Reader::new(bytes.as_ref());
+        let rdb = Self::parse_image(&mut reader, verify)?;
+        bytes.advance(reader.position().try_into()?);
+        Ok(rdb)
+    }
 
-        let version = slice_to_int::<u32>(&bytes.split_to(4))?;
+    fn parse_image(reader: &mut Reader<impl BufRead>, verify: bool) -> anyhow::Result<Self> {
+        ensure!(&*reader.split_to(5)? == b"REDIS", "Expected magic string");
+
+        let version = slice_to_int::<u32>(&reader.split_to(4)?)?;
         tracing::debug!("Parsed version: {version:?}");
 
-        let aux_fields = AuxFields::parse(&mut bytes);
+        let aux_fields = AuxFields::parse(reader)?;
         tracing::debug!("Parsed aux_fields: {aux_fields:#?}");
 
-        let db = Db::parse(&mut bytes)?;
+        let functions = Self::parse_functions(reader)?;
+
+        let db = Db::parse(reader)?;
         tracing::debug!("Parsed db: {db:#?}");
 
-        ensure!(bytes.get_u8() == 0xff, "End of RDB");
-        let checksum = bytes.split_to(8);
-        // FIXME test adds \n ?
-        if bytes.remaining() == 1 && bytes[0] == b'\n' {
-            bytes.advance(1);
+        ensure!(reader.get_u8()? == 0xff, "End of RDB");
+        let actual = reader.crc();
+        let checksum = reader.split_to(8).context("Truncated RDB checksum")?;
+        let expected = u64::from_le_bytes(checksum[..].try_into()?);
+        if verify && expected != 0 {
+            ensure!(
+                actual == expected,
+                "Wrong RDB checksum expected: ({expected:#x}) got: ({actual:#x})"
+            );
         }
-        ensure!(bytes.is_empty());
 
         let rdb = Self {
             version,
             aux_fields,
+            functions,
             db,
             checksum,
         };
@@ -53,21 +110,79 @@ impl Rdb {
         Ok(rdb)
     }
 
-    fn parse_string(string: &mut Bytes) -> Bytes {
-        tracing::trace!("Parsing string: {string:?}");
+    const FUNCTION: u8 = 0xF5;
 
-        let (len, encoded) = Self::parse_len(string);
-        let string = if encoded {
-            Self::parse_int_str(string, len)
-        } else {
-            string.split_to(len as usize)
+    fn parse_functions(reader: &mut Reader<impl BufRead>) -> anyhow::Result<Vec<Bytes>> {
+        let mut functions = Vec::new();
+        while reader.peek()? == Some(Self::FUNCTION) {
+            reader.get_u8()?;
+            functions.push(Self::parse_string(reader)?);
+        }
+        Ok(functions)
+    }
+
+    /// Parses a FUNCTION DUMP payload, see [`Rdb::dump_functions`]
+    #[cfg_attr(not(feature = "scripting"), allow(dead_code))]
+    pub(crate) fn restore_functions(mut payload: Bytes) -> anyhow::Result<Vec<Bytes>> {
+        const TRAILER_LEN: usize = 2 + 8;
+        let valid = payload.len() >= TRAILER_LEN && {
+            let body = payload.len() - 8;
+            let checksum = u64::from_le_bytes(payload[body..].try_into()?);
+            let version = u16::from_le_bytes(payload[body - 2..body].try_into()?);
+            version <= Self::VERSION_NUMBER && crc64::crc64(0, &payload[..body]) == checksum
+        };
+        ensure!(valid, "ERR payload version or checksum are wrong");
+        payload.truncate(payload.len() - TRAILER_LEN);
+        let mut reader = Reader::new(payload.as_ref());
+        let functions = Self::parse_functions(&mut reader)?;
+        ensure!(
+            reader.peek()?.is_none(),
+            "ERR given payload is not a valid function dump"
+        );
+        Ok(functions)
+    }
+
+    /// Replication id and offset of the dataset, if the dump recorded them
+    pub fn repl_info(&self) -> Option<ReplInfo> {
+        self.aux_fields.repl_info()
+    }
+
+    #[inline]
+    #[must_use]
+    pub const fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// Whether the writer computed a checksum, which a zero one means it didn't
+    #[must_use]
+    pub fn checksummed(&self) -> bool {
+        self.checksum.iter().any(|&b| b != 0)
+    }
+
+    fn parse_string(reader: &mut Reader<impl BufRead>) -> anyhow::Result<Bytes> {
+        let (len, encoded) = Self::parse_len(reader)?;
+        let string = match (encoded, len) {
+            (true, Self::ENC_LZF) => Self::parse_lzf(reader)?,
+            (true, _) => Self::parse_int_str(reader, len)?,
+            (false, _) => reader.split_to(len as usize).context("Truncated string")?,
         };
         tracing::trace!("Parsed string: {string:?}");
-        string
+        Ok(string)
+    }
+
+    const ENC_LZF: u32 = 3;
+
+    fn parse_lzf(reader: &mut Reader<impl BufRead>) -> anyhow::Result<Bytes> {
+        let compressed = Self::parse_len_u64(reader)?.try_into()?;
+        let len = Self::parse_len_u64(reader)?.try_into()?;
+        let compressed = reader
+            .split_to(compressed)
+            .context("Truncated LZF string")?;
+        lzf::decompress(&compressed, len)
     }
 
-    fn parse_len(bytes: &mut Bytes) -> (u32, bool) {
-        let encoding = bytes.get_u8();
+    fn parse_len(reader: &mut Reader<impl BufRead>) -> anyhow::Result<(u32, bool)> {
+        let encoding = reader.get_u8()?;
         // 0b1100_0000 -> 0b______11
         let first_2_bits = encoding.shr(6_u8);
         tracing::trace!("length encoding first 2 bits: {first_2_bits:02b}");
@@ -76,10 +191,10 @@ impl Rdb {
         let len = match first_2_bits {
             0b00 => u32::from(encoding.bitand(0b00_111_111)),
             0b01 => {
-                let byte = bytes.get_u8();
-                u32::from(encoding.bitand(0b00_111_111).bitor(byte))
+                let byte = reader.get_u8()?;
+                (u32::from(encoding.bitand(0b00_111_111)) << 8).bitor(u32::from(byte))
             }
-            0b10 => bytes.get_u32(),
+            0b10 => reader.get_u32()?,
             0b11 => {
                 is_encoded = true;
                 u32::from(encoding.bitand(0b00_111_111))
@@ -88,17 +203,38 @@ impl Rdb {
         };
 
         tracing::trace!("Parsed len: {len}");
-        (len, is_encoded)
+        Ok((len, is_encoded))
     }
 
-    fn parse_int_str(str: &mut Bytes, fmt: u32) -> Bytes {
-        match fmt {
-            0 => str.get_i8().to_string().into(),
-            1 => str.get_i16_le().to_string().into(),
-            2 => str.get_i32_le().to_string().into(),
-            3 => todo!("lzf"),
-            _ => unreachable!(),
+    /// Like [`Self::parse_len`], also accepting the 64 bit length encoding
+    fn parse_len_u64(reader: &mut Reader<impl BufRead>) -> anyhow::Result<u64> {
+        const LEN_64BIT: u8 = 0x81;
+
+        if reader.peek()? == Some(LEN_64BIT) {
+            reader.get_u8()?;
+            return reader.get_u64();
         }
+        Ok(u64::from(Self::parse_len(reader)?.0))
+    }
+
+    /// Doubles of the old ZSET type: a length byte followed by the ASCII value,
+    /// with lengths 253, 254 and 255 standing for NaN, +inf and -inf
+    fn parse_double_str(reader: &mut Reader<impl BufRead>) -> anyhow::Result<f64> {
+        Ok(match reader.get_u8()? {
+            253 => f64::NAN,
+            254 => f64::INFINITY,
+            255 => f64::NEG_INFINITY,
+            len => str_utf8(&reader.split_to(len.into())?)?.parse()?,
+        })
+    }
+
+    fn parse_int_str(reader: &mut Reader<impl BufRead>, fmt: u32) -> anyhow::Result<Bytes> {
+        Ok(match fmt {
+            0 => reader.get_i8()?.to_string().into(),
+            1 => reader.get_i16_le()?.to_string().into(),
+            2 => reader.get_i32_le()?.to_string().into(),
+            _ => bail!("Unknown string encoding: {fmt}"),
+        })
     }
 }
 
@@ -111,27 +247,39 @@ pub struct AuxFields {
     ctime: Option<Bytes>,
     used_mem: Option<Bytes>,
     aof_base: Option<Bytes>,
+    repl_id: Option<Bytes>,
+    repl_offset: Option<Bytes>,
+}
+
+/// Replication id and offset a dump was taken at, stored in the
+/// `repl-id` and `repl-offset` aux fields
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplInfo {
+    pub id: String,
+    pub offset: u64,
 }
 
 impl AuxFields {
     const AUX_FIELDS: u8 = 0xfa;
 
-    fn parse(bytes: &mut Bytes) -> Self {
+    fn parse(reader: &mut Reader<impl BufRead>) -> anyhow::Result<Self> {
         let mut redis_ver = None;
         let mut redis_bits = None;
         let mut ctime = None;
         let mut used_mem = None;
         let mut aof_base = None;
+        let mut repl_id = None;
+        let mut repl_offset = None;
 
         loop {
-            if bytes.chunk()[0] != Self::AUX_FIELDS {
+            if reader.peek()? != Some(Self::AUX_FIELDS) {
                 break;
             }
-            bytes.advance(1);
+            reader.get_u8()?;
 
             let (key, value) = {
-                let key = Rdb::parse_string(bytes);
-                let value = Rdb::parse_string(bytes);
+                let key = Rdb::parse_string(reader)?;
+                let value = Rdb::parse_string(reader)?;
                 (key, value)
             };
 
@@ -141,19 +289,29 @@ impl AuxFields {
                 b"ctime" => ctime.insert(value),
                 b"used-mem" => used_mem.insert(value),
                 b"aof-base" => aof_base.insert(value),
+                b"repl-id" => repl_id.insert(value),
+                b"repl-offset" => repl_offset.insert(value),
                 _ => {
                     tracing::info!("Unknown aux field: {key:?}");
                     continue;
                 }
             };
         }
-        Self {
+        Ok(Self {
             redis_ver,
             redis_bits,
             ctime,
             used_mem,
             aof_base,
-        }
+            repl_id,
+            repl_offset,
+        })
+    }
+
+    fn repl_info(&self) -> Option<ReplInfo> {
+        let id = str_utf8(self.repl_id.as_deref()?).ok()?.to_owned();
+        let offset = slice_to_int(self.repl_offset.as_deref()?).ok()?;
+        Some(ReplInfo { id, offset })
     }
 }
 
@@ -169,51 +327,51 @@ impl Db {
     const EXPIRE_S: u8 = 0xFD;
     const EXPIRE_MS: u8 = 0xFC;
 
-    fn parse(bytes: &mut Bytes) -> anyhow::Result<Self> {
+    fn parse(reader: &mut Reader<impl BufRead>) -> anyhow::Result<Self> {
         let mut maps = Vec::new();
         loop {
-            if bytes.chunk()[0] != Self::DB_SELECTOR {
+            if reader.peek()? != Some(Self::DB_SELECTOR) {
                 break;
             }
-            bytes.advance(1);
+            reader.get_u8()?;
 
-            let (_db_num, _) = Rdb::parse_len(bytes);
+            let (_db_num, _) = Rdb::parse_len(reader)?;
 
-            ensure!(bytes.get_u8() == Self::RESIZEDB);
+            ensure!(reader.get_u8()? == Self::RESIZEDB);
 
-            let (db_size, _exp_size) = Self::parse_size(bytes);
+            let (db_size, _exp_size) = Self::parse_size(reader)?;
             let map = (0..db_size)
-                .map(|_| Self::parse_entry(bytes))
+                .map(|_| Self::parse_entry(reader))
                 .collect::<anyhow::Result<HashMap<_, _>>>()?;
             maps.push(map);
         }
         Ok(Self { maps })
     }
 
-    fn parse_size(bytes: &mut Bytes) -> (u32, u32) {
-        let (db, _) = Rdb::parse_len(bytes);
+    fn parse_size(reader: &mut Reader<impl BufRead>) -> anyhow::Result<(u32, u32)> {
+        let (db, _) = Rdb::parse_len(reader)?;
         tracing::trace!("db size: {db:?}");
-        let (exp, _) = Rdb::parse_len(bytes);
+        let (exp, _) = Rdb::parse_len(reader)?;
         tracing::trace!("expiry size: {exp:?}");
-        (db, exp)
+        Ok((db, exp))
     }
 
-    fn parse_entry(bytes: &mut Bytes) -> anyhow::Result<(String, Value)> {
+    fn parse_entry(reader: &mut Reader<impl BufRead>) -> anyhow::Result<(String, Value)> {
         let expiration: Option<SystemTime>;
         let flag: u8;
 
-        match bytes.get_u8() {
+        match reader.get_u8()? {
             Self::EXPIRE_S => {
-                let dur = u64::from(bytes.get_u32());
+                let dur = u64::from(reader.get_u32()?);
                 let time = UNIX_EPOCH + Duration::from_secs(dur);
                 expiration = Some(time);
-                flag = bytes.get_u8();
+                flag = reader.get_u8()?;
             }
             Self::EXPIRE_MS => {
-                let dur = bytes.get_u64_le();
+                let dur = reader.get_u64_le()?;
                 let time = UNIX_EPOCH + Duration::from_millis(dur);
                 expiration = Some(time);
-                flag = bytes.get_u8();
+                flag = reader.get_u8()?;
             }
             b => {
                 expiration = None;
@@ -223,11 +381,11 @@ impl Db {
 
         let (key, value) = {
             let key = {
-                let string = Rdb::parse_string(bytes);
+                let string = Rdb::parse_string(reader)?;
                 str_utf8(&string)?.to_owned()
             };
             let value = {
-                let v_type = Type::parse(bytes, flag);
+                let v_type = Type::parse(reader, flag)?;
                 Value { v_type, expiration }
             };
             (key, value)
@@ -238,14 +396,301 @@ impl Db {
 }
 
 impl Type {
-    fn parse(bytes: &mut Bytes, flag: u8) -> Self {
-        match flag {
-            0 => {
-                let string = Rdb::parse_string(bytes);
-                Self::String(string)
+    const STRING: u8 = 0;
+    const LIST: u8 = 1;
+    const SET: u8 = 2;
+    const ZSET: u8 = 3;
+    const HASH: u8 = 4;
+    const ZSET_2: u8 = 5;
+    const HASH_ZIPMAP: u8 = 9;
+    const LIST_ZIPLIST: u8 = 10;
+    const SET_INTSET: u8 = 11;
+    const ZSET_ZIPLIST: u8 = 12;
+    const HASH_ZIPLIST: u8 = 13;
+    const LIST_QUICKLIST: u8 = 14;
+    const STREAM_LISTPACKS: u8 = 15;
+    const HASH_LISTPACK: u8 = 16;
+    const ZSET_LISTPACK: u8 = 17;
+    const LIST_QUICKLIST_2: u8 = 18;
+    const SET_LISTPACK: u8 = 20;
+    const STREAM_LISTPACKS_2: u8 = 19;
+    const STREAM_LISTPACKS_3: u8 = 21;
+    const HASH_METADATA: u8 = 24;
+    const HASH_LISTPACK_EX: u8 = 25;
+
+    fn parse(reader: &mut Reader<impl BufRead>, flag: u8) -> anyhow::Result<Self> {
+        Ok(match flag {
+            Self::STRING => Self::String(Rdb::parse_string(reader)?.into()),
+            Self::LIST => {
+                let len = Rdb::parse_len_u64(reader)?;
+                Self::List(
+                    (0..len)
+                        .map(|_| Rdb::parse_string(reader))
+                        .collect::<anyhow::Result<_>>()?,
+                )
+            }
+            Self::SET => {
+                let len = Rdb::parse_len_u64(reader)?;
+                Self::Set(
+                    (0..len)
+                        .map(|_| Rdb::parse_string(reader))
+                        .collect::<anyhow::Result<_>>()?,
+                )
+            }
+            Self::ZSET | Self::ZSET_2 => {
+                let len = Rdb::parse_len_u64(reader)?;
+                let zset = (0..len)
+                    .map(|_| {
+                        let member = Rdb::parse_string(reader)?;
+                        let score = if flag == Self::ZSET {
+                            Rdb::parse_double_str(reader)?
+                        } else {
+                            reader.get_f64_le()?
+                        };
+                        Ok((member, score))
+                    })
+                    .collect::<anyhow::Result<_>>()?;
+                Self::SortedSet(zset)
+            }
+            Self::HASH => {
+                let len = Rdb::parse_len_u64(reader)?;
+                let hash = (0..len)
+                    .map(|_| Ok((Rdb::parse_string(reader)?, Rdb::parse_string(reader)?)))
+                    .collect::<anyhow::Result<_>>()?;
+                Self::Hash(hash)
+            }
+            Self::HASH_ZIPMAP => {
+                let pairs = zipmap::parse(Rdb::parse_string(reader)?)?;
+                Self::Hash(pairs.into_iter().collect())
+            }
+            Self::LIST_ZIPLIST => {
+                let entries = ziplist::parse(Rdb::parse_string(reader)?)?;
+                Self::List(entries.iter().map(ListpackEntry::to_bytes).collect())
+            }
+            Self::SET_INTSET => {
+                let ints = intset::parse(Rdb::parse_string(reader)?)?;
+                Self::Set(ints.into_iter().map(|x| x.to_string().into()).collect())
+            }
+            Self::SET_LISTPACK => {
+                let entries = listpack::parse(Rdb::parse_string(reader)?)?;
+                Self::Set(entries.iter().map(ListpackEntry::to_bytes).collect())
+            }
+            Self::ZSET_ZIPLIST | Self::ZSET_LISTPACK => {
+                let packed = Rdb::parse_string(reader)?;
+                let entries = if flag == Self::ZSET_ZIPLIST {
+                    ziplist::parse(packed)?
+                } else {
+                    listpack::parse(packed)?
+                };
+                Self::SortedSet(Self::parse_zset_pairs(&entries)?)
+            }
+            Self::HASH_ZIPLIST | Self::HASH_LISTPACK => {
+                let packed = Rdb::parse_string(reader)?;
+                let entries = if flag == Self::HASH_ZIPLIST {
+                    ziplist::parse(packed)?
+                } else {
+                    listpack::parse(packed)?
+                };
+                ensure!(
+                    entries.len().is_multiple_of(2),
+                    "Hash with a field without value"
+                );
+                let hash = entries
+                    .chunks_exact(2)
+                    .map(|x| (x[0].to_bytes(), x[1].to_bytes()))
+                    .collect();
+                Self::Hash(hash)
+            }
+            Self::HASH_METADATA | Self::HASH_LISTPACK_EX => {
+                Self::Hash(Self::parse_hash_metadata(reader, flag)?)
+            }
+            Self::LIST_QUICKLIST | Self::LIST_QUICKLIST_2 => {
+                Self::List(Self::parse_quicklist(reader, flag)?)
+            }
+            Self::STREAM_LISTPACKS | Self::STREAM_LISTPACKS_2 | Self::STREAM_LISTPACKS_3 => {
+                Self::Stream(Stream::parse(reader, flag)?)
+            }
+            _ => bail!("Unsupported rdb value type: {flag}"),
+        })
+    }
+}
+
+impl Type {
+    /// Hashes with field expirations, in Unix milliseconds or 0 for none.
+    /// In a listpack they follow each field and value, otherwise they precede them
+    /// as offsets from the earliest one, plus one.
+    fn parse_hash_metadata(reader: &mut Reader<impl BufRead>, flag: u8) -> anyhow::Result<Hash> {
+        let (mut fields, mut expires) = (Vec::new(), HashMap::new());
+        let min_expire = reader.get_u64_le()?;
+        let mut insert = |field: Bytes, value: Bytes, at: u64| {
+            if at != 0 {
+                let at = UNIX_EPOCH + Duration::from_millis(at);
+                expires.insert(field.clone(), at);
+            }
+            fields.push((field, value));
+        };
+
+        if flag == Self::HASH_LISTPACK_EX {
+            let entries = listpack::parse(Rdb::parse_string(reader)?)?;
+            ensure!(
+                entries.len().is_multiple_of(3),
+                "Hash field without value or expiration"
+            );
+            for x in entries.chunks_exact(3) {
+                let at = u64::try_from(x[2].to_int()?)?;
+                insert(x[0].to_bytes(), x[1].to_bytes(), at);
+            }
+        } else {
+            for _ in 0..Rdb::parse_len_u64(reader)? {
+                let ttl = Rdb::parse_len_u64(reader)?;
+                let field = Rdb::parse_string(reader)?;
+                let value = Rdb::parse_string(reader)?;
+                let at = match ttl {
+                    0 => 0,
+                    ttl => min_expire
+                        .checked_add(ttl - 1)
+                        .context("Invalid hash field expiration")?,
+                };
+                insert(field, value, at);
+            }
+        }
+        let mut hash = fields.into_iter().collect::<Hash>();
+        hash.expires = expires;
+        Ok(hash)
+    }
+
+    /// Quicklist nodes are ziplists, or for the second version
+    /// listpacks and plain elements too big to be packed
+    fn parse_quicklist(reader: &mut Reader<impl BufRead>, flag: u8) -> anyhow::Result<List> {
+        const PLAIN: u64 = 1;
+        const PACKED: u64 = 2;
+
+        let mut list = VecDeque::new();
+        for _ in 0..Rdb::parse_len_u64(reader)? {
+            if flag == Self::LIST_QUICKLIST {
+                let entries = ziplist::parse(Rdb::parse_string(reader)?)?;
+                list.extend(entries.iter().map(ListpackEntry::to_bytes));
+                continue;
+            }
+            match Rdb::parse_len_u64(reader)? {
+                PLAIN => list.push_back(Rdb::parse_string(reader)?),
+                PACKED => {
+                    let entries = listpack::parse(Rdb::parse_string(reader)?)?;
+                    list.extend(entries.iter().map(ListpackEntry::to_bytes));
+                }
+                container => bail!("Invalid quicklist container: {container}"),
+            }
+        }
+        Ok(List::Quicklist(list))
+    }
+
+    /// Packed sorted sets alternate members and scores
+    fn parse_zset_pairs(entries: &[ListpackEntry]) -> anyhow::Result<SortedSet> {
+        ensure!(
+            entries.len().is_multiple_of(2),
+            "Sorted set member without score"
+        );
+        entries
+            .chunks_exact(2)
+            .map(|x| {
+                let score = match &x[1] {
+                    #[allow(clippy::cast_precision_loss)]
+                    ListpackEntry::Int(int) => *int as f64,
+                    ListpackEntry::Str(str) => str_utf8(str)?.parse()?,
+                };
+                Ok((x[0].to_bytes(), score))
+            })
+            .collect()
+    }
+}
+
+impl Stream {
+    // https://github.com/redis/redis/blob/unstable/src/rdb.c rdbLoadObject
+    fn parse(reader: &mut Reader<impl BufRead>, flag: u8) -> anyhow::Result<Self> {
+        let mut stream = Self::new();
+
+        let listpacks = Rdb::parse_len_u64(reader)?;
+        for _ in 0..listpacks {
+            let master = {
+                let key = Rdb::parse_string(reader)?;
+                ensure!(key.len() == 16, "Invalid stream node key");
+                Self::parse_raw_id(&mut Reader::new(key.as_ref()))?
+            };
+            stream.inner.push_node(master, Rdb::parse_string(reader)?)?;
+        }
+
+        let _len = Rdb::parse_len_u64(reader)?;
+        stream.last_id = Self::parse_id(reader)?;
+        if flag >= Type::STREAM_LISTPACKS_2 {
+            let _first_id = Self::parse_id(reader)?;
+            stream.max_deleted_id = Self::parse_id(reader)?;
+            stream.entries_added = Rdb::parse_len_u64(reader)?;
+        } else {
+            stream.entries_added = stream.inner.len() as u64;
+        }
+
+        let groups = Rdb::parse_len_u64(reader)?;
+        for _ in 0..groups {
+            let name = String::from_utf8(Rdb::parse_string(reader)?.to_vec())?;
+            let mut group = ConsumerGroup {
+                last_delivered: Self::parse_id(reader)?,
+                entries_read: 0,
+                pending: BTreeMap::new(),
+                consumers: HashMap::new(),
+            };
+            if flag >= Type::STREAM_LISTPACKS_2 {
+                group.entries_read = Rdb::parse_len_u64(reader)?;
             }
-            _ => unimplemented!("flag: {flag}"),
+
+            let pending = Rdb::parse_len_u64(reader)?;
+            for _ in 0..pending {
+                let id = Self::parse_raw_id(reader)?;
+                let delivery_time = Self::parse_ms_time(reader)?;
+                let delivery_count = Rdb::parse_len_u64(reader)?;
+                let entry = PendingEntry {
+                    consumer: String::new(),
+                    delivery_time,
+                    delivery_count,
+                };
+                group.pending.insert(id, entry);
+            }
+
+            let consumers = Rdb::parse_len_u64(reader)?;
+            for _ in 0..consumers {
+                let name = String::from_utf8(Rdb::parse_string(reader)?.to_vec())?;
+                let seen_time = Self::parse_ms_time(reader)?;
+                if flag >= Type::STREAM_LISTPACKS_3 {
+                    let _active_time = Self::parse_ms_time(reader)?;
+                }
+                let owned = Rdb::parse_len_u64(reader)?;
+                for _ in 0..owned {
+                    let id = Self::parse_raw_id(reader)?;
+                    let Some(entry) = group.pending.get_mut(&id) else {
+                        bail!("Consumer \"{name}\" owns {id} missing from the group PEL");
+                    };
+                    entry.consumer.clone_from(&name);
+                }
+                group.consumers.insert(name, Consumer { seen_time });
+            }
+            stream.groups.insert(name, group);
         }
+        Ok(stream)
+    }
+
+    fn parse_id(reader: &mut Reader<impl BufRead>) -> anyhow::Result<EntryId> {
+        let ms_time = Rdb::parse_len_u64(reader)?;
+        let sq_num = Rdb::parse_len_u64(reader)?;
+        Ok(EntryId::new(Duration::from_millis(ms_time), sq_num))
+    }
+
+    fn parse_raw_id(reader: &mut Reader<impl BufRead>) -> anyhow::Result<EntryId> {
+        let ms_time = reader.get_u64()?;
+        let sq_num = reader.get_u64()?;
+        Ok(EntryId::new(Duration::from_millis(ms_time), sq_num))
+    }
+
+    fn parse_ms_time(reader: &mut Reader<impl BufRead>) -> anyhow::Result<SystemTime> {
+        Ok(UNIX_EPOCH + Duration::from_millis(reader.get_u64_le()?))
     }
 }
 
@@ -260,42 +705,43 @@ mod tests {
     fn parse_len() {
         // 0b00
         {
-            let mut bytes = Bytes::from_static(&[0b0010_0000]);
-            pretty_assertions::assert_eq!(Rdb::parse_len(&mut bytes).0, 0b10_0000);
+            let mut bytes = Reader::new(Cursor::new(Bytes::from_static(&[0b0010_0000])));
+            pretty_assertions::assert_eq!(Rdb::parse_len(&mut bytes).unwrap().0, 0b10_0000);
         }
 
         // 0b01
         {
-            let mut bytes = Bytes::from_static(&[0b0100_0010, 0b000_0001]);
-            pretty_assertions::assert_eq!(Rdb::parse_len(&mut bytes).0, 0b11);
+            let mut bytes =
+                Reader::new(Cursor::new(Bytes::from_static(&[0b0100_0010, 0b000_0001])));
+            pretty_assertions::assert_eq!(Rdb::parse_len(&mut bytes).unwrap().0, 0b10_0000_0001);
         }
 
         // 0b10
         {
             let bytes = [[0b1000_0000].as_ref(), 1_u32.to_be_bytes().as_ref()].concat();
-            let mut bytes = Bytes::from(bytes);
-            pretty_assertions::assert_eq!(Rdb::parse_len(&mut bytes).0, 1_u32);
+            let mut bytes = Reader::new(Cursor::new(Bytes::from(bytes)));
+            pretty_assertions::assert_eq!(Rdb::parse_len(&mut bytes).unwrap().0, 1_u32);
         }
 
         // 0b11
         {
-            let mut bytes = Bytes::from_static(&[0b1100_0000]);
-            let (len, encoded) = Rdb::parse_len(&mut bytes);
+            let mut bytes = Reader::new(Cursor::new(Bytes::from_static(&[0b1100_0000])));
+            let (len, encoded) = Rdb::parse_len(&mut bytes).unwrap();
             assert!(encoded);
             pretty_assertions::assert_eq!(len, 0);
 
-            let mut bytes = Bytes::from_static(&[0b1100_0001]);
-            let (len, encoded) = Rdb::parse_len(&mut bytes);
+            let mut bytes = Reader::new(Cursor::new(Bytes::from_static(&[0b1100_0001])));
+            let (len, encoded) = Rdb::parse_len(&mut bytes).unwrap();
             assert!(encoded);
             pretty_assertions::assert_eq!(len, 1);
 
-            let mut bytes = Bytes::from_static(&[0b1100_0010]);
-            let (len, encoded) = Rdb::parse_len(&mut bytes);
+            let mut bytes = Reader::new(Cursor::new(Bytes::from_static(&[0b1100_0010])));
+            let (len, encoded) = Rdb::parse_len(&mut bytes).unwrap();
             assert!(encoded);
             pretty_assertions::assert_eq!(len, 2);
 
-            let mut bytes = Bytes::from_static(&[0b1100_0011]);
-            let (len, encoded) = Rdb::parse_len(&mut bytes);
+            let mut bytes = Reader::new(Cursor::new(Bytes::from_static(&[0b1100_0011])));
+            let (len, encoded) = Rdb::parse_len(&mut bytes).unwrap();
             assert!(encoded);
             pretty_assertions::assert_eq!(len, 3);
         }
@@ -306,47 +752,209 @@ mod tests {
     fn parse_encoded_str() {
         // 0
         {
-            let mut bytes =
-                Bytes::from([[0b1100_0000].as_ref(), i8::MIN.to_le_bytes().as_ref()].concat());
-            let (len, encoded) = Rdb::parse_len(&mut bytes);
+            let mut bytes = Reader::new(Cursor::new(Bytes::from(
+                [[0b1100_0000].as_ref(), i8::MIN.to_le_bytes().as_ref()].concat(),
+            )));
+            let (len, encoded) = Rdb::parse_len(&mut bytes).unwrap();
             assert!(encoded);
             pretty_assertions::assert_eq!(len, 0);
             pretty_assertions::assert_eq!(
-                Rdb::parse_int_str(&mut bytes, len),
+                Rdb::parse_int_str(&mut bytes, len).unwrap(),
                 Bytes::from(i8::MIN.to_string())
             );
         }
         // 1
         {
-            let mut bytes =
-                Bytes::from([[0b1100_0001].as_ref(), i16::MIN.to_le_bytes().as_ref()].concat());
-            let (len, encoded) = Rdb::parse_len(&mut bytes);
+            let mut bytes = Reader::new(Cursor::new(Bytes::from(
+                [[0b1100_0001].as_ref(), i16::MIN.to_le_bytes().as_ref()].concat(),
+            )));
+            let (len, encoded) = Rdb::parse_len(&mut bytes).unwrap();
             assert!(encoded);
             pretty_assertions::assert_eq!(len, 1);
             pretty_assertions::assert_eq!(
-                Rdb::parse_int_str(&mut bytes, len),
+                Rdb::parse_int_str(&mut bytes, len).unwrap(),
                 Bytes::from(i16::MIN.to_string())
             );
         }
         // 2
         {
-            let mut bytes =
-                Bytes::from([[0b1100_0010].as_ref(), i32::MIN.to_le_bytes().as_ref()].concat());
-            let (len, encoded) = Rdb::parse_len(&mut bytes);
+            let mut bytes = Reader::new(Cursor::new(Bytes::from(
+                [[0b1100_0010].as_ref(), i32::MIN.to_le_bytes().as_ref()].concat(),
+            )));
+            let (len, encoded) = Rdb::parse_len(&mut bytes).unwrap();
             assert!(encoded);
             pretty_assertions::assert_eq!(len, 2);
             pretty_assertions::assert_eq!(
-                Rdb::parse_int_str(&mut bytes, len),
+                Rdb::parse_int_str(&mut bytes, len).unwrap(),
                 Bytes::from(i32::MIN.to_string())
             );
         }
         // TODO
         // 3
         // {
-        //     let mut bytes = Bytes::from_static(&[0b1100_0011]);
-        //     let (len, encoded) = Rdb::parse_len(&mut bytes);
+        //     let mut bytes = Reader::new(Cursor::new(Bytes::from_static(&[0b1100_0011])));
+        //     let (len, encoded) = Rdb::parse_len(&mut bytes).unwrap();
         //     assert!(encoded);
         //     pretty_assertions::assert_eq!(len, 3);
         // }
     }
+
+    #[test]
+    #[traced_test]
+    #[allow(clippy::float_cmp)]
+    fn parse_containers() {
+        let mut list = Reader::new(Cursor::new(Bytes::from_static(b"\x02\x01a\x01b")));
+        let Type::List(list) = Type::parse(&mut list, Type::LIST).unwrap() else {
+            unreachable!();
+        };
+        pretty_assertions::assert_eq!(list.iter().collect::<Vec<_>>(), ["a", "b"].map(Bytes::from));
+
+        let mut set = Reader::new(Cursor::new(Bytes::from_static(b"\x01\x01a")));
+        let Type::Set(set) = Type::parse(&mut set, Type::SET).unwrap() else {
+            unreachable!();
+        };
+        assert!(set.contains(b"a".as_ref()));
+
+        let mut zset = Reader::new(Cursor::new(Bytes::from_static(
+            b"\x02\x01a\x031.5\x01b\xfe",
+        )));
+        let Type::SortedSet(zset) = Type::parse(&mut zset, Type::ZSET).unwrap() else {
+            unreachable!();
+        };
+        pretty_assertions::assert_eq!(zset.score(b"a"), Some(1.5));
+        pretty_assertions::assert_eq!(zset.score(b"b"), Some(f64::INFINITY));
+
+        let mut hash = Reader::new(Cursor::new(Bytes::from_static(b"\x01\x01f\x01v")));
+        let Type::Hash(hash) = Type::parse(&mut hash, Type::HASH).unwrap() else {
+            unreachable!();
+        };
+        pretty_assertions::assert_eq!(hash.get(b"f", UNIX_EPOCH), Some(Bytes::from("v")));
+
+        // A plain node, then a packed one
+        let packed = listpack::encode(&[ListpackEntry::Str("b".into()), ListpackEntry::Int(7)]);
+        let quicklist = [
+            &[0x02, 0x01, 0x01, b'a', 0x02][..],
+            &[u8::try_from(packed.len()).unwrap()],
+            &packed,
+        ]
+        .concat();
+        let mut quicklist = Reader::new(Cursor::new(Bytes::from(quicklist)));
+        let Type::List(list) = Type::parse(&mut quicklist, Type::LIST_QUICKLIST_2).unwrap() else {
+            unreachable!();
+        };
+        pretty_assertions::assert_eq!(
+            list.iter().collect::<Vec<_>>(),
+            ["a", "b", "7"].map(Bytes::from)
+        );
+    }
+
+    #[test]
+    #[traced_test]
+    fn parse_stream() {
+        enum Lp {
+            Int(u8),
+            Str(&'static str),
+        }
+        fn listpack(entries: &[Lp]) -> Vec<u8> {
+            let mut body = Vec::new();
+            for entry in entries {
+                match entry {
+                    Lp::Int(int) => body.extend([*int, 1]),
+                    Lp::Str(str) => {
+                        body.push(0x80 | u8::try_from(str.len()).unwrap());
+                        body.extend(str.as_bytes());
+                        body.push(u8::try_from(str.len()).unwrap() + 1);
+                    }
+                }
+            }
+            body.push(0xFF);
+            let total = u32::try_from(body.len() + 6).unwrap();
+            let num = u16::try_from(entries.len()).unwrap();
+            [&total.to_le_bytes()[..], &num.to_le_bytes(), &body].concat()
+        }
+        fn raw_id(ms: u64, seq: u64) -> Vec<u8> {
+            [ms.to_be_bytes(), seq.to_be_bytes()].concat()
+        }
+        fn string(bytes: &[u8]) -> Vec<u8> {
+            [&[u8::try_from(bytes.len()).unwrap()], bytes].concat()
+        }
+
+        let listpack = listpack(&[
+            // master entry: count, deleted, fields, "a", terminator
+            Lp::Int(2),
+            Lp::Int(1),
+            Lp::Int(1),
+            Lp::Str("a"),
+            Lp::Int(0),
+            // 1-0 same fields
+            Lp::Int(2),
+            Lp::Int(0),
+            Lp::Int(0),
+            Lp::Str("1"),
+            Lp::Int(4),
+            // 1-1 deleted
+            Lp::Int(3),
+            Lp::Int(0),
+            Lp::Int(1),
+            Lp::Str("x"),
+            Lp::Int(4),
+            // 2-0 own fields
+            Lp::Int(0),
+            Lp::Int(1),
+            Lp::Int(0),
+            Lp::Int(1),
+            Lp::Str("b"),
+            Lp::Str("2"),
+            Lp::Int(6),
+        ]);
+        let ms_time = 1_700_000_000_000_u64;
+        let bytes = [
+            &[1][..],
+            &string(&raw_id(1, 0)),
+            &string(&listpack),
+            // len, last id, first id, max deleted id, entries added
+            &[2, 2, 0, 1, 0, 1, 1, 3],
+            // 1 group "g" at 1-0 with 1 entry read
+            &[1],
+            &string(b"g"),
+            &[1, 0, 1],
+            // PEL
+            &[1],
+            &raw_id(1, 0),
+            &ms_time.to_le_bytes(),
+            &[1],
+            // consumers
+            &[1],
+            &string(b"c"),
+            &ms_time.to_le_bytes(),
+            &ms_time.to_le_bytes(),
+            &[1],
+            &raw_id(1, 0),
+        ]
+        .concat();
+
+        let mut bytes = Reader::new(Cursor::new(Bytes::from(bytes)));
+        let Type::Stream(stream) = Type::parse(&mut bytes, Type::STREAM_LISTPACKS_3).unwrap()
+        else {
+            panic!("Expected stream");
+        };
+        assert!(bytes.peek().unwrap().is_none());
+
+        let id = |ms, seq| EntryId::new(Duration::from_millis(ms), seq);
+        pretty_assertions::assert_eq!(
+            stream.inner.range(..).collect::<Vec<_>>(),
+            vec![
+                (id(1, 0), vec![("a".into(), "1".into())]),
+                (id(2, 0), vec![("b".into(), "2".into())]),
+            ]
+        );
+        pretty_assertions::assert_eq!(stream.last_id, id(2, 0));
+        pretty_assertions::assert_eq!(stream.max_deleted_id, id(1, 1));
+        pretty_assertions::assert_eq!(stream.entries_added, 3);
+
+        let group = &stream.groups["g"];
+        pretty_assertions::assert_eq!(group.last_delivered, id(1, 0));
+        pretty_assertions::assert_eq!(group.pending[&id(1, 0)].consumer, "c");
+        assert!(group.consumers.contains_key("c"));
+    }
 }
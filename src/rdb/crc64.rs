@@ -0,0 +1,45 @@
+//! CRC-64/Jones, the checksum Redis appends to RDB files:
+//! reflected, polynomial `0xad93d23594c935a9`, no initial or final xor.
+
+const POLY: u64 = 0x95ac_9329_ac4b_c9b5; // 0xad93d23594c935a9 reflected
+
+static TABLE: [u64; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u64;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ POLY
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// Continues the checksum `crc` over `bytes`. Start with 0.
+pub fn crc64(crc: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(crc, |crc, &b| {
+        #[allow(clippy::cast_possible_truncation)]
+        let idx = (crc as u8 ^ b) as usize;
+        TABLE[idx] ^ (crc >> 8)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check() {
+        pretty_assertions::assert_eq!(crc64(0, b"123456789"), 0xe9c6_d914_c4b8_d9ca);
+        let (a, b) = b"123456789".split_at(4);
+        pretty_assertions::assert_eq!(crc64(crc64(0, a), b), 0xe9c6_d914_c4b8_d9ca);
+    }
+}
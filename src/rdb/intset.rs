@@ -0,0 +1,66 @@
+use anyhow::{bail, ensure};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+// https://github.com/redis/redis/blob/unstable/src/intset.c
+/// Sorted integers, all stored with the width of the largest one
+pub fn parse(mut bytes: Bytes) -> anyhow::Result<Vec<i64>> {
+    ensure!(bytes.remaining() >= 8, "Intset header too short");
+    let width = bytes.get_u32_le() as usize;
+    let len = bytes.get_u32_le() as usize;
+    ensure!(
+        bytes.remaining() >= width * len,
+        "Intset shorter than its {len} entries"
+    );
+    (0..len)
+        .map(|_| {
+            Ok(match width {
+                2 => bytes.get_i16_le().into(),
+                4 => bytes.get_i32_le().into(),
+                8 => bytes.get_i64_le(),
+                _ => bail!("Invalid intset encoding: {width}"),
+            })
+        })
+        .collect()
+}
+
+/// Encodes sorted integers
+pub fn encode(ints: &[i64]) -> Bytes {
+    let width = ints
+        .iter()
+        .map(|&int| {
+            if i16::try_from(int).is_ok() {
+                2
+            } else if i32::try_from(int).is_ok() {
+                4
+            } else {
+                8
+            }
+        })
+        .max()
+        .unwrap_or(2);
+    let mut intset = BytesMut::with_capacity(8 + width * ints.len());
+    intset.put_u32_le(u32::try_from(width).unwrap_or_default());
+    intset.put_u32_le(u32::try_from(ints.len()).unwrap_or(u32::MAX));
+    for &int in ints {
+        intset.put_int_le(int, width);
+    }
+    intset.freeze()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_intset() {
+        let bytes = [
+            &4_u32.to_le_bytes()[..],
+            &2_u32.to_le_bytes(),
+            &(-5_i32).to_le_bytes(),
+            &70_000_i32.to_le_bytes(),
+        ]
+        .concat();
+        pretty_assertions::assert_eq!(parse(bytes.clone().into()).unwrap(), vec![-5, 70_000]);
+        pretty_assertions::assert_eq!(encode(&[-5, 70_000]), bytes);
+    }
+}
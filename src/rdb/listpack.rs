@@ -0,0 +1,289 @@
+use anyhow::{bail, ensure, Context};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+// https://github.com/antirez/listpack/blob/master/listpack.md
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ListpackEntry {
+    Int(i64),
+    Str(Bytes),
+}
+
+impl ListpackEntry {
+    /// Strings of integers are packed as integers, if they'd be formatted back the same
+    pub(crate) fn from_bytes(bytes: Bytes) -> Self {
+        std::str::from_utf8(&bytes)
+            .ok()
+            .and_then(|s| s.parse::<i64>().ok().filter(|int| int.to_string() == s))
+            .map_or(Self::Str(bytes), Self::Int)
+    }
+
+    pub(crate) fn to_bytes(&self) -> Bytes {
+        match self {
+            Self::Int(int) => int.to_string().into(),
+            Self::Str(str) => str.clone(),
+        }
+    }
+
+    pub(crate) fn to_string(&self) -> anyhow::Result<String> {
+        String::from_utf8(self.to_bytes().to_vec()).context("Invalid String in listpack")
+    }
+
+    pub(crate) fn to_int(&self) -> anyhow::Result<i64> {
+        match self {
+            Self::Int(int) => Ok(*int),
+            Self::Str(str) => crate::slice_to_int(str),
+        }
+    }
+}
+
+const EOF: u8 = 0xFF;
+
+pub fn parse(bytes: Bytes) -> anyhow::Result<Vec<ListpackEntry>> {
+    let entries = Entries::new(bytes)?;
+    let mut parsed = Vec::with_capacity(entries.num_elements.into());
+    for entry in entries {
+        parsed.push(entry?);
+    }
+    Ok(parsed)
+}
+
+/// Entries of a listpack, parsed as they're iterated
+#[derive(Debug, Clone)]
+pub struct Entries {
+    bytes: Bytes,
+    num_elements: u16,
+}
+
+impl Entries {
+    pub fn new(mut bytes: Bytes) -> anyhow::Result<Self> {
+        ensure!(bytes.remaining() >= 6, "Listpack header too short");
+        let total_bytes = bytes.get_u32_le() as usize;
+        let num_elements = bytes.get_u16_le();
+        tracing::trace!("Listpack of {total_bytes} bytes with {num_elements} elements");
+        Ok(Self {
+            bytes,
+            num_elements,
+        })
+    }
+
+    fn parse_next(&mut self) -> anyhow::Result<Option<ListpackEntry>> {
+        let bytes = &mut self.bytes;
+        ensure!(bytes.has_remaining(), "Listpack without terminator");
+        if bytes.chunk()[0] == EOF {
+            return Ok(None);
+        }
+        let (entry, entry_len) = parse_entry(bytes)?;
+        ensure!(
+            bytes.remaining() >= backlen_size(entry_len),
+            "Listpack entry without backlen"
+        );
+        bytes.advance(backlen_size(entry_len));
+        Ok(Some(entry))
+    }
+}
+
+impl Iterator for Entries {
+    type Item = anyhow::Result<ListpackEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let next = self.parse_next().transpose();
+        if matches!(next, Some(Err(_))) {
+            // Nothing past an invalid entry can be parsed
+            self.bytes = Bytes::from_static(&[EOF]);
+        }
+        next
+    }
+}
+
+pub fn encode(entries: &[ListpackEntry]) -> Bytes {
+    let mut body = BytesMut::new();
+    for entry in entries {
+        let start = body.len();
+        encode_entry(&mut body, entry);
+        let len = body.len() - start;
+        encode_backlen(&mut body, len);
+    }
+    body.put_u8(EOF);
+
+    let mut listpack = BytesMut::with_capacity(body.len() + 6);
+    listpack.put_u32_le(u32::try_from(body.len() + 6).unwrap_or(u32::MAX));
+    // The count saturates, readers then have to walk the whole listpack
+    listpack.put_u16_le(u16::try_from(entries.len()).unwrap_or(u16::MAX));
+    listpack.put(body);
+    listpack.freeze()
+}
+
+fn encode_entry(dst: &mut BytesMut, entry: &ListpackEntry) {
+    match entry {
+        ListpackEntry::Int(int @ 0..=0x7F) => dst.put_u8(u8::try_from(*int).unwrap_or_default()),
+        #[allow(clippy::cast_sign_loss, clippy::cast_possible_truncation)]
+        ListpackEntry::Int(int @ -4096..=4095) => {
+            let uint = (*int as u16) & 0x1FFF;
+            dst.put_u8(0xC0 | (uint >> 8) as u8);
+            dst.put_u8(uint as u8);
+        }
+        ListpackEntry::Int(int) => {
+            if let Ok(int) = i16::try_from(*int) {
+                dst.put_u8(0xF1);
+                dst.put_i16_le(int);
+            } else if (-(1 << 23)..(1 << 23)).contains(int) {
+                dst.put_u8(0xF2);
+                dst.put_int_le(*int, 3);
+            } else if let Ok(int) = i32::try_from(*int) {
+                dst.put_u8(0xF3);
+                dst.put_i32_le(int);
+            } else {
+                dst.put_u8(0xF4);
+                dst.put_i64_le(*int);
+            }
+        }
+        ListpackEntry::Str(str) => {
+            let len = str.len();
+            #[allow(clippy::cast_possible_truncation)]
+            match len {
+                0..=0x3F => dst.put_u8(0x80 | len as u8),
+                0x40..=0xFFF => {
+                    dst.put_u8(0xE0 | (len >> 8) as u8);
+                    dst.put_u8(len as u8);
+                }
+                _ => {
+                    dst.put_u8(0xF0);
+                    dst.put_u32_le(u32::try_from(len).unwrap_or(u32::MAX));
+                }
+            }
+            dst.put_slice(str);
+        }
+    }
+}
+
+/// Stores `len` so the listpack can be walked back to front
+fn encode_backlen(dst: &mut BytesMut, len: usize) {
+    let size = backlen_size(len);
+    for i in (0..size).rev() {
+        #[allow(clippy::cast_possible_truncation)]
+        let mut byte = ((len >> (7 * i)) & 0x7F) as u8;
+        if i != size - 1 {
+            byte |= 0x80;
+        }
+        dst.put_u8(byte);
+    }
+}
+
+fn parse_entry(bytes: &mut Bytes) -> anyhow::Result<(ListpackEntry, usize)> {
+    let encoding = bytes.get_u8();
+
+    let need = |bytes: &Bytes, n: usize| {
+        ensure!(bytes.remaining() >= n, "Truncated listpack entry");
+        Ok(())
+    };
+    let string = |bytes: &mut Bytes, len: usize, header: usize| {
+        need(bytes, len)?;
+        anyhow::Ok((ListpackEntry::Str(bytes.split_to(len)), header + len))
+    };
+
+    Ok(match encoding {
+        // 0xxxxxxx 7 bit unsigned int
+        0x00..=0x7F => (ListpackEntry::Int(i64::from(encoding)), 1),
+        // 10xxxxxx 6 bit str len
+        0x80..=0xBF => string(bytes, usize::from(encoding & 0x3F), 1)?,
+        // 110xxxxx yyyyyyyy 13 bit signed int
+        0xC0..=0xDF => {
+            need(bytes, 1)?;
+            let uint = (u16::from(encoding & 0x1F) << 8) | u16::from(bytes.get_u8());
+            // sign extend from 13 bits
+            #[allow(clippy::cast_possible_wrap)]
+            let int = ((uint << 3) as i16) >> 3;
+            (ListpackEntry::Int(int.into()), 2)
+        }
+        // 1110xxxx yyyyyyyy 12 bit str len
+        0xE0..=0xEF => {
+            need(bytes, 1)?;
+            let len = (usize::from(encoding & 0x0F) << 8) | usize::from(bytes.get_u8());
+            string(bytes, len, 2)?
+        }
+        0xF0 => {
+            need(bytes, 4)?;
+            let len = bytes.get_u32_le() as usize;
+            string(bytes, len, 5)?
+        }
+        0xF1 => {
+            need(bytes, 2)?;
+            (ListpackEntry::Int(bytes.get_i16_le().into()), 3)
+        }
+        0xF2 => {
+            need(bytes, 3)?;
+            // sign extend from 24 bits
+            (ListpackEntry::Int((bytes.get_int_le(3) << 40) >> 40), 4)
+        }
+        0xF3 => {
+            need(bytes, 4)?;
+            (ListpackEntry::Int(bytes.get_i32_le().into()), 5)
+        }
+        0xF4 => {
+            need(bytes, 8)?;
+            (ListpackEntry::Int(bytes.get_i64_le()), 9)
+        }
+        _ => bail!("Invalid listpack encoding: {encoding:#x}"),
+    })
+}
+
+/// Number of bytes used to store the length of an entry of `len` bytes
+const fn backlen_size(len: usize) -> usize {
+    match len {
+        0..=127 => 1,
+        128..=16_383 => 2,
+        16_384..=2_097_151 => 3,
+        2_097_152..=268_435_455 => 4,
+        _ => 5,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_listpack() {
+        #[rustfmt::skip]
+        let bytes = Bytes::from_static(&[
+            // header
+            0x18, 0, 0, 0, 4, 0,
+            // 7 bit uint 5
+            0x05, 0x01,
+            // 6 bit str "ab"
+            0x82, b'a', b'b', 0x03,
+            // 13 bit int -1
+            0xDF, 0xFF, 0x02,
+            // int16 1000
+            0xF1, 0xE8, 0x03, 0x03,
+            // end
+            0xFF,
+        ]);
+        pretty_assertions::assert_eq!(
+            parse(bytes).unwrap(),
+            vec![
+                ListpackEntry::Int(5),
+                ListpackEntry::Str(Bytes::from_static(b"ab")),
+                ListpackEntry::Int(-1),
+                ListpackEntry::Int(1000),
+            ]
+        );
+    }
+
+    #[test]
+    fn encode_roundtrip() {
+        let entries = vec![
+            ListpackEntry::Int(5),
+            ListpackEntry::Int(-1),
+            ListpackEntry::Int(4095),
+            ListpackEntry::Int(1000),
+            ListpackEntry::Int(-70_000),
+            ListpackEntry::Int(1 << 30),
+            ListpackEntry::Int(i64::MIN),
+            ListpackEntry::Str(Bytes::from_static(b"ab")),
+            ListpackEntry::Str(Bytes::from("x".repeat(200))),
+            ListpackEntry::Str(Bytes::from("y".repeat(5000))),
+        ];
+        pretty_assertions::assert_eq!(parse(encode(&entries)).unwrap(), entries);
+    }
+}
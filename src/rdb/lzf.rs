@@ -0,0 +1,65 @@
+use anyhow::ensure;
+use bytes::Bytes;
+
+// http://oldhome.schmorp.de/marc/liblzf.html
+/// Decompresses `src` into exactly `len` bytes
+pub fn decompress(src: &[u8], len: usize) -> anyhow::Result<Bytes> {
+    let mut out = Vec::with_capacity(len);
+    let mut i = 0;
+    while i < src.len() {
+        let ctrl = usize::from(src[i]);
+        i += 1;
+
+        // 000LLLLL: literal run of L + 1 bytes
+        if ctrl < 1 << 5 {
+            let run = ctrl + 1;
+            ensure!(i + run <= src.len(), "Truncated LZF literal");
+            out.extend_from_slice(&src[i..i + run]);
+            i += run;
+            continue;
+        }
+
+        // LLLOOOOO [LLLLLLLL] OOOOOOOO: back reference of L + 2 bytes
+        let mut run = ctrl >> 5;
+        if run == 7 {
+            ensure!(i < src.len(), "Truncated LZF back reference");
+            run += usize::from(src[i]);
+            i += 1;
+        }
+        run += 2;
+        ensure!(i < src.len(), "Truncated LZF back reference");
+        let back = ((ctrl & 0x1F) << 8) + usize::from(src[i]) + 1;
+        i += 1;
+        ensure!(back <= out.len(), "LZF back reference out of bounds");
+
+        // The reference can overlap with the bytes it produces
+        let start = out.len() - back;
+        for k in start..start + run {
+            out.push(out[k]);
+        }
+    }
+    ensure!(
+        out.len() == len,
+        "LZF data decompressed to {} bytes, expected {len}",
+        out.len()
+    );
+    Ok(out.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decompress_runs() {
+        // Literal "ab", then 8 bytes copied from 2 back
+        let src = [0x01, b'a', b'b', 0xC0, 0x01];
+        pretty_assertions::assert_eq!(decompress(&src, 10).unwrap(), "ababababab");
+        // Extended length: literal "a", then 9 bytes copied from 1 back
+        let src = [0x00, b'a', 0xE0, 0x00, 0x00];
+        pretty_assertions::assert_eq!(decompress(&src, 10).unwrap(), "aaaaaaaaaa");
+
+        assert!(decompress(&src, 11).is_err());
+        assert!(decompress(&[0x20, 0x05], 3).is_err());
+    }
+}
@@ -0,0 +1,105 @@
+use anyhow::{ensure, Context};
+use bytes::Bytes;
+use std::io::{BufRead, Read};
+
+use super::crc64::crc64;
+
+/// Reads a RDB image from a buffered source as it's parsed, so a dump never
+/// has to be in memory at once. The checksum of what was read is kept along.
+#[derive(Debug)]
+pub struct Reader<R> {
+    inner: R,
+    /// CRC64 of the bytes read so far
+    crc: u64,
+    pos: u64,
+}
+
+impl<R: BufRead> Reader<R> {
+    pub const fn new(inner: R) -> Self {
+        Self {
+            inner,
+            crc: 0,
+            pos: 0,
+        }
+    }
+
+    /// Number of bytes read so far
+    #[inline]
+    pub const fn position(&self) -> u64 {
+        self.pos
+    }
+
+    #[inline]
+    pub const fn crc(&self) -> u64 {
+        self.crc
+    }
+
+    /// Next byte without consuming it, `None` at the end of the source
+    pub fn peek(&mut self) -> anyhow::Result<Option<u8>> {
+        Ok(self.inner.fill_buf()?.first().copied())
+    }
+
+    fn consumed(&mut self, bytes: &[u8]) {
+        self.crc = crc64(self.crc, bytes);
+        self.pos += bytes.len() as u64;
+    }
+
+    fn read_array<const N: usize>(&mut self) -> anyhow::Result<[u8; N]> {
+        let mut buf = [0; N];
+        let available = self.inner.fill_buf()?;
+        if available.len() >= N {
+            buf.copy_from_slice(&available[..N]);
+            self.inner.consume(N);
+        } else {
+            self.inner
+                .read_exact(&mut buf)
+                .context("Unexpected end of the RDB")?;
+        }
+        self.consumed(&buf);
+        Ok(buf)
+    }
+
+    /// Reads the next `len` bytes. The buffer grows as they're read,
+    /// so a corrupted length fails at the end of the source instead of exhausting the memory.
+    pub fn split_to(&mut self, len: usize) -> anyhow::Result<Bytes> {
+        const MAX_PREALLOC: usize = 64 * 1024;
+
+        let mut buf = Vec::with_capacity(len.min(MAX_PREALLOC));
+        (&mut self.inner).take(len as u64).read_to_end(&mut buf)?;
+        ensure!(buf.len() == len, "Unexpected end of the RDB");
+        self.consumed(&buf);
+        Ok(buf.into())
+    }
+
+    pub fn get_u8(&mut self) -> anyhow::Result<u8> {
+        Ok(self.read_array::<1>()?[0])
+    }
+
+    pub fn get_i8(&mut self) -> anyhow::Result<i8> {
+        Ok(i8::from_le_bytes(self.read_array()?))
+    }
+
+    pub fn get_i16_le(&mut self) -> anyhow::Result<i16> {
+        Ok(i16::from_le_bytes(self.read_array()?))
+    }
+
+    pub fn get_i32_le(&mut self) -> anyhow::Result<i32> {
+        Ok(i32::from_le_bytes(self.read_array()?))
+    }
+
+    pub fn get_u32(&mut self) -> anyhow::Result<u32> {
+        Ok(u32::from_be_bytes(self.read_array()?))
+    }
+
+    pub fn get_u64(&mut self) -> anyhow::Result<u64> {
+        Ok(u64::from_be_bytes(self.read_array()?))
+    }
+
+    pub fn get_u64_le(&mut self) -> anyhow::Result<u64> {
+        Ok(u64::from_le_bytes(self.read_array()?))
+    }
+
+    pub fn get_f64_le(&mut self) -> anyhow::Result<f64> {
+        Ok(f64::from_le_bytes(self.read_array()?))
+    }
+}
@@ -0,0 +1,588 @@
+use bytes::{BufMut, Bytes, BytesMut};
+use std::{
+    borrow::Borrow,
+    collections::HashMap,
+    fs::File,
+    io::Write,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use super::{crc64::crc64, intset, AuxFields, Db, Rdb, ReplInfo};
+use crate::db::{set::Set, stream::EntryId, Hash, List, SortedSet, Stream, Type, Value};
+
+impl Rdb {
+    /// Version of the files written by [`Rdb::encode`]
+    const VERSION: &'static [u8] = b"0011";
+    #[cfg_attr(not(feature = "scripting"), allow(dead_code))]
+    pub(super) const VERSION_NUMBER: u16 = 11;
+    const EOF: u8 = 0xFF;
+
+    /// Serializes the keys and the code of the function libraries as a RDB file,
+    /// skipping what expired by `now`, which is also recorded as its creation time.
+    /// Without `checksum` the trailing CRC64 is left zeroed, as readers skip it then.
+    pub(crate) fn encode(
+        map: &HashMap<String, impl Borrow<Value>>,
+        functions: &[Bytes],
+        checksum: bool,
+        repl: Option<&ReplInfo>,
+        now: SystemTime,
+    ) -> Bytes {
+        let mut rdb = Bytes::new();
+        // Never split, so the whole image is the single chunk
+        Self::encode_chunked(map, functions, checksum, repl, now, usize::MAX, |chunk| {
+            rdb = chunk;
+        });
+        rdb
+    }
+
+    /// Serializes the dataset like [`Rdb::encode`], handing it to `emit` in chunks of
+    /// at least `chunk_len` bytes, the last one excepted, as they're encoded
+    pub(crate) fn encode_chunked(
+        map: &HashMap<String, impl Borrow<Value>>,
+        functions: &[Bytes],
+        checksum: bool,
+        repl: Option<&ReplInfo>,
+        now: SystemTime,
+        chunk_len: usize,
+        mut emit: impl FnMut(Bytes),
+    ) {
+        let mut dst = BytesMut::new();
+        let mut crc = 0;
+        dst.put_slice(b"REDIS");
+        dst.put_slice(Self::VERSION);
+        AuxFields::encode(&mut dst, now, repl);
+        Self::encode_functions(&mut dst, functions);
+
+        let live = map
+            .iter()
+            .map(|(key, value)| (key, value.borrow()))
+            .filter(|(_, value)| !value.is_expired(now))
+            .collect::<Vec<_>>();
+        if !live.is_empty() {
+            let expires = live.iter().filter(|(_, v)| v.expiration.is_some()).count();
+            dst.put_u8(Db::DB_SELECTOR);
+            Self::encode_len(&mut dst, 0);
+            dst.put_u8(Db::RESIZEDB);
+            Self::encode_len(&mut dst, live.len() as u64);
+            Self::encode_len(&mut dst, expires as u64);
+            for (key, value) in live {
+                Db::encode_entry(&mut dst, key, value, now);
+                if dst.len() >= chunk_len {
+                    let chunk = dst.split().freeze();
+                    if checksum {
+                        crc = crc64(crc, &chunk);
+                    }
+                    emit(chunk);
+                }
+            }
+        }
+
+        dst.put_u8(Self::EOF);
+        if checksum {
+            crc = crc64(crc, &dst);
+        }
+        dst.put_u64_le(crc);
+        emit(dst.freeze());
+    }
+
+    fn encode_functions(dst: &mut BytesMut, functions: &[Bytes]) {
+        for code in functions {
+            dst.put_u8(Self::FUNCTION);
+            Self::encode_string(dst, code);
+        }
+    }
+
+    /// FUNCTION DUMP payload: the libraries like in a RDB file,
+    #[cfg_attr(not(feature = "scripting"), allow(dead_code))]
+    /// followed by the RDB version and a CRC64 of it all
+    pub(crate) fn dump_functions(functions: &[Bytes]) -> Bytes {
+        let mut dst = BytesMut::new();
+        Self::encode_functions(&mut dst, functions);
+        dst.put_u16_le(Self::VERSION_NUMBER);
+        let checksum = crc64(0, &dst);
+        dst.put_u64_le(checksum);
+        dst.freeze()
+    }
+
+    fn encode_len(dst: &mut BytesMut, len: u64) {
+        #[allow(clippy::cast_possible_truncation)]
+        match len {
+            0..=0x3F => dst.put_u8(len as u8),
+            0x40..=0x3FFF => dst.put_u16(0x4000 | len as u16),
+            0x4000..=0xFFFF_FFFF => {
+                dst.put_u8(0x80);
+                dst.put_u32(len as u32);
+            }
+            _ => {
+                dst.put_u8(0x81);
+                dst.put_u64(len);
+            }
+        }
+    }
+
+    fn encode_string(dst: &mut BytesMut, string: &[u8]) {
+        Self::encode_len(dst, string.len() as u64);
+        dst.put_slice(string);
+    }
+}
+
+/// Writes an RDB image to `path` through a temporary file renamed once synced,
+/// so a crash never leaves a truncated dump behind
+pub fn write(path: &Path, rdb: &[u8]) -> std::io::Result<()> {
+    let tmp = path.with_file_name(format!("temp-{}.rdb", std::process::id()));
+    let res = File::create(&tmp).and_then(|mut file| {
+        file.write_all(rdb)?;
+        file.sync_all()
+    });
+    if let Err(e) = res.and_then(|()| std::fs::rename(&tmp, path)) {
+        let _ = std::fs::remove_file(&tmp);
+        return Err(e);
+    }
+    Ok(())
+}
+
+impl AuxFields {
+    fn encode(dst: &mut BytesMut, now: SystemTime, repl: Option<&ReplInfo>) {
+        let ctime = now
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+            .to_string();
+        let fields = [
+            ("redis-ver", "7.4.0"),
+            ("redis-bits", "64"),
+            ("ctime", &ctime),
+            ("aof-base", "0"),
+        ];
+        for (key, value) in fields {
+            Self::encode_field(dst, key, value);
+        }
+        if let Some(repl) = repl {
+            Self::encode_field(dst, "repl-id", &repl.id);
+            Self::encode_field(dst, "repl-offset", &repl.offset.to_string());
+        }
+    }
+
+    fn encode_field(dst: &mut BytesMut, key: &str, value: &str) {
+        dst.put_u8(Self::AUX_FIELDS);
+        Rdb::encode_string(dst, key.as_bytes());
+        Rdb::encode_string(dst, value.as_bytes());
+    }
+}
+
+impl Db {
+    fn encode_entry(dst: &mut BytesMut, key: &str, value: &Value, now: SystemTime) {
+        if let Some(expiration) = value.expiration {
+            let ms = expiration
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis();
+            dst.put_u8(Self::EXPIRE_MS);
+            dst.put_u64_le(u64::try_from(ms).unwrap_or(u64::MAX));
+        }
+        match &value.v_type {
+            Type::String(string) => {
+                dst.put_u8(Type::STRING);
+                Rdb::encode_string(dst, key.as_bytes());
+                Rdb::encode_string(dst, &string.to_bytes());
+            }
+            Type::List(list) => list.encode(dst, key),
+            Type::Set(set) => set.encode(dst, key),
+            Type::SortedSet(zset) => zset.encode(dst, key),
+            Type::Hash(hash) => hash.encode(dst, key, now),
+            Type::Stream(stream) => {
+                dst.put_u8(Type::STREAM_LISTPACKS_3);
+                Rdb::encode_string(dst, key.as_bytes());
+                stream.encode(dst);
+            }
+        }
+    }
+}
+
+impl List {
+    /// A listpack is saved as a quicklist of a single node
+    fn encode(&self, dst: &mut BytesMut, key: &str) {
+        const PACKED: u64 = 2;
+
+        match self {
+            Self::Listpack(listpack) => {
+                dst.put_u8(Type::LIST_QUICKLIST_2);
+                Rdb::encode_string(dst, key.as_bytes());
+                Rdb::encode_len(dst, 1);
+                Rdb::encode_len(dst, PACKED);
+                Rdb::encode_string(dst, listpack.as_bytes());
+            }
+            Self::Quicklist(list) => {
+                dst.put_u8(Type::LIST);
+                Rdb::encode_string(dst, key.as_bytes());
+                Rdb::encode_len(dst, list.len() as u64);
+                for item in list {
+                    Rdb::encode_string(dst, item);
+                }
+            }
+        }
+    }
+}
+
+impl Set {
+    fn encode(&self, dst: &mut BytesMut, key: &str) {
+        match self {
+            Self::Intset(ints) => {
+                dst.put_u8(Type::SET_INTSET);
+                Rdb::encode_string(dst, key.as_bytes());
+                Rdb::encode_string(dst, &intset::encode(ints));
+            }
+            Self::Listpack(listpack) => {
+                dst.put_u8(Type::SET_LISTPACK);
+                Rdb::encode_string(dst, key.as_bytes());
+                Rdb::encode_string(dst, listpack.as_bytes());
+            }
+            Self::Hashtable(set) => {
+                dst.put_u8(Type::SET);
+                Rdb::encode_string(dst, key.as_bytes());
+                Rdb::encode_len(dst, set.len() as u64);
+                for member in set {
+                    Rdb::encode_string(dst, member);
+                }
+            }
+        }
+    }
+}
+
+impl SortedSet {
+    fn encode(&self, dst: &mut BytesMut, key: &str) {
+        match self {
+            Self::Listpack(listpack) => {
+                dst.put_u8(Type::ZSET_LISTPACK);
+                Rdb::encode_string(dst, key.as_bytes());
+                Rdb::encode_string(dst, listpack.as_bytes());
+            }
+            Self::Skiplist(zset) => {
+                dst.put_u8(Type::ZSET_2);
+                Rdb::encode_string(dst, key.as_bytes());
+                Rdb::encode_len(dst, zset.len() as u64);
+                for (member, score) in zset.iter() {
+                    Rdb::encode_string(dst, &member);
+                    dst.put_f64_le(score);
+                }
+            }
+        }
+    }
+}
+
+impl Hash {
+    /// A listpack is saved as is. Hashes with field expirations are saved as `HASH_METADATA`,
+    /// with each expiration as an offset from the earliest one, plus one, or 0 for none.
+    fn encode(&self, dst: &mut BytesMut, key: &str, now: SystemTime) {
+        if let Some(listpack) = self.listpack() {
+            dst.put_u8(Type::HASH_LISTPACK);
+            Rdb::encode_string(dst, key.as_bytes());
+            Rdb::encode_string(dst, listpack.as_bytes());
+            return;
+        }
+        let fields = self
+            .iter(now)
+            .map(|(field, value)| {
+                let at = self.expires.get(&field).copied().map(ms);
+                (field, value, at)
+            })
+            .collect::<Vec<_>>();
+        let min_expire = fields.iter().filter_map(|&(_, _, at)| at).min();
+
+        dst.put_u8(min_expire.map_or(Type::HASH, |_| Type::HASH_METADATA));
+        Rdb::encode_string(dst, key.as_bytes());
+        if let Some(min_expire) = min_expire {
+            dst.put_u64_le(min_expire);
+        }
+        Rdb::encode_len(dst, fields.len() as u64);
+        for (field, value, at) in fields {
+            if let Some(min_expire) = min_expire {
+                Rdb::encode_len(dst, at.map_or(0, |at| at - min_expire + 1));
+            }
+            Rdb::encode_string(dst, &field);
+            Rdb::encode_string(dst, &value);
+        }
+    }
+}
+
+impl Stream {
+    /// The listpack nodes are saved as they are kept
+    fn encode(&self, dst: &mut BytesMut) {
+        Rdb::encode_len(dst, self.inner.nodes().count() as u64);
+        for (master, listpack) in self.inner.nodes() {
+            Rdb::encode_string(dst, &Self::encode_raw_id(master));
+            Rdb::encode_string(dst, listpack);
+        }
+
+        Rdb::encode_len(dst, self.inner.len() as u64);
+        Self::encode_id(dst, self.last_id);
+        Self::encode_id(dst, self.inner.first_id().unwrap_or(EntryId::MIN));
+        Self::encode_id(dst, self.max_deleted_id);
+        Rdb::encode_len(dst, self.entries_added);
+
+        Rdb::encode_len(dst, self.groups.len() as u64);
+        for (name, group) in &self.groups {
+            Rdb::encode_string(dst, name.as_bytes());
+            Self::encode_id(dst, group.last_delivered);
+            Rdb::encode_len(dst, group.entries_read);
+
+            Rdb::encode_len(dst, group.pending.len() as u64);
+            for (id, entry) in &group.pending {
+                dst.put_slice(&Self::encode_raw_id(*id));
+                Self::encode_ms_time(dst, entry.delivery_time);
+                Rdb::encode_len(dst, entry.delivery_count);
+            }
+
+            Rdb::encode_len(dst, group.consumers.len() as u64);
+            for (consumer_name, consumer) in &group.consumers {
+                Rdb::encode_string(dst, consumer_name.as_bytes());
+                // seen time, then active time
+                Self::encode_ms_time(dst, consumer.seen_time);
+                Self::encode_ms_time(dst, consumer.seen_time);
+                let owned = group
+                    .pending
+                    .iter()
+                    .filter(|(_, entry)| &entry.consumer == consumer_name)
+                    .collect::<Vec<_>>();
+                Rdb::encode_len(dst, owned.len() as u64);
+                for (id, _) in owned {
+                    dst.put_slice(&Self::encode_raw_id(*id));
+                }
+            }
+        }
+    }
+
+    fn encode_id(dst: &mut BytesMut, id: EntryId) {
+        Rdb::encode_len(dst, id.ms());
+        Rdb::encode_len(dst, id.seq());
+    }
+
+    fn encode_raw_id(id: EntryId) -> [u8; 16] {
+        let mut raw = [0; 16];
+        raw[..8].copy_from_slice(&id.ms().to_be_bytes());
+        raw[8..].copy_from_slice(&id.seq().to_be_bytes());
+        raw
+    }
+
+    fn encode_ms_time(dst: &mut BytesMut, time: SystemTime) {
+        dst.put_u64_le(ms(time));
+    }
+}
+
+/// Unix time in milliseconds
+fn ms(time: SystemTime) -> u64 {
+    let ms = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    u64::try_from(ms).unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::db::{EncodingLimits, Map};
+
+    #[test]
+    fn roundtrip() {
+        let mut stream = Stream::new();
+        let id = |ms, seq| EntryId::new(Duration::from_millis(ms), seq);
+        let kv = |k: &str, v: &str| vec![(k.to_owned(), v.to_owned())];
+        stream.xadd(id(1, 0), &kv("a", "1"));
+        stream.xadd(id(1, 1), &kv("a", "2"));
+        stream.xadd(id(5, 0), &kv("b", "3"));
+
+        let expiration = SystemTime::now() + Duration::from_mins(1);
+        let map = HashMap::from([
+            (
+                "str".to_owned(),
+                Value::new(Type::String("x".repeat(100).into()), Some(expiration)),
+            ),
+            (
+                "stream".to_owned(),
+                Value::new_no_expiry(Type::Stream(stream)),
+            ),
+            (
+                "expired".to_owned(),
+                Value::new(Type::String("old".into()), Some(UNIX_EPOCH)),
+            ),
+        ]);
+
+        let rdb = Rdb::parse(Rdb::encode(&map, &[], true, None, SystemTime::now()), true).unwrap();
+        let mut parsed = rdb.db.maps.into_iter().flatten().collect::<HashMap<_, _>>();
+        pretty_assertions::assert_eq!(parsed.len(), 2);
+
+        let string = parsed.remove("str").unwrap();
+        pretty_assertions::assert_eq!(
+            string.v_type.as_string().unwrap().to_bytes(),
+            Bytes::from("x".repeat(100))
+        );
+        let ms = |t: SystemTime| t.duration_since(UNIX_EPOCH).unwrap().as_millis();
+        pretty_assertions::assert_eq!(string.expiration.map(ms), Some(ms(expiration)));
+
+        let stream = parsed.remove("stream").unwrap();
+        let stream = stream.v_type.as_stream().unwrap();
+        pretty_assertions::assert_eq!(
+            stream.inner.range(..).collect::<Vec<_>>(),
+            vec![
+                (id(1, 0), kv("a", "1")),
+                (id(1, 1), kv("a", "2")),
+                (id(5, 0), kv("b", "3")),
+            ]
+        );
+        pretty_assertions::assert_eq!(stream.last_id, id(5, 0));
+        pretty_assertions::assert_eq!(stream.entries_added, 3);
+    }
+
+    #[test]
+    fn containers() {
+        let list = Type::List(["a", "b"].map(Bytes::from).into_iter().collect());
+        let set = Type::Set(["a"].map(Bytes::from).into_iter().collect());
+        let ints = Type::Set(["3", "-70000"].map(Bytes::from).into_iter().collect());
+        let zset = Type::SortedSet([(Bytes::from("a"), -1.5), (Bytes::from("b"), 2.0)].into());
+        let hash = Type::Hash(std::iter::once((Bytes::from("f"), Bytes::from("v"))).collect());
+        // Field expirations are stored with a millisecond precision
+        let mut ttl_hash = [("f", "v"), ("g", "w")]
+            .map(|(f, v)| (Bytes::from(f), Bytes::from(v)))
+            .into_iter()
+            .collect::<Hash>();
+        let now = SystemTime::now();
+        let at = UNIX_EPOCH + Duration::from_millis(ms(now) + 60_000);
+        ttl_hash.set_expiration(&Bytes::from("g"), at, None, now);
+        let ttl_hash = Type::Hash(ttl_hash);
+        // Each container is saved as a hashtable or such, and packed
+        let mut map = HashMap::new();
+        for (key, value) in [
+            ("l", list),
+            ("s", set),
+            ("i", ints),
+            ("z", zset),
+            ("h", hash),
+            ("t", ttl_hash),
+        ] {
+            let mut packed = value.clone();
+            packed.fit_encoding(&EncodingLimits::default());
+            assert!(matches!(
+                packed.encoding(),
+                "listpack" | "listpackex" | "intset"
+            ));
+            map.insert(format!("{key}-packed"), Value::new_no_expiry(packed));
+            map.insert(key.to_owned(), Value::new_no_expiry(value));
+        }
+
+        let rdb = Rdb::parse(Rdb::encode(&map, &[], true, None, SystemTime::now()), true).unwrap();
+        let parsed = rdb.db.maps.into_iter().flatten().collect::<HashMap<_, _>>();
+        pretty_assertions::assert_eq!(parsed.len(), 12);
+        for (key, value) in &map {
+            let parsed = &parsed[key].v_type;
+            match (&value.v_type, parsed) {
+                (Type::List(a), Type::List(b)) => pretty_assertions::assert_eq!(a, b),
+                (Type::Set(a), Type::Set(b)) => pretty_assertions::assert_eq!(a, b),
+                (Type::SortedSet(a), Type::SortedSet(b)) => pretty_assertions::assert_eq!(a, b),
+                (Type::Hash(a), Type::Hash(b)) => pretty_assertions::assert_eq!(a, b),
+                _ => panic!("{key} changed type: {parsed:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn checksum() {
+        let map = HashMap::from([(
+            "key".to_owned(),
+            Value::new_no_expiry(Type::String("value".into())),
+        )]);
+        let rdb = Rdb::encode(&map, &[], true, None, SystemTime::now());
+        let mut corrupt = rdb.to_vec();
+        let at = corrupt.len() - 12;
+        corrupt[at] ^= 1;
+        assert!(Rdb::parse(corrupt.clone().into(), true).is_err());
+        assert!(Rdb::parse(corrupt.into(), false).is_ok());
+
+        let unchecked = Rdb::encode(&map, &[], false, None, SystemTime::now());
+        pretty_assertions::assert_eq!(&unchecked[unchecked.len() - 8..], &[0; 8]);
+        assert!(Rdb::parse(unchecked, true).is_ok());
+    }
+
+    #[test]
+    fn chunked() {
+        let map = (0..10)
+            .map(|i| {
+                (
+                    format!("k{i}"),
+                    Value::new_no_expiry(Type::String("v".into())),
+                )
+            })
+            .collect::<HashMap<_, _>>();
+        let mut chunks = Vec::new();
+        let now = SystemTime::now();
+        Rdb::encode_chunked(&map, &[], true, None, now, 16, |chunk| chunks.push(chunk));
+        assert!(chunks.len() > 1);
+        // The checksum covers every chunk
+        let rdb = Rdb::parse(chunks.concat().into(), true).unwrap();
+        pretty_assertions::assert_eq!(rdb.db.maps.iter().map(HashMap::len).sum::<usize>(), 10);
+    }
+
+    #[test]
+    fn streamed() {
+        let map = HashMap::from([
+            (
+                "a".to_owned(),
+                Value::new_no_expiry(Type::String("x".repeat(100).into())),
+            ),
+            (
+                "b".to_owned(),
+                Value::new(
+                    Type::String("1".into()),
+                    Some(SystemTime::now() + Duration::from_mins(1)),
+                ),
+            ),
+        ]);
+        let rdb = Rdb::encode(&map, &[], true, None, SystemTime::now());
+        // Fields straddle the buffer
+        let reader = std::io::BufReader::with_capacity(7, &rdb[..]);
+        let parsed = Rdb::read(reader, true).unwrap();
+        pretty_assertions::assert_eq!(parsed.db.maps.iter().map(HashMap::len).sum::<usize>(), 2);
+
+        for len in 0..rdb.len() {
+            assert!(Rdb::read(&rdb[..len], true).is_err(), "cut at {len}");
+        }
+    }
+
+    #[test]
+    fn functions() {
+        let functions = [Bytes::from("#!lua name=a\n"), Bytes::from("#!lua name=b\n")];
+        let rdb = Rdb::parse(
+            Rdb::encode(&Map::new(), &functions, true, None, SystemTime::now()),
+            true,
+        )
+        .unwrap();
+        pretty_assertions::assert_eq!(rdb.functions, functions);
+
+        let payload = Rdb::dump_functions(&functions);
+        pretty_assertions::assert_eq!(Rdb::restore_functions(payload.clone()).unwrap(), functions);
+        let mut corrupt = payload.to_vec();
+        corrupt[3] ^= 1;
+        assert!(Rdb::restore_functions(corrupt.into()).is_err());
+    }
+
+    #[test]
+    fn repl_info() {
+        let map = Map::new();
+        let rdb = Rdb::parse(Rdb::encode(&map, &[], true, None, SystemTime::now()), true).unwrap();
+        pretty_assertions::assert_eq!(rdb.repl_info(), None);
+
+        let repl = ReplInfo {
+            id: "8371b4fb1155b71f4a04d3e1bc3e18c4a990aeeb".to_owned(),
+            offset: 42,
+        };
+        let rdb = Rdb::parse(
+            Rdb::encode(&map, &[], true, Some(&repl), SystemTime::now()),
+            true,
+        )
+        .unwrap();
+        pretty_assertions::assert_eq!(rdb.repl_info(), Some(repl));
+    }
+}
@@ -0,0 +1,121 @@
+use anyhow::{bail, ensure};
+use bytes::{Buf, Bytes};
+
+use super::ListpackEntry;
+
+// https://github.com/redis/redis/blob/unstable/src/ziplist.c
+const EOF: u8 = 0xFF;
+/// Previous entry lengths from this value on take 4 more bytes
+const BIG_PREVLEN: u8 = 0xFE;
+
+/// Entries of a ziplist, the encoding listpacks replaced in Redis 7
+pub fn parse(mut bytes: Bytes) -> anyhow::Result<Vec<ListpackEntry>> {
+    ensure!(bytes.remaining() >= 10, "Ziplist header too short");
+    let _total_bytes = bytes.get_u32_le();
+    let _tail_offset = bytes.get_u32_le();
+    let num_entries = bytes.get_u16_le();
+
+    let mut entries = Vec::with_capacity(num_entries.into());
+    loop {
+        ensure!(bytes.has_remaining(), "Ziplist without terminator");
+        if bytes.chunk()[0] == EOF {
+            break;
+        }
+        if bytes.get_u8() == BIG_PREVLEN {
+            ensure!(bytes.remaining() >= 4, "Truncated ziplist entry");
+            bytes.advance(4);
+        }
+        entries.push(parse_entry(&mut bytes)?);
+    }
+    Ok(entries)
+}
+
+fn parse_entry(bytes: &mut Bytes) -> anyhow::Result<ListpackEntry> {
+    let need = |bytes: &Bytes, n: usize| {
+        ensure!(bytes.remaining() >= n, "Truncated ziplist entry");
+        Ok(())
+    };
+    let string = |bytes: &mut Bytes, len: usize| {
+        need(bytes, len)?;
+        anyhow::Ok(ListpackEntry::Str(bytes.split_to(len)))
+    };
+
+    need(bytes, 1)?;
+    let encoding = bytes.get_u8();
+    Ok(match encoding {
+        // 00pppppp 6 bit str len
+        0x00..=0x3F => string(bytes, usize::from(encoding))?,
+        // 01pppppp qqqqqqqq 14 bit str len
+        0x40..=0x7F => {
+            need(bytes, 1)?;
+            let len = (usize::from(encoding & 0x3F) << 8) | usize::from(bytes.get_u8());
+            string(bytes, len)?
+        }
+        // 10000000 + 4 bytes big endian str len
+        0x80 => {
+            need(bytes, 4)?;
+            let len = bytes.get_u32() as usize;
+            string(bytes, len)?
+        }
+        0xC0 => {
+            need(bytes, 2)?;
+            ListpackEntry::Int(bytes.get_i16_le().into())
+        }
+        0xD0 => {
+            need(bytes, 4)?;
+            ListpackEntry::Int(bytes.get_i32_le().into())
+        }
+        0xE0 => {
+            need(bytes, 8)?;
+            ListpackEntry::Int(bytes.get_i64_le())
+        }
+        0xF0 => {
+            need(bytes, 3)?;
+            // sign extend from 24 bits
+            ListpackEntry::Int((bytes.get_int_le(3) << 40) >> 40)
+        }
+        0xFE => {
+            need(bytes, 1)?;
+            ListpackEntry::Int(bytes.get_i8().into())
+        }
+        // 1111xxxx immediate 0 to 12, stored as xxxx - 1
+        0xF1..=0xFD => ListpackEntry::Int(i64::from(encoding & 0x0F) - 1),
+        _ => bail!("Invalid ziplist encoding: {encoding:#x}"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_ziplist() {
+        let body = [
+            &[0x00, 0x02, b'h', b'i'][..], // "hi"
+            &[0x04, 0xF4],                 // 3
+            &[0x02, 0xC0, 0x00, 0x80],     // i16::MIN
+            &[0x04, 0xFE, 0xFF],           // -1
+            &[EOF],
+        ]
+        .concat();
+        let header = [
+            u32::try_from(body.len() + 10)
+                .unwrap()
+                .to_le_bytes()
+                .as_ref(),
+            &0_u32.to_le_bytes(),
+            &4_u16.to_le_bytes(),
+        ]
+        .concat();
+        let entries = parse([header, body].concat().into()).unwrap();
+        pretty_assertions::assert_eq!(
+            entries,
+            vec![
+                ListpackEntry::Str("hi".into()),
+                ListpackEntry::Int(3),
+                ListpackEntry::Int(i16::MIN.into()),
+                ListpackEntry::Int(-1),
+            ]
+        );
+    }
+}
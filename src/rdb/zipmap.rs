@@ -0,0 +1,59 @@
+use anyhow::{ensure, Context};
+use bytes::{Buf, Bytes};
+
+// https://github.com/redis/redis/blob/unstable/src/zipmap.c
+const EOF: u8 = 0xFF;
+/// Lengths from this value on are stored in the next 4 bytes
+const BIGLEN: u8 = 0xFE;
+
+/// Field value pairs of a zipmap, the hash encoding used before Redis 2.6
+pub fn parse(mut bytes: Bytes) -> anyhow::Result<Vec<(Bytes, Bytes)>> {
+    ensure!(bytes.has_remaining(), "Empty zipmap");
+    let _len = bytes.get_u8();
+
+    let mut pairs = Vec::new();
+    while let Some(len) = read_len(&mut bytes)? {
+        ensure!(bytes.remaining() >= len, "Truncated zipmap field");
+        let field = bytes.split_to(len);
+
+        let len = read_len(&mut bytes)?.context("Zipmap field without value")?;
+        ensure!(bytes.has_remaining(), "Truncated zipmap value");
+        let free = usize::from(bytes.get_u8());
+        ensure!(bytes.remaining() >= len + free, "Truncated zipmap value");
+        let value = bytes.split_to(len);
+        bytes.advance(free);
+
+        pairs.push((field, value));
+    }
+    Ok(pairs)
+}
+
+/// `None` once the end of the zipmap is reached
+fn read_len(bytes: &mut Bytes) -> anyhow::Result<Option<usize>> {
+    ensure!(bytes.has_remaining(), "Zipmap without terminator");
+    Ok(match bytes.get_u8() {
+        EOF => None,
+        BIGLEN => {
+            ensure!(bytes.remaining() >= 4, "Truncated zipmap length");
+            Some(bytes.get_u32_le() as usize)
+        }
+        len => Some(len.into()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_zipmap() {
+        let bytes = Bytes::from_static(b"\x02\x01a\x02\x01bc\x00\x03foo\x03\x00bar\xff");
+        pretty_assertions::assert_eq!(
+            parse(bytes).unwrap(),
+            vec![
+                (Bytes::from("a"), Bytes::from("bc")),
+                (Bytes::from("foo"), Bytes::from("bar")),
+            ]
+        );
+    }
+}
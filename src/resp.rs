@@ -1,15 +1,28 @@
 use anyhow::{bail, Context};
 use atoi::FromRadix10SignedChecked;
-use bytes::{Buf, Bytes};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 use std::io::Cursor;
 use thiserror::Error;
 
-use crate::slice_to_int;
+use crate::{slice_to_int, RedisError};
+
+mod codec;
+pub use codec::RespCodec;
+
+mod payload;
+pub use payload::{stream_payload, Chunks, EOF_MARK};
 
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("Incomplete resp")]
     Incomplete,
+    /// A big bulk string is still missing this many bytes
+    #[error("Incomplete bulk string")]
+    IncompleteBulk(usize),
+    #[error("ERR Protocol error: {0}")]
+    Protocol(&'static str),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
     #[error(transparent)]
     Other(#[from] anyhow::Error),
 }
@@ -23,68 +36,171 @@ pub enum Resp {
     Integer(i64),
     Data(Bytes),
     Null,
+    /// `*-1` in RESP2, replied instead of an array when there's none, like when XREAD times out
+    NullArray,
+    /// RESP3 out-of-band data, like pub/sub messages
+    Push(Vec<Self>),
+    /// RESP3 map, written as a flat array to RESP2 connections
+    Map(Vec<(Self, Self)>),
 }
 
-impl Resp {
-    const CRLF_LEN: usize = b"\r\n".len();
+/// Protocol version negotiated by a connection
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    #[default]
+    Resp2,
+    Resp3,
+}
 
-    pub fn parse_rdb(cur: &mut Cursor<&[u8]>) -> anyhow::Result<Bytes> {
-        if get_u8(cur)? != b'$' {
-            bail!("Not a rdb");
+impl Protocol {
+    #[inline]
+    pub(crate) const fn version(self) -> i64 {
+        match self {
+            Self::Resp2 => 2,
+            Self::Resp3 => 3,
         }
-        let len = slice_to_int::<usize>(read_line(cur)?)?;
-        let data = Bytes::copy_from_slice(&cur.chunk()[..len]);
-        advance(cur, len)?;
-        Ok(data)
     }
+}
 
-    pub fn parse(cur: &mut Cursor<&[u8]>) -> anyhow::Result<Self> {
+impl Resp {
+    const CRLF_LEN: usize = b"\r\n".len();
+    /// Maximum number of elements in an aggregate frame
+    const MAX_AGGREGATE_LEN: usize = 1024 * 1024;
+    /// Maximum nesting of aggregate frames
+    const MAX_DEPTH: usize = 32;
+    /// Bulk strings from this length on are sliced out of the frame instead of copied
+    pub const BIG_BULK_LEN: usize = 32 * 1024;
+    /// Default for `proto-max-bulk-len`
+    pub const DEFAULT_MAX_BULK_LEN: usize = 512 * 1024 * 1024;
+
+    pub fn parse(cur: &mut Cursor<&[u8]>) -> Result<Self, Error> {
         tracing::trace!("Parsing: {:?}", Bytes::copy_from_slice(cur.chunk()));
 
+        let resp = Self::parse_nested(cur, 0, None)?;
+        tracing::debug!("Parsed {resp:?}");
+
+        Ok(resp)
+    }
+
+    /// Parses a whole frame, sharing the memory of big bulk strings with it
+    pub fn parse_frame(frame: &Bytes) -> Result<Self, Error> {
+        tracing::trace!("Parsing frame of {} bytes", frame.len());
+
+        let resp = Self::parse_nested(&mut Cursor::new(frame.as_ref()), 0, Some(frame))?;
+        tracing::debug!("Parsed {resp:?}");
+
+        Ok(resp)
+    }
+
+    fn parse_nested(
+        cur: &mut Cursor<&[u8]>,
+        depth: usize,
+        frame: Option<&Bytes>,
+    ) -> Result<Self, Error> {
+        if depth > Self::MAX_DEPTH {
+            return Err(Error::Protocol("too many nested aggregates"));
+        }
+
         let resp = match get_u8(cur)? {
-            b'*' => {
-                let len = slice_to_int::<usize>(read_line(cur)?)?;
+            b'*' if cur.chunk().starts_with(b"-1") => {
+                advance(cur, b"-1\r\n".len())?;
+                Self::NullArray
+            }
+            c @ (b'*' | b'>') => {
+                let len = read_aggregate_len(cur)?;
                 let mut elems = Vec::with_capacity(len);
 
                 for _ in 0..len {
-                    elems.push(Self::parse(cur)?);
+                    elems.push(Self::parse_nested(cur, depth + 1, frame)?);
+                }
+                if c == b'>' {
+                    Self::Push(elems)
+                } else {
+                    Self::Array(elems)
                 }
-                Self::Array(elems)
             }
-            b'+' => String::from_utf8(read_line(cur)?.to_vec())
-                .map(Self::Simple)
-                .map_err(anyhow::Error::from)?,
+            b'+' => Self::Simple(read_string(cur)?),
+            b'-' => Self::Err(read_string(cur)?),
             b'$' => 'bulk: {
                 if cur.chunk().starts_with(b"-1") {
                     advance(cur, b"-1\r\n".len())?;
                     break 'bulk Self::Null;
                 }
-                let len = slice_to_int::<usize>(read_line(cur)?)?;
-
-                let data = Bytes::copy_from_slice(&cur.chunk()[..len]);
-                advance(cur, len + Self::CRLF_LEN)?;
+                let len = read_len(cur, "invalid bulk length")?;
+
+                let data = cur.chunk().get(..len).ok_or(Error::Incomplete)?;
+                let data = match frame {
+                    Some(frame) if len >= Self::BIG_BULK_LEN => frame.slice_ref(data),
+                    _ => Bytes::copy_from_slice(data),
+                };
+                skip_bulk(cur, len)?;
                 Self::Bulk(data)
             }
-            b':' => Self::Integer(slice_to_int::<i64>(read_line(cur)?)?),
-            c => unimplemented!("{:?}", c as char),
-        };
-        tracing::debug!("Parsed {resp:?}");
+            b':' => Self::Integer(
+                slice_to_int::<i64>(read_line(cur)?)
+                    .map_err(|_| Error::Protocol("invalid integer"))?,
+            ),
+            b'%' => {
+                let len = read_aggregate_len(cur)?;
+                let mut pairs = Vec::with_capacity(len);
 
+                for _ in 0..len {
+                    pairs.push((
+                        Self::parse_nested(cur, depth + 1, frame)?,
+                        Self::parse_nested(cur, depth + 1, frame)?,
+                    ));
+                }
+                Self::Map(pairs)
+            }
+            b'_' => {
+                read_line(cur)?;
+                Self::Null
+            }
+            _ => return Err(Error::Protocol("unknown frame type")),
+        };
         Ok(resp)
     }
 
     pub fn check(cur: &mut Cursor<&[u8]>) -> Result<(), Error> {
+        Self::check_bounded(cur, Self::DEFAULT_MAX_BULK_LEN)
+    }
+
+    /// Like [`Resp::check`], rejecting bulk strings longer than `max_bulk_len`
+    /// before their payload is buffered
+    pub fn check_bounded(cur: &mut Cursor<&[u8]>, max_bulk_len: usize) -> Result<(), Error> {
         tracing::trace!("Checking: {:?}", Bytes::copy_from_slice(cur.chunk()));
 
+        Self::check_nested(cur, 0, max_bulk_len)
+    }
+
+    fn check_nested(
+        cur: &mut Cursor<&[u8]>,
+        depth: usize,
+        max_bulk_len: usize,
+    ) -> Result<(), Error> {
+        if depth > Self::MAX_DEPTH {
+            return Err(Error::Protocol("too many nested aggregates"));
+        }
+
         match get_u8(cur)? {
-            b'*' => {
-                let len = slice_to_int::<usize>(read_line(cur)?)?;
+            b'*' if cur.chunk().starts_with(b"-1") => {
+                advance(cur, b"-1\r\n".len())?;
+            }
+            b'*' | b'>' => {
+                let len = read_aggregate_len(cur)?;
 
                 for _ in 0..len {
-                    Self::check(cur)?;
+                    Self::check_nested(cur, depth + 1, max_bulk_len)?;
                 }
             }
-            b'+' | b':' => {
+            b'%' => {
+                let len = read_aggregate_len(cur)?;
+
+                for _ in 0..len * 2 {
+                    Self::check_nested(cur, depth + 1, max_bulk_len)?;
+                }
+            }
+            b'+' | b'-' | b':' | b'_' => {
                 read_line(cur)?;
             }
             b'$' => 'bulk: {
@@ -92,11 +208,19 @@ impl Resp {
                     advance(cur, b"-1\r\n".len())?;
                     break 'bulk;
                 }
-                let len = slice_to_int::<usize>(read_line(cur)?)?;
+                let len = read_len(cur, "invalid bulk length")?;
+                if len > max_bulk_len {
+                    return Err(Error::Protocol("invalid bulk length"));
+                }
+                if len >= Self::BIG_BULK_LEN && cur.remaining() < len + Self::CRLF_LEN {
+                    return Err(Error::IncompleteBulk(
+                        len + Self::CRLF_LEN - cur.remaining(),
+                    ));
+                }
 
-                advance(cur, len + Self::CRLF_LEN)?;
+                skip_bulk(cur, len)?;
             }
-            c => unimplemented!("{:?}", c as char),
+            _ => return Err(Error::Protocol("unknown frame type")),
         }
         Ok(())
     }
@@ -122,7 +246,7 @@ impl Resp {
             Self::Bulk(resp) => slice_to_int(resp)?,
             Self::Simple(resp) => slice_to_int(resp.as_bytes())?,
             // Self::Integer(resp) => *resp,
-            resp => bail!("Not valid RESP for a int {resp:?}"),
+            _ => bail!(RedisError::NotInteger),
         })
     }
 
@@ -142,11 +266,12 @@ impl Resp {
         }
     }
 
-    #[inline]
-    pub(crate) const fn as_simple(&self) -> Option<&String> {
-        match self {
-            Self::Simple(inner) => Some(inner),
-            _ => None,
+    /// Turns an array into a push frame when `protocol` supports it
+    #[must_use]
+    pub(crate) fn into_push(self, protocol: Protocol) -> Self {
+        match (self, protocol) {
+            (Self::Array(elems), Protocol::Resp3) => Self::Push(elems),
+            (resp, _) => resp,
         }
     }
 
@@ -160,6 +285,75 @@ impl Resp {
         Self::Simple(s.into())
     }
 
+    /// Serializes the frame as RESP2
+    pub fn encode(&self, dst: &mut BytesMut) {
+        self.encode_with(dst, Protocol::Resp2);
+    }
+
+    /// Serializes the frame, using the RESP3 types when `protocol` allows it
+    pub fn encode_with(&self, dst: &mut BytesMut, protocol: Protocol) {
+        let put_header = |dst: &mut BytesMut, prefix: u8, len: usize| {
+            dst.put_u8(prefix);
+            dst.put_slice(len.to_string().as_bytes());
+            dst.put_slice(b"\r\n");
+        };
+
+        match self {
+            Self::Simple(inner) | Self::Err(inner) => {
+                dst.put_u8(if matches!(self, Self::Simple(_)) {
+                    b'+'
+                } else {
+                    b'-'
+                });
+                dst.put_slice(inner.as_bytes());
+                dst.put_slice(b"\r\n");
+            }
+            Self::Bulk(inner) => {
+                put_header(dst, b'$', inner.len());
+                dst.put_slice(inner);
+                dst.put_slice(b"\r\n");
+            }
+            Self::Array(elems) | Self::Push(elems) => {
+                let prefix = if matches!(self, Self::Push(_)) {
+                    b'>'
+                } else {
+                    b'*'
+                };
+                put_header(dst, prefix, elems.len());
+                for elem in elems {
+                    elem.encode_with(dst, protocol);
+                }
+            }
+            Self::Integer(inner) => {
+                dst.put_u8(b':');
+                dst.put_slice(inner.to_string().as_bytes());
+                dst.put_slice(b"\r\n");
+            }
+            Self::Map(pairs) => {
+                match protocol {
+                    Protocol::Resp2 => put_header(dst, b'*', pairs.len() * 2),
+                    Protocol::Resp3 => put_header(dst, b'%', pairs.len()),
+                }
+                for (key, value) in pairs {
+                    key.encode_with(dst, protocol);
+                    value.encode_with(dst, protocol);
+                }
+            }
+            Self::Data(inner) => {
+                put_header(dst, b'$', inner.len());
+                dst.put_slice(inner);
+            }
+            Self::Null => match protocol {
+                Protocol::Resp2 => dst.put_slice(b"$-1\r\n"),
+                Protocol::Resp3 => dst.put_slice(b"_\r\n"),
+            },
+            Self::NullArray => match protocol {
+                Protocol::Resp2 => dst.put_slice(b"*-1\r\n"),
+                Protocol::Resp3 => dst.put_slice(b"_\r\n"),
+            },
+        }
+    }
+
     #[allow(clippy::len_without_is_empty)]
     pub fn len(&self) -> usize {
         let mut len = 1_usize;
@@ -170,11 +364,18 @@ impl Resp {
             Self::Bulk(inner) => {
                 len += int_len(inner.len()) + Self::CRLF_LEN + inner.len() + Self::CRLF_LEN;
             }
-            Self::Array(elems) => {
+            Self::Array(elems) | Self::Push(elems) => {
                 len += int_len(elems.len())
                     + Self::CRLF_LEN
                     + elems.iter().fold(0, |acc, x| acc + Self::len(x));
             }
+            Self::Map(pairs) => {
+                len += int_len(pairs.len())
+                    + Self::CRLF_LEN
+                    + pairs
+                        .iter()
+                        .fold(0, |acc, (k, v)| acc + Self::len(k) + Self::len(v));
+            }
             #[allow(clippy::cast_possible_truncation)]
             Self::Integer(inner) => {
                 if inner.is_negative() {
@@ -183,8 +384,8 @@ impl Resp {
                 len += int_len(inner.unsigned_abs() as usize) + Self::CRLF_LEN;
             }
             Self::Data(inner) => len += int_len(inner.len()) + Self::CRLF_LEN + inner.len(),
-            Self::Null => len += b"-1".len() + Self::CRLF_LEN,
-        };
+            Self::Null | Self::NullArray => len += b"-1".len() + Self::CRLF_LEN,
+        }
         len
     }
 }
@@ -204,6 +405,32 @@ fn advance(cur: &mut Cursor<&[u8]>, n: usize) -> Result<(), Error> {
     Ok(())
 }
 
+/// Skips a bulk string's payload and the CRLF that must end it
+fn skip_bulk(cur: &mut Cursor<&[u8]>, len: usize) -> Result<(), Error> {
+    advance(cur, len)?;
+    match cur.chunk().get(..Resp::CRLF_LEN) {
+        Some(b"\r\n") => advance(cur, Resp::CRLF_LEN),
+        Some(_) => Err(Error::Protocol("expected CRLF")),
+        None => Err(Error::Incomplete),
+    }
+}
+
+fn read_len(cur: &mut Cursor<&[u8]>, err: &'static str) -> Result<usize, Error> {
+    slice_to_int::<usize>(read_line(cur)?).map_err(|_| Error::Protocol(err))
+}
+
+fn read_aggregate_len(cur: &mut Cursor<&[u8]>) -> Result<usize, Error> {
+    let len = read_len(cur, "invalid multibulk length")?;
+    if len > Resp::MAX_AGGREGATE_LEN {
+        return Err(Error::Protocol("invalid multibulk length"));
+    }
+    Ok(len)
+}
+
+fn read_string(cur: &mut Cursor<&[u8]>) -> Result<String, Error> {
+    String::from_utf8(read_line(cur)?.to_vec()).map_err(|_| Error::Protocol("invalid string"))
+}
+
 fn read_line<'a>(cur: &mut Cursor<&'a [u8]>) -> Result<&'a [u8], Error> {
     let chunk = cur.chunk();
     let start = cur.get_ref().len() - chunk.len();
@@ -241,6 +468,163 @@ mod tests {
         assert!(!cur.has_remaining());
     }
 
+    #[test]
+    fn protocol_errors() {
+        let check = |bytes: &[u8]| Resp::check(&mut Cursor::new(bytes));
+
+        assert!(matches!(check(b"?\r\n"), Err(Error::Protocol(_))));
+        assert!(matches!(check(b"*abc\r\n"), Err(Error::Protocol(_))));
+        assert!(matches!(
+            check(b"*99999999999\r\n"),
+            Err(Error::Protocol(_))
+        ));
+        assert!(matches!(check(b"$-5\r\n"), Err(Error::Protocol(_))));
+        assert!(matches!(check(b"*1\r\n$5\r\nab"), Err(Error::Incomplete)));
+        let unterminated = b"*1\r\n$4\r\nPINGxx\r\n";
+        assert!(matches!(
+            check(unterminated),
+            Err(Error::Protocol("expected CRLF"))
+        ));
+        assert!(matches!(
+            Resp::parse(&mut Cursor::new(unterminated)),
+            Err(Error::Protocol("expected CRLF"))
+        ));
+        let big = format!("*1\r\n${}\r\nab", Resp::BIG_BULK_LEN);
+        assert!(matches!(
+            check(big.as_bytes()),
+            Err(Error::IncompleteBulk(n)) if n == Resp::BIG_BULK_LEN
+        ));
+        assert!(matches!(
+            Resp::check_bounded(&mut Cursor::new(b"$5\r\n"), 4),
+            Err(Error::Protocol(_))
+        ));
+
+        let nested = "*1\r\n".repeat(Resp::MAX_DEPTH + 2);
+        assert!(matches!(check(nested.as_bytes()), Err(Error::Protocol(_))));
+    }
+
+    #[test]
+    fn error_frames() {
+        let parse = |bytes: &[u8]| Resp::parse(&mut Cursor::new(bytes)).unwrap();
+
+        pretty_assertions::assert_eq!(parse(b"-ERR oops\r\n"), Resp::Err("ERR oops".to_owned()));
+        // Like the reply to an EXEC with a failed command
+        let exec = b"*2\r\n+OK\r\n-WRONGTYPE Operation against a key\r\n";
+        pretty_assertions::assert_eq!(
+            parse(exec),
+            Resp::Array(vec![
+                Resp::simple("OK"),
+                Resp::Err("WRONGTYPE Operation against a key".to_owned())
+            ])
+        );
+        pretty_assertions::assert_eq!(parse(exec).len(), exec.len());
+        let mut cur = Cursor::new(&exec[..]);
+        Resp::check(&mut cur).unwrap();
+        assert!(!cur.has_remaining());
+        assert!(matches!(
+            Resp::check(&mut Cursor::new(b"-ERR oo")),
+            Err(Error::Incomplete)
+        ));
+
+        let mut dst = BytesMut::new();
+        parse(b"-ERR oops\r\n").encode(&mut dst);
+        pretty_assertions::assert_eq!(dst.as_ref(), b"-ERR oops\r\n");
+    }
+
+    #[test]
+    fn max_bulk_len() {
+        let check = |bytes: &[u8]| Resp::check_bounded(&mut Cursor::new(bytes), 5);
+
+        assert!(check(b"$5\r\nhello\r\n").is_ok());
+        // Refused from the length alone, before the payload is even received
+        assert!(matches!(check(b"$6\r\n"), Err(Error::Protocol(_))));
+        assert!(matches!(
+            check(b"*2\r\n$1\r\na\r\n$6\r\n"),
+            Err(Error::Protocol(_))
+        ));
+
+        let at_default = format!("${}\r\n", Resp::DEFAULT_MAX_BULK_LEN);
+        assert!(matches!(
+            Resp::check(&mut Cursor::new(at_default.as_bytes())),
+            Err(Error::IncompleteBulk(_))
+        ));
+        let over_default = format!("${}\r\n", Resp::DEFAULT_MAX_BULK_LEN + 1);
+        assert!(matches!(
+            Resp::check(&mut Cursor::new(over_default.as_bytes())),
+            Err(Error::Protocol(_))
+        ));
+    }
+
+    #[test]
+    fn encode() {
+        let frames: [&[u8]; 7] = [
+            b"*2\r\n$4\r\necho\r\n$3\r\nhey\r\n",
+            b"-ERR oops\r\n",
+            b":-42\r\n",
+            b"$-1\r\n",
+            b"*-1\r\n",
+            b">2\r\n$7\r\nmessage\r\n$2\r\nhi\r\n",
+            b"%1\r\n$5\r\nproto\r\n:3\r\n",
+        ];
+        for frame in frames {
+            let resp = Resp::parse(&mut Cursor::new(frame)).unwrap();
+            let mut dst = BytesMut::new();
+            resp.encode_with(&mut dst, Protocol::Resp3);
+            if matches!(resp, Resp::Null | Resp::NullArray) {
+                pretty_assertions::assert_eq!(dst.as_ref(), b"_\r\n");
+            } else {
+                pretty_assertions::assert_eq!(dst.as_ref(), frame);
+            }
+        }
+
+        let map = Resp::Map(vec![(Resp::bulk("proto"), Resp::Integer(2))]);
+        let mut dst = BytesMut::new();
+        map.encode(&mut dst);
+        pretty_assertions::assert_eq!(dst.as_ref(), b"*2\r\n$5\r\nproto\r\n:2\r\n");
+
+        // Both nulls keep their own type in RESP2
+        for (null, frame) in [(Resp::Null, b"$-1\r\n"), (Resp::NullArray, b"*-1\r\n")] {
+            let mut dst = BytesMut::new();
+            null.encode(&mut dst);
+            pretty_assertions::assert_eq!(dst.as_ref(), frame);
+            pretty_assertions::assert_eq!(null.len(), frame.len());
+            let mut cur = Cursor::new(&frame[..]);
+            Resp::check(&mut cur).unwrap();
+            assert!(!cur.has_remaining());
+            pretty_assertions::assert_eq!(Resp::parse(&mut Cursor::new(&frame[..])).unwrap(), null);
+        }
+    }
+
+    #[test]
+    fn parse_frame() {
+        let value = "x".repeat(Resp::BIG_BULK_LEN);
+        let frame = Bytes::from(format!(
+            "*2\r\n$3\r\nset\r\n${}\r\n{value}\r\n",
+            value.len()
+        ));
+
+        let resp = Resp::parse_frame(&frame).unwrap();
+        let Resp::Array(elems) = resp else {
+            panic!("Expected array");
+        };
+        pretty_assertions::assert_eq!(elems[0], Resp::bulk("set"));
+        let Resp::Bulk(big) = &elems[1] else {
+            panic!("Expected bulk");
+        };
+        // The big bulk points into the frame instead of being copied
+        assert!(frame.as_ptr_range().contains(&big.as_ptr()));
+    }
+
+    #[test]
+    fn into_push() {
+        let array = Resp::Array(vec![Resp::bulk("message")]);
+        pretty_assertions::assert_eq!(array.clone().into_push(Protocol::Resp2), array);
+        pretty_assertions::assert_eq!(
+            array.into_push(Protocol::Resp3),
+            Resp::Push(vec![Resp::bulk("message")])
+        );
+    }
+
     #[test]
     fn len() {
         let to_resp = |bytes: &[u8]| Resp::parse(&mut Cursor::new(bytes)).unwrap();
@@ -259,5 +643,15 @@ mod tests {
 
         let simple = b"+OK\r\n";
         pretty_assertions::assert_eq!(to_resp(simple).len(), simple.len());
+
+        let err = b"-ERR unknown command\r\n";
+        pretty_assertions::assert_eq!(to_resp(err), Resp::Err("ERR unknown command".into()));
+        pretty_assertions::assert_eq!(to_resp(err).len(), err.len());
+
+        let push = b">2\r\n$7\r\nmessage\r\n$2\r\nhi\r\n";
+        pretty_assertions::assert_eq!(to_resp(push).len(), push.len());
+
+        let map = b"%1\r\n$5\r\nproto\r\n:3\r\n";
+        pretty_assertions::assert_eq!(to_resp(map).len(), map.len());
     }
 }
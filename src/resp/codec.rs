@@ -0,0 +1,130 @@
+use bytes::{Buf, BytesMut};
+use std::io::Cursor;
+use tokio_util::codec::{Decoder, Encoder};
+
+use super::{Error, Protocol, Resp};
+
+/// Frames RESP over a byte stream, for use with [`tokio_util::codec::Framed`]
+#[derive(Debug, Clone, Copy)]
+pub struct RespCodec {
+    max_bulk_len: usize,
+    /// Version replies are encoded with
+    protocol: Protocol,
+}
+
+impl Default for RespCodec {
+    fn default() -> Self {
+        Self::new(Resp::DEFAULT_MAX_BULK_LEN)
+    }
+}
+
+impl RespCodec {
+    /// Codec refusing bulk strings longer than `max_bulk_len`
+    #[must_use]
+    pub const fn new(max_bulk_len: usize) -> Self {
+        Self {
+            max_bulk_len,
+            protocol: Protocol::Resp2,
+        }
+    }
+
+    #[inline]
+    #[must_use]
+    pub const fn protocol(&self) -> Protocol {
+        self.protocol
+    }
+
+    #[inline]
+    pub const fn set_protocol(&mut self, protocol: Protocol) {
+        self.protocol = protocol;
+    }
+
+    /// Whether `buf` holds a complete frame, so decoding it won't wait for more bytes
+    #[must_use]
+    pub fn has_frame(&self, buf: &[u8]) -> bool {
+        !buf.is_empty()
+            && !matches!(
+                Resp::check_bounded(&mut Cursor::new(buf), self.max_bulk_len),
+                Err(Error::Incomplete | Error::IncompleteBulk(_))
+            )
+    }
+}
+
+impl Decoder for RespCodec {
+    type Item = Resp;
+    type Error = Error;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Resp>, Error> {
+        if buf.is_empty() {
+            return Ok(None);
+        }
+        let mut cur = Cursor::new(buf.as_ref());
+
+        match Resp::check_bounded(&mut cur, self.max_bulk_len) {
+            Ok(()) => {
+                let len = cur.position().try_into().map_err(anyhow::Error::from)?;
+                if len < Resp::BIG_BULK_LEN {
+                    cur.set_position(0);
+                    let resp = Resp::parse(&mut cur).map(Option::Some);
+                    buf.advance(len);
+                    return resp;
+                }
+                // Big values keep pointing into the read buffer instead of being copied
+                let frame = buf.split_to(len).freeze();
+                Resp::parse_frame(&frame).map(Option::Some)
+            }
+            Err(Error::Incomplete) => Ok(None),
+            Err(Error::IncompleteBulk(missing)) => {
+                // Grow the buffer once for the whole payload, rather than doubling it while reading
+                buf.reserve(missing);
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// A frame cut by the end of the stream is dropped, like the connection
+    fn decode_eof(&mut self, buf: &mut BytesMut) -> Result<Option<Resp>, Error> {
+        self.decode(buf)
+    }
+}
+
+impl Encoder<&Resp> for RespCodec {
+    type Error = std::io::Error;
+
+    fn encode(&mut self, resp: &Resp, dst: &mut BytesMut) -> std::io::Result<()> {
+        resp.encode_with(dst, self.protocol);
+        Ok(())
+    }
+}
+
+impl Encoder<Resp> for RespCodec {
+    type Error = std::io::Error;
+
+    fn encode(&mut self, resp: Resp, dst: &mut BytesMut) -> std::io::Result<()> {
+        self.encode(&resp, dst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::{SinkExt, StreamExt};
+    use tokio_util::codec::{FramedRead, FramedWrite};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn framed() {
+        let mut out = Vec::new();
+        let mut writer = FramedWrite::new(&mut out, RespCodec::default());
+        writer.send(Resp::simple("OK")).await.unwrap();
+        writer.send(Resp::bulk("abc")).await.unwrap();
+        pretty_assertions::assert_eq!(out, b"+OK\r\n$3\r\nabc\r\n");
+
+        // A frame cut by the end of the stream is dropped
+        let input: &[u8] = b"+OK\r\n$3\r\nab";
+        let mut reader = FramedRead::new(input, RespCodec::default());
+        pretty_assertions::assert_eq!(reader.next().await.unwrap().unwrap(), Resp::simple("OK"));
+        assert!(reader.next().await.is_none());
+    }
+}
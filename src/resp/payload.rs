@@ -0,0 +1,221 @@
+//! The RDB payload a master sends for a full resync, which is read as it arrives
+//! instead of being framed whole: it's either `$<len>\r\n` and that many bytes,
+//! or `$EOF:<delimiter>\r\n` and the bytes up to the delimiter
+
+use anyhow::{bail, ensure, Context};
+use bytes::{Buf, Bytes, BytesMut};
+use std::{
+    io::{BufRead, Read},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt},
+    sync::mpsc,
+};
+
+use crate::slice_to_int;
+
+/// Starts a RDB payload of unknown length, followed by its delimiter
+pub const EOF_MARK: &[u8] = b"$EOF:";
+const EOF_DELIMITER_LEN: usize = 40;
+/// Longest line announcing a payload, which `$EOF:<delimiter>` is
+const MAX_HEADER_LEN: usize = EOF_MARK.len() + EOF_DELIMITER_LEN;
+/// Room made in the buffer before each read
+const READ_LEN: usize = 64 * 1024;
+
+#[derive(Debug, PartialEq, Eq)]
+enum Length {
+    Known(usize),
+    Delimited(Bytes),
+}
+
+/// Reads the payload starting in `buf`, then on `stream`, and sends it to `chunks` as it's read.
+/// What follows the payload is left in `buf`. Returns early if `chunks` is closed,
+/// its receiver having given up on the payload. Fails if nothing is read for `timeout`.
+pub async fn stream_payload(
+    stream: &mut (impl AsyncRead + Unpin),
+    buf: &mut BytesMut,
+    chunks: &mpsc::Sender<Bytes>,
+    timeout: Duration,
+) -> anyhow::Result<()> {
+    let mut stream = Timed { stream, timeout };
+    let length = loop {
+        if let Some(end) = buf.windows(2).position(|b| b == b"\r\n") {
+            let length = parse_header(&buf[..end])?;
+            buf.advance(end + 2);
+            break length;
+        }
+        ensure!(buf.len() <= MAX_HEADER_LEN, "Expected the RDB payload");
+        stream.fill(buf).await?;
+    };
+
+    match length {
+        Length::Known(mut remaining) => loop {
+            let len = remaining.min(buf.len());
+            if len > 0 {
+                remaining -= len;
+                if chunks.send(buf.split_to(len).freeze()).await.is_err() {
+                    return Ok(());
+                }
+            }
+            if remaining == 0 {
+                return Ok(());
+            }
+            stream.fill(buf).await?;
+        },
+        Length::Delimited(delimiter) => loop {
+            if let Some(end) = buf.windows(delimiter.len()).position(|b| b == delimiter) {
+                if end > 0 {
+                    // The receiver is done either way
+                    let _ = chunks.send(buf.split_to(end).freeze()).await;
+                }
+                buf.advance(delimiter.len());
+                return Ok(());
+            }
+            // The delimiter may begin in the last bytes, once more are read
+            let len = buf.len().saturating_sub(delimiter.len() - 1);
+            if len > 0 && chunks.send(buf.split_to(len).freeze()).await.is_err() {
+                return Ok(());
+            }
+            stream.fill(buf).await?;
+        },
+    }
+}
+
+/// Stream that's expected to send something every `timeout`
+struct Timed<'a, S> {
+    stream: &'a mut S,
+    timeout: Duration,
+}
+
+impl<S: AsyncRead + Unpin> Timed<'_, S> {
+    async fn fill(&mut self, buf: &mut BytesMut) -> anyhow::Result<()> {
+        buf.reserve(READ_LEN);
+        let read = tokio::time::timeout(self.timeout, self.stream.read_buf(buf))
+            .await
+            .with_context(|| format!("No data received for {:?}", self.timeout))??;
+        ensure!(
+            read > 0,
+            "Connection closed before the end of the RDB payload"
+        );
+        Ok(())
+    }
+}
+
+fn parse_header(line: &[u8]) -> anyhow::Result<Length> {
+    if let Some(delimiter) = line.strip_prefix(EOF_MARK) {
+        ensure!(
+            delimiter.len() == EOF_DELIMITER_LEN,
+            "Invalid RDB EOF delimiter"
+        );
+        return Ok(Length::Delimited(Bytes::copy_from_slice(delimiter)));
+    }
+    let Some(len) = line.strip_prefix(b"$") else {
+        bail!(
+            "Expected the RDB payload, got {:?}",
+            String::from_utf8_lossy(line)
+        );
+    };
+    let len = slice_to_int::<usize>(len).context("Invalid RDB payload length")?;
+    Ok(Length::Known(len))
+}
+
+/// Blocking reader of the chunks sent by [`stream_payload`], so that
+/// [`crate::Rdb::read`] parses them on a thread of its own as they arrive
+#[derive(Debug)]
+pub struct Chunks {
+    rx: mpsc::Receiver<Bytes>,
+    chunk: Bytes,
+}
+
+impl Chunks {
+    pub const fn new(rx: mpsc::Receiver<Bytes>) -> Self {
+        Self {
+            rx,
+            chunk: Bytes::new(),
+        }
+    }
+}
+
+impl Read for Chunks {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let available = self.fill_buf()?;
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl BufRead for Chunks {
+    /// Waits for the next chunk once this one is consumed, empty once the sender is dropped
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        while self.chunk.is_empty() {
+            match self.rx.blocking_recv() {
+                Some(chunk) => self.chunk = chunk,
+                None => break,
+            }
+        }
+        Ok(&self.chunk)
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.chunk.advance(amt);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncWriteExt;
+
+    use super::*;
+
+    /// The payload and what follows it, written a few bytes at a time
+    async fn stream(input: String) -> (Vec<Bytes>, BytesMut) {
+        let (mut reader, mut writer) = tokio::io::duplex(7);
+        tokio::spawn(async move { writer.write_all(input.as_bytes()).await });
+        let (tx, mut rx) = mpsc::channel(1024);
+        let mut buf = BytesMut::new();
+        stream_payload(&mut reader, &mut buf, &tx, Duration::from_secs(5))
+            .await
+            .unwrap();
+        drop(tx);
+        let mut chunks = Vec::new();
+        while let Some(chunk) = rx.recv().await {
+            chunks.push(chunk);
+        }
+        // Bytes after the payload may still be in flight
+        while !buf.ends_with(b"\r\n") {
+            reader.read_buf(&mut buf).await.unwrap();
+        }
+        (chunks, buf)
+    }
+
+    #[tokio::test]
+    async fn known_length() {
+        let payload = format!("REDIS{}", "x".repeat(50));
+        let (chunks, rest) = stream(format!("$55\r\n{payload}+OK\r\n")).await;
+        assert!(chunks.len() > 1);
+        pretty_assertions::assert_eq!(chunks.concat(), payload.as_bytes());
+        pretty_assertions::assert_eq!(rest, &b"+OK\r\n"[..]);
+    }
+
+    #[tokio::test]
+    async fn until_delimiter() {
+        let delimiter = "0123456789".repeat(4);
+        let payload = format!("REDIS{}0123", "x".repeat(50));
+        // The delimiter straddles reads
+        let (chunks, rest) =
+            stream(format!("$EOF:{delimiter}\r\n{payload}{delimiter}+OK\r\n")).await;
+        pretty_assertions::assert_eq!(chunks.concat(), payload.as_bytes());
+        pretty_assertions::assert_eq!(rest, &b"+OK\r\n"[..]);
+    }
+
+    #[test]
+    fn headers() {
+        pretty_assertions::assert_eq!(parse_header(b"$5").unwrap(), Length::Known(5));
+        for line in [&b"-ERR no"[..], b"$EOF:short", b"$-1", b"+OK"] {
+            assert!(parse_header(line).is_err(), "{line:?}");
+        }
+    }
+}
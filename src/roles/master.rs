@@ -1,37 +1,103 @@
+use bytes::{Bytes, BytesMut};
+use parking_lot::{Mutex, RwLock};
 use rand::{distributions::Alphanumeric, Rng};
 use std::{
     net::SocketAddr,
-    sync::atomic::{AtomicU64, Ordering},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
 };
-use tokio::sync::RwLock;
+use tokio::{
+    io::{AsyncWriteExt, BufWriter},
+    net::tcp::OwnedWriteHalf,
+    sync::mpsc::{self, error::TrySendError},
+    task::JoinHandle,
+    time::MissedTickBehavior,
+};
+use tracing::{info_span, Instrument};
 
-use crate::{Handler, Resp};
+use crate::{
+    commands::{Ping, Psync, ReplConf, Select},
+    db::Waiters,
+    handler::Reader,
+    resp::EOF_MARK,
+    Command, Db, Handler, ReplInfo, Resp, Slave, AOF,
+};
 
 #[derive(Debug)]
 pub struct Master {
-    replid: String,
+    replid: Mutex<String>,
     repl_offset: AtomicU64,
+    /// Previous replication id, with the first offset it isn't valid for anymore.
+    /// Replicas of the former master share its history up to there.
+    replid2: Mutex<Option<ReplInfo>>,
+    /// Only ever locked briefly and never across an `.await`: frames are handed
+    /// to each replica's writer task, so a stalled replica can't hold it
     pub(crate) slaves: RwLock<Vec<Replica>>,
+    /// Database the replication stream last selected, unknown once a replica joins
+    selected_db: Mutex<Option<usize>>,
+    /// Clients running WAIT, woken whenever a replica acknowledges an offset
+    acks: Arc<Waiters<()>>,
 }
 
 impl Default for Master {
     fn default() -> Self {
         Self {
-            replid: rand::thread_rng()
-                .sample_iter(Alphanumeric)
-                .take(40)
-                .map(char::from)
-                .collect(),
+            replid: Mutex::new(random_id()),
             repl_offset: AtomicU64::new(0),
+            replid2: Mutex::new(None),
             slaves: RwLock::new(Vec::new()),
+            selected_db: Mutex::new(None),
+            acks: Arc::default(),
         }
     }
 }
 
 impl Master {
+    /// Master taking over from a promoted replica: it starts a new history at the offset
+    /// the replica reached, keeping the one of its former master as the secondary id
+    pub fn promoted(slave: &Slave) -> Self {
+        let offset = slave.offset();
+        let master = Self::default();
+        master.repl_offset.store(offset, Ordering::Relaxed);
+        *master.replid2.lock() = slave.master_replid().map(|id| ReplInfo {
+            id,
+            offset: offset + 1,
+        });
+        master
+    }
+
     #[inline]
-    pub fn replid(&self) -> &str {
-        &self.replid
+    pub fn replid(&self) -> String {
+        self.replid.lock().clone()
+    }
+
+    /// Secondary replication id and the first offset past the history it names, if any
+    pub fn replid2(&self) -> Option<ReplInfo> {
+        self.replid2.lock().clone()
+    }
+
+    /// Starts a new replication history, keeping the current id as the secondary one
+    pub fn shift_replid(&self) {
+        let replid = random_id();
+        let previous = std::mem::replace(&mut *self.replid.lock(), replid.clone());
+        let offset = self.repl_offset() + 1;
+        tracing::info!("New replication id {replid}, previous {previous} valid until {offset}");
+        *self.replid2.lock() = Some(ReplInfo {
+            id: previous,
+            offset,
+        });
+    }
+
+    /// Starts a new replication history forgetting the current one, like DEBUG CHANGE-REPL-ID,
+    /// so replicas have to fully resync
+    pub fn change_replid(&self) {
+        let replid = random_id();
+        tracing::info!("New replication id {replid}");
+        *self.replid.lock() = replid;
+        *self.replid2.lock() = None;
     }
 
     #[inline]
@@ -45,50 +111,503 @@ impl Master {
         tracing::info!("Increased offset of {prev} to {}", by + prev);
     }
 
-    // FIXME async closure https://github.com/rust-lang/rust/issues/62290
-    pub async fn propagate(&self, resp: &Resp, incr_offset: bool) {
-        // FIXME
-        let len = if incr_offset { resp.len() } else { 0 };
+    /// Replication id and offset the dataset currently corresponds to
+    pub fn repl_info(&self) -> ReplInfo {
+        ReplInfo {
+            id: self.replid(),
+            offset: self.repl_offset(),
+        }
+    }
 
-        let mut lock = self.slaves.write().await;
+    /// Resumes the replication history recorded in a dump loaded at startup
+    pub fn restore(&self, repl: ReplInfo) {
+        tracing::info!("Restoring replication id {} at {}", repl.id, repl.offset);
+        *self.replid.lock() = repl.id;
+        self.repl_offset.store(repl.offset, Ordering::Relaxed);
+    }
+
+    pub fn propagate(&self, db: Option<usize>, resp: &Resp, incr_offset: bool) {
+        self.propagate_all(db, std::slice::from_ref(resp), incr_offset);
+    }
+
+    /// Queues the frames on each replica while holding the replicas lock,
+    /// so no other propagation can be interleaved between them.
+    /// Writes to `db` are preceded by a `SELECT` unless the stream already selected it,
+    /// while `None` is for the frames that don't depend on a database, like pings.
+    /// Replicas whose queue is full or whose connection closed are dropped.
+    pub fn propagate_all(&self, db: Option<usize>, resps: &[Resp], incr_offset: bool) {
+        let mut frame = BytesMut::new();
+        for resp in resps {
+            resp.encode(&mut frame);
+        }
+
+        let mut lock = self.slaves.write();
         if lock.is_empty() {
             return;
         }
-        let mut to_retain = Vec::<bool>::with_capacity(lock.len());
-        for slave in &mut *lock {
-            let retain = !slave
-                .handler
-                .write(resp)
-                .await
-                .is_err_and(|e| Handler::disconnected(&e));
-            slave.offset += len as u64;
-            to_retain.push(retain);
+        let mut selected = self.selected_db.lock();
+        if let Some(db) = db.filter(|&db| *selected != Some(db)) {
+            let mut select = BytesMut::new();
+            Select::new(db).into_resp().encode(&mut select);
+            select.unsplit(frame);
+            frame = select;
+            *selected = Some(db);
+        }
+        drop(selected);
+        let frame = frame.freeze();
+        let len = if incr_offset { frame.len() as u64 } else { 0 };
+        lock.retain(|slave| match slave.queue.try_send(frame.clone()) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                tracing::warn!("Replica {} can't keep up, disconnecting it", slave.addr);
+                false
+            }
+            Err(TrySendError::Closed(_)) => {
+                tracing::info!("Replica {} disconnected", slave.addr);
+                false
+            }
+        });
+        drop(lock);
+        self.increase_offset(len);
+    }
+
+    /// Pings the replicas every `period`, which keeps their offsets moving
+    /// and drops the ones that disconnected
+    pub async fn ping_replicas(&self, period: Duration) {
+        let ping = Ping::new(None).into_resp();
+        let mut interval = tokio::time::interval(period);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            self.propagate(None, &ping, true);
         }
-        self.increase_offset(len as u64);
-        let mut retain = to_retain.into_iter();
-        lock.retain(|_| retain.next().unwrap());
     }
 
-    pub async fn add_slave(&self, handler: Handler) {
-        let slave = Replica::new(handler);
-        self.slaves.write().await.push(slave);
+    /// Propagates a DEL for every key of `db` that expired since the last call
+    pub async fn propagate_expired(&self, db: &Db) {
+        let dels = expired_dels(db);
+        if !dels.is_empty() {
+            AOF.feed(&dels).await;
+            self.propagate_all(Some(Db::INDEX), &dels, true);
+        }
     }
+
+    /// Actively expires keys every `period`, so replicas see
+    /// them deleted even if no client touches them
+    pub async fn expire_keys(&self, db: &Db, period: Duration) {
+        let mut interval = tokio::time::interval(period);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            db.expire_all();
+            self.propagate_expired(db).await;
+        }
+    }
+
+    /// Drops the replicas that stayed silent for longer than `timeout`,
+    /// checking every second
+    pub async fn check_replicas(&self, timeout: Duration) {
+        let mut interval = tokio::time::interval(Duration::from_secs(1));
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            self.slaves.write().retain(|slave| {
+                let alive = slave.ack.at.lock().elapsed() < timeout;
+                if !alive {
+                    tracing::warn!("Replica {} timed out, disconnecting it", slave.addr);
+                }
+                alive
+            });
+        }
+    }
+
+    /// Snapshots the dataset for the replica and registers it.
+    /// The replicas lock is held throughout, so no write propagated meanwhile is missed,
+    /// but only while the snapshot is taken: it's sent by the replica's writer task.
+    /// With `stream`, the RDB is serialized while it's sent, delimited by a random mark.
+    /// `listening_port` is the one the replica announced, if it did.
+    pub fn full_resync(
+        &self,
+        db: &Db,
+        handler: Handler,
+        psync: &Psync,
+        stream: bool,
+        listening_port: Option<u16>,
+    ) {
+        let mut slaves = self.slaves.write();
+        // The replica doesn't know which database the stream is at
+        *self.selected_db.lock() = None;
+        let mut header = BytesMut::new();
+        let resync = if stream {
+            let repl = self.repl_info();
+            let delimiter = Bytes::from(random_id());
+            Resp::Simple(format!("FULLRESYNC {} {}", repl.id, repl.offset)).encode(&mut header);
+            header.extend_from_slice(EOF_MARK);
+            header.extend_from_slice(&delimiter);
+            header.extend_from_slice(b"\r\n");
+            let (tx, chunks) = mpsc::channel(RDB_CHUNKS_IN_FLIGHT);
+            db.stream_rdb(repl, RDB_CHUNK_LEN, tx);
+            Resync::Streamed {
+                header: header.freeze(),
+                chunks,
+                delimiter,
+            }
+        } else {
+            let (resp, data) = psync.execute(self, db);
+            resp.encode(&mut header);
+            data.encode(&mut header);
+            Resync::Whole(header.freeze())
+        };
+
+        let mut replica = Replica::new(handler, Arc::clone(&self.acks), resync);
+        replica.listening_port = listening_port;
+        slaves.push(replica);
+        drop(slaves);
+    }
+
+    /// Number of replicas that acknowledged at least `offset`
+    pub fn acked_replicas(&self, offset: u64) -> usize {
+        self.slaves
+            .read()
+            .iter()
+            .filter(|slave| slave.acked_offset() >= offset)
+            .count()
+    }
+
+    /// Waits until `count` replicas acknowledged at least `offset`, or until
+    /// `timeout` elapses if it's not zero. Returns the number of replicas that did.
+    pub async fn wait_for_acks(&self, offset: u64, count: usize, timeout: Duration) -> usize {
+        // Registered before counting, so an ACK arriving in between isn't missed
+        let waiter = self.acks.register([()]);
+        let acked = waiter
+            .block(timeout, || {
+                let acked = self.acked_replicas(offset);
+                Ok((acked >= count).then_some(acked))
+            })
+            .await;
+        match acked {
+            Ok(Some(acked)) => acked,
+            _ => self.acked_replicas(offset),
+        }
+    }
+}
+
+/// DEL commands for the keys that expired since the last call,
+/// and HDEL commands for the hash fields
+pub(crate) fn expired_dels(db: &Db) -> Vec<Resp> {
+    let fields = db.take_expired_fields().into_iter().map(|(key, fields)| {
+        let args = fields.into_iter().map(Resp::Bulk);
+        Resp::Array(
+            [Resp::bulk("HDEL"), Resp::bulk(key)]
+                .into_iter()
+                .chain(args)
+                .collect(),
+        )
+    });
+    db.take_expired()
+        .into_iter()
+        .map(|key| Resp::Array(vec![Resp::bulk("DEL"), Resp::bulk(key)]))
+        .chain(fields)
+        .collect()
+}
+
+/// 40 random characters, like replication ids and RDB delimiters are
+fn random_id() -> String {
+    rand::thread_rng()
+        .sample_iter(Alphanumeric)
+        .take(40)
+        .map(char::from)
+        .collect()
+}
+
+/// Frames a replica can lag behind before it's disconnected
+const REPLICA_QUEUE_LEN: usize = 4096;
+/// Size of the pieces a streamed RDB is sent in
+const RDB_CHUNK_LEN: usize = 64 * 1024;
+/// Chunks serialized ahead of the replica's socket
+const RDB_CHUNKS_IN_FLIGHT: usize = 16;
+
+/// What a replica is sent on a full resync, before the propagated writes
+#[derive(Debug)]
+enum Resync {
+    /// `+FULLRESYNC` and the RDB, prefixed by its length
+    Whole(Bytes),
+    /// `+FULLRESYNC` and `$EOF:<delimiter>`, then the RDB as it's
+    /// serialized, then the delimiter again
+    Streamed {
+        header: Bytes,
+        chunks: mpsc::Receiver<Bytes>,
+        delimiter: Bytes,
+    },
 }
 
 #[derive(Debug)]
 pub struct Replica {
-    pub(crate) handler: Handler,
-    pub(crate) offset: u64,
+    addr: SocketAddr,
+    /// Port the replica serves clients on, rather than the one it connected from
+    listening_port: Option<u16>,
+    /// Frames sent by the replica's writer task
+    queue: mpsc::Sender<Bytes>,
+    /// Updated by the reader task
+    ack: Arc<Ack>,
+    reader: JoinHandle<()>,
+}
+
+#[derive(Debug)]
+struct Ack {
+    /// Last offset acknowledged with `REPLCONF ACK`
+    offset: AtomicU64,
+    /// Last time the replica sent anything
+    at: Mutex<Instant>,
 }
 
 impl Replica {
-    const fn new(handler: Handler) -> Self {
-        Self { handler, offset: 0 }
+    fn new(handler: Handler, acks: Arc<Waiters<()>>, resync: Resync) -> Self {
+        let addr = handler.addr;
+        let (reader, writer) = handler.into_split();
+        let (queue, rx) = mpsc::channel(REPLICA_QUEUE_LEN);
+        tokio::spawn(
+            async move {
+                if let Err(e) = Self::write_frames(writer, resync, rx).await {
+                    tracing::warn!("Failed writing to replica {addr}: {e}");
+                }
+            }
+            .instrument(info_span!("replica-writer", %addr)),
+        );
+        let ack = Arc::new(Ack {
+            offset: AtomicU64::new(0),
+            at: Mutex::new(Instant::now()),
+        });
+        let reader = {
+            let ack = Arc::clone(&ack);
+            tokio::spawn(
+                async move {
+                    if let Err(e) = Self::read_acks(reader, &ack, &acks).await {
+                        tracing::warn!("Failed reading from replica {addr}: {e}");
+                    }
+                }
+                .instrument(info_span!("replica-acks", %addr)),
+            )
+        };
+        Self {
+            addr,
+            listening_port: None,
+            queue,
+            ack,
+            reader,
+        }
+    }
+
+    /// Records the offsets acknowledged by the replica until it disconnects
+    async fn read_acks(mut reader: Reader, ack: &Ack, acks: &Waiters<()>) -> anyhow::Result<()> {
+        while let Some(resp) = reader.read().await? {
+            *ack.at.lock() = Instant::now();
+            if let Ok((Command::ReplConf(ReplConf::Ack(offset)), _)) = Command::parse(&resp) {
+                ack.offset.fetch_max(offset, Ordering::Relaxed);
+                acks.notify(&());
+            } else {
+                tracing::warn!("Unexpected frame from replica: {resp:?}");
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the snapshot, then the queued frames until the replica is dropped,
+    /// flushing once for everything queued at the time
+    async fn write_frames(
+        mut writer: BufWriter<OwnedWriteHalf>,
+        resync: Resync,
+        mut rx: mpsc::Receiver<Bytes>,
+    ) -> std::io::Result<()> {
+        match resync {
+            Resync::Whole(frame) => writer.write_all(&frame).await?,
+            Resync::Streamed {
+                header,
+                mut chunks,
+                delimiter,
+            } => {
+                writer.write_all(&header).await?;
+                while let Some(chunk) = chunks.recv().await {
+                    writer.write_all(&chunk).await?;
+                }
+                writer.write_all(&delimiter).await?;
+            }
+        }
+        writer.flush().await?;
+        while let Some(frame) = rx.recv().await {
+            writer.write_all(&frame).await?;
+            while let Ok(frame) = rx.try_recv() {
+                writer.write_all(&frame).await?;
+            }
+            writer.flush().await?;
+        }
+        Ok(())
     }
 
     #[inline]
     #[must_use]
     pub const fn addr(&self) -> &SocketAddr {
-        &self.handler.addr
+        &self.addr
+    }
+
+    /// Address clients reach the replica at, its connection's if it didn't announce a port
+    #[inline]
+    #[must_use]
+    pub const fn listening_addr(&self) -> SocketAddr {
+        let mut addr = self.addr;
+        if let Some(port) = self.listening_port {
+            addr.set_port(port);
+        }
+        addr
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn acked_offset(&self) -> u64 {
+        self.ack.offset.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for Replica {
+    /// The writer task stops once the queue is dropped, the reader has to be stopped
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpStream;
+
+    use crate::{
+        testutil::{master, Client},
+        Arguments, Server,
+    };
+
+    use super::*;
+
+    /// Replica played by the test: it syncs with `server`, then the commands the
+    /// master propagates can be read from the returned link
+    async fn scripted_replica(server: &Server) -> Handler {
+        let stream = TcpStream::connect(server.addr()).await.unwrap();
+        let mut link = Handler::new(stream, &Arguments::default());
+        for cmd in [
+            Ping::new(None).into_resp(),
+            ReplConf::ListeningPort(0).into_resp(),
+            ReplConf::Capa(vec!["eof".into(), "psync2".into()]).into_resp(),
+            Psync::first_sync().into_resp(),
+        ] {
+            link.write(&cmd).await.unwrap();
+            link.read().await.unwrap().unwrap();
+        }
+        link.reader
+            .read_rdb(false, Duration::from_secs(5))
+            .await
+            .unwrap();
+        link
+    }
+
+    /// Next command propagated to `link`, skipping the pings and database selections
+    async fn propagated(link: &mut Handler) -> Vec<String> {
+        loop {
+            let resp = tokio::time::timeout(Duration::from_secs(5), link.read())
+                .await
+                .expect("Nothing propagated in time")
+                .unwrap()
+                .unwrap();
+            let cmd = resp
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|arg| arg.to_string().unwrap())
+                .collect::<Vec<_>>();
+            if !matches!(cmd[0].as_str(), "PING" | "SELECT") {
+                return cmd;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn propagates_transactions() {
+        let server = master().await;
+        let mut link = scripted_replica(&server).await;
+        let mut client = Client::connect(&server).await;
+
+        for cmd in [
+            &["MULTI"][..],
+            &["SET", "a", "1"],
+            &["GET", "a"],
+            &["SET", "b", "2"],
+            &["EXEC"],
+        ] {
+            client.cmd(cmd).await;
+        }
+        // Only the writes, still between MULTI and EXEC
+        for expected in [
+            &["MULTI"][..],
+            &["SET", "a", "1"],
+            &["SET", "b", "2"],
+            &["EXEC"],
+        ] {
+            pretty_assertions::assert_eq!(propagated(&mut link).await, expected);
+        }
+    }
+
+    /// Offset of the replication stream, as the master reports it
+    async fn master_offset(client: &mut Client) -> u64 {
+        let info = client.cmd(&["INFO", "replication"]).await;
+        String::from_utf8_lossy(info.as_bulk().unwrap())
+            .lines()
+            .find_map(|line| line.strip_prefix("master_repl_offset:"))
+            .unwrap()
+            .parse()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn wait_for_acks() {
+        let server = master().await;
+        let mut link = scripted_replica(&server).await;
+        let mut client = Client::connect(&server).await;
+
+        client.cmd(&["SET", "k", "v"]).await;
+        let offset = master_offset(&mut client).await;
+        client.send(&["WAIT", "1", "0"]).await;
+        // The master asks for an ACK after the write, which doesn't count until it covers it
+        pretty_assertions::assert_eq!(propagated(&mut link).await, ["SET", "k", "v"]);
+        pretty_assertions::assert_eq!(propagated(&mut link).await, ["REPLCONF", "GETACK", "*"]);
+        link.write(&ReplConf::Ack(offset - 1).into_resp())
+            .await
+            .unwrap();
+        link.write(&ReplConf::Ack(offset).into_resp())
+            .await
+            .unwrap();
+        pretty_assertions::assert_eq!(client.read().await, Resp::Integer(1));
+
+        // Times out with the replicas that acknowledged
+        client.cmd(&["SET", "k", "w"]).await;
+        let started = Instant::now();
+        pretty_assertions::assert_eq!(client.cmd(&["WAIT", "1", "100"]).await, Resp::Integer(0));
+        assert!(started.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn wait_fast_paths() {
+        let server = master().await;
+        let _link = scripted_replica(&server).await;
+        let mut writer = Client::connect(&server).await;
+        let mut reader = Client::connect(&server).await;
+
+        // Nothing to wait for without a write of its own, even if others wrote since
+        writer.cmd(&["SET", "k", "v"]).await;
+        pretty_assertions::assert_eq!(reader.cmd(&["WAIT", "1", "0"]).await, Resp::Integer(1));
+
+        // Inside a transaction, the count at that time instead of blocking forever
+        writer.cmd(&["MULTI"]).await;
+        writer.cmd(&["WAIT", "1", "0"]).await;
+        pretty_assertions::assert_eq!(
+            writer.cmd(&["EXEC"]).await,
+            Resp::Array(vec![Resp::Integer(0)])
+        );
     }
 }
@@ -4,6 +4,8 @@ pub use master::Master;
 pub mod slave;
 pub use slave::Slave;
 
+use crate::ReplInfo;
+
 #[derive(Debug)]
 pub enum Role {
     Master(Master),
@@ -15,3 +17,14 @@ impl Default for Role {
         Self::Master(Master::default())
     }
 }
+
+impl Role {
+    /// Replication id and offset to record in dumps. Only masters have a
+    /// history of their own to resume.
+    pub fn repl_info(&self) -> Option<ReplInfo> {
+        match self {
+            Self::Master(master) => Some(master.repl_info()),
+            Self::Slave(_) => None,
+        }
+    }
+}
@@ -1,31 +1,71 @@
-use anyhow::{bail, Context};
-use bytes::Buf;
+use anyhow::{anyhow, bail, ensure, Context};
+use parking_lot::Mutex;
+use rand::Rng;
 use std::{
-    io::Cursor,
+    future::Future,
     net::SocketAddrV4,
-    sync::atomic::{AtomicU64, Ordering},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
 };
-use tokio::net::TcpStream;
+use tokio::{net::TcpStream, time::MissedTickBehavior};
 
 use crate::{
-    commands::{Ping, Psync, ReplConf},
-    Command, Handler, Rdb, Resp, DB,
+    commands::{CommandExec, Ctx, Ping, Psync, ReplConf, Session},
+    Arguments, Command, Db, Handler, ReplInfo, Resp, AOF, TRACKING,
 };
 
+/// Delay before the first reconnection attempt, doubled after each failure
+const RECONNECT_MIN_DELAY: Duration = Duration::from_millis(100);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(30);
+/// How often the replica acknowledges its offset without being asked
+const ACK_PERIOD: Duration = Duration::from_secs(1);
+
 #[derive(Debug)]
 pub struct Slave {
     pub addr: SocketAddrV4,
     offset: AtomicU64,
+    /// Whether the replication link is established and synced
+    link_up: AtomicBool,
+    /// Last time something was received from the master
+    last_io: Mutex<Option<Instant>>,
+    /// Replication id of the master, as of the last full resync
+    master_replid: Mutex<Option<String>>,
 }
 
 impl Slave {
-    pub(crate) const fn new(addr: SocketAddrV4) -> Self {
+    #[must_use]
+    pub const fn new(addr: SocketAddrV4) -> Self {
         Self {
             addr,
             offset: AtomicU64::new(0),
+            link_up: AtomicBool::new(false),
+            last_io: Mutex::new(None),
+            master_replid: Mutex::new(None),
         }
     }
 
+    /// Replication id the master gave on the last full resync, if any
+    pub fn master_replid(&self) -> Option<String> {
+        self.master_replid.lock().clone()
+    }
+
+    #[inline]
+    pub fn link_up(&self) -> bool {
+        self.link_up.load(Ordering::Relaxed)
+    }
+
+    /// Seconds since the master last sent something, if it ever did
+    pub fn last_io_seconds_ago(&self) -> Option<u64> {
+        self.last_io.lock().map(|at| at.elapsed().as_secs())
+    }
+
+    fn touch(&self) {
+        *self.last_io.lock() = Some(Instant::now());
+    }
+
     #[inline]
     pub fn offset(&self) -> u64 {
         self.offset.load(Ordering::Relaxed)
@@ -37,99 +77,547 @@ impl Slave {
         tracing::info!("Increased offset of {prev} to {}", by + prev);
     }
 
-    pub async fn connect(&self, port: u16) -> anyhow::Result<()> {
+    /// Keeps the replica in sync with its master, reconnecting with
+    /// exponential backoff whenever the link drops or the handshake fails
+    pub async fn connect(&self, args: &Arc<Arguments>, db: &Arc<Db>) {
+        let mut delay = RECONNECT_MIN_DELAY;
+        loop {
+            match self.sync(args, db).await {
+                Ok(handler) => {
+                    delay = RECONNECT_MIN_DELAY;
+                    self.link_up.store(true, Ordering::Relaxed);
+                    let res = self.handle_connection(db, args, handler).await;
+                    self.link_up.store(false, Ordering::Relaxed);
+                    match res {
+                        Ok(()) => tracing::warn!("Master closed the connection"),
+                        Err(e) => tracing::error!("Lost connection to master: {e:#}"),
+                    }
+                }
+                Err(e) => tracing::error!("Failed to sync with master: {e:#}"),
+            }
+
+            let jitter = rand::thread_rng().gen_range(0..=delay.as_millis() / 2);
+            let wait = delay + Duration::from_millis(jitter.try_into().unwrap_or_default());
+            tracing::info!("Reconnecting to master in {wait:?}");
+            tokio::time::sleep(wait).await;
+            delay = (delay * 2).min(RECONNECT_MAX_DELAY);
+        }
+    }
+
+    async fn sync(&self, args: &Arguments, db: &Db) -> anyhow::Result<Handler> {
         tracing::info!("Connecting slave to master at {}", self.addr);
-        let master = TcpStream::connect(self.addr)
+        let connect = async { Ok(TcpStream::connect(self.addr).await?) };
+        let master = step(args.repl_timeout, "connect", connect)
             .await
             .with_context(|| format!("Failed to connect to master at {}", self.addr))?;
-        let handler = self.handshake(master, port).await?;
-
-        self.handle_connection(handler).await
+        self.handshake(master, args, db).await
     }
 
-    async fn handle_connection(&self, mut handler: Handler) -> anyhow::Result<()> {
-        #[allow(clippy::enum_glob_use)]
-        use Command::*;
+    /// Applies what the master sends, until it closes the link
+    /// or stays silent for longer than `repl-timeout`
+    async fn handle_connection(
+        &self,
+        db: &Arc<Db>,
+        args: &Arc<Arguments>,
+        mut handler: Handler,
+    ) -> anyhow::Result<()> {
+        let timeout = args.repl_timeout;
+        let mut session = Session::default();
+        let mut ctx = Ctx::new(db, args, &mut session);
+        // Commands received between MULTI and EXEC, applied together on EXEC
+        let mut transaction: Option<Vec<(Command, Resp)>> = None;
+        // Database the master's writes are for, which are ignored unless it's ours
+        let mut selected = Db::INDEX;
+        let mut acks =
+            tokio::time::interval_at(tokio::time::Instant::now() + ACK_PERIOD, ACK_PERIOD);
+        acks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut last_io = tokio::time::Instant::now();
 
         loop {
-            let Some(resp) = handler.read().await? else {
+            let resp = tokio::select! {
+                resp = handler.read() => resp?,
+                _ = acks.tick() => {
+                    handler.write(&ReplConf::Ack(self.offset()).into_resp()).await?;
+                    continue;
+                }
+                () = tokio::time::sleep_until(last_io + timeout) => {
+                    bail!("Timeout connecting to the master, no data for {timeout:?}");
+                }
+            };
+            let Some(resp) = resp else {
                 return Ok(());
             };
-            let parsed_cmd = match Command::parse(&resp) {
-                Ok((cmd, _)) => cmd,
+            last_io = tokio::time::Instant::now();
+            self.touch();
+            let (parsed_cmd, raw_cmd) = match Command::parse(&resp) {
+                Ok(parsed) => parsed,
                 Err(e) => {
                     tracing::error!("{}", e);
+                    // The master counted it all the same
+                    self.increase_offset(resp.len() as u64);
                     continue;
                 }
             };
             match parsed_cmd {
-                Set(set) => {
-                    let _ = set.execute();
+                Command::Select(select) => selected = select.index,
+                cmd if selected != Db::INDEX && cmd.is_write(&raw_cmd) => {
+                    tracing::warn!("Ignoring a write to database {selected} from master");
                 }
-                Del(del) => {
-                    let _ = del.execute();
+                Command::Multi(_) => transaction = Some(Vec::new()),
+                Command::Exec => {
+                    let queued = transaction.take().unwrap_or_default();
+                    tracing::debug!("Applying transaction of {} commands", queued.len());
+                    let mut applied = queued
+                        .into_iter()
+                        .filter_map(|(cmd, raw)| Self::apply(cmd, &raw, &mut ctx).then_some(raw))
+                        .collect::<Vec<_>>();
+                    if !applied.is_empty() {
+                        applied.insert(0, Resp::Array(vec![Resp::bulk("MULTI")]));
+                        applied.push(resp.clone());
+                        AOF.feed(&applied).await;
+                    }
                 }
-                Xadd(xadd) => {
-                    let _ = xadd.execute();
-                }
-                Incr(incr) => {
-                    let _ = incr.execute();
-                }
-                ReplConf(replconf) => {
+                Command::Discard(_) => transaction = None,
+                Command::ReplConf(replconf) => {
                     let resp = replconf.execute_slave(self)?;
                     handler.write(&resp).await?;
                 }
-                Ping(_) | Echo(_) | Xread(_) | Xrange(_) | Type(_) | Info(_) | Get(_)
-                | Multi(_) | Keys(_) | Psync(_) | Wait(_) | Config(_) | Discard(_) | Exec => { /* */ }
+                cmd => match &mut transaction {
+                    Some(queued) => queued.push((cmd, resp.clone())),
+                    None => {
+                        if Self::apply(cmd, &resp, &mut ctx) {
+                            AOF.feed(std::slice::from_ref(&resp)).await;
+                        }
+                    }
+                },
             }
             self.increase_offset(resp.len() as u64);
         }
     }
 
-    async fn handshake(&self, stream: TcpStream, port: u16) -> anyhow::Result<Handler> {
-        let mut handler = Handler::new(stream);
+    /// Applies a write from the master, returning whether it succeeded. Replies are
+    /// discarded, and anything else than a write is ignored since it can't change the dataset,
+    /// except for PUBLISH which reaches the subscribers of the replica.
+    fn apply(cmd: Command, raw: &Resp, ctx: &mut Ctx<'_>) -> bool {
+        if let Command::Publish(publish) = cmd {
+            // Nothing to append to the AOF
+            let _ = publish.execute(ctx);
+            return false;
+        }
+        if !raw.as_array().is_some_and(|raw| cmd.is_write(raw)) {
+            tracing::debug!("Ignoring {cmd:?} from master");
+            return false;
+        }
+        let applied = cmd
+            .execute_write(ctx)
+            .inspect_err(|e| tracing::warn!("Failed applying write from master: {e}"))
+            .is_ok();
+        if applied {
+            if let Some(raw) = raw.as_array() {
+                TRACKING.invalidate_command(raw, 0);
+            }
+        }
+        applied
+    }
+
+    async fn handshake(
+        &self,
+        stream: TcpStream,
+        args: &Arguments,
+        db: &Db,
+    ) -> anyhow::Result<Handler> {
+        let mut handler = Handler::new(stream, args);
+        let timeout = args.repl_timeout;
         tracing::info!("Starting handshake");
 
         tracing::info!("Sending PING to master");
-        handler.write(&Ping::new(None).into_resp()).await?;
-        check_handshake(&mut handler, "PONG").await?;
+        let ping = Ping::new(None).into_resp();
+        step(timeout, "PING", exchange(&mut handler, &ping, "PONG")).await?;
 
         tracing::info!("Sending first REPLCONF to master");
-        handler
-            .write(&ReplConf::ListeningPort(port).into_resp())
-            .await?;
-        check_handshake(&mut handler, "OK").await?;
+        let replconf = ReplConf::ListeningPort(args.port).into_resp();
+        let listening_port = exchange(&mut handler, &replconf, "OK");
+        step(timeout, "REPLCONF listening-port", listening_port).await?;
 
         tracing::info!("Sending second REPLCONF to master");
-        handler
-            .write(&ReplConf::Capa("psync2".into()).into_resp())
-            .await?;
-        check_handshake(&mut handler, "OK").await?;
+        let replconf = ReplConf::Capa(vec!["eof".into(), "psync2".into()]).into_resp();
+        let capa = exchange(&mut handler, &replconf, "OK");
+        step(timeout, "REPLCONF capa", capa).await?;
 
         tracing::info!("Sending PSYNC to master");
-        handler.write(&Psync::first_sync().into_resp()).await?;
-        let recv = handler.read().await?;
-        tracing::info!("Received: {recv:?}");
-
-        let rdb = {
-            if handler.buf.is_empty() {
-                handler.read_bytes().await?;
-            }
-            let mut cur = Cursor::new(handler.buf.as_ref());
-            let rdb = Resp::parse_rdb(&mut cur)?;
-            handler.buf.advance(cur.position().try_into()?);
-            Rdb::parse(rdb)?
+        let psync = async {
+            handler.write(&Psync::first_sync().into_resp()).await?;
+            parse_fullresync(handler.read().await?)
         };
-        DB.apply_rdb(rdb);
+        let repl = step(timeout, "PSYNC", psync).await?;
+        tracing::info!(
+            "Full resync with replication id {} at {}",
+            repl.id,
+            repl.offset
+        );
+
+        // As long as it takes, while the master keeps sending it
+        let rdb = handler
+            .reader
+            .read_rdb(db.persistence.rdbchecksum(), timeout)
+            .await
+            .context("Replication handshake failed at the RDB transfer")?;
+        // A full resync replaces whatever was replicated before
+        db.clear();
+        db.apply_rdb(rdb);
+        // Offsets continue the master's, so they compare with its own for WAIT
+        self.offset.store(repl.offset, Ordering::Relaxed);
+        *self.master_replid.lock() = Some(repl.id);
+        self.touch();
 
         Ok(handler)
     }
 }
 
-async fn check_handshake(handler: &mut Handler, msg: &str) -> anyhow::Result<()> {
-    let recv = handler.read().await?;
-    if !recv.is_some_and(|x| x.as_simple().is_some_and(|x| x == msg)) {
-        bail!("Expected {msg}")
+/// Replication id and offset of a `+FULLRESYNC <replid> <offset>` reply to PSYNC
+fn parse_fullresync(recv: Option<Resp>) -> anyhow::Result<ReplInfo> {
+    let reply = match recv {
+        Some(Resp::Simple(reply)) => reply,
+        Some(Resp::Err(e)) => bail!("Master refused PSYNC: {e}"),
+        Some(other) => bail!("Expected FULLRESYNC, got {other:?}"),
+        None => bail!("Master closed the connection instead of replying to PSYNC"),
+    };
+    let mut parts = reply.split(' ');
+    let (Some("FULLRESYNC"), Some(id), Some(offset), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        bail!("Expected FULLRESYNC <replid> <offset>, got {reply:?}");
+    };
+    ensure!(
+        id.len() == 40 && id.bytes().all(|b| b.is_ascii_alphanumeric()),
+        "Invalid replication id in {reply:?}"
+    );
+    let offset = offset
+        .parse()
+        .with_context(|| format!("Invalid replication offset in {reply:?}"))?;
+    Ok(ReplInfo {
+        id: id.to_owned(),
+        offset,
+    })
+}
+
+/// Runs a step of the handshake, failing it if the master takes longer than `timeout`
+async fn step<T>(
+    timeout: Duration,
+    name: &str,
+    step: impl Future<Output = anyhow::Result<T>>,
+) -> anyhow::Result<T> {
+    tokio::time::timeout(timeout, step)
+        .await
+        .unwrap_or_else(|_| Err(anyhow!("No reply from the master within {timeout:?}")))
+        .with_context(|| format!("Replication handshake failed at {name}"))
+}
+
+/// Sends `cmd` to the master, which should reply the simple string `msg`
+async fn exchange(handler: &mut Handler, cmd: &Resp, msg: &str) -> anyhow::Result<()> {
+    handler.write(cmd).await?;
+    match handler.read().await? {
+        Some(Resp::Simple(reply)) if reply == msg => Ok(()),
+        Some(Resp::Err(e)) => bail!("Master replied with an error: {e}"),
+        Some(other) => bail!("Expected {msg}, got {other:?}"),
+        None => bail!("Master closed the connection"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+    use tokio::net::TcpListener;
+
+    use crate::{
+        testutil::{master, replica, until, Client},
+        Arguments, Master, RedisError, Role, Server, ServerBuilder,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn replicates_writes() {
+        let master = master().await;
+        let replica = replica(&master).await;
+        let mut client = Client::connect(&master).await;
+
+        client.cmd(&["SET", "k", "v"]).await;
+        until(|| async { replica.db().view("k", |_| ()).is_some() }).await;
+
+        client.cmd(&["SET", "gone", "v", "PX", "50"]).await;
+        until(|| async { replica.db().view("gone", |_| ()).is_some() }).await;
+        until(|| async { replica.db().view("gone", |_| ()).is_none() }).await;
+
+        let mut client = Client::connect(&replica).await;
+        pretty_assertions::assert_eq!(client.cmd(&["GET", "k"]).await, Resp::bulk("v"));
+        pretty_assertions::assert_eq!(
+            client.cmd(&["SET", "k", "w"]).await,
+            RedisError::ReadOnly.into()
+        );
+    }
+
+    /// Replica synced with a master played by the test, through the returned link
+    async fn scripted_master() -> (Server, Handler) {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, listener.local_addr().unwrap().port());
+        let replica = Server::builder()
+            .port(0)
+            .role(Role::Slave(Slave::new(addr)))
+            .spawn()
+            .await
+            .unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut link = Handler::new(stream, &Arguments::default());
+        for reply in ["PONG", "OK", "OK"] {
+            link.read().await.unwrap();
+            link.write(&Resp::simple(reply)).await.unwrap();
+        }
+        link.read().await.unwrap();
+        link.queue(&Resp::Simple(format!("FULLRESYNC {} 0", "a".repeat(40))));
+        link.queue(&Resp::Data(Db::default().dump_rdb(None)));
+        (replica, link)
+    }
+
+    fn command(args: &[&'static str]) -> Resp {
+        Resp::Array(args.iter().copied().map(Resp::bulk).collect())
+    }
+
+    #[tokio::test]
+    async fn replicates_messages() {
+        let (replica, mut link) = scripted_master().await;
+        link.flush().await.unwrap();
+        let mut subscriber = Client::connect(&replica).await;
+        subscriber.cmd(&["SUBSCRIBE", "news"]).await;
+        link.write(&command(&["PUBLISH", "news", "hi"]))
+            .await
+            .unwrap();
+        pretty_assertions::assert_eq!(
+            subscriber.read().await,
+            Resp::Array(vec![
+                Resp::bulk("message"),
+                Resp::bulk("news"),
+                Resp::bulk("hi")
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn applies_transactions() {
+        let (replica, mut link) = scripted_master().await;
+        for cmd in [
+            &["MULTI"][..],
+            &["SET", "a", "1"],
+            &["INCR", "a"],
+            &["SET", "b", "2"],
+        ] {
+            link.queue(&command(cmd));
+        }
+        // Once it acknowledged, the replica has read the whole transaction but EXEC
+        link.write(&command(&["REPLCONF", "GETACK", "*"]))
+            .await
+            .unwrap();
+        loop {
+            let ack = link.read().await.unwrap().unwrap();
+            if ack
+                .as_array()
+                .is_some_and(|ack| ack[1] == Resp::bulk("ACK"))
+            {
+                break;
+            }
+        }
+        assert!(replica.db().view("a", |_| ()).is_none());
+
+        link.write(&command(&["EXEC"])).await.unwrap();
+        until(|| async { replica.db().view("a", |_| ()).is_some() }).await;
+        assert!(replica.db().view("b", |_| ()).is_some());
+        let mut client = Client::connect(&replica).await;
+        pretty_assertions::assert_eq!(client.cmd(&["GET", "a"]).await, Resp::bulk("2"));
+
+        // Nothing of a discarded one is applied
+        for cmd in [
+            &["MULTI"][..],
+            &["SET", "c", "1"],
+            &["DISCARD"],
+            &["SET", "d", "1"],
+        ] {
+            link.queue(&command(cmd));
+        }
+        link.flush().await.unwrap();
+        until(|| async { replica.db().view("d", |_| ()).is_some() }).await;
+        assert!(replica.db().view("c", |_| ()).is_none());
+    }
+
+    /// Commands the replica can't parse still count in its offset, like on the master
+    #[tokio::test]
+    async fn offset_of_unknown_commands() {
+        let (_replica, mut link) = scripted_master().await;
+        let sent = [
+            command(&["NOSUCHCOMMAND", "x"]),
+            command(&["SET", "k", "v"]),
+        ];
+        for cmd in &sent {
+            link.queue(cmd);
+        }
+        link.write(&command(&["REPLCONF", "GETACK", "*"]))
+            .await
+            .unwrap();
+        let offset = sent.iter().map(Resp::len).sum::<usize>();
+        loop {
+            let ack = link.read().await.unwrap().unwrap();
+            if let Some(ack) = ack.as_array().filter(|ack| ack[1] == Resp::bulk("ACK")) {
+                pretty_assertions::assert_eq!(ack[2], Resp::bulk(offset.to_string()));
+                break;
+            }
+        }
+    }
+
+    /// Masters select the database before writing, and Redis ones may write to others
+    #[tokio::test]
+    async fn selected_database() {
+        let (replica, mut link) = scripted_master().await;
+        for cmd in [
+            &["SELECT", "0"][..],
+            &["SET", "first", "v"],
+            &["SELECT", "1"],
+            &["SET", "other", "v"],
+            &["SELECT", "0"],
+            &["SET", "last", "v"],
+        ] {
+            link.queue(&command(cmd));
+        }
+        link.flush().await.unwrap();
+
+        until(|| async { replica.db().view("last", |_| ()).is_some() }).await;
+        assert!(replica.db().view("first", |_| ()).is_some());
+        assert!(replica.db().view("other", |_| ()).is_none());
+    }
+
+    /// A master that stops replying fails the step it's stuck at
+    #[tokio::test]
+    async fn handshake_timeouts() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, listener.local_addr().unwrap().port());
+        let args = Arguments {
+            repl_timeout: Duration::from_millis(100),
+            ..Arguments::default()
+        };
+        let slave = Slave::new(addr);
+        let db = Db::default();
+        let master = async {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut link = Handler::new(stream, &Arguments::default());
+            link.read().await.unwrap();
+            link.write(&Resp::simple("PONG")).await.unwrap();
+            link.read().await.unwrap();
+            // Then nothing, until the replica gives up
+            link.read().await.unwrap();
+        };
+        let (res, ()) = tokio::join!(slave.sync(&args, &db), master);
+        pretty_assertions::assert_eq!(
+            format!("{:#}", res.unwrap_err()),
+            "Replication handshake failed at REPLCONF listening-port: No reply from the master within 100ms"
+        );
+    }
+
+    #[tokio::test]
+    async fn announces_listening_port() {
+        let master = master().await;
+        let replica = replica(&master).await;
+        let expected = format!("slave0:ip=127.0.0.1,port={},", replica.addr().port());
+        until(|| async {
+            let mut client = Client::connect(&master).await;
+            let info = client.cmd(&["INFO", "replication"]).await;
+            info.as_bulk()
+                .is_some_and(|info| String::from_utf8_lossy(info).contains(&expected))
+        })
+        .await;
+    }
+
+    #[test]
+    fn fullresync_reply() {
+        let id = "8371b4fb1155b71f4a04d3e1bc3e18c4a990aeeb";
+        pretty_assertions::assert_eq!(
+            parse_fullresync(Some(Resp::Simple(format!("FULLRESYNC {id} 42")))).unwrap(),
+            ReplInfo {
+                id: id.to_owned(),
+                offset: 42
+            }
+        );
+        for reply in [
+            Some(Resp::Simple(format!("FULLRESYNC {id}"))),
+            Some(Resp::Simple(format!("FULLRESYNC {id} -1"))),
+            Some(Resp::Simple("FULLRESYNC short 0".to_owned())),
+            Some(Resp::Simple(format!("CONTINUE {id}"))),
+            Some(Resp::bulk("FULLRESYNC")),
+            Some(Resp::Err("ERR no".to_owned())),
+            None,
+        ] {
+            assert!(parse_fullresync(reply.clone()).is_err(), "{reply:?}");
+        }
+    }
+
+    #[test]
+    fn promotion() {
+        let slave = Slave::new(SocketAddrV4::new(std::net::Ipv4Addr::LOCALHOST, 6379));
+        let former = "8371b4fb1155b71f4a04d3e1bc3e18c4a990aeeb".to_owned();
+        *slave.master_replid.lock() = Some(former.clone());
+        slave.offset.store(100, Ordering::Relaxed);
+
+        // Siblings synced up to offset 100 of the former master can carry on
+        let master = Master::promoted(&slave);
+        pretty_assertions::assert_eq!(master.repl_offset(), 100);
+        pretty_assertions::assert_eq!(
+            master.replid2(),
+            Some(ReplInfo {
+                id: former,
+                offset: 101
+            })
+        );
+        assert_ne!(master.replid(), master.replid2().unwrap().id);
+
+        master.increase_offset(10);
+        let replid = master.replid();
+        master.shift_replid();
+        pretty_assertions::assert_eq!(
+            master.replid2(),
+            Some(ReplInfo {
+                id: replid,
+                offset: 111
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn full_resync() {
+        // The RDB is either streamed with a delimiter, or sent with its length
+        for diskless in [true, false] {
+            let args = Arguments {
+                repl_diskless_sync: diskless,
+                ..Arguments::default()
+            };
+            let master = ServerBuilder::from(args).port(0).spawn().await.unwrap();
+            let mut client = Client::connect(&master).await;
+            for i in 0..100 {
+                client.cmd(&["SET", &format!("k{i}"), "v"]).await;
+            }
+
+            let replica = replica(&master).await;
+            until(|| async { replica.db().view("k99", |_| ()).is_some() }).await;
+            client.cmd(&["SET", "after", "v"]).await;
+            until(|| async { replica.db().view("after", |_| ()).is_some() }).await;
+
+            // The replica follows the master's replication id
+            let replid = |info: Resp| {
+                let info = String::from_utf8(info.as_bulk().unwrap().to_vec()).unwrap();
+                info.lines()
+                    .find_map(|line| line.strip_prefix("master_replid:").map(str::to_owned))
+            };
+            let info = client.cmd(&["INFO", "replication"]).await;
+            let mut replica_client = Client::connect(&replica).await;
+            let replica_info = replica_client.cmd(&["INFO", "replication"]).await;
+            assert!(replid(info.clone()).is_some());
+            pretty_assertions::assert_eq!(replid(replica_info), replid(info));
+        }
     }
-    Ok(())
 }
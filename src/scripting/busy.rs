@@ -0,0 +1,124 @@
+use parking_lot::Mutex;
+use std::{
+    sync::{
+        atomic::{AtomicU64, AtomicU8, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::RedisError;
+
+/// The script a server is running, if any. Once it ran for longer than
+/// `busy-reply-threshold`, other clients are told the server is busy and may kill it.
+#[derive(Debug)]
+pub struct Busy {
+    current: Mutex<Option<Arc<Run>>>,
+    /// `busy-reply-threshold`, in milliseconds
+    threshold: AtomicU64,
+}
+
+impl Default for Busy {
+    fn default() -> Self {
+        Self {
+            current: Mutex::default(),
+            threshold: AtomicU64::new(5000),
+        }
+    }
+}
+
+/// A running script or function
+#[derive(Debug)]
+pub(super) struct Run {
+    started: Instant,
+    function: bool,
+    /// [`Run::RUNNING`], then either [`Run::KILLED`] or [`Run::WROTE`]
+    state: AtomicU8,
+}
+
+impl Run {
+    const RUNNING: u8 = 0;
+    const KILLED: u8 = 1;
+    /// Scripts that wrote can't be killed, or the dataset would be left half updated
+    const WROTE: u8 = 2;
+
+    pub(super) fn killed(&self) -> bool {
+        self.state.load(Ordering::Relaxed) == Self::KILLED
+    }
+
+    /// Makes the script unkillable before it writes, unless it was killed already
+    pub(super) fn start_write(&self) -> bool {
+        self.state
+            .compare_exchange(
+                Self::RUNNING,
+                Self::WROTE,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            )
+            .map_or_else(|state| state == Self::WROTE, |_| true)
+    }
+}
+
+/// Clears the running script once it returns
+pub(super) struct RunGuard<'a> {
+    busy: &'a Busy,
+    pub(super) run: Arc<Run>,
+}
+
+impl Drop for RunGuard<'_> {
+    fn drop(&mut self) {
+        *self.busy.current.lock() = None;
+    }
+}
+
+impl Busy {
+    pub fn threshold(&self) -> Duration {
+        Duration::from_millis(self.threshold.load(Ordering::Relaxed))
+    }
+
+    pub fn set_threshold(&self, threshold: Duration) {
+        let millis = u64::try_from(threshold.as_millis()).unwrap_or(u64::MAX);
+        self.threshold.store(millis, Ordering::Relaxed);
+    }
+
+    /// Registers a script, or a function, as running until the guard is dropped
+    pub(super) fn start(&self, function: bool) -> RunGuard<'_> {
+        let run = Arc::new(Run {
+            started: Instant::now(),
+            function,
+            state: AtomicU8::new(Run::RUNNING),
+        });
+        *self.current.lock() = Some(Arc::clone(&run));
+        RunGuard { busy: self, run }
+    }
+
+    /// Fails with `BUSY` while a script runs for longer than the threshold
+    pub fn check(&self) -> Result<(), RedisError> {
+        match &*self.current.lock() {
+            Some(run) if run.started.elapsed() >= self.threshold() => Err(RedisError::Busy {
+                function: run.function,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// `SCRIPT KILL` or `FUNCTION KILL`: interrupts the running script, which
+    /// fails as soon as it executes its next instructions
+    pub fn kill(&self, function: bool) -> Result<(), RedisError> {
+        let run = self.current.lock().clone().ok_or(RedisError::NotBusy)?;
+        if run.function != function {
+            return Err(RedisError::Busy {
+                function: run.function,
+            });
+        }
+        match run.state.compare_exchange(
+            Run::RUNNING,
+            Run::KILLED,
+            Ordering::Relaxed,
+            Ordering::Relaxed,
+        ) {
+            Err(Run::WROTE) => Err(RedisError::Unkillable),
+            _ => Ok(()),
+        }
+    }
+}
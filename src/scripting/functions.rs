@@ -0,0 +1,386 @@
+use anyhow::{bail, ensure};
+use bytes::Bytes;
+use mlua::{Lua, LuaString, Table, Value, Variadic};
+use parking_lot::RwLock;
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    rc::Rc,
+    sync::{Arc, LazyLock},
+};
+
+use super::{add_calls, from_lua, sandbox, script_error, sequence, CallError, Effects};
+use crate::{glob::glob_match, Arguments, Db, Rdb, RedisError, Resp};
+
+pub static FUNCTIONS: LazyLock<Functions> = LazyLock::new(Functions::default);
+
+/// Function libraries registered with FUNCTION LOAD, by name
+#[derive(Debug, Default)]
+pub struct Functions {
+    libraries: RwLock<BTreeMap<String, Library>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Library {
+    pub name: String,
+    pub code: Bytes,
+    pub functions: Vec<Function>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Function {
+    pub name: String,
+    pub flags: Vec<String>,
+}
+
+/// What FUNCTION RESTORE does with the libraries that already exist
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestorePolicy {
+    /// Fails if a restored library already exists
+    Append,
+    Replace,
+    /// Deletes every library first
+    Flush,
+}
+
+/// Functions registered while running the code of a library, by name
+type Registered = Rc<RefCell<BTreeMap<String, (mlua::Function, Vec<String>)>>>;
+
+impl Functions {
+    /// Registers the library defined by `code`, returning its name
+    pub fn load(&self, code: Bytes, replace: bool) -> anyhow::Result<String> {
+        let library = Library::compile(code)?;
+        let name = library.name.clone();
+        insert_all(&mut self.libraries.write(), vec![library], replace)?;
+        Ok(name)
+    }
+
+    pub fn delete(&self, name: &str) -> anyhow::Result<()> {
+        self.libraries
+            .write()
+            .remove(name)
+            .map(drop)
+            .ok_or_else(|| RedisError::LibraryNotFound.into())
+    }
+
+    pub fn flush(&self) {
+        self.libraries.write().clear();
+    }
+
+    /// Libraries whose name matches the glob `pattern`
+    pub fn list(&self, pattern: Option<&str>) -> Vec<Library> {
+        self.libraries
+            .read()
+            .values()
+            .filter(|library| pattern.is_none_or(|pattern| glob_match(pattern, &library.name)))
+            .cloned()
+            .collect()
+    }
+
+    /// Code of every library, as persisted in the RDB
+    pub fn codes(&self) -> Vec<Bytes> {
+        self.libraries
+            .read()
+            .values()
+            .map(|library| library.code.clone())
+            .collect()
+    }
+
+    pub fn dump(&self) -> Bytes {
+        Rdb::dump_functions(&self.codes())
+    }
+
+    /// Registers the libraries of a FUNCTION DUMP payload, all of them or none
+    pub fn restore(&self, payload: Bytes, policy: RestorePolicy) -> anyhow::Result<()> {
+        let restored = Rdb::restore_functions(payload)?
+            .into_iter()
+            .map(Library::compile)
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        match policy {
+            RestorePolicy::Append => insert_all(&mut self.libraries.write(), restored, false),
+            RestorePolicy::Replace => insert_all(&mut self.libraries.write(), restored, true),
+            RestorePolicy::Flush => {
+                let mut fresh = BTreeMap::new();
+                insert_all(&mut fresh, restored, false)?;
+                *self.libraries.write() = fresh;
+                Ok(())
+            }
+        }
+    }
+
+    /// Runs a function on `db` as `user`, returning its reply and the writes it made, to propagate
+    pub fn call(
+        &self,
+        db: &Arc<Db>,
+        server_args: &Arc<Arguments>,
+        name: &str,
+        keys: &[Bytes],
+        args: &[Bytes],
+        user: &str,
+    ) -> anyhow::Result<(Resp, Vec<Vec<Resp>>)> {
+        let code = self
+            .libraries
+            .read()
+            .values()
+            .find(|library| library.defines(name))
+            .map(|library| library.code.clone())
+            .ok_or(RedisError::FunctionNotFound)?;
+        let effects = Effects::default();
+        let running = db.busy.start(true);
+        let resp = (|| {
+            let (lua, registered) = Library::instantiate(&code)?;
+            add_calls(&lua, db, server_args, user, &effects, &running.run)?;
+            let callback = registered.borrow()[name].0.clone();
+            let value = callback.call::<Value>((sequence(&lua, keys)?, sequence(&lua, args)?))?;
+            Ok(from_lua(value))
+        })()
+        .map_err(|e| script_error(&e, "function"))?;
+        Ok((resp, effects.take()))
+    }
+}
+
+/// Adds the libraries, or none of them if a library or one of their functions already exists.
+/// With `replace`, existing libraries of the same name are replaced instead.
+fn insert_all(
+    libraries: &mut BTreeMap<String, Library>,
+    new: Vec<Library>,
+    replace: bool,
+) -> anyhow::Result<()> {
+    for (i, library) in new.iter().enumerate() {
+        ensure!(
+            replace || !libraries.contains_key(&library.name),
+            RedisError::LibraryExists(library.name.clone())
+        );
+        let others = libraries
+            .values()
+            .filter(|other| other.name != library.name)
+            .chain(&new[..i]);
+        for other in others {
+            if let Some(function) = library.functions.iter().find(|f| other.defines(&f.name)) {
+                bail!(RedisError::FunctionExists(function.name.clone()));
+            }
+        }
+    }
+    for library in new {
+        libraries.insert(library.name.clone(), library);
+    }
+    Ok(())
+}
+
+impl Library {
+    const FLAGS: &[&str] = &[
+        "no-writes",
+        "allow-oom",
+        "allow-stale",
+        "no-cluster",
+        "allow-cross-slot-keys",
+    ];
+
+    fn defines(&self, function: &str) -> bool {
+        self.functions.iter().any(|f| f.name == function)
+    }
+
+    /// Checks the code registers functions, naming the library after
+    /// the `#!lua name=<name>` line it must start with
+    fn compile(code: Bytes) -> anyhow::Result<Self> {
+        let name = Self::parse_metadata(&code)?;
+        let (_lua, registered) =
+            Self::instantiate(&code).map_err(|e| script_error(&e, "library"))?;
+        let functions = registered
+            .take()
+            .into_iter()
+            .map(|(name, (_, flags))| Function { name, flags })
+            .collect::<Vec<_>>();
+        ensure!(!functions.is_empty(), RedisError::NoFunctions);
+        Ok(Self {
+            name,
+            code,
+            functions,
+        })
+    }
+
+    fn parse_metadata(code: &[u8]) -> anyhow::Result<String> {
+        let first_line = code.split(|&b| b == b'\n').next().unwrap_or_default();
+        let shebang = std::str::from_utf8(first_line)
+            .ok()
+            .and_then(|line| line.strip_prefix("#!"))
+            .ok_or(RedisError::MissingMetadata)?;
+        let mut parts = shebang.split_ascii_whitespace();
+        let engine = parts.next().unwrap_or_default();
+        ensure!(
+            engine.eq_ignore_ascii_case("lua"),
+            RedisError::UnknownEngine(engine.to_owned())
+        );
+        let mut name = None;
+        for part in parts {
+            match part.split_once('=') {
+                Some(("name", value)) => name = Some(value),
+                _ => bail!(RedisError::InvalidMetadata(part.to_owned())),
+            }
+        }
+        let name = name.ok_or(RedisError::MissingLibraryName)?;
+        ensure!(is_valid_name(name), RedisError::InvalidLibraryName);
+        Ok(name.to_owned())
+    }
+
+    /// Runs the code in a new interpreter, collecting the functions it registers
+    fn instantiate(code: &[u8]) -> mlua::Result<(Lua, Registered)> {
+        let lua = sandbox()?;
+        let registered = Registered::default();
+        let register = Rc::clone(&registered);
+        lua.globals().get::<Table>("redis")?.set(
+            "register_function",
+            lua.create_function(move |_, args: Variadic<Value>| {
+                let (name, callback, flags) = parse_registration(&args)
+                    .map_err(|e| mlua::Error::external(CallError(e.to_string())))?;
+                if register.borrow().contains_key(&name) {
+                    return Err(mlua::Error::external(CallError(
+                        RedisError::FunctionExistsInLibrary.to_string(),
+                    )));
+                }
+                register.borrow_mut().insert(name, (callback, flags));
+                Ok(())
+            })?,
+        )?;
+        // The shebang isn't Lua, the line is kept so error line numbers are right
+        let body = code
+            .iter()
+            .position(|&b| b == b'\n')
+            .map_or(&[][..], |at| &code[at..]);
+        lua.load(body).set_name("@user_function").exec()?;
+        Ok((lua, registered))
+    }
+}
+
+/// Arguments of `redis.register_function`: a name and a callback,
+/// or a table with them and optionally flags
+fn parse_registration(args: &[Value]) -> anyhow::Result<(String, mlua::Function, Vec<String>)> {
+    let (name, callback, flags) = match args {
+        [Value::String(name), Value::Function(callback)] => {
+            (name.to_string_lossy(), callback.clone(), Vec::new())
+        }
+        [Value::Table(table)] => {
+            let name = table
+                .get::<Option<LuaString>>("function_name")
+                .ok()
+                .flatten()
+                .ok_or(RedisError::RegisterName)?
+                .to_string_lossy();
+            let callback = table
+                .get::<Option<mlua::Function>>("callback")
+                .ok()
+                .flatten()
+                .ok_or(RedisError::RegisterCallback)?;
+            let flags = table
+                .get::<Option<Vec<LuaString>>>("flags")
+                .ok()
+                .ok_or(RedisError::RegisterFlags)?
+                .unwrap_or_default()
+                .into_iter()
+                .map(|flag| flag.to_string_lossy())
+                .collect::<Vec<_>>();
+            if let Some(flag) = flags
+                .iter()
+                .find(|flag| !Library::FLAGS.contains(&flag.as_str()))
+            {
+                bail!(RedisError::UnknownFunctionFlag(flag.clone()));
+            }
+            (name, callback, flags)
+        }
+        _ => bail!(RedisError::RegisterArity),
+    };
+    ensure!(is_valid_name(&name), RedisError::InvalidFunctionName);
+    Ok((name, callback, flags))
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn libraries() {
+        let functions = Functions::default();
+        let db = Arc::new(Db::default());
+        let server_args = Arc::new(Arguments::default());
+        let lib = |code: &'static str| Bytes::from(code);
+        let name = functions
+            .load(
+                lib("#!lua name=mylib\nredis.register_function('echo_args', function(keys, args) return {keys[1], args[1]} end)\nredis.register_function{function_name='flagged', callback=function() return 1 end, flags={'no-writes'}}"),
+                false,
+            )
+            .unwrap();
+        pretty_assertions::assert_eq!(name, "mylib");
+        pretty_assertions::assert_eq!(
+            functions
+                .call(
+                    &db,
+                    &server_args,
+                    "echo_args",
+                    &[lib("k")],
+                    &[lib("a")],
+                    "default"
+                )
+                .unwrap()
+                .0,
+            Resp::Array(vec![Resp::bulk("k"), Resp::bulk("a")])
+        );
+        let listed = functions.list(Some("my*"));
+        pretty_assertions::assert_eq!(listed[0].functions[1].flags, ["no-writes"]);
+
+        let error = |code: &'static str| {
+            functions
+                .load(lib(code), false)
+                .unwrap_err()
+                .downcast::<RedisError>()
+                .unwrap()
+        };
+        pretty_assertions::assert_eq!(error("return 1"), RedisError::MissingMetadata);
+        pretty_assertions::assert_eq!(
+            error("#!lua name=mylib\nredis.register_function('x', function() end)"),
+            RedisError::LibraryExists("mylib".to_owned())
+        );
+        pretty_assertions::assert_eq!(
+            error("#!lua name=other\nredis.register_function('flagged', function() end)"),
+            RedisError::FunctionExists("flagged".to_owned())
+        );
+        pretty_assertions::assert_eq!(
+            error("#!lua name=empty\nlocal x = 1"),
+            RedisError::NoFunctions
+        );
+        pretty_assertions::assert_eq!(
+            error("#!js name=other\n"),
+            RedisError::UnknownEngine("js".to_owned())
+        );
+        pretty_assertions::assert_eq!(error("#!lua name=my-lib\n"), RedisError::InvalidLibraryName);
+        pretty_assertions::assert_eq!(
+            functions
+                .delete("nope")
+                .unwrap_err()
+                .downcast::<RedisError>()
+                .unwrap(),
+            RedisError::LibraryNotFound
+        );
+
+        let dump = functions.dump();
+        functions.flush();
+        pretty_assertions::assert_eq!(
+            functions
+                .call(&db, &server_args, "echo_args", &[], &[], "default")
+                .unwrap_err()
+                .downcast::<RedisError>()
+                .unwrap(),
+            RedisError::FunctionNotFound
+        );
+        functions
+            .restore(dump.clone(), RestorePolicy::Append)
+            .unwrap();
+        assert!(functions.restore(dump, RestorePolicy::Append).is_err());
+        assert!(functions
+            .call(&db, &server_args, "flagged", &[], &[], "default")
+            .is_ok());
+    }
+}
@@ -0,0 +1,351 @@
+use bytes::Bytes;
+use mlua::{HookTriggers, Lua, LuaOptions, LuaString, StdLib, Table, Value, Variadic, VmState};
+use parking_lot::RwLock;
+use sha1::{Digest, Sha1};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    rc::Rc,
+    sync::{Arc, LazyLock},
+};
+use thiserror::Error;
+
+use crate::{
+    commands::{Ctx, Session},
+    Arguments, Command, Db, Resp, ACL,
+};
+
+mod busy;
+pub mod functions;
+pub use busy::Busy;
+use busy::Run;
+pub use functions::FUNCTIONS;
+
+pub static SCRIPTS: LazyLock<Scripts> = LazyLock::new(Scripts::default);
+
+/// Bodies of the scripts evaluated or loaded so far, by SHA1
+#[derive(Debug, Default)]
+pub struct Scripts {
+    cache: RwLock<HashMap<String, Bytes>>,
+}
+
+impl Scripts {
+    /// Caches the script, returning its SHA1
+    pub fn load(&self, body: Bytes) -> String {
+        let sha = hex::encode(Sha1::digest(&body));
+        self.cache.write().insert(sha.clone(), body);
+        sha
+    }
+
+    pub fn get(&self, sha: &str) -> Option<Bytes> {
+        self.cache.read().get(&sha.to_ascii_lowercase()).cloned()
+    }
+
+    pub fn exists(&self, sha: &str) -> bool {
+        self.cache.read().contains_key(&sha.to_ascii_lowercase())
+    }
+
+    pub fn flush(&self) {
+        self.cache.write().clear();
+    }
+}
+
+/// Error of a command called by a script, which aborts it with that error
+#[derive(Debug, Error)]
+#[error("{0}")]
+struct CallError(String);
+
+/// Writes made by the commands of a script, in order
+type Effects = Rc<RefCell<Vec<Vec<Resp>>>>;
+
+/// Instructions run by a script between checks for `SCRIPT KILL`
+const KILL_CHECK_INSTRUCTIONS: u32 = 100_000;
+
+const KILLED: &str = "ERR Script killed by user with SCRIPT KILL...";
+
+/// Runs a script as `user`, returning its reply and the writes it made, to propagate.
+/// Every script gets a fresh interpreter, so they can't leak state to each other.
+pub fn run(
+    db: &Arc<Db>,
+    server_args: &Arc<Arguments>,
+    body: &[u8],
+    keys: &[Bytes],
+    argv: &[Bytes],
+    user: &str,
+) -> anyhow::Result<(Resp, Vec<Vec<Resp>>)> {
+    let effects = Effects::default();
+    let running = db.busy.start(false);
+    let lua = (|| {
+        let lua = sandbox()?;
+        add_calls(&lua, db, server_args, user, &effects, &running.run)?;
+        let globals = lua.globals();
+        globals.set("KEYS", sequence(&lua, keys)?)?;
+        globals.set("ARGV", sequence(&lua, argv)?)?;
+        Ok::<_, mlua::Error>(lua)
+    })()
+    .map_err(|e| anyhow::anyhow!("ERR {e}"))?;
+    let value = lua
+        .load(body)
+        .set_name("@user_script")
+        .eval::<Value>()
+        .map_err(|e| script_error(&e, "script"))?;
+    let reply = from_lua(value);
+    drop(lua);
+    Ok((reply, effects.take()))
+}
+
+/// Interpreter without access to the system, with the `redis` helpers that don't run commands
+fn sandbox() -> mlua::Result<Lua> {
+    let lua = Lua::new_with(
+        StdLib::TABLE | StdLib::STRING | StdLib::MATH,
+        LuaOptions::default(),
+    )?;
+    let redis = lua.create_table()?;
+    redis.set(
+        "status_reply",
+        lua.create_function(|lua, status: LuaString| lua.create_table_from([("ok", status)]))?,
+    )?;
+    redis.set(
+        "error_reply",
+        lua.create_function(|lua, err: LuaString| lua.create_table_from([("err", err)]))?,
+    )?;
+    lua.globals().set("redis", redis)?;
+    Ok(lua)
+}
+
+/// Adds `redis.call` and `redis.pcall`, running commands on `db` as `user`,
+/// and aborts the script once `run` is killed
+fn add_calls(
+    lua: &Lua,
+    db: &Arc<Db>,
+    server_args: &Arc<Arguments>,
+    user: &str,
+    effects: &Effects,
+    run: &Arc<Run>,
+) -> mlua::Result<()> {
+    let killed = Arc::clone(run);
+    lua.set_hook(
+        HookTriggers::new().every_nth_instruction(KILL_CHECK_INSTRUCTIONS),
+        move |_, _| {
+            if killed.killed() {
+                return Err(mlua::Error::external(CallError(KILLED.to_owned())));
+            }
+            Ok(VmState::Continue)
+        },
+    )?;
+    let redis = lua.globals().get::<Table>("redis")?;
+    let caller = Caller {
+        db: Arc::clone(db),
+        args: Arc::clone(server_args),
+        user: user.to_owned(),
+        effects: Rc::clone(effects),
+        run: Arc::clone(run),
+    };
+    let pcaller = caller.clone();
+    redis.set(
+        "call",
+        lua.create_function(move |lua, args: Variadic<Value>| {
+            let reply = caller
+                .call(&args)
+                .map_err(|e| mlua::Error::external(CallError(e.to_string())))?;
+            to_lua(lua, reply)
+        })?,
+    )?;
+    redis.set(
+        "pcall",
+        lua.create_function(move |lua, args: Variadic<Value>| {
+            let reply = pcaller
+                .call(&args)
+                .unwrap_or_else(|e| Resp::Err(e.to_string()));
+            to_lua(lua, reply)
+        })?,
+    )
+}
+
+fn sequence(lua: &Lua, values: &[Bytes]) -> mlua::Result<Table> {
+    let values = values
+        .iter()
+        .map(|value| lua.create_string(value))
+        .collect::<mlua::Result<Vec<_>>>()?;
+    lua.create_sequence_from(values)
+}
+
+/// What `redis.call` runs commands against
+#[derive(Clone)]
+struct Caller {
+    db: Arc<Db>,
+    args: Arc<Arguments>,
+    user: String,
+    effects: Effects,
+    run: Arc<Run>,
+}
+
+impl Caller {
+    /// `redis.call`: runs a command, subject to the ACL of the script's user
+    fn call(&self, args: &[Value]) -> anyhow::Result<Resp> {
+        anyhow::ensure!(
+            !args.is_empty(),
+            "ERR Please specify at least one argument for this redis lib call"
+        );
+        let args = args
+            .iter()
+            .map(|arg| match arg {
+                Value::String(s) => Ok(Resp::Bulk(Bytes::copy_from_slice(&s.as_bytes()))),
+                Value::Integer(i) => Ok(Resp::bulk(i.to_string())),
+                Value::Number(n) => Ok(Resp::bulk(n.to_string())),
+                _ => {
+                    anyhow::bail!("ERR Lua redis lib command arguments must be strings or integers")
+                }
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let (cmd, raw_cmd) = Command::parse(&Resp::Array(args))?;
+        ACL.check(&self.user, &raw_cmd)?;
+        anyhow::ensure!(!cmd.is_write(&raw_cmd) || self.run.start_write(), KILLED);
+        let mut session = Session {
+            user: Some(self.user.clone()),
+            ..Session::default()
+        };
+        let mut ctx = Ctx::new(&self.db, &self.args, &mut session);
+        let reply = cmd.execute_scripted(&mut ctx, raw_cmd)?;
+        self.effects.borrow_mut().append(&mut ctx.effects);
+        Ok(reply)
+    }
+}
+
+/// Converts a reply like Redis does for scripts, with RESP2 semantics
+fn to_lua(lua: &Lua, resp: Resp) -> mlua::Result<Value> {
+    Ok(match resp {
+        Resp::Simple(status) => Value::Table(lua.create_table_from([("ok", status)])?),
+        Resp::Err(err) => Value::Table(lua.create_table_from([("err", err)])?),
+        Resp::Bulk(bytes) | Resp::Data(bytes) => Value::String(lua.create_string(&bytes)?),
+        Resp::Integer(i) => Value::Integer(i),
+        Resp::Null | Resp::NullArray => Value::Boolean(false),
+        Resp::Array(values) | Resp::Push(values) => {
+            let values = values
+                .into_iter()
+                .map(|value| to_lua(lua, value))
+                .collect::<mlua::Result<Vec<_>>>()?;
+            Value::Table(lua.create_sequence_from(values)?)
+        }
+        Resp::Map(pairs) => {
+            let values = pairs
+                .into_iter()
+                .flat_map(<[Resp; 2]>::from)
+                .map(|value| to_lua(lua, value))
+                .collect::<mlua::Result<Vec<_>>>()?;
+            Value::Table(lua.create_sequence_from(values)?)
+        }
+    })
+}
+
+/// Converts the value returned by a script to a reply. Arrays stop at the first nil.
+#[allow(clippy::cast_possible_truncation)]
+fn from_lua(value: Value) -> Resp {
+    match value {
+        Value::Boolean(true) => Resp::Integer(1),
+        // Like Redis, numbers are truncated to integers
+        Value::Integer(i) => Resp::Integer(i),
+        Value::Number(n) => Resp::Integer(n as i64),
+        Value::String(s) => Resp::Bulk(Bytes::copy_from_slice(&s.as_bytes())),
+        Value::Table(table) => {
+            if let Ok(err) = table.raw_get::<LuaString>("err") {
+                return Resp::Err(err.to_string_lossy());
+            }
+            if let Ok(status) = table.raw_get::<LuaString>("ok") {
+                return Resp::Simple(status.to_string_lossy());
+            }
+            Resp::Array(
+                table
+                    .sequence_values::<Value>()
+                    .map_while(Result::ok)
+                    .map(from_lua)
+                    .collect(),
+            )
+        }
+        _ => Resp::Null,
+    }
+}
+
+/// Errors of `redis.call` are replied as is, others only by their first line
+/// as the interpreter may add a traceback
+fn script_error(e: &mlua::Error, of: &str) -> anyhow::Error {
+    if let Some(CallError(err)) = e.chain().find_map(|e| e.downcast_ref::<CallError>()) {
+        return anyhow::anyhow!("{err}");
+    }
+    let what = match e {
+        mlua::Error::SyntaxError { .. } => "compiling",
+        _ => "running",
+    };
+    let msg = e.to_string();
+    anyhow::anyhow!(
+        "ERR Error {what} {of}: {}",
+        msg.lines().next().unwrap_or_default()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(body: &str, keys: &[&'static str], args: &[&'static str]) -> anyhow::Result<Resp> {
+        let keys = keys.iter().copied().map(Bytes::from).collect::<Vec<_>>();
+        let args = args.iter().copied().map(Bytes::from).collect::<Vec<_>>();
+        let db = Arc::new(Db::default());
+        let server_args = Arc::new(Arguments::default());
+        run(&db, &server_args, body.as_bytes(), &keys, &args, "default").map(|(reply, _)| reply)
+    }
+
+    #[test]
+    fn conversions() {
+        pretty_assertions::assert_eq!(
+            eval("return {1, 'two', 3.9, true, false, 'x'}", &[], &[]).unwrap(),
+            Resp::Array(vec![
+                Resp::Integer(1),
+                Resp::bulk("two"),
+                Resp::Integer(3),
+                Resp::Integer(1),
+                Resp::Null,
+                Resp::bulk("x"),
+            ])
+        );
+        pretty_assertions::assert_eq!(
+            eval(
+                "return {KEYS[1], ARGV[2], nil, 'lost'}",
+                &["k"],
+                &["a", "b"]
+            )
+            .unwrap(),
+            Resp::Array(vec![Resp::bulk("k"), Resp::bulk("b")])
+        );
+        pretty_assertions::assert_eq!(
+            eval("return redis.status_reply('FINE')", &[], &[]).unwrap(),
+            Resp::simple("FINE")
+        );
+        pretty_assertions::assert_eq!(
+            eval("return redis.error_reply('ERR nope')", &[], &[]).unwrap(),
+            Resp::Err("ERR nope".into())
+        );
+        pretty_assertions::assert_eq!(
+            eval("return redis.pcall('echo')", &[], &[]).unwrap(),
+            Resp::Err("ERR wrong number of arguments for 'echo' command".into())
+        );
+    }
+
+    #[test]
+    fn errors() {
+        pretty_assertions::assert_eq!(
+            eval("return redis.call('echo')", &[], &[])
+                .unwrap_err()
+                .to_string(),
+            "ERR wrong number of arguments for 'echo' command"
+        );
+        assert!(eval("return (", &[], &[])
+            .unwrap_err()
+            .to_string()
+            .starts_with("ERR Error compiling script: "));
+        assert!(eval("return os.exit()", &[], &[])
+            .unwrap_err()
+            .to_string()
+            .starts_with("ERR Error running script: "));
+    }
+}
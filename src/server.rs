@@ -0,0 +1,554 @@
+use anyhow::Context;
+use socket2::{Domain, Socket, Type};
+use std::{
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::{mpsc, oneshot},
+    task::JoinSet,
+};
+use tracing::{info_span, Instrument};
+
+use crate::{
+    cluster::{bus::Bus, CLUSTER},
+    hooks::{Hook, Hooks},
+    Aof, Arguments, AuditLog, CommandHandler, Db, Handler, ReplInfo, Role, ACL, AOF, CLIENTS,
+    PUBSUB, STATS,
+};
+
+/// A running server, which is shut down with [`Server::shutdown`] or when dropped.
+///
+/// Each server has its own dataset, but clients, ACL users, the AOF
+/// and the stats are still process globals. The AOF is disabled again
+/// when the server that enabled it stops, so later servers don't feed it.
+#[derive(Debug)]
+pub struct Server {
+    addrs: Vec<SocketAddr>,
+    db: Arc<Db>,
+    /// Whether this server enabled the AOF and flushes it
+    aof: bool,
+    shutdown: Option<oneshot::Sender<()>>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl Server {
+    #[must_use]
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder::default()
+    }
+
+    /// First address the server accepts connections on
+    #[must_use]
+    #[inline]
+    pub fn addr(&self) -> SocketAddr {
+        self.addrs[0]
+    }
+
+    /// Every address the server accepts connections on, one per bound address
+    #[must_use]
+    #[inline]
+    pub fn addrs(&self) -> &[SocketAddr] {
+        &self.addrs
+    }
+
+    /// Dataset of the server
+    #[inline]
+    #[must_use]
+    pub const fn db(&self) -> &Arc<Db> {
+        &self.db
+    }
+
+    /// Stops accepting connections, then closes the open ones and stops the background tasks
+    pub async fn shutdown(mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        let _ = (&mut self.task).await;
+    }
+
+    /// Runs until the server stops, which it only does if it panics
+    pub async fn wait(mut self) {
+        let _ = (&mut self.task).await;
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.task.abort();
+        if self.aof {
+            AOF.disable();
+        }
+    }
+}
+
+/// Configures a [`Server`], starting from the defaults of the command line flags
+#[derive(Debug, Default)]
+pub struct ServerBuilder {
+    args: Arguments,
+    hooks: Hooks,
+}
+
+impl From<Arguments> for ServerBuilder {
+    fn from(args: Arguments) -> Self {
+        Self {
+            args,
+            hooks: Hooks::default(),
+        }
+    }
+}
+
+impl ServerBuilder {
+    /// Port to listen on, 0 picks a free one which [`Server::addr`] tells
+    #[must_use]
+    pub const fn port(mut self, port: u16) -> Self {
+        self.args.port = port;
+        self
+    }
+
+    /// Addresses to listen on, instead of the loopback one
+    #[must_use]
+    pub fn bind<I>(mut self, addrs: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<IpAddr>,
+    {
+        self.args.bind = addrs.into_iter().map(Into::into).collect();
+        self
+    }
+
+    #[must_use]
+    pub fn role(mut self, role: Role) -> Self {
+        self.args.role = role;
+        self
+    }
+
+    /// Directory of the RDB and append-only files
+    #[must_use]
+    pub fn dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.args.dir = Some(dir.into());
+        self
+    }
+
+    #[must_use]
+    pub fn dbfilename(mut self, name: impl Into<PathBuf>) -> Self {
+        self.args.db_filename = Some(name.into());
+        self
+    }
+
+    #[must_use]
+    pub const fn appendonly(mut self, enabled: bool) -> Self {
+        self.args.appendonly = enabled;
+        self
+    }
+
+    #[must_use]
+    pub const fn protected_mode(mut self, enabled: bool) -> Self {
+        self.args.protected_mode = enabled;
+        self
+    }
+
+    #[must_use]
+    pub fn requirepass(mut self, password: impl Into<String>) -> Self {
+        self.args.requirepass = Some(password.into());
+        self
+    }
+
+    /// Adds a hook run around every command sent by a client, after the ones added before
+    #[must_use]
+    pub fn hook(mut self, hook: impl Hook) -> Self {
+        self.hooks.push(hook);
+        self
+    }
+
+    /// Loads the dataset, starts the background tasks and listens for connections
+    pub async fn spawn(self) -> anyhow::Result<Server> {
+        let mut args = self.args;
+        let mut hooks = self.hooks;
+        let listeners = listen_all(&mut args)?;
+        let addrs = listeners
+            .iter()
+            .map(TcpListener::local_addr)
+            .collect::<std::io::Result<Vec<_>>>()?;
+        let args = Arc::new(args);
+        let db = Arc::new(Db::default());
+
+        configure(&args, &db);
+        // The AOF has every write up to the shutdown, so it wins over the RDB
+        db.persistence.set_loading(true);
+        let repl = if args.appendonly && Aof::load(&args.aof_path(), &db, &args)? {
+            None
+        } else {
+            load_rdb(&args, &db)?
+        };
+        db.persistence.set_loading(false);
+        if let (Some(repl), Role::Master(master)) = (repl, &args.role) {
+            master.restore(repl);
+        }
+        db.persistence.set_save_points(args.save.clone());
+        AOF.set_fsync(args.appendfsync);
+
+        let mut tasks = JoinSet::new();
+        if let Some(path) = args.audit_log_path() {
+            let (audit, writer) =
+                AuditLog::open(path, args.audit_log_max_size, args.audit_log_max_files).await?;
+            hooks.push(audit);
+            tasks.spawn(writer.instrument(info_span!("audit-log")));
+        }
+        let hooks = Arc::new(hooks);
+        if args.appendonly {
+            AOF.enable();
+            let file = Aof::open(&args.aof_path(), &db).await?;
+            tasks.spawn(
+                async {
+                    if let Err(e) = AOF.flush(file).await {
+                        tracing::error!("{e:#}");
+                        std::process::exit(1);
+                    }
+                }
+                .instrument(info_span!("aof-flush")),
+            );
+        }
+        spawn_background(&mut tasks, &args, &db)?;
+
+        let (accepted_tx, mut accepted) = mpsc::channel(listeners.len());
+        let mut acceptors = JoinSet::new();
+        for (listener, addr) in listeners.into_iter().zip(&addrs) {
+            let accept = accept(listener, accepted_tx.clone());
+            acceptors.spawn(accept.instrument(info_span!("accept", %addr)));
+        }
+        drop(accepted_tx);
+
+        let (shutdown, mut stopped) = oneshot::channel();
+        let server_db = Arc::clone(&db);
+        let server_addrs = addrs.clone();
+        let aof = args.appendonly;
+        let task = tokio::spawn(async move {
+            let db = server_db;
+            let mut connections = JoinSet::new();
+            loop {
+                tokio::select! {
+                    Some(stream) = accepted.recv() => {
+                        let mut handler = CommandHandler::new(
+                            Handler::new(stream, &args),
+                            Arc::clone(&args),
+                            Arc::clone(&db),
+                            Arc::clone(&hooks),
+                        );
+                        connections.spawn(async move {
+                            if let Err(e) = handler.handle_commands().await {
+                                tracing::error!("{e}");
+                            }
+                        });
+                    }
+                    Some(_) = connections.join_next(), if !connections.is_empty() => {}
+                    _ = &mut stopped => break,
+                }
+            }
+            tracing::info!("Shutting down the server at {server_addrs:?}");
+            // Dropping the sets aborts the listeners, the connections and the background tasks
+            drop(acceptors);
+            drop(connections);
+            drop(tasks);
+        });
+
+        Ok(Server {
+            addrs,
+            db,
+            aof,
+            shutdown: Some(shutdown),
+            task,
+        })
+    }
+}
+
+/// Starts the tasks running as long as the server, each in a span named after it
+fn spawn_background(
+    tasks: &mut JoinSet<()>,
+    args: &Arc<Arguments>,
+    db: &Arc<Db>,
+) -> anyhow::Result<()> {
+    let repl_args = Arc::clone(args);
+    tasks.spawn(
+        Arc::clone(db)
+            .save_on_schedule(args.rdb_path(), move || repl_args.role.repl_info())
+            .instrument(info_span!("save-schedule")),
+    );
+    tasks.spawn(STATS.track_ops().instrument(info_span!("stats")));
+    if args.cluster_enabled {
+        tasks.spawn(
+            Bus::bind(&CLUSTER, args)?
+                .run()
+                .instrument(info_span!("cluster-bus")),
+        );
+    }
+    tasks.spawn(replicate(Arc::clone(args), Arc::clone(db)));
+    #[cfg(unix)]
+    if let Some(path) = args.config_file.clone() {
+        let reload = reload_on_sighup(path, Arc::clone(args), Arc::clone(db));
+        tasks.spawn(reload.instrument(info_span!("config-reload")));
+    }
+    Ok(())
+}
+
+/// Listens on every bound address. The first listener picks the port
+/// the others use when it's 0, which is then written to `args`.
+fn listen_all(args: &mut Arguments) -> anyhow::Result<Vec<TcpListener>> {
+    anyhow::ensure!(!args.bind.is_empty(), "No address to listen on");
+    let mut listeners = Vec::with_capacity(args.bind.len());
+    for &ip in &args.bind {
+        let listener = listen((ip, args.port).into())?;
+        args.port = listener.local_addr()?.port();
+        listeners.push(listener);
+    }
+    Ok(listeners)
+}
+
+/// Listens on `addr`. An IPv6 address doesn't also accept IPv4 connections,
+/// so that `::` and `0.0.0.0` can both be bound on the same port.
+pub fn listen(addr: SocketAddr) -> anyhow::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)
+        .and_then(|socket| {
+            if addr.is_ipv6() {
+                socket.set_only_v6(true)?;
+            }
+            #[cfg(unix)]
+            socket.set_reuse_address(true)?;
+            socket.set_nonblocking(true)?;
+            socket.bind(&addr.into())?;
+            socket.listen(1024)?;
+            Ok(socket)
+        })
+        .with_context(|| format!("Can't listen on {addr}"))?;
+    Ok(TcpListener::from_std(socket.into())?)
+}
+
+/// Hands the connections accepted by `listener` to the server
+async fn accept(listener: TcpListener, accepted: mpsc::Sender<TcpStream>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                if accepted.send(stream).await.is_err() {
+                    return;
+                }
+            }
+            Err(e) => tracing::error!("{e}"),
+        }
+    }
+}
+
+/// Keeps the replicas in sync, or this replica in sync with its master
+async fn replicate(args: Arc<Arguments>, db: Arc<Db>) {
+    match &args.role {
+        Role::Slave(slave) => {
+            let link = slave.connect(&args, &db);
+            link.instrument(info_span!("replication-link")).await;
+        }
+        Role::Master(master) => {
+            tokio::join!(
+                master
+                    .ping_replicas(args.repl_ping_replica_period)
+                    .instrument(info_span!("replica-pings")),
+                master
+                    .expire_keys(&db, Duration::from_millis(100))
+                    .instrument(info_span!("expire-cycle")),
+                master
+                    .check_replicas(args.repl_timeout)
+                    .instrument(info_span!("replica-timeouts")),
+            );
+        }
+    }
+}
+
+/// Re-reads the config file each time the process receives SIGHUP
+#[cfg(unix)]
+async fn reload_on_sighup(path: PathBuf, args: Arc<Arguments>, db: Arc<Db>) {
+    use crate::commands::Config;
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(e) => {
+            tracing::error!("Can't handle SIGHUP, the config won't be reloaded: {e}");
+            return;
+        }
+    };
+    while hangups.recv().await.is_some() {
+        match Config::reload(&path, &args, &db) {
+            Ok(restart) => {
+                tracing::info!("Reloaded the config from {}", path.display());
+                for name in restart {
+                    tracing::warn!("Changing '{name}' requires a restart, ignoring it");
+                }
+            }
+            Err(e) => tracing::error!("Can't reload the config, nothing was changed: {e:#}"),
+        }
+    }
+}
+
+/// Applies the parameters kept by the dataset and the server-wide registries
+fn configure(args: &Arguments, db: &Db) {
+    CLIENTS.set_protected_mode(args.protected_mode);
+    CLIENTS.set_maxmemory_clients(args.maxmemory_clients);
+    PUBSUB.set_queue_len(args.pubsub_queue_len);
+    PUBSUB.set_overflow(args.pubsub_overflow);
+    if let Some(password) = &args.requirepass {
+        ACL.set_requirepass(password);
+    }
+    db.set_replica(matches!(args.role, Role::Slave(_)));
+    db.persistence.set_rdbchecksum(args.rdbchecksum);
+    *db.encoding.write() = args.encoding;
+    #[cfg(feature = "scripting")]
+    db.busy.set_threshold(args.busy_reply_threshold);
+}
+
+/// Loads the dump SAVE writes, if there is one
+fn load_rdb(args: &Arguments, db: &Db) -> anyhow::Result<Option<ReplInfo>> {
+    db.load_rdb(args.rdb_path())
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
+
+    use std::net::Ipv6Addr;
+
+    use crate::{aof::Fsync, client::Client, testutil::until, Resp};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn spawn_and_shutdown() {
+        let server = Server::builder().port(0).spawn().await.unwrap();
+        let addr = server.addr();
+        assert_ne!(addr.port(), 0);
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"*1\r\n$4\r\nPING\r\n").await.unwrap();
+        let mut buf = [0; 7];
+        stream.read_exact(&mut buf).await.unwrap();
+        pretty_assertions::assert_eq!(&buf, b"+PONG\r\n");
+
+        server.shutdown().await;
+        // Open connections are closed too
+        pretty_assertions::assert_eq!(stream.read(&mut buf).await.unwrap(), 0);
+        assert!(TcpStream::connect(addr).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn aof_after_shutdown() {
+        let dir = std::env::temp_dir().join(format!("aof-shutdown-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let args = || Arguments {
+            port: 0,
+            dir: Some(dir.clone()),
+            appendonly: true,
+            appendfsync: Fsync::Always,
+            ..Arguments::default()
+        };
+        let server = ServerBuilder::from(args()).spawn().await.unwrap();
+        let mut client = Client::connect(server.addr()).await.unwrap();
+        client.cmd(["SET", "k", "v"]).await.unwrap();
+        server.shutdown().await;
+        assert!(!AOF.enabled());
+
+        // Writes don't wait for the flush task of the stopped server
+        let server = Server::builder().port(0).spawn().await.unwrap();
+        let mut client = Client::connect(server.addr()).await.unwrap();
+        let set = client.cmd(["SET", "k", "w"]);
+        let set = tokio::time::timeout(Duration::from_secs(5), set).await;
+        pretty_assertions::assert_eq!(set.unwrap().unwrap(), Resp::simple("OK"));
+        server.shutdown().await;
+
+        // What the first server appended is replayed
+        let server = ServerBuilder::from(args()).spawn().await.unwrap();
+        let mut client = Client::connect(server.addr()).await.unwrap();
+        pretty_assertions::assert_eq!(client.cmd(["GET", "k"]).await.unwrap(), Resp::bulk("v"));
+        server.shutdown().await;
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn separate_datasets() {
+        let first = Server::builder().port(0).spawn().await.unwrap();
+        let second = Server::builder().port(0).spawn().await.unwrap();
+
+        let mut stream = TcpStream::connect(first.addr()).await.unwrap();
+        stream
+            .write_all(b"*3\r\n$3\r\nSET\r\n$3\r\nfoo\r\n$3\r\nbar\r\n")
+            .await
+            .unwrap();
+        let mut buf = [0; 5];
+        stream.read_exact(&mut buf).await.unwrap();
+        pretty_assertions::assert_eq!(&buf, b"+OK\r\n");
+
+        assert!(first.db().view("foo", |_| ()).is_some());
+        assert!(second.db().view("foo", |_| ()).is_none());
+    }
+
+    /// Records the names of the spans created
+    struct SpanNames(Arc<parking_lot::Mutex<Vec<&'static str>>>);
+
+    impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for SpanNames {
+        fn on_new_span(
+            &self,
+            attrs: &tracing::span::Attributes<'_>,
+            _: &tracing::span::Id,
+            _: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            self.0.lock().push(attrs.metadata().name());
+        }
+    }
+
+    #[tokio::test]
+    async fn task_spans() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let spans = Arc::default();
+        let subscriber = tracing_subscriber::registry().with(SpanNames(Arc::clone(&spans)));
+        // The runtime of the test polls every task on this thread
+        let _default = tracing::subscriber::set_default(subscriber);
+        let server = Server::builder().port(0).spawn().await.unwrap();
+        let names = [
+            "accept",
+            "save-schedule",
+            "stats",
+            "replica-pings",
+            "expire-cycle",
+            "replica-timeouts",
+        ];
+        until(|| async { names.iter().all(|name| spans.lock().contains(name)) }).await;
+        server.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn several_addresses() {
+        let server = Server::builder()
+            .port(0)
+            .bind([
+                IpAddr::from([127, 0, 0, 1]),
+                IpAddr::from(Ipv6Addr::LOCALHOST),
+            ])
+            .spawn()
+            .await
+            .unwrap();
+        let addrs = server.addrs();
+        pretty_assertions::assert_eq!(addrs.len(), 2);
+        pretty_assertions::assert_eq!(addrs[0].port(), addrs[1].port());
+        assert!(addrs[1].is_ipv6());
+
+        for &addr in addrs {
+            let mut client = Client::connect(addr).await.unwrap();
+            pretty_assertions::assert_eq!(
+                client.cmd(["PING"]).await.unwrap(),
+                Resp::simple("PONG")
+            );
+        }
+    }
+}
@@ -0,0 +1,162 @@
+use parking_lot::Mutex;
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+use tokio::time::MissedTickBehavior;
+
+pub static STATS: Stats = Stats::new();
+
+/// Server-wide counters reported by INFO stats
+#[derive(Debug)]
+pub struct Stats {
+    connections_received: AtomicU64,
+    commands_processed: AtomicU64,
+    keyspace_hits: AtomicU64,
+    keyspace_misses: AtomicU64,
+    expired_keys: AtomicU64,
+    evicted_clients: AtomicU64,
+    ops: Mutex<OpsSamples>,
+}
+
+/// Commands per second measured over the last [`OpsSamples::LEN`] samples
+#[derive(Debug)]
+struct OpsSamples {
+    samples: [u64; Self::LEN],
+    idx: usize,
+    last: Option<(Instant, u64)>,
+}
+
+impl OpsSamples {
+    const LEN: usize = 16;
+}
+
+impl Stats {
+    const SAMPLE_PERIOD: Duration = Duration::from_millis(100);
+
+    const fn new() -> Self {
+        Self {
+            connections_received: AtomicU64::new(0),
+            commands_processed: AtomicU64::new(0),
+            keyspace_hits: AtomicU64::new(0),
+            keyspace_misses: AtomicU64::new(0),
+            expired_keys: AtomicU64::new(0),
+            evicted_clients: AtomicU64::new(0),
+            ops: Mutex::new(OpsSamples {
+                samples: [0; OpsSamples::LEN],
+                idx: 0,
+                last: None,
+            }),
+        }
+    }
+
+    #[inline]
+    pub fn incr_connections(&self) {
+        self.connections_received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn incr_commands(&self) {
+        self.commands_processed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a key lookup, depending on whether the key existed
+    #[inline]
+    pub fn incr_lookup(&self, hit: bool) {
+        let counter = if hit {
+            &self.keyspace_hits
+        } else {
+            &self.keyspace_misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn incr_expired(&self, by: u64) {
+        self.expired_keys.fetch_add(by, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn connections_received(&self) -> u64 {
+        self.connections_received.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub fn commands_processed(&self) -> u64 {
+        self.commands_processed.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub fn keyspace_hits(&self) -> u64 {
+        self.keyspace_hits.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub fn keyspace_misses(&self) -> u64 {
+        self.keyspace_misses.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub fn expired_keys(&self) -> u64 {
+        self.expired_keys.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub fn incr_evicted_clients(&self) {
+        self.evicted_clients.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn evicted_clients(&self) -> u64 {
+        self.evicted_clients.load(Ordering::Relaxed)
+    }
+
+    /// Average of the recent samples of commands per second
+    pub fn instantaneous_ops_per_sec(&self) -> u64 {
+        let ops = self.ops.lock();
+        ops.samples.iter().sum::<u64>() / OpsSamples::LEN as u64
+    }
+
+    /// Samples the commands per second every 100ms, forever
+    pub async fn track_ops(&self) {
+        let mut interval = tokio::time::interval(Self::SAMPLE_PERIOD);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            self.sample_ops(Instant::now());
+        }
+    }
+
+    fn sample_ops(&self, now: Instant) {
+        let count = self.commands_processed();
+        let mut ops = self.ops.lock();
+        if let Some((at, last_count)) = ops.last {
+            let ms = now.duration_since(at).as_millis().max(1);
+            let per_sec = u128::from(count - last_count) * 1000 / ms;
+            let idx = ops.idx;
+            ops.samples[idx] = u64::try_from(per_sec).unwrap_or(u64::MAX);
+            ops.idx = (idx + 1) % OpsSamples::LEN;
+        }
+        ops.last = Some((now, count));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instantaneous_ops() {
+        let stats = Stats::new();
+        let mut now = Instant::now();
+        stats.sample_ops(now);
+        for _ in 0..OpsSamples::LEN {
+            for _ in 0..50 {
+                stats.incr_commands();
+            }
+            now += Stats::SAMPLE_PERIOD;
+            stats.sample_ops(now);
+        }
+        pretty_assertions::assert_eq!(stats.instantaneous_ops_per_sec(), 500);
+    }
+}
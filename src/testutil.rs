@@ -0,0 +1,71 @@
+//! Servers on ephemeral ports and clients talking to them, for tests going through the network
+
+use crate::{client, Resp, Role, Server, Slave};
+use std::{
+    future::Future,
+    net::{Ipv4Addr, SocketAddrV4},
+    time::Duration,
+};
+
+/// How long a reply or a condition is waited for before the test fails
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+pub async fn master() -> Server {
+    Server::builder().port(0).spawn().await.unwrap()
+}
+
+/// Replica of `master`, which may still be syncing when returned
+pub async fn replica(master: &Server) -> Server {
+    let addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, master.addr().port());
+    Server::builder()
+        .port(0)
+        .role(Role::Slave(Slave::new(addr)))
+        .spawn()
+        .await
+        .unwrap()
+}
+
+/// Polls `cond` until it holds, failing the test after [`TIMEOUT`]
+pub async fn until<F, Fut>(mut cond: F)
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = bool>,
+{
+    tokio::time::timeout(TIMEOUT, async {
+        while !cond().await {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("condition not met in time");
+}
+
+/// [`client::Client`] failing the test on errors, and when a reply takes too long
+pub struct Client(client::Client);
+
+impl Client {
+    pub async fn connect(server: &Server) -> Self {
+        Self(client::Client::connect(server.addr()).await.unwrap())
+    }
+
+    /// Sends a command and reads its reply
+    pub async fn cmd(&mut self, args: &[&str]) -> Resp {
+        self.send(args).await;
+        self.read().await
+    }
+
+    pub async fn send(&mut self, args: &[&str]) {
+        self.0
+            .send(args.iter().map(|&arg| arg.to_owned()))
+            .await
+            .unwrap();
+    }
+
+    /// Reads the next frame, a reply or a pushed message
+    pub async fn read(&mut self) -> Resp {
+        tokio::time::timeout(TIMEOUT, self.0.read())
+            .await
+            .expect("no reply in time")
+            .unwrap()
+    }
+}
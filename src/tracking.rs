@@ -0,0 +1,295 @@
+use bytes::Bytes;
+use parking_lot::RwLock;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::LazyLock,
+};
+use tokio::sync::mpsc;
+
+use crate::{clients::CLIENTS, commands::CommandSpec, resp::Protocol, Resp, PUBSUB};
+
+pub static TRACKING: LazyLock<Tracking> = LazyLock::new(Tracking::new);
+
+/// Channel invalidations are published on to the clients redirected to
+pub const INVALIDATE_CHANNEL: &str = "__redis__:invalidate";
+
+/// Options of `CLIENT TRACKING ON`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Options {
+    /// Client the invalidations are sent to instead, through [`INVALIDATE_CHANNEL`]
+    pub redirect: Option<u64>,
+    pub mode: Mode,
+    /// Keys modified by the client itself aren't invalidated to it
+    pub noloop: bool,
+}
+
+/// Which keys a client is told about
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Mode {
+    /// The keys it read
+    #[default]
+    Default,
+    /// The keys read by the commands following `CLIENT CACHING YES`
+    OptIn,
+    /// The keys it read, except by the commands following `CLIENT CACHING NO`
+    OptOut,
+    /// Every key starting with one of the prefixes, or every key if there are none
+    Bcast(Vec<Bytes>),
+}
+
+#[derive(Debug)]
+struct Tracker {
+    options: Options,
+    /// Pushes to the connection itself, if it negotiated RESP3
+    tx: mpsc::UnboundedSender<Resp>,
+}
+
+/// Keys read by the clients with tracking enabled, server-assisted client-side caching
+#[derive(Debug)]
+pub struct Tracking {
+    clients: RwLock<HashMap<u64, Tracker>>,
+    /// Clients that may have cached each key, forgotten once it's invalidated
+    keys: RwLock<HashMap<Bytes, HashSet<u64>>>,
+}
+
+impl Tracking {
+    fn new() -> Self {
+        Self {
+            clients: RwLock::new(HashMap::new()),
+            keys: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub(crate) fn enable(&self, id: u64, options: Options, tx: mpsc::UnboundedSender<Resp>) {
+        self.clients.write().insert(id, Tracker { options, tx });
+    }
+
+    /// Stops tracking for a client, and forgets the keys it read
+    pub(crate) fn disable(&self, id: u64) {
+        if self.clients.write().remove(&id).is_none() {
+            return;
+        }
+        self.keys.write().retain(|_, ids| {
+            ids.remove(&id);
+            !ids.is_empty()
+        });
+    }
+
+    pub(crate) fn options(&self, id: u64) -> Option<Options> {
+        self.clients.read().get(&id).map(|t| t.options.clone())
+    }
+
+    /// Remembers the keys of a read command for `id`, if it tracks them.
+    /// `caching` is what `CLIENT CACHING` set for the command, if it preceded it.
+    pub(crate) fn remember(&self, id: u64, caching: Option<bool>, raw_cmd: &[Resp]) {
+        let tracked =
+            self.clients
+                .read()
+                .get(&id)
+                .is_some_and(|t| match (&t.options.mode, caching) {
+                    (Mode::Bcast(_), _) => false,
+                    (_, Some(caching)) => caching,
+                    (mode, None) => *mode != Mode::OptIn,
+                });
+        if !tracked {
+            return;
+        }
+        let Some(spec) = raw_cmd
+            .first()
+            .and_then(Resp::as_bulk)
+            .and_then(|name| CommandSpec::lookup(name))
+        else {
+            return;
+        };
+        if !spec.categories.contains(&"read") {
+            return;
+        }
+        let mut keys = self.keys.write();
+        for key in spec
+            .keys
+            .keys(raw_cmd)
+            .into_iter()
+            .filter_map(Resp::as_bulk)
+        {
+            keys.entry(key.clone()).or_default().insert(id);
+        }
+    }
+
+    /// Invalidates the keys of a write command, `by` being the client that sent it
+    pub(crate) fn invalidate_command(&self, raw_cmd: &[Resp], by: u64) {
+        let Some(spec) = raw_cmd
+            .first()
+            .and_then(Resp::as_bulk)
+            .and_then(|name| CommandSpec::lookup(name))
+        else {
+            return;
+        };
+        let keys = spec.keys.keys(raw_cmd);
+        self.invalidate(keys.into_iter().filter_map(Resp::as_bulk).cloned(), by);
+    }
+
+    /// Sends the clients that may have cached any of `keys` an invalidation message.
+    /// `by` is the client that modified them, 0 for the server itself.
+    pub(crate) fn invalidate(&self, keys: impl IntoIterator<Item = Bytes>, by: u64) {
+        let clients = self.clients.read();
+        if clients.is_empty() {
+            return;
+        }
+        let mut invalidated = HashMap::<u64, Vec<Bytes>>::new();
+        let mut readers_by_key = self.keys.write();
+        for key in keys {
+            let readers = readers_by_key.remove(&key).unwrap_or_default();
+            let prefixed = clients.iter().filter(|(_, t)| match &t.options.mode {
+                Mode::Bcast(prefixes) => {
+                    prefixes.is_empty() || prefixes.iter().any(|p| key.starts_with(p))
+                }
+                _ => false,
+            });
+            let ids = readers
+                .into_iter()
+                .chain(prefixed.map(|(&id, _)| id))
+                .collect::<HashSet<_>>();
+            for id in ids {
+                invalidated.entry(id).or_default().push(key.clone());
+            }
+        }
+        drop(readers_by_key);
+
+        for (id, keys) in invalidated {
+            let Some(tracker) = clients.get(&id) else {
+                continue;
+            };
+            if tracker.options.noloop && id == by {
+                continue;
+            }
+            let keys = Resp::Array(keys.into_iter().map(Resp::Bulk).collect());
+            Self::send(id, tracker, keys);
+        }
+        drop(clients);
+    }
+
+    /// Tells every tracking client to drop its whole cache, like after a flush
+    pub(crate) fn invalidate_all(&self) {
+        let clients = self.clients.read();
+        self.keys.write().clear();
+        for (&id, tracker) in &*clients {
+            Self::send(id, tracker, Resp::Null);
+        }
+    }
+
+    /// Sends an invalidation of `keys`, an array or null for every key,
+    /// to the client itself or to the one it redirects to
+    fn send(id: u64, tracker: &Tracker, keys: Resp) {
+        if let Some(redirect) = tracker.options.redirect {
+            let message = Resp::Array(vec![
+                Resp::bulk("message"),
+                Resp::bulk(INVALIDATE_CHANNEL),
+                keys,
+            ]);
+            if !PUBSUB.send_to(INVALIDATE_CHANNEL.as_bytes(), redirect, message)
+                && CLIENTS.with(redirect, |_| ()).is_none()
+                && Self::protocol(id) == Protocol::Resp3
+            {
+                let broken = Resp::Array(vec![
+                    Resp::bulk("tracking-redir-broken"),
+                    Resp::Integer(redirect.try_into().unwrap_or(i64::MAX)),
+                ]);
+                let _ = tracker.tx.send(broken);
+            }
+            return;
+        }
+        // RESP2 connections can only receive invalidations through a redirection
+        if Self::protocol(id) == Protocol::Resp3 {
+            let _ = tracker
+                .tx
+                .send(Resp::Array(vec![Resp::bulk("invalidate"), keys]));
+        }
+    }
+
+    fn protocol(id: u64) -> Protocol {
+        CLIENTS
+            .with(id, |client| client.protocol)
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::testutil::{master, Client};
+
+    use super::*;
+
+    fn invalidate(keys: Resp) -> Resp {
+        Resp::Push(vec![Resp::bulk("invalidate"), keys])
+    }
+
+    #[tokio::test]
+    async fn invalidations() {
+        let server = master().await;
+        let mut client = Client::connect(&server).await;
+        let mut writer = Client::connect(&server).await;
+        client.cmd(&["HELLO", "3"]).await;
+        client.cmd(&["CLIENT", "TRACKING", "ON"]).await;
+
+        writer.cmd(&["SET", "k", "1"]).await;
+        pretty_assertions::assert_eq!(client.cmd(&["GET", "k"]).await, Resp::bulk("1"));
+        writer.cmd(&["SET", "k", "2"]).await;
+        pretty_assertions::assert_eq!(
+            client.read().await,
+            invalidate(Resp::Array(vec![Resp::bulk("k")]))
+        );
+        // Only once, until the key is read again
+        writer.cmd(&["SET", "k", "3"]).await;
+        pretty_assertions::assert_eq!(client.cmd(&["PING"]).await, Resp::simple("PONG"));
+
+        client.cmd(&["CLIENT", "TRACKING", "OFF"]).await;
+        client
+            .cmd(&[
+                "CLIENT", "TRACKING", "ON", "BCAST", "PREFIX", "user:", "NOLOOP",
+            ])
+            .await;
+        client.cmd(&["SET", "user:1", "a"]).await;
+        writer.cmd(&["SET", "other", "a"]).await;
+        writer.cmd(&["SET", "user:2", "a"]).await;
+        pretty_assertions::assert_eq!(
+            client.read().await,
+            invalidate(Resp::Array(vec![Resp::bulk("user:2")]))
+        );
+    }
+
+    #[tokio::test]
+    async fn redirect() {
+        let server = master().await;
+        let mut client = Client::connect(&server).await;
+        let mut listener = Client::connect(&server).await;
+        let Resp::Integer(id) = listener.cmd(&["CLIENT", "ID"]).await else {
+            panic!("CLIENT ID didn't reply an integer");
+        };
+        listener.cmd(&["SUBSCRIBE", INVALIDATE_CHANNEL]).await;
+        let id = id.to_string();
+        client
+            .cmd(&["CLIENT", "TRACKING", "ON", "REDIRECT", &id, "OPTIN"])
+            .await;
+
+        client.cmd(&["GET", "a"]).await;
+        client.cmd(&["CLIENT", "CACHING", "YES"]).await;
+        client.cmd(&["GET", "b"]).await;
+        client.cmd(&["SET", "a", "1"]).await;
+        client.cmd(&["SET", "b", "1"]).await;
+        pretty_assertions::assert_eq!(
+            listener.read().await,
+            Resp::Array(vec![
+                Resp::bulk("message"),
+                Resp::bulk(INVALIDATE_CHANNEL),
+                Resp::Array(vec![Resp::bulk("b")]),
+            ])
+        );
+        assert!(
+            tokio::time::timeout(Duration::from_millis(100), listener.read())
+                .await
+                .is_err()
+        );
+    }
+}